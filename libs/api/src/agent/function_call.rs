@@ -1,18 +1,21 @@
 use cloudflare::models::text_generation::{
-    Message, MessageRequest, ModelParameters, TextGeneration,
-    TextGenerationJsonResult, TextGenerationRequest, Tool,
-    HERMES_2_PRO_MISTRAL_7B,
+    Message, ModelParameters, TextGenerationJsonResult, Tool,
 };
-use langfuse::{apis::configuration, models::CreateGenerationBody};
+use langfuse::trace::Generation;
+use llm::{ChatStream, Model};
 use tracing::info;
-use uuid::Uuid;
 
-use super::{get_template, Agent};
+use super::{
+    messages,
+    prompt_store::{PromptStore, Template},
+    with_result, Agent,
+};
 
 pub struct FunctionCallAgent {
-    client: cloudflare::models::Models,
-    name: String,
-    system_prompt: String,
+    model: Model,
+    system: Vec<Message>,
+    // Also sent as tools, for models that call them natively
+    tools: Vec<Tool>,
     history: Vec<Message>,
     model_parameters: Option<ModelParameters>,
 }
@@ -20,46 +23,26 @@ pub struct FunctionCallAgent {
 impl FunctionCallAgent {
     #[allow(clippy::too_many_arguments)]
     pub async fn new(
-        client: cloudflare::models::Models,
-        configuration: &configuration::Configuration,
-        name: String,
+        model: Model,
+        prompts: &PromptStore,
         available_tools: Vec<Tool>,
         history: Vec<Message>,
         model_parameters: Option<ModelParameters>,
     ) -> anyhow::Result<Self> {
-        let mut system_prompt =
-            get_template(configuration, "function-calls-system").await?;
-        system_prompt = system_prompt.replace(
-            "{{tools}}",
-            &serde_json::to_string(&available_tools)?.replace('"', "'"),
-        );
+        let tools = serde_json::to_string(&available_tools)?.replace('"', "'");
+        let system = prompts
+            .get("function-calls-system")
+            .await?
+            .messages("system", &[("{{tools}}", &tools)]);
 
         Ok(Self {
-            client,
-            name,
-            system_prompt,
+            model,
+            system,
+            tools: available_tools,
             history,
             model_parameters,
         })
     }
-
-    async fn call(
-        &self,
-        messages: Vec<Message>,
-        model_parameters: Option<ModelParameters>,
-    ) -> anyhow::Result<TextGenerationJsonResult> {
-        let response = self
-            .client
-            .hermes_2_pro_mistral_7b(TextGenerationRequest::Message(
-                MessageRequest {
-                    messages,
-                    model_parameters,
-                    stream: Some(false),
-                },
-            ))
-            .await?;
-        Ok(response.result)
-    }
 }
 
 impl Agent for FunctionCallAgent {
@@ -67,70 +50,44 @@ impl Agent for FunctionCallAgent {
 
     async fn prompt(
         self,
-        user_prompt_template: &str,
+        generation: Generation,
+        user_prompt_template: &Template,
         prompt: &str,
         context: Option<&str>,
-    ) -> anyhow::Result<(Self::Item, CreateGenerationBody)> {
-        let contexts: Vec<_> = self
-            .history
-            .clone()
-            .into_iter()
-            .filter(|m| m.role == "system")
-            .map(|m| m.content)
-            .collect();
-        let mut user_messages = vec![];
-        for (i, mut message) in self
-            .history
-            .clone()
-            .into_iter()
-            .filter(|m| m.role == "user")
-            .enumerate()
-        {
-            message.content = user_prompt_template
-                .replace("{{prompt}}", &message.content)
-                .replace(
-                    "{{context}}",
-                    contexts.get(i).unwrap_or(&"".to_string()),
-                );
-            user_messages.push(message);
-        }
-
-        let mut messages = vec![];
-        let assistant_messages: Vec<_> = self
-            .history
-            .clone()
-            .into_iter()
-            .filter(|m| m.role == "assistant")
-            .collect();
-        for (i, user_message) in user_messages.into_iter().enumerate() {
-            messages.push(user_message);
-            messages.push(assistant_messages.get(i).unwrap().clone());
-        }
-
-        messages.insert(
-            0,
-            Message {
-                role: "system".to_string(),
-                content: self.system_prompt.clone(),
-            },
+    ) -> anyhow::Result<Self::Item> {
+        let messages = messages(
+            &self.system,
+            user_prompt_template,
+            &self.history,
+            prompt,
+            context,
         );
-        messages.push(Message {
-            role: "user".to_string(),
-            content: user_prompt_template
-                .replace("{{prompt}}", prompt)
-                .replace("{{context}}", context.unwrap_or_default()),
-        });
 
         let messages: Vec<_> = messages
             .into_iter()
             .filter(|m| !m.content.is_empty())
             .collect();
 
-        let start_time = chrono::Utc::now().to_rfc3339();
+        let generation = generation
+            .model(self.model.name())
+            .model_parameters(&self.model_parameters)
+            .input(serde_json::json!(messages));
 
-        let mut response = self
-            .call(messages.clone(), self.model_parameters.clone())
-            .await?;
+        let response = self
+            .model
+            .chat_with_tools(
+                messages,
+                self.tools,
+                self.model_parameters.clone(),
+            )
+            .await;
+        let mut response = match response {
+            Ok(response) => response,
+            Err(e) => {
+                generation.error(&e).end();
+                return Err(e);
+            }
+        };
 
         if response.tool_calls.is_none() {
             let re = regex::Regex::new(r"<tool_call>|</tool_call>").unwrap();
@@ -187,60 +144,128 @@ impl Agent for FunctionCallAgent {
             response.tool_calls = Some(rpcs);
         }
 
-        let generation = CreateGenerationBody {
-            id: Some(Some(Uuid::new_v4().to_string())),
-            name: Some(Some(self.name.clone())),
-            model: Some(Some(HERMES_2_PRO_MISTRAL_7B.to_string())),
-            model_parameters: Some(Some(
-                serde_json::from_value(
-                    serde_json::to_value(self.model_parameters).unwrap(),
-                )
-                .unwrap(),
-            )),
-            start_time: Some(Some(start_time)),
-            end_time: Some(Some(chrono::Utc::now().to_rfc3339())),
-            input: Some(Some(serde_json::Value::Array(
-                messages
-                    .iter()
-                    .map(|t| {
-                        serde_json::json!({
-                            "role": t.role,
-                            "content": t.content,
-                        })
-                    })
-                    .collect(),
-            ))),
-            output: Some(Some(serde_json::Value::String(
-                serde_json::to_string_pretty(&response).unwrap(),
-            ))),
-            ..Default::default()
-        };
+        with_result(generation, &response).end();
 
         info!(
             "FunctionCallAgent response: {:?}, prompt:{}",
             response, prompt
         );
 
-        Ok((response, generation))
+        Ok(response)
     }
 
     async fn prompt_with_stream(
         self,
-        user_prompt_template: &str,
+        user_prompt_template: &Template,
         prompt: &str,
         context: Option<&str>,
-    ) -> (
-        Vec<Message>,
-        std::pin::Pin<
-            Box<
-                dyn futures_util::Stream<Item = anyhow::Result<Self::Item>>
-                    + Send,
-            >,
-        >,
-    ) {
+    ) -> (Vec<Message>, ChatStream) {
         let _ = user_prompt_template;
         let _ = context;
         let _ = prompt;
         todo!()
     }
 }
+
+#[cfg(test)]
+mod test {
+    use std::{collections::HashMap, time::Duration};
+
+    use cloudflare::{
+        fake::{FakeCloudflare, Reply},
+        models::text_generation::{Function, TextGenerationJsonResult, Tool},
+    };
+    use langfuse::{
+        apis::configuration::Configuration,
+        trace::{Observe, Trace},
+    };
+    use llm::{Model, Provider};
+
+    use super::FunctionCallAgent;
+    use crate::agent::{
+        prompt_store::{PromptStore, Template},
+        Agent,
+    };
+
+    #[tokio::test]
+    async fn test_prompt_parses_tool_calls() {
+        // Arrange
+        let fake = FakeCloudflare::start().await.unwrap();
+        fake.reply(
+            "@hf/hermes",
+            Reply::Generation(TextGenerationJsonResult {
+                response: Some(
+                    "<tool_call>\n{'arguments': {'query': 'axum'}, 'name': 'get_article_summary'}\n</tool_call>"
+                        .to_string(),
+                ),
+                tool_calls: None,
+                usage: None,
+            }),
+        );
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(
+            dir.path().join("function-calls-system.txt"),
+            "<tools>{{tools}}</tools>",
+        )
+        .unwrap();
+        // Langfuse is the fake too, so prompts fall back to `dir`
+        let prompts = PromptStore::new(
+            Configuration {
+                base_path: fake.base_url().to_string(),
+                ..Default::default()
+            },
+            Duration::from_secs(60),
+            None,
+            HashMap::new(),
+            dir.path().to_path_buf(),
+        );
+        let tools = vec![Tool {
+            r#type: "function".to_string(),
+            function: Function {
+                name: "get_article_summary".to_string(),
+                ..Default::default()
+            },
+        }];
+        let agent = FunctionCallAgent::new(
+            Model::new(Provider::Cloudflare(fake.models()), "@hf/hermes"),
+            &prompts,
+            tools,
+            vec![],
+            None,
+        )
+        .await
+        .unwrap();
+        let trace = Trace::new("test");
+
+        // Act
+        let response = agent
+            .prompt(
+                trace.generation("function call"),
+                &Template::Text("{{prompt}}".to_string()),
+                "axum?",
+                None,
+            )
+            .await
+            .unwrap();
+
+        // Assert
+        let tool_calls = response.tool_calls.unwrap();
+        assert_eq!(tool_calls.len(), 1);
+        assert_eq!(tool_calls[0].name, "get_article_summary");
+        assert_eq!(
+            tool_calls[0].arguments.as_ref().unwrap()["query"],
+            Some("axum".to_string())
+        );
+        let request = &fake
+            .requests()
+            .into_iter()
+            .find(|request| request.model == "@hf/hermes")
+            .unwrap()
+            .body;
+        assert!(request["messages"][0]["content"]
+            .as_str()
+            .unwrap()
+            .contains("get_article_summary"));
+        assert_eq!(request["messages"][1]["content"], "axum?");
+    }
+}
@@ -1,47 +1,92 @@
-use std::pin::Pin;
+use cloudflare::models::text_generation::{Message, TextGenerationJsonResult};
+use langfuse::trace::Generation;
+use llm::ChatStream;
 
-use cloudflare::models::text_generation::Message;
-use futures_util::Stream;
-use langfuse::{
-    apis::{configuration, prompts_api::prompts_get},
-    models::CreateGenerationBody,
-};
+use self::prompt_store::Template;
 
 pub mod function_call;
+pub mod prompt_store;
 pub mod question_and_answer;
 
 pub trait Agent {
     type Item;
     fn prompt_with_stream(
         self,
-        user_prompt_template: &str,
+        user_prompt_template: &Template,
         prompt: &str,
         context: Option<&str>,
-    ) -> impl std::future::Future<
-        Output = (
-            Vec<Message>,
-            Pin<Box<dyn Stream<Item = anyhow::Result<Self::Item>> + Send>>,
-        ),
-    > + Send;
+    ) -> impl std::future::Future<Output = (Vec<Message>, ChatStream)> + Send;
 
+    // Fills in and ends `generation` with the call to the model.
     fn prompt(
         self,
-        user_prompt_template: &str,
+        generation: Generation,
+        user_prompt_template: &Template,
         prompt: &str,
         context: Option<&str>,
-    ) -> impl std::future::Future<
-        Output = anyhow::Result<(Self::Item, CreateGenerationBody)>,
-    > + Send;
+    ) -> impl std::future::Future<Output = anyhow::Result<Self::Item>> + Send;
 }
 
-pub async fn get_template(
-    configuration: &configuration::Configuration,
-    name: &str,
-) -> anyhow::Result<String> {
-    let result = prompts_get(configuration, name, None, None).await?;
+// The system messages, then each turn of `history` with the user template
+// filled by its prompt and the context of the turn, then `prompt`. A chat
+// template keeps the roles of its messages.
+pub fn messages(
+    system: &[Message],
+    user_prompt_template: &Template,
+    history: &[Message],
+    prompt: &str,
+    context: Option<&str>,
+) -> Vec<Message> {
+    let by_role = |role: &str| {
+        history
+            .iter()
+            .filter(|m| m.role == role)
+            .map(|m| m.content.as_str())
+            .collect::<Vec<_>>()
+    };
+    let contexts = by_role("system");
+    let assistant_messages = by_role("assistant");
 
-    match result {
-        langfuse::models::Prompt::PromptOneOf1(prompt) => Ok(prompt.prompt),
-        _ => Err(anyhow::anyhow!("Prompt is not of type PromptOneOf1")),
+    let mut messages = system.to_vec();
+    for (i, user_message) in by_role("user").into_iter().enumerate() {
+        messages.extend(user_prompt_template.messages(
+            "user",
+            &[
+                ("{{prompt}}", user_message),
+                ("{{context}}", contexts.get(i).copied().unwrap_or_default()),
+            ],
+        ));
+        messages.push(Message {
+            role: "assistant".to_string(),
+            content: assistant_messages
+                .get(i)
+                .copied()
+                .unwrap_or_default()
+                .to_string(),
+        });
+    }
+    messages.extend(user_prompt_template.messages(
+        "user",
+        &[
+            ("{{prompt}}", prompt),
+            ("{{context}}", context.unwrap_or_default()),
+        ],
+    ));
+
+    messages
+}
+
+// Sets the response and token usage of a text generation on `generation`.
+pub fn with_result(
+    generation: Generation,
+    result: &TextGenerationJsonResult,
+) -> Generation {
+    let generation = generation
+        .output(serde_json::to_string_pretty(result).unwrap_or_default());
+    match &result.usage {
+        Some(usage) => {
+            generation.usage(usage.prompt_tokens, usage.completion_tokens)
+        }
+        None => generation,
     }
 }
@@ -0,0 +1,361 @@
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::RwLock,
+    time::{Duration, Instant},
+};
+
+use anyhow::Context;
+use cloudflare::models::text_generation::Message;
+use langfuse::{
+    apis::{configuration::Configuration, prompts_api::prompts_get},
+    models::Prompt,
+};
+use toml::{map::Map, Value};
+use tracing::error;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Template {
+    Text(String),
+    Chat(Vec<Message>),
+}
+
+impl Template {
+    // The messages of the template with each `(placeholder, value)` of
+    // `variables` filled in. A text template is a single message of `role`,
+    // while the messages of a chat prompt keep their own roles.
+    pub fn messages(
+        &self,
+        role: &str,
+        variables: &[(&str, &str)],
+    ) -> Vec<Message> {
+        let fill = |content: &str| {
+            variables.iter().fold(
+                content.to_string(),
+                |content, (placeholder, value)| {
+                    content.replace(placeholder, value)
+                },
+            )
+        };
+
+        match self {
+            Template::Text(text) => vec![Message {
+                role: role.to_string(),
+                content: fill(text),
+            }],
+            Template::Chat(messages) => messages
+                .iter()
+                .map(|message| Message {
+                    role: message.role.clone(),
+                    content: fill(&message.content),
+                })
+                .collect(),
+        }
+    }
+}
+
+impl From<Prompt> for Template {
+    fn from(prompt: Prompt) -> Self {
+        match prompt {
+            Prompt::PromptOneOf(prompt) => Template::Chat(
+                prompt
+                    .prompt
+                    .into_iter()
+                    .map(|message| Message {
+                        role: message.role,
+                        content: message.content,
+                    })
+                    .collect(),
+            ),
+            Prompt::PromptOneOf1(prompt) => Template::Text(prompt.prompt),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Pin {
+    Version(i32),
+    Label(String),
+}
+
+struct Entry {
+    template: Template,
+    fetched_at: Instant,
+}
+
+// Prompt templates from Langfuse cached for `ttl`. When Langfuse can't be
+// reached, the last fetched template is used, then the bundled one.
+pub struct PromptStore {
+    configuration: Configuration,
+    ttl: Duration,
+    default_pin: Option<Pin>,
+    pins: HashMap<String, Pin>,
+    fallback_dir: PathBuf,
+    cache: RwLock<HashMap<String, Entry>>,
+}
+
+impl PromptStore {
+    pub fn new(
+        configuration: Configuration,
+        ttl: Duration,
+        default_pin: Option<Pin>,
+        pins: HashMap<String, Pin>,
+        fallback_dir: PathBuf,
+    ) -> Self {
+        Self {
+            configuration,
+            ttl,
+            default_pin,
+            pins,
+            fallback_dir,
+            cache: RwLock::new(HashMap::new()),
+        }
+    }
+
+    // Builds the store from the `[prompts]` table of the config. Pins are
+    // either a version number or a label.
+    pub fn from_config(
+        mut configuration: Configuration,
+        config: &Map<String, Value>,
+        workspace_dir: &Path,
+    ) -> anyhow::Result<Self> {
+        let ttl = config
+            .get("ttl_secs")
+            .and_then(Value::as_integer)
+            .context("failed to find ttl_secs")?;
+        let request_timeout = config
+            .get("request_timeout_secs")
+            .and_then(Value::as_integer)
+            .context("failed to find request_timeout_secs")?;
+        configuration.client = reqwest::Client::builder()
+            .timeout(Duration::from_secs(request_timeout as u64))
+            .build()
+            .context("failed to build prompts client")?;
+        let fallback_dir = config
+            .get("fallback_dir")
+            .and_then(Value::as_str)
+            .context("failed to find fallback_dir")?;
+
+        let mut pins = HashMap::new();
+        if let Some(table) = config.get("pins").and_then(Value::as_table) {
+            for (name, pin) in table {
+                pins.insert(
+                    name.to_string(),
+                    pin_from_value(pin).context("failed to parse pin")?,
+                );
+            }
+        }
+
+        Ok(Self::new(
+            configuration,
+            Duration::from_secs(ttl as u64),
+            config.get("label").map(pin_from_value).transpose()?,
+            pins,
+            workspace_dir.join(fallback_dir),
+        ))
+    }
+
+    pub async fn get(&self, name: &str) -> anyhow::Result<Template> {
+        if let Some(template) = self.cached(name, true) {
+            return Ok(template);
+        }
+
+        let pin = self.pins.get(name).or(self.default_pin.as_ref());
+        let (version, label) = match pin {
+            Some(Pin::Version(version)) => (Some(*version), None),
+            Some(Pin::Label(label)) => (None, Some(label.as_str())),
+            None => (None, None),
+        };
+
+        let template = match prompts_get(
+            &self.configuration,
+            name,
+            version,
+            label,
+        )
+        .await
+        {
+            Ok(prompt) => Template::from(prompt),
+            Err(e) => {
+                error!(task = "get prompt", name, error = e.to_string());
+                match self.cached(name, false) {
+                    Some(template) => template,
+                    None => load_fallback(&self.fallback_dir, name)?,
+                }
+            }
+        };
+
+        // A failed fetch is cached too, so that it's only retried after the
+        // ttl rather than on every request while Langfuse is down
+        if let Ok(mut cache) = self.cache.write() {
+            cache.insert(
+                name.to_string(),
+                Entry {
+                    template: template.clone(),
+                    fetched_at: Instant::now(),
+                },
+            );
+        }
+
+        Ok(template)
+    }
+
+    fn cached(&self, name: &str, fresh_only: bool) -> Option<Template> {
+        let cache = self.cache.read().ok()?;
+        let entry = cache.get(name)?;
+        if fresh_only && entry.fetched_at.elapsed() > self.ttl {
+            return None;
+        }
+
+        Some(entry.template.clone())
+    }
+}
+
+fn pin_from_value(value: &Value) -> anyhow::Result<Pin> {
+    match value {
+        Value::Integer(version) => Ok(Pin::Version(*version as i32)),
+        Value::String(label) => Ok(Pin::Label(label.to_string())),
+        value => Err(anyhow::anyhow!("invalid pin: {}", value)),
+    }
+}
+
+// Bundled templates are `<name>.txt` for text prompts and `<name>.json`
+// holding `[{"role": .., "content": ..}]` for chat prompts.
+fn load_fallback(dir: &Path, name: &str) -> anyhow::Result<Template> {
+    let text = dir.join(format!("{}.txt", name));
+    if text.exists() {
+        let text = std::fs::read_to_string(text)
+            .context("failed to read fallback prompt")?;
+        return Ok(Template::Text(text.trim_end().to_string()));
+    }
+
+    let chat = std::fs::read_to_string(dir.join(format!("{}.json", name)))
+        .with_context(|| format!("failed to find fallback prompt {}", name))?;
+    let messages = serde_json::from_str::<Vec<Message>>(&chat)
+        .context("failed to parse fallback prompt")?;
+
+    Ok(Template::Chat(messages))
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        collections::HashMap,
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc,
+        },
+        time::Duration,
+    };
+
+    use axum::{http::StatusCode, routing::get, Router};
+    use cloudflare::models::text_generation::Message;
+    use langfuse::{
+        apis::configuration::Configuration,
+        models::{ChatMessage, Prompt, PromptOneOf},
+    };
+
+    use super::{load_fallback, PromptStore, Template};
+
+    #[test]
+    fn test_chat_prompt_messages() {
+        // Arrange
+        let prompt = Prompt::PromptOneOf(Box::new(PromptOneOf {
+            prompt: vec![
+                ChatMessage::new("system".to_string(), "Be brief.".to_string()),
+                ChatMessage::new("user".to_string(), "{{prompt}}".to_string()),
+            ],
+            ..Default::default()
+        }));
+
+        // Act
+        let template = Template::from(prompt);
+
+        // Assert
+        assert_eq!(
+            template.messages("user", &[("{{prompt}}", "axum?")]),
+            vec![
+                Message {
+                    role: "system".to_string(),
+                    content: "Be brief.".to_string(),
+                },
+                Message {
+                    role: "user".to_string(),
+                    content: "axum?".to_string(),
+                },
+            ]
+        );
+        assert_eq!(
+            Template::Text("{{prompt}}".to_string())
+                .messages("user", &[("{{prompt}}", "axum?")]),
+            vec![Message {
+                role: "user".to_string(),
+                content: "axum?".to_string(),
+            }]
+        );
+    }
+
+    #[test]
+    fn test_load_fallback() {
+        // Arrange
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("text.txt"), "{{prompt}}\n").unwrap();
+        std::fs::write(
+            dir.path().join("chat.json"),
+            r#"[{"role": "system", "content": "Be brief."}]"#,
+        )
+        .unwrap();
+
+        // Act
+        let text = load_fallback(dir.path(), "text").unwrap();
+        let chat = load_fallback(dir.path(), "chat").unwrap();
+        let missing = load_fallback(dir.path(), "missing");
+
+        // Assert
+        assert_eq!(text, Template::Text("{{prompt}}".to_string()));
+        assert_eq!(
+            chat,
+            Template::Chat(vec![Message {
+                role: "system".to_string(),
+                content: "Be brief.".to_string(),
+            }])
+        );
+        assert!(missing.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_get_caches_failed_fetch() {
+        // Arrange
+        let requests = Arc::new(AtomicUsize::new(0));
+        let counter = requests.clone();
+        let app = Router::new().fallback(get(move || async move {
+            counter.fetch_add(1, Ordering::SeqCst);
+            StatusCode::INTERNAL_SERVER_ERROR
+        }));
+        let listener =
+            tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await });
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("text.txt"), "{{prompt}}").unwrap();
+        let prompts = PromptStore::new(
+            Configuration {
+                base_path: format!("http://{}", address),
+                ..Default::default()
+            },
+            Duration::from_secs(60),
+            None,
+            HashMap::new(),
+            dir.path().to_path_buf(),
+        );
+
+        // Act
+        let first = prompts.get("text").await.unwrap();
+        let second = prompts.get("text").await.unwrap();
+
+        // Assert
+        assert_eq!(first, Template::Text("{{prompt}}".to_string()));
+        assert_eq!(second, first);
+        assert_eq!(requests.load(Ordering::SeqCst), 1);
+    }
+}
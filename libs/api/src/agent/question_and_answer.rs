@@ -1,36 +1,29 @@
-use std::pin::Pin;
-
 use cloudflare::models::text_generation::{
-    Message, MessageRequest, ModelParameters, TextGeneration,
-    TextGenerationJsonResult, TextGenerationRequest, LLAMA_3_8B_INSTRUCT,
+    Message, ModelParameters, TextGenerationJsonResult,
 };
-use futures_util::Stream;
-use langfuse::models::CreateGenerationBody;
+use langfuse::trace::Generation;
+use llm::{ChatStream, Model};
 use tracing::info;
-use uuid::Uuid;
 
-use super::Agent;
+use super::{messages, prompt_store::Template, with_result, Agent};
 
 pub struct QuestionAnswerAgent {
-    client: cloudflare::models::Models,
-    name: String,
-    system_prompt: String,
+    model: Model,
+    system: Vec<Message>,
     history: Vec<Message>,
     model_parameters: Option<ModelParameters>,
 }
 
 impl QuestionAnswerAgent {
     pub fn new(
-        client: cloudflare::models::Models,
-        name: String,
-        system_prompt: String,
+        model: Model,
+        system: Vec<Message>,
         history: Vec<Message>,
         model_parameters: Option<ModelParameters>,
     ) -> Self {
         Self {
-            client,
-            name,
-            system_prompt,
+            model,
+            system,
             history,
             model_parameters,
         }
@@ -42,183 +35,154 @@ impl Agent for QuestionAnswerAgent {
 
     async fn prompt_with_stream(
         self,
-        user_prompt_template: &str,
+        user_prompt_template: &Template,
         prompt: &str,
         context: Option<&str>,
-    ) -> (
-        Vec<Message>,
-        Pin<Box<dyn Stream<Item = anyhow::Result<Self::Item>> + Send>>,
-    ) {
-        let contexts: Vec<_> = self
-            .history
-            .clone()
-            .into_iter()
-            .filter(|m| m.role == "system")
-            .map(|m| m.content)
-            .collect();
-        let mut user_messages = vec![];
-        for (i, mut message) in self
-            .history
-            .clone()
-            .into_iter()
-            .filter(|m| m.role == "user")
-            .enumerate()
-        {
-            message.content = user_prompt_template
-                .replace("{{prompt}}", &message.content)
-                .replace(
-                    "{{context}}",
-                    contexts.get(i).unwrap_or(&"".to_string()),
-                );
-            user_messages.push(message);
-        }
-
-        let mut messages = vec![];
-        let assistant_messages: Vec<_> = self
-            .history
-            .clone()
-            .into_iter()
-            .filter(|m| m.role == "assistant")
-            .collect();
-        for (i, user_message) in user_messages.into_iter().enumerate() {
-            messages.push(user_message);
-            messages.push(assistant_messages.get(i).unwrap().clone());
-        }
-
-        messages.insert(
-            0,
-            Message {
-                role: "system".to_string(),
-                content: self.system_prompt.clone(),
-            },
+    ) -> (Vec<Message>, ChatStream) {
+        let messages = messages(
+            &self.system,
+            user_prompt_template,
+            &self.history,
+            prompt,
+            context,
         );
 
-        messages.push(Message {
-            role: "user".to_string(),
-            content: user_prompt_template
-                .replace("{{prompt}}", prompt)
-                .replace("{{context}}", context.unwrap_or_default()),
-        });
-
-        let stream = self.client.llama_3_8b_instruct_with_stream(
-            TextGenerationRequest::Message(MessageRequest {
-                messages: messages.clone(),
-                stream: Some(true),
-                model_parameters: self.model_parameters.clone(),
-            }),
-        );
+        let stream = self
+            .model
+            .chat_stream(messages.clone(), self.model_parameters.clone());
 
-        (messages, Box::pin(stream))
+        (messages, stream)
     }
 
     async fn prompt(
         self,
-        user_prompt_template: &str,
+        generation: Generation,
+        user_prompt_template: &Template,
         prompt: &str,
         context: Option<&str>,
-    ) -> anyhow::Result<(Self::Item, CreateGenerationBody)> {
-        let contexts: Vec<_> = self
-            .history
-            .clone()
-            .into_iter()
-            .filter(|m| m.role == "system")
-            .map(|m| m.content)
-            .collect();
-        let mut user_messages = vec![];
-        for (i, mut message) in self
-            .history
-            .clone()
-            .into_iter()
-            .filter(|m| m.role == "user")
-            .enumerate()
-        {
-            message.content = user_prompt_template
-                .replace("{{prompt}}", &message.content)
-                .replace(
-                    "{{context}}",
-                    contexts.get(i).unwrap_or(&"".to_string()),
-                );
-            user_messages.push(message);
-        }
-
-        let mut messages = vec![];
-        let assistant_messages: Vec<_> = self
-            .history
-            .clone()
-            .into_iter()
-            .filter(|m| m.role == "assistant")
-            .collect();
-        for (i, user_message) in user_messages.into_iter().enumerate() {
-            messages.push(user_message);
-            messages.push(assistant_messages.get(i).unwrap().clone());
-        }
-
-        messages.insert(
-            0,
-            Message {
-                role: "system".to_string(),
-                content: self.system_prompt.clone(),
-            },
+    ) -> anyhow::Result<Self::Item> {
+        let messages = messages(
+            &self.system,
+            user_prompt_template,
+            &self.history,
+            prompt,
+            context,
         );
-        messages.push(Message {
-            role: "user".to_string(),
-            content: user_prompt_template
-                .replace("{{prompt}}", prompt)
-                .replace("{{context}}", context.unwrap_or_default()),
-        });
 
         let messages: Vec<_> = messages
             .into_iter()
             .filter(|m| !m.content.is_empty())
             .collect();
 
-        let start_time = chrono::Utc::now().to_rfc3339();
+        let generation = generation
+            .model(self.model.name())
+            .model_parameters(&self.model_parameters)
+            .input(serde_json::json!(messages));
 
         let response = self
-            .client
-            .llama_3_8b_instruct(TextGenerationRequest::Message(
-                MessageRequest {
-                    messages: messages.clone(),
-                    model_parameters: self.model_parameters.clone(),
-                    ..Default::default()
-                },
-            ))
-            .await?;
-
-        let generation = CreateGenerationBody {
-            id: Some(Some(Uuid::new_v4().to_string())),
-            name: Some(Some(self.name.clone())),
-            model: Some(Some(LLAMA_3_8B_INSTRUCT.to_string())),
-            model_parameters: Some(Some(
-                serde_json::from_value(
-                    serde_json::to_value(self.model_parameters).unwrap(),
-                )
-                .unwrap(),
-            )),
-            start_time: Some(Some(start_time)),
-            end_time: Some(Some(chrono::Utc::now().to_rfc3339())),
-            input: Some(Some(serde_json::Value::Array(
-                messages
-                    .iter()
-                    .map(|t| {
-                        serde_json::json!({
-                            "role": t.role,
-                            "content": t.content,
-                        })
-                    })
-                    .collect(),
-            ))),
-            output: Some(Some(serde_json::Value::String(
-                serde_json::to_string_pretty(&response).unwrap(),
-            ))),
-            ..Default::default()
+            .model
+            .chat(messages, self.model_parameters.clone())
+            .await;
+        let response = match response {
+            Ok(response) => response,
+            Err(e) => {
+                generation.error(&e).end();
+                return Err(e);
+            }
         };
 
+        with_result(generation, &response).end();
+
         info!(
             "QuestionAnswerAgent response: {:?}, prompt:{}",
             response, prompt
         );
 
-        Ok((vec![response.result], generation))
+        Ok(vec![response])
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use cloudflare::{
+        fake::{FakeCloudflare, Reply},
+        models::text_generation::{
+            Message, StreamEvent, TextGenerationJsonResult,
+        },
+    };
+    use futures_util::StreamExt;
+    use llm::{Model, Provider};
+
+    use super::QuestionAnswerAgent;
+    use crate::agent::{prompt_store::Template, Agent};
+
+    fn result(response: &str) -> TextGenerationJsonResult {
+        TextGenerationJsonResult {
+            response: Some(response.to_string()),
+            tool_calls: None,
+            usage: None,
+        }
+    }
+
+    fn message(role: &str, content: &str) -> Message {
+        Message {
+            role: role.to_string(),
+            content: content.to_string(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_prompt_with_stream() {
+        // Arrange
+        let fake = FakeCloudflare::start().await.unwrap();
+        fake.reply(
+            "@cf/llama",
+            Reply::Stream(vec![result("Axum "), result("is a web framework.")]),
+        );
+        let history = vec![
+            message("system", "earlier context"),
+            message("user", "what is tokio?"),
+            message("assistant", "A runtime."),
+        ];
+        let agent = QuestionAnswerAgent::new(
+            Model::new(Provider::Cloudflare(fake.models()), "@cf/llama"),
+            vec![message("system", "Be brief.")],
+            history,
+            None,
+        );
+
+        // Act
+        let (messages, stream) = agent
+            .prompt_with_stream(
+                &Template::Text("{{context}}\n{{prompt}}".to_string()),
+                "what is axum?",
+                Some("context"),
+            )
+            .await;
+        let answer = stream
+            .collect::<Vec<_>>()
+            .await
+            .into_iter()
+            .filter_map(|event| match event.unwrap() {
+                StreamEvent::Delta(delta) => Some(delta),
+                StreamEvent::Usage(_) => None,
+            })
+            .collect::<String>();
+
+        // Assert
+        assert_eq!(answer, "Axum is a web framework.");
+        assert_eq!(
+            messages,
+            vec![
+                message("system", "Be brief."),
+                message("user", "earlier context\nwhat is tokio?"),
+                message("assistant", "A runtime."),
+                message("user", "context\nwhat is axum?"),
+            ]
+        );
+        assert_eq!(
+            fake.requests()[0].body["messages"],
+            serde_json::json!(messages)
+        );
     }
 }
@@ -8,18 +8,20 @@ use axum::{
     middleware::Next,
     response::Response,
 };
+use chrono::{DateTime, Duration, Utc};
 use entity::user::User;
 use serde::{Deserialize, Serialize};
 use tokio::sync::OnceCell;
 
 use crate::{
     response::IntoApiResponse, ApiError, ApiState, ADMIN_USER, JWKS_URL,
+    PREVIEW_SECRET,
 };
 
 use jsonwebtoken::{
-    decode, decode_header,
+    decode, decode_header, encode,
     jwk::{AlgorithmParameters, JwkSet},
-    DecodingKey, Validation,
+    Algorithm, DecodingKey, EncodingKey, Header, Validation,
 };
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
@@ -28,8 +30,18 @@ pub struct Claims {
     pub user_id: Option<i32>,
 }
 
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct PreviewClaims {
+    pub sub: String,
+    pub aud: String,
+    pub exp: i64,
+}
+
 static JWKS: OnceCell<JwkSet> = OnceCell::const_new();
 
+const PREVIEW_AUDIENCE: &str = "preview";
+const PREVIEW_TOKEN_TTL_SECS: i64 = 60 * 60;
+
 pub async fn set_user_id(
     State(state): State<Arc<ApiState>>,
     mut req: Request,
@@ -181,3 +193,68 @@ async fn validate_token(token: &str) -> anyhow::Result<Claims> {
 
     Ok(decoded_token.claims)
 }
+
+pub fn issue_preview_token(
+    page_id: &str,
+) -> anyhow::Result<(String, DateTime<Utc>)> {
+    let secret = PREVIEW_SECRET.get().context("failed to get secret")?;
+    let expires_at = Utc::now() + Duration::seconds(PREVIEW_TOKEN_TTL_SECS);
+
+    let claims = PreviewClaims {
+        sub: page_id.to_string(),
+        aud: PREVIEW_AUDIENCE.to_string(),
+        exp: expires_at.timestamp(),
+    };
+    let token = encode(
+        &Header::new(Algorithm::HS256),
+        &claims,
+        &EncodingKey::from_secret(secret.as_bytes()),
+    )?;
+
+    Ok((token, expires_at))
+}
+
+// A preview token only unlocks the draft page it was issued for.
+pub fn can_preview(token: Option<&str>, page_id: &str) -> bool {
+    let (Some(token), Some(secret)) = (token, PREVIEW_SECRET.get()) else {
+        return false;
+    };
+
+    let validation = {
+        let mut validation = Validation::new(Algorithm::HS256);
+        validation.set_audience(&[PREVIEW_AUDIENCE]);
+        validation.set_required_spec_claims(&["sub", "aud", "exp"]);
+        validation
+    };
+
+    let claims = decode::<PreviewClaims>(
+        token,
+        &DecodingKey::from_secret(secret.as_bytes()),
+        &validation,
+    );
+
+    matches!(claims, Ok(claims) if claims.claims.sub == page_id)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{can_preview, issue_preview_token};
+    use crate::PREVIEW_SECRET;
+
+    #[test]
+    fn test_preview_token() {
+        // Arrange
+        PREVIEW_SECRET.set("secret".to_string()).unwrap();
+        let (token, _) = issue_preview_token("page").unwrap();
+
+        // Act
+        let same_page = can_preview(Some(&token), "page");
+        let other_page = can_preview(Some(&token), "other");
+        let no_token = can_preview(None, "page");
+
+        // Assert
+        assert!(same_page);
+        assert!(!other_page);
+        assert!(!no_token);
+    }
+}
@@ -1,19 +1,37 @@
 use axum::{
-    extract::{Path, State},
+    extract::{Path, Query, State},
+    http::HeaderMap,
+    response::{IntoResponse, Response},
     Json,
 };
 use repository::Repository;
-mod request;
-mod response;
+pub mod request;
+pub mod response;
 
+use crate::auth::can_preview;
+use crate::cache::{conditional_json, private};
+use crate::request::{encode_cursor, PreviewParam};
 use crate::response::{ApiResponse, IntoApiResponse};
 
+use self::request::GetBlocksParam;
 use self::response::{BlockResp, GetBlockResp, GetBlocksResp};
 
 pub async fn get_blocks(
     State(repo): State<Repository>,
+    Query(params): Query<GetBlocksParam>,
 ) -> ApiResponse<Json<GetBlocksResp>> {
-    let blocks = repo.block.find_all().await.into_response("502-003")?;
+    let cursor = params.pagination.cursor().into_response("400-001")?;
+
+    let (blocks, next_cursor) = repo
+        .block
+        .find(
+            params.pagination.limit(),
+            cursor,
+            params.date_range.from,
+            params.date_range.to,
+        )
+        .await
+        .into_response("502-003")?;
 
     let response = Json(GetBlocksResp {
         blocks: blocks
@@ -23,6 +41,7 @@ pub async fn get_blocks(
                 contents: a.contents,
             })
             .collect(),
+        next_cursor: next_cursor.as_ref().map(encode_cursor),
     });
 
     Ok(response)
@@ -31,7 +50,15 @@ pub async fn get_blocks(
 pub async fn get_block(
     State(repo): State<Repository>,
     Path(id): Path<String>,
-) -> ApiResponse<Json<GetBlockResp>> {
+    Query(params): Query<PreviewParam>,
+    headers: HeaderMap,
+) -> ApiResponse<Response> {
+    let page = repo.page.find_by_id(&id).await.into_response("502-002")?;
+    let draft = page.is_some_and(|page| page.draft);
+    if draft && !can_preview(params.preview.as_deref(), &id) {
+        return Ok(Json(GetBlockResp { block: None }).into_response());
+    }
+
     let block = repo
         .block
         .find_by_notion_page_id(&id)
@@ -39,13 +66,21 @@ pub async fn get_block(
         .into_response("502-004")?;
 
     let Some(block) = block else {
-        return Ok(Json(GetBlockResp { block: None }));
+        return Ok(Json(GetBlockResp { block: None }).into_response());
     };
 
-    Ok(Json(GetBlockResp {
+    let response = GetBlockResp {
         block: Some(BlockResp {
             parent_id: block.notion_page_id,
             contents: block.contents,
         }),
-    }))
+    };
+
+    let response = conditional_json(&headers, block.updated_at, &response)
+        .into_response("502-024")?;
+    if draft {
+        return Ok(private(response));
+    }
+
+    Ok(response)
 }
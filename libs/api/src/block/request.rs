@@ -1 +1,12 @@
+use serde::Deserialize;
+use utoipa::{IntoParams, ToSchema};
 
+use crate::request::{CursorPagination, DateRange};
+
+#[derive(Deserialize, ToSchema, IntoParams)]
+pub struct GetBlocksParam {
+    #[serde(flatten)]
+    pub date_range: DateRange,
+    #[serde(flatten)]
+    pub pagination: CursorPagination,
+}
@@ -9,6 +9,7 @@ pub struct BlockResp {
 #[derive(Serialize)]
 pub struct GetBlocksResp {
     pub blocks: Vec<BlockResp>,
+    pub next_cursor: Option<String>,
 }
 
 #[derive(Serialize)]
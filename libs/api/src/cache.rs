@@ -0,0 +1,134 @@
+use std::fmt::Write as _;
+use std::time::SystemTime;
+
+use anyhow::Context;
+use axum::{
+    http::{header, HeaderMap, HeaderValue, StatusCode},
+    response::{IntoResponse, Response},
+};
+use chrono::{DateTime, Utc};
+use httpdate::HttpDate;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+
+// Serves `body` as json unless the client already holds the same version.
+pub fn conditional_json<T: Serialize>(
+    headers: &HeaderMap,
+    last_modified: Option<DateTime<Utc>>,
+    body: &T,
+) -> anyhow::Result<Response> {
+    let bytes = serde_json::to_vec(body).context("failed to serialize")?;
+    let etag = etag(&bytes);
+    let last_modified = last_modified.map(SystemTime::from);
+
+    let not_modified = match headers.get(header::IF_NONE_MATCH) {
+        // If-None-Match takes precedence over If-Modified-Since.
+        Some(if_none_match) => matches_etag(if_none_match, &etag),
+        None => {
+            let if_modified_since = headers
+                .get(header::IF_MODIFIED_SINCE)
+                .and_then(|value| value.to_str().ok())
+                .and_then(|value| value.parse::<HttpDate>().ok());
+            match (if_modified_since, last_modified) {
+                (Some(if_modified_since), Some(last_modified)) => {
+                    // Http dates only have second precision.
+                    HttpDate::from(last_modified) <= if_modified_since
+                }
+                _ => false,
+            }
+        }
+    };
+
+    let mut response = if not_modified {
+        StatusCode::NOT_MODIFIED.into_response()
+    } else {
+        ([(header::CONTENT_TYPE, "application/json")], bytes).into_response()
+    };
+
+    let response_headers = response.headers_mut();
+    response_headers.insert(header::ETAG, HeaderValue::from_str(&etag)?);
+    if let Some(last_modified) = last_modified {
+        response_headers.insert(
+            header::LAST_MODIFIED,
+            HeaderValue::from_str(&httpdate::fmt_http_date(last_modified))?,
+        );
+    }
+
+    Ok(response)
+}
+
+// Keeps a previewed draft out of shared caches. The cache control of the
+// route only applies when the handler didn't set one.
+pub fn private(mut response: Response) -> Response {
+    response.headers_mut().insert(
+        header::CACHE_CONTROL,
+        HeaderValue::from_static("private, no-store"),
+    );
+    response
+}
+
+fn etag(bytes: &[u8]) -> String {
+    let digest = Sha256::digest(bytes);
+    let hash = digest[..16].iter().fold(String::new(), |mut hash, byte| {
+        let _ = write!(hash, "{:02x}", byte);
+        hash
+    });
+
+    format!("\"{}\"", hash)
+}
+
+fn matches_etag(if_none_match: &HeaderValue, etag: &str) -> bool {
+    let Ok(if_none_match) = if_none_match.to_str() else {
+        return false;
+    };
+
+    if_none_match
+        .split(',')
+        .map(str::trim)
+        .any(|tag| tag == "*" || tag.strip_prefix("W/").unwrap_or(tag) == etag)
+}
+
+#[cfg(test)]
+mod test {
+    use axum::http::{header, HeaderMap, HeaderValue, StatusCode};
+    use chrono::{Duration, TimeZone, Utc};
+
+    use super::conditional_json;
+
+    #[test]
+    fn test_conditional_json() {
+        // Arrange
+        let updated_at = Utc.with_ymd_and_hms(2024, 5, 1, 0, 0, 0).unwrap();
+        let body = vec!["page"];
+        let first =
+            conditional_json(&HeaderMap::new(), Some(updated_at), &body)
+                .unwrap();
+        let etag = first.headers()[header::ETAG].clone();
+
+        let mut if_none_match = HeaderMap::new();
+        if_none_match.insert(header::IF_NONE_MATCH, etag);
+        let mut stale = HeaderMap::new();
+        stale.insert(
+            header::IF_MODIFIED_SINCE,
+            HeaderValue::from_str(&httpdate::fmt_http_date(
+                (updated_at - Duration::seconds(1)).into(),
+            ))
+            .unwrap(),
+        );
+
+        // Act
+        let cached =
+            conditional_json(&if_none_match, Some(updated_at), &body).unwrap();
+        let modified =
+            conditional_json(&stale, Some(updated_at), &body).unwrap();
+
+        // Assert
+        assert_eq!(first.status(), StatusCode::OK);
+        assert_eq!(
+            first.headers()[header::LAST_MODIFIED],
+            "Wed, 01 May 2024 00:00:00 GMT"
+        );
+        assert_eq!(cached.status(), StatusCode::NOT_MODIFIED);
+        assert_eq!(modified.status(), StatusCode::OK);
+    }
+}
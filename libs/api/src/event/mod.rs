@@ -1,5 +1,7 @@
 use axum::{
     extract::{Path, Query, State},
+    http::HeaderMap,
+    response::{IntoResponse, Response},
     Json,
 };
 use repository::Repository;
@@ -7,6 +9,8 @@ use repository::Repository;
 pub mod request;
 pub mod response;
 
+use crate::cache::conditional_json;
+use crate::request::encode_cursor;
 use crate::response::{ApiResponse, IntoApiResponse};
 
 use self::{
@@ -18,9 +22,18 @@ pub async fn get_events(
     State(repo): State<Repository>,
     Query(params): Query<GetEventsParam>,
 ) -> ApiResponse<Json<GetEventsResp>> {
-    let events = repo
+    let cursor = params.pagination.cursor().into_response("400-001")?;
+
+    let (events, next_cursor) = repo
         .event
-        .find(params.pagination.offset, params.pagination.limit)
+        .find(
+            params.pagination.limit(),
+            cursor,
+            params.repo,
+            params.r#type,
+            params.date_range.from,
+            params.date_range.to,
+        )
         .await
         .into_response("502-005")?;
 
@@ -31,6 +44,7 @@ pub async fn get_events(
                 contents: a.contents,
             })
             .collect(),
+        next_cursor: next_cursor.as_ref().map(encode_cursor),
     });
 
     Ok(response)
@@ -39,7 +53,8 @@ pub async fn get_events(
 pub async fn get_event(
     State(repo): State<Repository>,
     Path(id): Path<String>,
-) -> ApiResponse<Json<GetEventResp>> {
+    headers: HeaderMap,
+) -> ApiResponse<Response> {
     let event = repo
         .event
         .find_by_event_id(id)
@@ -47,12 +62,15 @@ pub async fn get_event(
         .into_response("502-006")?;
 
     let Some(event) = event else {
-        return Ok(Json(GetEventResp { event: None }));
+        return Ok(Json(GetEventResp { event: None }).into_response());
     };
 
-    Ok(Json(GetEventResp {
+    let response = GetEventResp {
         event: Some(EventResp {
             contents: event.contents,
         }),
-    }))
+    };
+
+    conditional_json(&headers, event.updated_at, &response)
+        .into_response("502-024")
 }
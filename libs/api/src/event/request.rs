@@ -1,10 +1,14 @@
 use serde::Deserialize;
 use utoipa::{IntoParams, ToSchema};
 
-use crate::request::Pagination;
+use crate::request::{CursorPagination, DateRange};
 
 #[derive(Deserialize, ToSchema, IntoParams)]
 pub struct GetEventsParam {
+    pub repo: Option<String>,
+    pub r#type: Option<String>,
     #[serde(flatten)]
-    pub pagination: Pagination,
+    pub date_range: DateRange,
+    #[serde(flatten)]
+    pub pagination: CursorPagination,
 }
@@ -8,6 +8,7 @@ pub struct EventResp {
 #[derive(Serialize)]
 pub struct GetEventsResp {
     pub events: Vec<EventResp>,
+    pub next_cursor: Option<String>,
 }
 
 #[derive(Serialize)]
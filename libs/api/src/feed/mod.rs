@@ -0,0 +1,361 @@
+use std::fmt::Write as _;
+use std::sync::Arc;
+
+use anyhow::{anyhow, Context};
+use axum::{
+    extract::{Query, State},
+    http::header,
+    response::{IntoResponse, Response},
+    Json,
+};
+use chrono::{DateTime, Utc};
+use entity::{post::Category, prelude::PostEntity};
+use notion_client::objects::page::Page;
+use serde_json::Value;
+use tracing::error;
+
+pub mod request;
+pub mod response;
+
+use crate::render::rich_text_property;
+use crate::response::{ApiResponse, IntoApiResponse};
+use crate::xml::escape;
+use crate::{ApiState, Site};
+
+use self::{
+    request::GetFeedParam,
+    response::{JsonFeedItemResp, JsonFeedResp},
+};
+
+const FEED_LIMIT: u64 = 50;
+
+struct Item {
+    id: String,
+    title: String,
+    link: String,
+    summary: Option<String>,
+    category: Category,
+    published: DateTime<Utc>,
+    updated: DateTime<Utc>,
+}
+
+/// RSS 2.0 feed of posts
+#[utoipa::path(
+    get,
+    path = "/feed.rss",
+    responses(
+        (status = 200, description = "Get rss feed successfully", body = String, content_type = "application/rss+xml")
+    ),
+    params(
+        GetFeedParam
+    )
+)]
+pub async fn get_rss(
+    State(state): State<Arc<ApiState>>,
+    Query(params): Query<GetFeedParam>,
+) -> ApiResponse<Response> {
+    let items = find_items(&state, params).await.into_response("502-007")?;
+
+    Ok((
+        [(header::CONTENT_TYPE, "application/rss+xml; charset=utf-8")],
+        rss(&state.config.site, &items),
+    )
+        .into_response())
+}
+
+/// Atom feed of posts
+#[utoipa::path(
+    get,
+    path = "/feed.atom",
+    responses(
+        (status = 200, description = "Get atom feed successfully", body = String, content_type = "application/atom+xml")
+    ),
+    params(
+        GetFeedParam
+    )
+)]
+pub async fn get_atom(
+    State(state): State<Arc<ApiState>>,
+    Query(params): Query<GetFeedParam>,
+) -> ApiResponse<Response> {
+    let items = find_items(&state, params).await.into_response("502-007")?;
+
+    Ok((
+        [(header::CONTENT_TYPE, "application/atom+xml; charset=utf-8")],
+        atom(&state.config.site, &items),
+    )
+        .into_response())
+}
+
+/// JSON Feed of posts
+#[utoipa::path(
+    get,
+    path = "/feed.json",
+    responses(
+        (status = 200, description = "Get json feed successfully", content_type = "application/feed+json")
+    ),
+    params(
+        GetFeedParam
+    )
+)]
+pub async fn get_json(
+    State(state): State<Arc<ApiState>>,
+    Query(params): Query<GetFeedParam>,
+) -> ApiResponse<Response> {
+    let items = find_items(&state, params).await.into_response("502-007")?;
+
+    let site = &state.config.site;
+    let response = JsonFeedResp {
+        version: "https://jsonfeed.org/version/1.1".to_string(),
+        title: site.title.clone(),
+        home_page_url: site.url.clone(),
+        description: site.description.clone(),
+        items: items
+            .into_iter()
+            .map(|item| JsonFeedItemResp {
+                id: guid(&item),
+                tags: vec![category(&item.category).to_string()],
+                url: item.link,
+                title: item.title,
+                summary: item.summary,
+                date_published: item.published,
+                date_modified: item.updated,
+            })
+            .collect(),
+    };
+
+    Ok((
+        [(header::CONTENT_TYPE, "application/feed+json; charset=utf-8")],
+        Json(response),
+    )
+        .into_response())
+}
+
+async fn find_items(
+    state: &ApiState,
+    params: GetFeedParam,
+) -> anyhow::Result<Vec<Item>> {
+    let (posts, _) = state
+        .repo
+        .post
+        .find(
+            Some(FEED_LIMIT),
+            None,
+            params.category.map(Category::from),
+            None,
+            None,
+        )
+        .await
+        .context("failed to find posts")?;
+
+    let mut items = vec![];
+    for post in posts {
+        let id = post.id.clone();
+        let item = to_item(&state.config.site, post);
+        let Ok(item) = item else {
+            error!(
+                task = "build feed item",
+                id = id,
+                error = item.err().unwrap().to_string(),
+            );
+            continue;
+        };
+        items.push(item);
+    }
+
+    Ok(items)
+}
+
+fn to_item(site: &Site, post: PostEntity) -> anyhow::Result<Item> {
+    let contents = post.contents.context("failed to find contents")?;
+
+    match post.category {
+        Category::Page => {
+            let page = serde_json::from_str::<Page>(&contents)
+                .context("failed to parse page")?;
+
+            Ok(Item {
+                link: format!("{}/pages/{}", site.url, page.id),
+                title: rich_text_property(&page, "title")
+                    .context("failed to get title")?,
+                summary: rich_text_property(&page, "summary")
+                    .filter(|summary| !summary.is_empty()),
+                id: post.id,
+                category: post.category,
+                published: post.created_at,
+                updated: page.last_edited_time,
+            })
+        }
+        Category::Event => {
+            let event = serde_json::from_str::<Value>(&contents)
+                .context("failed to parse event")?;
+
+            let repo = event["repo"]["name"]
+                .as_str()
+                .ok_or_else(|| anyhow!("failed to get repo name"))?;
+            let r#type = event["type"].as_str().unwrap_or("Event");
+            let issue = &event["payload"]["issue"];
+
+            let (title, link) = match issue["title"].as_str() {
+                Some(title) => (
+                    format!("{} on {}: {}", r#type, repo, title),
+                    issue["html_url"].as_str().map(str::to_string),
+                ),
+                None => (format!("{} on {}", r#type, repo), None),
+            };
+
+            Ok(Item {
+                link: link
+                    .unwrap_or_else(|| format!("https://github.com/{}", repo)),
+                summary: event["payload"]["action"]
+                    .as_str()
+                    .map(|action| format!("{} {}", action, r#type)),
+                title,
+                id: post.id,
+                category: post.category,
+                published: post.created_at,
+                updated: post.created_at,
+            })
+        }
+    }
+}
+
+fn rss(site: &Site, items: &[Item]) -> String {
+    let mut xml = String::new();
+    xml.push_str("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    xml.push_str("<rss version=\"2.0\">\n<channel>\n");
+    let _ = writeln!(xml, "<title>{}</title>", escape(&site.title));
+    let _ = writeln!(xml, "<link>{}</link>", escape(&site.url));
+    let _ = writeln!(
+        xml,
+        "<description>{}</description>",
+        escape(&site.description)
+    );
+    if let Some(updated) = items.iter().map(|item| item.updated).max() {
+        let _ = writeln!(
+            xml,
+            "<lastBuildDate>{}</lastBuildDate>",
+            updated.to_rfc2822()
+        );
+    }
+
+    for item in items {
+        xml.push_str("<item>\n");
+        let _ = writeln!(xml, "<title>{}</title>", escape(&item.title));
+        let _ = writeln!(xml, "<link>{}</link>", escape(&item.link));
+        let _ = writeln!(
+            xml,
+            "<guid isPermaLink=\"false\">{}</guid>",
+            escape(&guid(item))
+        );
+        if let Some(summary) = &item.summary {
+            let _ =
+                writeln!(xml, "<description>{}</description>", escape(summary));
+        }
+        let _ =
+            writeln!(xml, "<category>{}</category>", category(&item.category));
+        let _ =
+            writeln!(xml, "<pubDate>{}</pubDate>", item.published.to_rfc2822());
+        xml.push_str("</item>\n");
+    }
+
+    xml.push_str("</channel>\n</rss>\n");
+    xml
+}
+
+fn atom(site: &Site, items: &[Item]) -> String {
+    let updated = items
+        .iter()
+        .map(|item| item.updated)
+        .max()
+        .unwrap_or_else(Utc::now);
+
+    let mut xml = String::new();
+    xml.push_str("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    xml.push_str("<feed xmlns=\"http://www.w3.org/2005/Atom\">\n");
+    let _ = writeln!(xml, "<id>{}</id>", escape(&site.url));
+    let _ = writeln!(xml, "<title>{}</title>", escape(&site.title));
+    let _ = writeln!(xml, "<subtitle>{}</subtitle>", escape(&site.description));
+    let _ = writeln!(xml, "<link href=\"{}\"/>", escape(&site.url));
+    let _ = writeln!(xml, "<updated>{}</updated>", updated.to_rfc3339());
+    let _ =
+        writeln!(xml, "<author><name>{}</name></author>", escape(&site.title));
+
+    for item in items {
+        xml.push_str("<entry>\n");
+        let _ = writeln!(xml, "<id>{}</id>", escape(&guid(item)));
+        let _ = writeln!(xml, "<title>{}</title>", escape(&item.title));
+        let _ = writeln!(xml, "<link href=\"{}\"/>", escape(&item.link));
+        let _ = writeln!(
+            xml,
+            "<published>{}</published>",
+            item.published.to_rfc3339()
+        );
+        let _ =
+            writeln!(xml, "<updated>{}</updated>", item.updated.to_rfc3339());
+        if let Some(summary) = &item.summary {
+            let _ = writeln!(xml, "<summary>{}</summary>", escape(summary));
+        }
+        let _ =
+            writeln!(xml, "<category term=\"{}\"/>", category(&item.category));
+        xml.push_str("</entry>\n");
+    }
+
+    xml.push_str("</feed>\n");
+    xml
+}
+
+fn guid(item: &Item) -> String {
+    format!("urn:{}:{}", category(&item.category), item.id)
+}
+
+fn category(category: &Category) -> &'static str {
+    match category {
+        Category::Page => "page",
+        Category::Event => "event",
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use chrono::{TimeZone, Utc};
+    use entity::post::Category;
+
+    use super::{atom, rss, Item};
+    use crate::Site;
+
+    #[test]
+    fn test_feed() {
+        // Arrange
+        let site = Site {
+            url: "https://example.com".to_string(),
+            title: "example".to_string(),
+            description: "notes & events".to_string(),
+        };
+        let date = Utc.with_ymd_and_hms(2024, 5, 1, 0, 0, 0).unwrap();
+        let items = vec![Item {
+            id: "1".to_string(),
+            title: "<Hello>".to_string(),
+            link: "https://example.com/pages/1".to_string(),
+            summary: Some("summary".to_string()),
+            category: Category::Page,
+            published: date,
+            updated: date,
+        }];
+
+        // Act
+        let rss = rss(&site, &items);
+        let atom = atom(&site, &items);
+
+        // Assert
+        assert!(rss.contains("<description>notes &amp; events</description>"));
+        assert!(rss.contains("<title>&lt;Hello&gt;</title>"));
+        assert!(
+            rss.contains("<pubDate>Wed, 1 May 2024 00:00:00 +0000</pubDate>")
+        );
+        assert!(rss.contains("<guid isPermaLink=\"false\">urn:page:1</guid>"));
+        assert!(atom.contains("<updated>2024-05-01T00:00:00+00:00</updated>"));
+        assert!(atom.contains("<link href=\"https://example.com/pages/1\"/>"));
+        assert!(atom.contains("<summary>summary</summary>"));
+    }
+}
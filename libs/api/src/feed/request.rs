@@ -0,0 +1,9 @@
+use serde::Deserialize;
+use utoipa::{IntoParams, ToSchema};
+
+use crate::request::PostCategory;
+
+#[derive(Deserialize, ToSchema, IntoParams)]
+pub struct GetFeedParam {
+    pub category: Option<PostCategory>,
+}
@@ -0,0 +1,24 @@
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use serde_with::skip_serializing_none;
+
+#[derive(Serialize)]
+pub struct JsonFeedResp {
+    pub version: String,
+    pub title: String,
+    pub home_page_url: String,
+    pub description: String,
+    pub items: Vec<JsonFeedItemResp>,
+}
+
+#[skip_serializing_none]
+#[derive(Serialize)]
+pub struct JsonFeedItemResp {
+    pub id: String,
+    pub url: String,
+    pub title: String,
+    pub summary: Option<String>,
+    pub date_published: DateTime<Utc>,
+    pub date_modified: DateTime<Utc>,
+    pub tags: Vec<String>,
+}
@@ -1,14 +1,22 @@
 use std::sync::Arc;
 
-use axum::{middleware, routing::get, routing::post, Router};
+use anyhow::Context;
+use axum::{
+    http::{header, HeaderValue},
+    middleware,
+    routing::get,
+    routing::post,
+    Router,
+};
 
-use langfuse::apis::configuration::Configuration;
-use qdrant_client::client::QdrantClient;
+use langfuse::{apis::configuration::Configuration, ingestion::Ingestion};
+use llm::Llms;
 use repository::Repository;
 use rpc_router::Router as RPCRouter;
 use tokio::sync::OnceCell;
 use toml::{map::Map, Value};
 use tower_http::cors::{Any, CorsLayer};
+use tower_http::set_header::SetResponseHeaderLayer;
 use tracing::info;
 use util::workspace_dir;
 use utoipa::OpenApi;
@@ -16,24 +24,31 @@ use utoipa_rapidoc::RapiDoc;
 use utoipa_redoc::{Redoc, Servable};
 use utoipa_swagger_ui::SwaggerUi;
 use utoipauto::utoipauto;
+use vector_store::Store;
 
+use crate::agent::prompt_store::PromptStore;
 use crate::top::{receive, send};
 
 pub mod agent;
 mod auth;
 pub mod block;
+mod cache;
 pub mod event;
+pub mod feed;
 pub mod healthz;
 pub mod not_found;
 pub mod nudge;
 pub mod page;
 pub mod post;
+pub mod render;
 mod request;
 mod response;
 pub mod runtime;
 pub mod search;
+pub mod sitemap;
 pub mod top;
 pub mod user;
+mod xml;
 
 pub enum ApiError {
     AuthError(String),
@@ -46,16 +61,18 @@ pub struct ApiState {
     repo: Repository,
     notion: notion_client::endpoints::Client,
     rpc: RPCRouter,
-    cloudflare: cloudflare::models::Models,
+    llm: Llms,
     s3: aws_sdk_s3::Client,
-    qdrant: QdrantClient,
+    store: Store,
     langfuse: Configuration,
+    ingestion: Ingestion,
+    prompts: PromptStore,
     config: Config,
 }
 
 pub struct Config {
     pub aws: AWS,
-    pub qdrant: Qdrant,
+    pub site: Site,
 }
 
 pub struct AWS {
@@ -63,12 +80,93 @@ pub struct AWS {
     pub s3_url: String,
 }
 
-pub struct Qdrant {
-    pub collection: String,
+pub struct Site {
+    pub url: String,
+    pub title: String,
+    pub description: String,
+}
+
+#[cfg(test)]
+impl ApiState {
+    // A state running the models of the config on `fake` and searching
+    // `store`, for tests of the handlers. The database can't be reached and
+    // Langfuse is `fake` too, so prompts are the bundled ones.
+    pub(crate) fn fake(
+        fake: &cloudflare::fake::FakeCloudflare,
+        store: Store,
+        spill_dir: &std::path::Path,
+    ) -> anyhow::Result<Self> {
+        let cloudflare = fake.models();
+        let config = load_config("Config.toml")?;
+        // Nothing listens on the port, so queries fail rather than hang
+        let pool = sqlx::postgres::PgPoolOptions::new()
+            .acquire_timeout(std::time::Duration::from_millis(100))
+            .connect_lazy("postgres://localhost:1/test")?;
+        let repo = Repository::from_connection(
+            sea_orm::SqlxPostgresConnector::from_sqlx_postgres_pool(pool),
+        );
+        let langfuse = Configuration {
+            base_path: fake.base_url().to_string(),
+            ..Default::default()
+        };
+
+        Ok(Self {
+            env: "test".to_string(),
+            rpc: rpc::serve(
+                "Config.toml",
+                repo.clone(),
+                store.clone(),
+                cloudflare.clone(),
+            )?,
+            repo,
+            notion: notion_client::endpoints::Client::new("token".to_string())?,
+            llm: Llms::from_config(
+                config["llm"].as_table().context("failed to find llm")?,
+                cloudflare,
+            )?,
+            s3: aws_sdk_s3::Client::from_conf(
+                aws_sdk_s3::Config::builder()
+                    .behavior_version(
+                        aws_sdk_s3::config::BehaviorVersion::latest(),
+                    )
+                    .build(),
+            ),
+            store,
+            prompts: PromptStore::new(
+                langfuse.clone(),
+                std::time::Duration::from_secs(60),
+                None,
+                Default::default(),
+                workspace_dir().join("libs/api/prompts"),
+            ),
+            ingestion: Ingestion::spawn(
+                langfuse.clone(),
+                langfuse::ingestion::IngestionOptions {
+                    batch_size: 100,
+                    flush_interval: std::time::Duration::from_secs(60),
+                    max_retries: 0,
+                    spill_dir: spill_dir.to_path_buf(),
+                },
+            ),
+            langfuse,
+            config: Config {
+                aws: AWS {
+                    bucket: "bucket".to_string(),
+                    s3_url: "http://localhost".to_string(),
+                },
+                site: Site {
+                    url: "http://localhost".to_string(),
+                    title: "title".to_string(),
+                    description: "description".to_string(),
+                },
+            },
+        })
+    }
 }
 
 static ADMIN_USER: OnceCell<String> = OnceCell::const_new();
 static JWKS_URL: OnceCell<String> = OnceCell::const_new();
+static PREVIEW_SECRET: OnceCell<String> = OnceCell::const_new();
 
 #[allow(clippy::too_many_arguments)]
 pub async fn serve(
@@ -78,11 +176,13 @@ pub async fn serve(
     rpc: RPCRouter,
     cloudflare: cloudflare::models::Models,
     s3: aws_sdk_s3::Client,
-    qdrant: QdrantClient,
+    store: Store,
     langfuse: Configuration,
+    ingestion: Ingestion,
     bucket: String,
     config_name: &str,
     admin_user: String,
+    preview_secret: String,
 ) -> anyhow::Result<Router> {
     #[utoipauto(paths = "./libs/api/src")]
     #[derive(OpenApi)]
@@ -98,6 +198,7 @@ pub async fn serve(
     let config = load_config(config_name)?;
 
     ADMIN_USER.set(admin_user).unwrap();
+    PREVIEW_SECRET.set(preview_secret).unwrap();
     JWKS_URL
         .set(config["auth0"]["jwks_url"].as_str().unwrap().to_string())
         .unwrap();
@@ -107,17 +208,32 @@ pub async fn serve(
         repo: repository.clone(),
         notion: notion_client,
         rpc,
-        cloudflare,
+        llm: Llms::from_config(
+            config["llm"]
+                .as_table()
+                .context("failed to find llm config")?,
+            cloudflare,
+        )?,
         s3,
-        qdrant,
+        store,
+        prompts: PromptStore::from_config(
+            langfuse.clone(),
+            config["prompts"]
+                .as_table()
+                .context("failed to find prompts config")?,
+            &workspace_dir(),
+        )?,
         langfuse,
+        ingestion,
         config: Config {
             aws: AWS {
                 bucket,
                 s3_url: config["aws"]["s3_url"].as_str().unwrap().to_string(),
             },
-            qdrant: Qdrant {
-                collection: config["qdrant"]["collection"]
+            site: Site {
+                url: config["site"]["url"].as_str().unwrap().to_string(),
+                title: config["site"]["title"].as_str().unwrap().to_string(),
+                description: config["site"]["description"]
                     .as_str()
                     .unwrap()
                     .to_string(),
@@ -127,6 +243,15 @@ pub async fn serve(
 
     // user
     let user_router = Router::new()
+        .route("/sessions", get(user::get_sessions))
+        .route(
+            "/sessions/:id",
+            get(user::get_session).delete(user::delete_session),
+        )
+        .route_layer(middleware::from_fn_with_state(
+            state.clone(),
+            auth::set_user_id,
+        ))
         .route("/", get(user::get_user))
         .route_layer(middleware::from_fn(auth::user_auth))
         .with_state(state.clone());
@@ -138,28 +263,59 @@ pub async fn serve(
             post(page::generate_cover_image),
         )
         .route("/:id/generate-summary", post(page::generate_summarize))
+        .route("/:id/preview-token", post(page::create_preview_token))
         .route_layer(middleware::from_fn(auth::admin_auth))
-        .route("/", get(page::get_pages))
-        .route("/:id", get(page::get_page))
+        .route(
+            "/",
+            get(page::get_pages).layer(cache_control(&config, "pages")?),
+        )
+        .route(
+            "/:id",
+            get(page::get_page).layer(cache_control(&config, "page")?),
+        )
+        .route("/:id/render", get(page::render_page))
         .with_state(state.clone());
 
     // blocks
     let block_router = Router::new()
         .route("/", get(block::get_blocks))
-        .route("/:id", get(block::get_block))
+        .route(
+            "/:id",
+            get(block::get_block).layer(cache_control(&config, "block")?),
+        )
         .with_state(repository.clone());
 
     // events
     let event_router = Router::new()
         .route("/", get(event::get_events))
-        .route("/:id", get(event::get_event))
+        .route(
+            "/:id",
+            get(event::get_event).layer(cache_control(&config, "event")?),
+        )
         .with_state(repository.clone());
 
     // posts
     let post_router = Router::new()
-        .route("/", get(post::get_posts))
+        .route(
+            "/",
+            get(post::get_posts).layer(cache_control(&config, "posts")?),
+        )
         .with_state(repository.clone());
 
+    // feeds
+    let feed_router = Router::new()
+        .route("/feed.rss", get(feed::get_rss))
+        .route("/feed.atom", get(feed::get_atom))
+        .route("/feed.json", get(feed::get_json))
+        .with_state(state.clone());
+
+    // sitemap
+    let sitemap_router = Router::new()
+        .route("/sitemap.xml", get(sitemap::get_sitemap))
+        .route("/sitemaps/:file", get(sitemap::get_sitemap_part))
+        .route("/robots.txt", get(sitemap::get_robots))
+        .with_state(state.clone());
+
     // top
     let top_router = Router::new()
         .route("/send", get(send))
@@ -167,8 +323,10 @@ pub async fn serve(
         .with_state(repository.clone());
 
     // search
+    // each request to these embeds its query with a paid model
     let search_router = Router::new()
         .route("/sse", post(search::search_text_with_sse))
+        .route("/hybrid", get(search::hybrid::search_hybrid))
         .route_layer(middleware::from_fn_with_state(
             state.clone(),
             auth::set_user_id,
@@ -178,6 +336,23 @@ pub async fn serve(
             auth::rate_limit,
         ))
         .route_layer(middleware::from_fn(auth::user_auth))
+        .route("/", get(search::search_pages))
+        .with_state(state.clone());
+
+    // evaluation runs are started by the admin
+    let eval_router = Router::new()
+        .route("/eval", post(search::eval::post_eval))
+        .route_layer(middleware::from_fn(auth::admin_auth))
+        .with_state(state.clone());
+
+    // feedback doesn't count against the search rate limit
+    let feedback_router = Router::new()
+        .route("/feedback", post(search::feedback::post_feedback))
+        .route_layer(middleware::from_fn_with_state(
+            state.clone(),
+            auth::set_user_id,
+        ))
+        .route_layer(middleware::from_fn(auth::user_auth))
         .with_state(state.clone());
 
     // nudge
@@ -196,12 +371,17 @@ pub async fn serve(
         .merge(Redoc::with_url("/redoc", ApiDoc::openapi()))
         .merge(RapiDoc::new("/api-docs/openapi.json").path("/rapidoc"))
         .route("/healthz", get(healthz::get_health))
+        .merge(feed_router)
+        .merge(sitemap_router)
         .nest("/user", user_router)
         .nest("/pages", page_router)
         .nest("/blocks", block_router)
         .nest("/events", event_router)
         .nest("/posts", post_router)
-        .nest("/search", search_router)
+        .nest(
+            "/search",
+            search_router.merge(feedback_router).merge(eval_router),
+        )
         .nest("/nudge", nudge_router)
         // .nest("/runtime", runtime_router)
         .nest("/top", top_router)
@@ -224,3 +404,56 @@ fn load_config(config_name: &str) -> anyhow::Result<Map<String, Value>> {
 
     Ok(config)
 }
+
+fn cache_control(
+    config: &Map<String, Value>,
+    route: &str,
+) -> anyhow::Result<SetResponseHeaderLayer<HeaderValue>> {
+    let value = config["cache_control"][route]
+        .as_str()
+        .context("failed to find cache control")?;
+
+    // Handlers set their own for responses that mustn't be shared
+    Ok(SetResponseHeaderLayer::if_not_present(
+        header::CACHE_CONTROL,
+        HeaderValue::from_str(value)?,
+    ))
+}
+
+#[cfg(test)]
+mod test {
+    use axum::{
+        body::Body,
+        http::{header, Request},
+        response::IntoResponse,
+        routing::get,
+        Router,
+    };
+    use tower::ServiceExt;
+
+    use super::{cache_control, load_config};
+    use crate::cache::private;
+
+    #[tokio::test]
+    async fn test_cache_control_of_preview() {
+        // Arrange
+        let config = load_config("Config.toml").unwrap();
+        let app = Router::new()
+            .route("/draft", get(|| async { private("draft".into_response()) }))
+            .route("/published", get(|| async { "published" }))
+            .layer(cache_control(&config, "page").unwrap());
+        let request =
+            |uri: &str| Request::get(uri).body(Body::empty()).unwrap();
+
+        // Act
+        let draft = app.clone().oneshot(request("/draft")).await.unwrap();
+        let published = app.oneshot(request("/published")).await.unwrap();
+
+        // Assert
+        assert_eq!(draft.headers()[header::CACHE_CONTROL], "private, no-store");
+        assert_eq!(
+            published.headers()[header::CACHE_CONTROL],
+            config["cache_control"]["page"].as_str().unwrap()
+        );
+    }
+}
@@ -3,7 +3,7 @@ use std::net::{Ipv4Addr, SocketAddr};
 use anyhow::Context;
 use api::serve;
 use aws_sdk_s3::config::Credentials;
-use langfuse::apis::configuration;
+use langfuse::{apis::configuration, ingestion::Ingestion};
 use repository::Repository;
 use tokio::net::TcpListener;
 use toml::{map::Map, Value};
@@ -42,6 +42,8 @@ async fn main() -> anyhow::Result<()> {
     let s3 = aws_sdk_s3::Client::new(&cfg);
 
     let admin_user = secrets.get("ADMIN_USER").unwrap().as_str().unwrap();
+    let preview_secret =
+        secrets.get("PREVIEW_SECRET").unwrap().as_str().unwrap();
 
     let config_name = &format!(
         "Config{}",
@@ -52,6 +54,8 @@ async fn main() -> anyhow::Result<()> {
             .unwrap()
     );
 
+    let config = load_config(config_name)?;
+
     let cloudflare = cloudflare::models::Models::new(
         secrets
             .get("CLOUDFLARE_ACCOUNT_ID")
@@ -59,9 +63,12 @@ async fn main() -> anyhow::Result<()> {
             .as_str()
             .unwrap(),
         secrets.get("CLOUDFLARE_TOKEN").unwrap().as_str().unwrap(),
-    );
-
-    let config = load_config(config_name)?;
+    )
+    .with_config(cloudflare::config::ClientConfig::from_config(
+        config["cloudflare"]
+            .as_table()
+            .context("failed to find cloudflare config")?,
+    )?);
 
     let repository = Repository::new(conn_string).await?.with_session(
         redis::Client::open(format!(
@@ -92,9 +99,9 @@ async fn main() -> anyhow::Result<()> {
         .unwrap(),
     );
 
-    let rpc = rpc::serve(
-        config_name,
-        repository.clone(),
+    // Shared by the api and rpc so that a memory store is the same for both
+    let store = vector_store::Store::from_config(
+        &config,
         qdrant_client::client::QdrantClient::from_url(
             config
                 .get("qdrant")
@@ -107,67 +114,85 @@ async fn main() -> anyhow::Result<()> {
         .with_api_key(secrets.get("QDRANT_API_KEY").unwrap().as_str().unwrap())
         .build()
         .unwrap(),
+    )?;
+
+    let rpc = rpc::serve(
+        config_name,
+        repository.clone(),
+        store.clone(),
         cloudflare.clone(),
     )?;
-    let router = serve(
-        secrets.get("ENV").unwrap().as_str().unwrap().to_string(),
-        repository,
-        notion_client,
-        rpc,
-        cloudflare,
-        s3,
-        qdrant_client::client::QdrantClient::from_url(
-            config
-                .get("qdrant")
-                .unwrap()
-                .get("base_url")
-                .unwrap()
-                .as_str()
-                .unwrap(),
-        )
-        .with_api_key(secrets.get("QDRANT_API_KEY").unwrap().as_str().unwrap())
-        .build()
-        .unwrap(),
-        configuration::Configuration {
-            base_path: config
-                .get("langfuse")
-                .unwrap()
-                .get("base_url")
+
+    let langfuse = configuration::Configuration {
+        base_path: config
+            .get("langfuse")
+            .unwrap()
+            .get("base_url")
+            .unwrap()
+            .as_str()
+            .unwrap()
+            .to_string(),
+        basic_auth: Some((
+            secrets
+                .get("LANGFUSE_PUBLIC_KEY")
                 .unwrap()
                 .as_str()
                 .unwrap()
                 .to_string(),
-            basic_auth: Some((
+            Some(
                 secrets
-                    .get("LANGFUSE_PUBLIC_KEY")
+                    .get("LANGFUSE_SECRET_KEY")
                     .unwrap()
                     .as_str()
                     .unwrap()
                     .to_string(),
-                Some(
-                    secrets
-                        .get("LANGFUSE_SECRET_KEY")
-                        .unwrap()
-                        .as_str()
-                        .unwrap()
-                        .to_string(),
-                ),
-            )),
-            ..Default::default()
-        },
+            ),
+        )),
+        ..Default::default()
+    };
+    let ingestion = Ingestion::from_config(
+        langfuse.clone(),
+        config
+            .get("langfuse")
+            .and_then(Value::as_table)
+            .context("failed to find langfuse config")?,
+        &workspace_dir(),
+    )?;
+
+    let router = serve(
+        secrets.get("ENV").unwrap().as_str().unwrap().to_string(),
+        repository,
+        notion_client,
+        rpc,
+        cloudflare,
+        s3,
+        store,
+        langfuse,
+        ingestion.clone(),
         bucket.to_string(),
         config_name,
         admin_user.to_string(),
+        preview_secret.to_string(),
     )
     .await?;
 
     let address = SocketAddr::from((Ipv4Addr::UNSPECIFIED, 8000));
     let listener = TcpListener::bind(&address).await?;
-    Ok(axum::serve(
+    axum::serve(
         listener,
         router.into_make_service_with_connect_info::<SocketAddr>(),
     )
-    .await?)
+    .with_graceful_shutdown(shutdown_signal())
+    .await?;
+
+    // Traces still buffered would be lost otherwise.
+    ingestion.shutdown().await
+}
+
+async fn shutdown_signal() {
+    if let Err(e) = tokio::signal::ctrl_c().await {
+        tracing::error!(task = "shutdown signal", error = e.to_string());
+    }
 }
 
 fn load_env() -> anyhow::Result<Map<String, Value>> {
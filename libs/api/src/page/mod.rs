@@ -5,19 +5,18 @@ use std::sync::Arc;
 use anyhow::anyhow;
 use anyhow::Context;
 use aws_sdk_s3::primitives::ByteStream;
+use axum::http::{header, HeaderMap, StatusCode};
+use axum::response::{IntoResponse, Response};
 use axum::Extension;
 use axum::{
     extract::{Path, Query, State},
     Json,
 };
-use cloudflare::models::text_generation::Message;
-use cloudflare::models::text_generation::MessageRequest;
-use cloudflare::models::text_generation::TextGeneration;
-use cloudflare::models::text_generation::TextGenerationRequest;
-use cloudflare::models::text_to_image::{TextToImage, TextToImageRequest};
 use entity::page::ParentType;
 use entity::prelude::PageEntity;
 use image::ImageFormat;
+use langfuse::trace::{Observe, Trace};
+use notion_client::objects::block::Block;
 use notion_client::objects::page::PageProperty;
 use notion_client::objects::rich_text::RichText;
 use notion_client::objects::rich_text::Text;
@@ -32,15 +31,20 @@ use notion_client::{
 pub mod request;
 pub mod response;
 
-use crate::auth::Claims;
+use crate::agent::with_result;
+use crate::auth::{can_preview, issue_preview_token, Claims};
+use crate::cache::{conditional_json, private};
+use crate::render::{html, markdown};
+use crate::request::PreviewParam;
 use crate::response::{ApiResponse, IntoApiResponse};
 use crate::ApiState;
 
 use self::request::GenerateCoverImageParam;
 use self::request::GenerateSummarizeParam;
+use self::request::{RenderFormat, RenderPageParam};
 use self::{
     request::GetPagesParam,
-    response::{GetPageResp, GetPagesResp, PageResp},
+    response::{GetPageResp, GetPagesResp, PageResp, PreviewTokenResp},
 };
 
 /// List all pages
@@ -57,7 +61,8 @@ use self::{
 pub async fn get_pages(
     State(state): State<Arc<ApiState>>,
     Query(params): Query<GetPagesParam>,
-) -> ApiResponse<Json<GetPagesResp>> {
+    headers: HeaderMap,
+) -> ApiResponse<Response> {
     let pages = state
         .repo
         .page
@@ -71,16 +76,18 @@ pub async fn get_pages(
         .await
         .into_response("502-001")?;
 
-    let response = Json(GetPagesResp {
+    let response = GetPagesResp {
         pages: pages
             .into_iter()
             .map(|a| PageResp {
                 contents: a.contents,
             })
             .collect(),
-    });
+    };
 
-    Ok(response)
+    // A page removed or turned into a draft leaves the latest update time
+    // of the rest as it was, so only the etag applies.
+    conditional_json(&headers, None, &response).into_response("502-024")
 }
 
 /// List a page
@@ -92,12 +99,15 @@ pub async fn get_pages(
     ),
     params(
         ("id", description = "page id"),
+        PreviewParam
     )
 )]
 pub async fn get_page(
     State(state): State<Arc<ApiState>>,
     Path(id): Path<String>,
-) -> ApiResponse<Json<GetPageResp>> {
+    Query(params): Query<PreviewParam>,
+    headers: HeaderMap,
+) -> ApiResponse<Response> {
     let page = state
         .repo
         .page
@@ -106,13 +116,113 @@ pub async fn get_page(
         .into_response("502-002")?;
 
     let Some(page) = page else {
-        return Ok(Json(GetPageResp { page: None }));
+        return Ok(Json(GetPageResp { page: None }).into_response());
     };
+    if page.draft && !can_preview(params.preview.as_deref(), &id) {
+        return Ok(Json(GetPageResp { page: None }).into_response());
+    }
 
-    Ok(Json(GetPageResp {
+    let response = GetPageResp {
         page: Some(PageResp {
             contents: page.contents,
         }),
+    };
+
+    let response = conditional_json(&headers, page.updated_at, &response)
+        .into_response("502-024")?;
+    if page.draft {
+        return Ok(private(response));
+    }
+
+    Ok(response)
+}
+
+/// Render a page as markdown or html
+#[utoipa::path(
+    get,
+    path = "/pages/:id/render",
+    responses(
+        (status = 200, description = "Render a page successfully", body = String),
+        (status = 404, description = "Page was not found")
+    ),
+    params(
+        ("id", description = "page id"),
+        RenderPageParam
+    )
+)]
+pub async fn render_page(
+    State(state): State<Arc<ApiState>>,
+    Path(id): Path<String>,
+    Query(params): Query<RenderPageParam>,
+) -> ApiResponse<Response> {
+    let page = state
+        .repo
+        .page
+        .find_by_id(&id)
+        .await
+        .into_response("502-002")?;
+
+    let Some(page) = page else {
+        return Ok(StatusCode::NOT_FOUND.into_response());
+    };
+    if page.draft && !can_preview(params.preview.as_deref(), &id) {
+        return Ok(StatusCode::NOT_FOUND.into_response());
+    }
+
+    let block = state
+        .repo
+        .block
+        .find_by_notion_page_id(&id)
+        .await
+        .into_response("502-004")?;
+
+    let Some(block) = block else {
+        return Ok(StatusCode::NOT_FOUND.into_response());
+    };
+
+    let blocks = serde_json::from_str::<Vec<Block>>(&block.contents)
+        .context("failed to parse blocks")
+        .into_response("502-021")?;
+
+    let (content_type, body) = match params.format {
+        RenderFormat::Markdown => {
+            ("text/markdown; charset=utf-8", markdown::render(&blocks))
+        }
+        RenderFormat::Html => {
+            ("text/html; charset=utf-8", html::render(&blocks))
+        }
+    };
+
+    let response =
+        ([(header::CONTENT_TYPE, content_type)], body).into_response();
+    if page.draft {
+        return Ok(private(response));
+    }
+
+    Ok(response)
+}
+
+/// Issue a short-lived token to preview a draft page
+#[utoipa::path(
+    post,
+    path = "/pages/:id/preview-token",
+    responses(
+        (status = 200, description = "Issue a preview token successfully", body = PreviewTokenResp)
+    ),
+    params(
+        ("id", description = "page id"),
+    )
+)]
+pub async fn create_preview_token(
+    Extension(ref _claims): Extension<Claims>,
+    Path(id): Path<String>,
+) -> ApiResponse<Json<PreviewTokenResp>> {
+    let (token, expires_at) =
+        issue_preview_token(&id).into_response("502-023")?;
+
+    Ok(Json(PreviewTokenResp {
+        token,
+        expires_at: expires_at.to_rfc3339(),
     }))
 }
 
@@ -133,17 +243,39 @@ pub async fn generate_cover_image(
     Path(id): Path<String>,
     Json(body): Json<GenerateCoverImageParam>,
 ) -> ApiResponse<()> {
-    let bytes = state
-        .cloudflare
-        .stable_diffusion_xl_lightning(TextToImageRequest {
-            prompt: body.prompt,
-            ..Default::default()
-        })
+    let template = state
+        .prompts
+        .get("cover-image")
         .await
         .into_response("502-009")?;
+    // Image models take a single prompt, so messages are joined
+    let prompt = template
+        .messages("user", &[("{{prompt}}", &body.prompt)])
+        .into_iter()
+        .map(|message| message.content)
+        .collect::<Vec<_>>()
+        .join("\n\n");
+    let model = state.llm.task("cover-image").into_response("502-009")?;
 
-    let image = image::load_from_memory_with_format(&bytes, ImageFormat::Jpeg)
-        .context("failed to load jpg image")
+    let trace = Trace::new("generate cover image")
+        .input(body.prompt)
+        .metadata(serde_json::json!({ "page_id": id }))
+        .tags(vec![state.env.clone()]);
+    let generation = trace
+        .generation("cover image generator")
+        .model(model.name())
+        .input(prompt.clone());
+
+    let bytes = model.text_to_image(&prompt).await;
+    generation.end_with(&bytes, |generation, bytes| {
+        generation.output(serde_json::json!({ "bytes": bytes.len() }))
+    });
+    state.ingestion.send(trace.finish()).await;
+    let bytes = bytes.into_response("502-009")?;
+
+    // Providers differ in the format of the image they return
+    let image = image::load_from_memory(&bytes)
+        .context("failed to load image")
         .into_response("502-009")?;
 
     let mut buffer: Vec<u8> = Vec::new();
@@ -210,20 +342,35 @@ pub async fn generate_summarize(
     Path(id): Path<String>,
     Json(body): Json<GenerateSummarizeParam>,
 ) -> ApiResponse<()> {
-    let response =state.cloudflare.llama_3_8b_instruct(TextGenerationRequest::Message(MessageRequest { messages:  vec![
-        Message {
-            role: "system".to_string(),
-            content: "You will summarize texts. You must reply only the summary. You can't add any other contexts.".to_string(),
-        },
-        Message {
-            role: "user".to_string(),
-            content:  format!(r#"
-            Please summarize the following text.
-            "{}"
-            summary:
-            "#,body.text).to_string(),
-        },
-    ], ..Default::default()})).await.into_response("502-011")?;
+    let (system_prompt, user_prompt) = tokio::try_join!(
+        state.prompts.get("page-summarizer-system"),
+        state.prompts.get("page-summarizer-user"),
+    )
+    .into_response("502-011")?;
+    let model = state.llm.task("page-summarizer").into_response("502-011")?;
+    let messages = system_prompt
+        .messages("system", &[])
+        .into_iter()
+        .chain(user_prompt.messages("user", &[("{{text}}", &body.text)]))
+        .collect::<Vec<_>>();
+
+    let trace = Trace::new("generate summary")
+        .input(body.text)
+        .metadata(serde_json::json!({ "page_id": id }))
+        .tags(vec![state.env.clone()]);
+    let generation = trace
+        .generation("page summarizer")
+        .model(model.name())
+        .input(serde_json::json!(messages));
+
+    let response = model.chat(messages, None).await;
+    generation.end_with(&response, with_result);
+    let trace = match &response {
+        Ok(response) => trace.output(response.response.clone()),
+        Err(_) => trace,
+    };
+    state.ingestion.send(trace.finish()).await;
+    let response = response.into_response("502-011")?;
 
     let mut properties = BTreeMap::new();
     properties.insert(
@@ -232,11 +379,7 @@ pub async fn generate_summarize(
             id: None,
             rich_text: vec![RichText::Text {
                 text: Text {
-                    content: response
-                        .result
-                        .response
-                        .clone()
-                        .unwrap_or_default(),
+                    content: response.response.clone().unwrap_or_default(),
                     link: None,
                 },
                 annotations: None,
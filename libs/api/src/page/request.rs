@@ -19,3 +19,17 @@ pub struct GenerateCoverImageParam {
 pub struct GenerateSummarizeParam {
     pub text: String,
 }
+
+#[derive(Deserialize, ToSchema, IntoParams)]
+pub struct RenderPageParam {
+    pub format: RenderFormat,
+    pub preview: Option<String>,
+}
+
+#[derive(Deserialize, ToSchema)]
+pub enum RenderFormat {
+    #[serde(rename = "md")]
+    Markdown,
+    #[serde(rename = "html")]
+    Html,
+}
@@ -15,3 +15,9 @@ pub struct GetPagesResp {
 pub struct GetPageResp {
     pub page: Option<PageResp>,
 }
+
+#[derive(Serialize, ToSchema)]
+pub struct PreviewTokenResp {
+    pub token: String,
+    pub expires_at: String,
+}
@@ -1,19 +1,40 @@
-use axum::{extract::State, Json};
+use axum::{
+    extract::{Query, State},
+    http::HeaderMap,
+    response::Response,
+};
 use repository::Repository;
 
-mod request;
+pub mod request;
 pub mod response;
 
+use crate::cache::conditional_json;
+use crate::request::encode_cursor;
 use crate::response::{ApiResponse, IntoApiResponse};
 
+use self::request::GetPostsParam;
 use self::response::{GetPostsResp, PostResp};
 
 pub async fn get_posts(
     State(repo): State<Repository>,
-) -> ApiResponse<Json<GetPostsResp>> {
-    let posts = repo.post.find_all().await.into_response("502-007")?;
+    Query(params): Query<GetPostsParam>,
+    headers: HeaderMap,
+) -> ApiResponse<Response> {
+    let cursor = params.pagination.cursor().into_response("400-001")?;
 
-    let response = Json(GetPostsResp {
+    let (posts, next_cursor) = repo
+        .post
+        .find(
+            Some(params.pagination.limit()),
+            cursor,
+            params.category.map(Into::into),
+            params.date_range.from,
+            params.date_range.to,
+        )
+        .await
+        .into_response("502-007")?;
+
+    let response = GetPostsResp {
         posts: posts
             .into_iter()
             .map(|post| PostResp {
@@ -21,7 +42,9 @@ pub async fn get_posts(
                 contents: post.contents.unwrap_or_default(),
             })
             .collect(),
-    });
+        next_cursor: next_cursor.as_ref().map(encode_cursor),
+    };
 
-    Ok(response)
+    // Posts carry no update time, so only the etag applies.
+    conditional_json(&headers, None, &response).into_response("502-024")
 }
@@ -1 +1,13 @@
+use serde::Deserialize;
+use utoipa::{IntoParams, ToSchema};
 
+use crate::request::{CursorPagination, DateRange, PostCategory};
+
+#[derive(Deserialize, ToSchema, IntoParams)]
+pub struct GetPostsParam {
+    pub category: Option<PostCategory>,
+    #[serde(flatten)]
+    pub date_range: DateRange,
+    #[serde(flatten)]
+    pub pagination: CursorPagination,
+}
@@ -10,4 +10,5 @@ pub struct PostResp {
 #[derive(Serialize)]
 pub struct GetPostsResp {
     pub posts: Vec<PostResp>,
+    pub next_cursor: Option<String>,
 }
@@ -0,0 +1,369 @@
+use notion_client::objects::{
+    block::{Block, BlockType},
+    rich_text::{Annotations, RichText, TextColor},
+};
+
+use super::{children, file_url, icon, language, safe_url};
+
+#[derive(PartialEq, Clone, Copy)]
+enum List {
+    Bulleted,
+    Numbered,
+    ToDo,
+}
+
+pub fn render(blocks: &[Block]) -> String {
+    let html = render_blocks(blocks);
+    if html.is_empty() {
+        return html;
+    }
+
+    format!("{}\n", html)
+}
+
+fn render_blocks(blocks: &[Block]) -> String {
+    let mut lines = vec![];
+    let mut open_list: Option<List> = None;
+
+    for block in blocks {
+        let list = match block.block_type {
+            BlockType::BulletedListItem { .. } => Some(List::Bulleted),
+            BlockType::NumberedListItem { .. } => Some(List::Numbered),
+            BlockType::ToDo { .. } => Some(List::ToDo),
+            _ => None,
+        };
+
+        if open_list != list {
+            if let Some(open_list) = open_list {
+                lines.push(close_list(open_list).to_string());
+            }
+            if let Some(list) = list {
+                lines.push(open(list).to_string());
+            }
+            open_list = list;
+        }
+
+        let rendered = render_block(block);
+        if !rendered.is_empty() {
+            lines.push(rendered);
+        }
+    }
+
+    if let Some(open_list) = open_list {
+        lines.push(close_list(open_list).to_string());
+    }
+
+    lines.join("\n")
+}
+
+fn open(list: List) -> &'static str {
+    match list {
+        List::Bulleted => "<ul>",
+        List::Numbered => "<ol>",
+        List::ToDo => "<ul class=\"to-do\">",
+    }
+}
+
+fn close_list(list: List) -> &'static str {
+    match list {
+        List::Bulleted | List::ToDo => "</ul>",
+        List::Numbered => "</ol>",
+    }
+}
+
+fn render_block(block: &Block) -> String {
+    let children = render_blocks(children(&block.block_type));
+
+    match &block.block_type {
+        BlockType::Paragraph { paragraph } => {
+            let text = rich_text(&paragraph.rich_text);
+            if text.is_empty() {
+                return children;
+            }
+            join(&format!("<p>{}</p>", text), &children)
+        }
+        BlockType::Heading1 { heading_1 } => {
+            format!("<h1>{}</h1>", rich_text(&heading_1.rich_text))
+        }
+        BlockType::Heading2 { heading_2 } => {
+            format!("<h2>{}</h2>", rich_text(&heading_2.rich_text))
+        }
+        BlockType::Heading3 { heading_3 } => {
+            format!("<h3>{}</h3>", rich_text(&heading_3.rich_text))
+        }
+        BlockType::BulletedListItem { bulleted_list_item } => {
+            list_item(&rich_text(&bulleted_list_item.rich_text), &children)
+        }
+        BlockType::NumberedListItem { numbered_list_item } => {
+            list_item(&rich_text(&numbered_list_item.rich_text), &children)
+        }
+        BlockType::ToDo { to_do } => list_item(
+            &format!(
+                "<input type=\"checkbox\" disabled{}> {}",
+                if to_do.checked.unwrap_or_default() {
+                    " checked"
+                } else {
+                    ""
+                },
+                rich_text(&to_do.rich_text)
+            ),
+            &children,
+        ),
+        BlockType::Quote { quote } => format!(
+            "<blockquote>{}</blockquote>",
+            join(&rich_text(&quote.rich_text), &children)
+        ),
+        BlockType::Callout { callout } => format!(
+            "<div class=\"callout\">{}<div>{}</div></div>",
+            icon(&callout.icon)
+                .map(|icon| format!(
+                    "<span class=\"callout-icon\">{}</span>",
+                    escape(icon)
+                ))
+                .unwrap_or_default(),
+            rich_text(&callout.rich_text)
+        ),
+        BlockType::Toggle { toggle } => format!(
+            "<details>\n<summary>{}</summary>\n{}\n</details>",
+            rich_text(&toggle.rich_text),
+            children
+        ),
+        BlockType::Code { code } => {
+            let content = code
+                .rich_text
+                .iter()
+                .flat_map(|t| t.plain_text())
+                .collect::<String>();
+            let language = language(&code.language);
+            let class = if language.is_empty() {
+                String::new()
+            } else {
+                format!(" class=\"language-{}\"", language)
+            };
+            format!("<pre><code{}>{}</code></pre>", class, escape(&content))
+        }
+        BlockType::Image { image } => {
+            match safe_url(file_url(&image.file_type)) {
+                Some(url) => format!(
+                "<figure><img src=\"{}\" alt=\"\" loading=\"lazy\"></figure>",
+                escape(url)
+            ),
+                None => String::new(),
+            }
+        }
+        BlockType::Table { table } => {
+            let mut lines = vec!["<table>".to_string()];
+            let rows = children_rows(block);
+            for (i, row) in rows.iter().enumerate() {
+                let is_header = i == 0 && table.has_column_header;
+                if is_header {
+                    lines.push("<thead>".to_string());
+                } else if i == 0 || (i == 1 && table.has_column_header) {
+                    lines.push("<tbody>".to_string());
+                }
+
+                let cells = row
+                    .iter()
+                    .enumerate()
+                    .map(|(j, cell)| {
+                        if is_header || (j == 0 && table.has_row_header) {
+                            format!("<th>{}</th>", cell)
+                        } else {
+                            format!("<td>{}</td>", cell)
+                        }
+                    })
+                    .collect::<String>();
+                lines.push(format!("<tr>{}</tr>", cells));
+
+                if is_header {
+                    lines.push("</thead>".to_string());
+                }
+            }
+            if rows.len() > usize::from(table.has_column_header) {
+                lines.push("</tbody>".to_string());
+            }
+            lines.push("</table>".to_string());
+            lines.join("\n")
+        }
+        BlockType::Divider { .. } => "<hr>".to_string(),
+        BlockType::Equation { equation } => format!(
+            "<div class=\"equation\">{}</div>",
+            escape(&equation.expression)
+        ),
+        BlockType::Bookmark { bookmark } => {
+            let caption = rich_text(&bookmark.caption);
+            let text = if caption.is_empty() {
+                escape(&bookmark.url)
+            } else {
+                caption
+            };
+            format!("<p>{}</p>", link(&text, &bookmark.url))
+        }
+        BlockType::Embed { embed } => {
+            format!("<p>{}</p>", link(&escape(&embed.url), &embed.url))
+        }
+        BlockType::LinkPreview { link_preview } => format!(
+            "<p>{}</p>",
+            link(&escape(&link_preview.url), &link_preview.url)
+        ),
+        BlockType::File { file } => format!(
+            "<p>{}</p>",
+            link(&escape(&file.name), file_url(&file.file_type))
+        ),
+        BlockType::Pdf { pdf } => {
+            let url = file_url(&pdf.file_type);
+            format!("<p>{}</p>", link(&escape(url), url))
+        }
+        BlockType::Video { video } => {
+            match safe_url(file_url(&video.file_type)) {
+                Some(url) => {
+                    format!("<video src=\"{}\" controls></video>", escape(url))
+                }
+                None => String::new(),
+            }
+        }
+        BlockType::ChildPage { child_page } => {
+            format!("<p>{}</p>", escape(&child_page.title))
+        }
+        BlockType::ChildDatabase { child_database } => {
+            format!("<p>{}</p>", escape(&child_database.title))
+        }
+        BlockType::Template { template } => {
+            let text = rich_text(&template.rich_text);
+            if text.is_empty() {
+                return children;
+            }
+            join(&format!("<p>{}</p>", text), &children)
+        }
+        BlockType::SyncedBlock { .. } => children,
+        _ => String::new(),
+    }
+}
+
+fn children_rows(block: &Block) -> Vec<Vec<String>> {
+    children(&block.block_type)
+        .iter()
+        .filter_map(|row| match &row.block_type {
+            BlockType::TableRow { table_row } => {
+                Some(table_row.cells.iter().map(|c| rich_text(c)).collect())
+            }
+            _ => None,
+        })
+        .collect()
+}
+
+fn rich_text(texts: &[RichText]) -> String {
+    texts
+        .iter()
+        .map(|text| match text {
+            RichText::None => String::new(),
+            RichText::Equation { equation, .. } => format!(
+                "<span class=\"equation\">{}</span>",
+                escape(&equation.expression)
+            ),
+            RichText::Mention {
+                annotations,
+                plain_text,
+                href,
+                ..
+            } => annotate(plain_text, Some(annotations), href.as_deref()),
+            RichText::Text {
+                text,
+                annotations,
+                href,
+                ..
+            } => {
+                let href = text
+                    .link
+                    .as_ref()
+                    .map(|l| l.url.as_str())
+                    .or(href.as_deref());
+                annotate(&text.content, annotations.as_ref(), href)
+            }
+        })
+        .collect()
+}
+
+fn annotate(
+    content: &str,
+    annotations: Option<&Annotations>,
+    href: Option<&str>,
+) -> String {
+    let mut text = escape(content).replace('\n', "<br>");
+    if let Some(annotations) = annotations {
+        if annotations.code {
+            text = format!("<code>{}</code>", text);
+        }
+        if annotations.bold {
+            text = format!("<strong>{}</strong>", text);
+        }
+        if annotations.italic {
+            text = format!("<em>{}</em>", text);
+        }
+        if annotations.strikethrough {
+            text = format!("<s>{}</s>", text);
+        }
+        if annotations.underline {
+            text = format!("<u>{}</u>", text);
+        }
+        if let Some(color) = color(&annotations.color) {
+            text = format!("<span class=\"color-{}\">{}</span>", color, text);
+        }
+    }
+    if let Some(href) = href {
+        text = link(&text, href);
+    }
+
+    text
+}
+
+fn color(color: &TextColor) -> Option<String> {
+    if *color == TextColor::Default {
+        return None;
+    }
+    let color = serde_json::to_value(color).ok()?;
+
+    Some(color.as_str()?.replace('_', "-"))
+}
+
+fn link(text: &str, url: &str) -> String {
+    match safe_url(url) {
+        Some(url) => format!(
+            "<a href=\"{}\" rel=\"noopener noreferrer\">{}</a>",
+            escape(url),
+            text
+        ),
+        None => text.to_string(),
+    }
+}
+
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+fn join(text: &str, children: &str) -> String {
+    if children.is_empty() {
+        return text.to_string();
+    }
+
+    format!("{}\n{}", text, children)
+}
+
+fn list_item(text: &str, children: &str) -> String {
+    if children.is_empty() {
+        return format!("<li>{}</li>", text);
+    }
+
+    format!("<li>{}\n{}\n</li>", text, children)
+}
@@ -0,0 +1,329 @@
+use notion_client::objects::{
+    block::{Block, BlockType},
+    rich_text::{Annotations, RichText},
+};
+
+use super::{children, file_url, icon, language, safe_url};
+
+pub fn render(blocks: &[Block]) -> String {
+    let markdown = render_blocks(blocks);
+    if markdown.is_empty() {
+        return markdown;
+    }
+
+    format!("{}\n", markdown)
+}
+
+fn render_blocks(blocks: &[Block]) -> String {
+    let mut output = String::new();
+    let mut number = 0;
+    let mut previous_is_list_item = false;
+
+    for block in blocks {
+        let is_list_item = matches!(
+            block.block_type,
+            BlockType::BulletedListItem { .. }
+                | BlockType::NumberedListItem { .. }
+                | BlockType::ToDo { .. }
+        );
+
+        if let BlockType::NumberedListItem { .. } = block.block_type {
+            number += 1;
+        } else {
+            number = 0;
+        }
+
+        let rendered = render_block(block, number);
+        if rendered.is_empty() {
+            continue;
+        }
+
+        if !output.is_empty() {
+            if is_list_item && previous_is_list_item {
+                output.push('\n');
+            } else {
+                output.push_str("\n\n");
+            }
+        }
+        output.push_str(&rendered);
+        previous_is_list_item = is_list_item;
+    }
+
+    output
+}
+
+fn render_block(block: &Block, number: usize) -> String {
+    let children = render_blocks(children(&block.block_type));
+
+    match &block.block_type {
+        BlockType::Paragraph { paragraph } => {
+            join(&rich_text(&paragraph.rich_text), &children)
+        }
+        BlockType::Heading1 { heading_1 } => {
+            format!("# {}", rich_text(&heading_1.rich_text))
+        }
+        BlockType::Heading2 { heading_2 } => {
+            format!("## {}", rich_text(&heading_2.rich_text))
+        }
+        BlockType::Heading3 { heading_3 } => {
+            format!("### {}", rich_text(&heading_3.rich_text))
+        }
+        BlockType::BulletedListItem { bulleted_list_item } => list_item(
+            "- ",
+            &join(&rich_text(&bulleted_list_item.rich_text), &children),
+        ),
+        BlockType::NumberedListItem { numbered_list_item } => list_item(
+            &format!("{}. ", number),
+            &join(&rich_text(&numbered_list_item.rich_text), &children),
+        ),
+        BlockType::ToDo { to_do } => list_item(
+            if to_do.checked.unwrap_or_default() {
+                "- [x] "
+            } else {
+                "- [ ] "
+            },
+            &join(&rich_text(&to_do.rich_text), &children),
+        ),
+        BlockType::Quote { quote } => {
+            prefix_lines(&join(&rich_text(&quote.rich_text), &children), "> ")
+        }
+        BlockType::Callout { callout } => {
+            let text = rich_text(&callout.rich_text);
+            let text = match icon(&callout.icon) {
+                Some(icon) => format!("{} {}", icon, text),
+                None => text,
+            };
+            prefix_lines(&text, "> ")
+        }
+        BlockType::Toggle { toggle } => format!(
+            "<details>\n<summary>{}</summary>\n\n{}\n\n</details>",
+            rich_text(&toggle.rich_text),
+            children
+        ),
+        BlockType::Code { code } => {
+            let content = code
+                .rich_text
+                .iter()
+                .flat_map(|t| t.plain_text())
+                .collect::<String>();
+            let fence = "`".repeat(longest_run(&content, '`').max(2) + 1);
+            format!(
+                "{}{}\n{}\n{}",
+                fence,
+                language(&code.language),
+                content,
+                fence
+            )
+        }
+        BlockType::Image { image } => {
+            match safe_url(file_url(&image.file_type)) {
+                Some(url) => format!("![]({})", url),
+                None => String::new(),
+            }
+        }
+        BlockType::Table { table } => {
+            let rows = children_rows(block);
+            if rows.is_empty() {
+                return String::new();
+            }
+            let width = table.table_width as usize;
+            let mut lines = vec![];
+            for (i, row) in rows.iter().enumerate() {
+                let mut cells = row.clone();
+                cells.resize(width, String::new());
+                lines.push(format!("| {} |", cells.join(" | ")));
+                if i == 0 {
+                    lines.push(format!("|{}", " --- |".repeat(width)));
+                }
+            }
+            lines.join("\n")
+        }
+        BlockType::Divider { .. } => "---".to_string(),
+        BlockType::Equation { equation } => {
+            format!("$$\n{}\n$$", equation.expression)
+        }
+        BlockType::Bookmark { bookmark } => {
+            let caption = rich_text(&bookmark.caption);
+            link(
+                if caption.is_empty() {
+                    &bookmark.url
+                } else {
+                    &caption
+                },
+                &bookmark.url,
+            )
+        }
+        BlockType::Embed { embed } => link(&embed.url, &embed.url),
+        BlockType::LinkPreview { link_preview } => {
+            link(&link_preview.url, &link_preview.url)
+        }
+        BlockType::File { file } => {
+            link(&escape(&file.name), file_url(&file.file_type))
+        }
+        BlockType::Pdf { pdf } => {
+            let url = file_url(&pdf.file_type);
+            link(url, url)
+        }
+        BlockType::Video { video } => {
+            let url = file_url(&video.file_type);
+            link(url, url)
+        }
+        BlockType::ChildPage { child_page } => escape(&child_page.title),
+        BlockType::ChildDatabase { child_database } => {
+            escape(&child_database.title)
+        }
+        BlockType::Template { template } => {
+            join(&rich_text(&template.rich_text), &children)
+        }
+        BlockType::SyncedBlock { .. } => children,
+        _ => String::new(),
+    }
+}
+
+fn children_rows(block: &Block) -> Vec<Vec<String>> {
+    children(&block.block_type)
+        .iter()
+        .filter_map(|row| match &row.block_type {
+            BlockType::TableRow { table_row } => Some(
+                table_row
+                    .cells
+                    .iter()
+                    .map(|cell| {
+                        rich_text(cell).replace('|', "\\|").replace('\n', " ")
+                    })
+                    .collect(),
+            ),
+            _ => None,
+        })
+        .collect()
+}
+
+fn rich_text(texts: &[RichText]) -> String {
+    texts
+        .iter()
+        .map(|text| match text {
+            RichText::None => String::new(),
+            RichText::Equation { equation, .. } => {
+                format!("${}$", equation.expression)
+            }
+            RichText::Mention {
+                annotations,
+                plain_text,
+                href,
+                ..
+            } => annotate(plain_text, Some(annotations), href.as_deref()),
+            RichText::Text {
+                text,
+                annotations,
+                href,
+                ..
+            } => {
+                let href = text
+                    .link
+                    .as_ref()
+                    .map(|l| l.url.as_str())
+                    .or(href.as_deref());
+                annotate(&text.content, annotations.as_ref(), href)
+            }
+        })
+        .collect()
+}
+
+fn annotate(
+    content: &str,
+    annotations: Option<&Annotations>,
+    href: Option<&str>,
+) -> String {
+    let trimmed = content.trim();
+    if trimmed.is_empty() {
+        return content.to_string();
+    }
+    let leading = &content[..content.len() - content.trim_start().len()];
+    let trailing = &content[content.trim_end().len()..];
+
+    let mut text = escape(trimmed);
+    if let Some(annotations) = annotations {
+        if annotations.code {
+            let ticks = "`".repeat(longest_run(trimmed, '`') + 1);
+            text = format!("{}{}{}", ticks, trimmed, ticks);
+        }
+        if annotations.bold {
+            text = format!("**{}**", text);
+        }
+        if annotations.italic {
+            text = format!("_{}_", text);
+        }
+        if annotations.strikethrough {
+            text = format!("~~{}~~", text);
+        }
+    }
+    if let Some(href) = href {
+        text = link(&text, href);
+    }
+
+    format!("{}{}{}", leading, text, trailing)
+}
+
+fn link(text: &str, url: &str) -> String {
+    match safe_url(url) {
+        Some(url) => format!("[{}]({})", text, url.replace(' ', "%20")),
+        None => text.to_string(),
+    }
+}
+
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        if matches!(c, '\\' | '`' | '*' | '_' | '[' | ']' | '<' | '>') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+fn join(text: &str, children: &str) -> String {
+    if children.is_empty() {
+        return text.to_string();
+    }
+    if text.is_empty() {
+        return children.to_string();
+    }
+
+    format!("{}\n{}", text, children)
+}
+
+fn list_item(marker: &str, body: &str) -> String {
+    let indent = " ".repeat(marker.len());
+    let mut lines = body.lines();
+    let mut output = format!("{}{}", marker, lines.next().unwrap_or_default());
+    for line in lines {
+        output.push('\n');
+        if !line.is_empty() {
+            output.push_str(&indent);
+            output.push_str(line);
+        }
+    }
+    output
+}
+
+fn prefix_lines(text: &str, prefix: &str) -> String {
+    text.lines()
+        .map(|line| format!("{}{}", prefix, line).trim_end().to_string())
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+fn longest_run(text: &str, target: char) -> usize {
+    let mut longest = 0;
+    let mut current = 0;
+    for c in text.chars() {
+        if c == target {
+            current += 1;
+            longest = longest.max(current);
+        } else {
+            current = 0;
+        }
+    }
+    longest
+}
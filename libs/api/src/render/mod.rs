@@ -0,0 +1,255 @@
+use entity::prelude::PageEntity;
+use notion_client::objects::{
+    block::{Block, BlockType, Icon, Language},
+    file::File,
+    page::{Page, PageProperty},
+};
+
+pub mod html;
+pub mod markdown;
+
+pub fn rich_text_property(page: &Page, name: &str) -> Option<String> {
+    let text = match page.properties.get(name)? {
+        PageProperty::Title { title, .. } => title,
+        PageProperty::RichText { rich_text, .. } => rich_text,
+        _ => return None,
+    };
+
+    Some(text.iter().flat_map(|t| t.plain_text()).collect())
+}
+
+// The stored title column is lowercased for lookups, so the display title
+// comes from the page itself.
+pub fn page_title(page: &PageEntity) -> String {
+    serde_json::from_str::<Page>(&page.contents)
+        .ok()
+        .and_then(|contents| rich_text_property(&contents, "title"))
+        .unwrap_or_else(|| page.title.clone())
+}
+
+// Callouts and columns have no children in their notion types, so sync
+// stores theirs right after them instead
+fn children(block_type: &BlockType) -> &[Block] {
+    let children = match block_type {
+        BlockType::BulletedListItem { bulleted_list_item } => {
+            &bulleted_list_item.children
+        }
+        BlockType::NumberedListItem { numbered_list_item } => {
+            &numbered_list_item.children
+        }
+        BlockType::Paragraph { paragraph } => &paragraph.children,
+        BlockType::Quote { quote } => &quote.children,
+        BlockType::SyncedBlock { synced_block } => &synced_block.children,
+        BlockType::Table { table } => &table.children,
+        BlockType::Template { template } => &template.children,
+        BlockType::ToDo { to_do } => &to_do.children,
+        BlockType::Toggle { toggle } => &toggle.children,
+        _ => return &[],
+    };
+
+    children.as_deref().unwrap_or_default()
+}
+
+fn file_url(file: &File) -> &str {
+    match file {
+        File::External { external } => &external.url,
+        File::File { file } => &file.url,
+    }
+}
+
+fn icon(icon: &Icon) -> Option<&str> {
+    match icon {
+        Icon::Emoji(emoji) => Some(&emoji.emoji),
+        Icon::File(_) => None,
+    }
+}
+
+fn language(language: &Language) -> String {
+    let language = serde_json::to_value(language).unwrap_or_default();
+    let language = language.as_str().unwrap_or_default();
+
+    match language {
+        "plain text" => String::new(),
+        "c++" => "cpp".to_string(),
+        "c#" => "csharp".to_string(),
+        "f#" => "fsharp".to_string(),
+        _ => language.replace(|c: char| !c.is_ascii_alphanumeric(), "-"),
+    }
+}
+
+// Only links that can't run script in the reader's browser are kept.
+fn safe_url(url: &str) -> Option<&str> {
+    let url = url.trim();
+    let lower = url.to_ascii_lowercase();
+    if lower.starts_with("https://")
+        || lower.starts_with("http://")
+        || lower.starts_with("mailto:")
+        || (url.starts_with('/') && !url.starts_with("//"))
+    {
+        return Some(url);
+    }
+
+    None
+}
+
+#[cfg(test)]
+mod test {
+    use notion_client::objects::block::Block;
+    use serde_json::json;
+
+    use super::{html, markdown};
+
+    fn blocks() -> Vec<Block> {
+        serde_json::from_value(json!([
+            {
+                "type": "heading_1",
+                "heading_1": {
+                    "rich_text": [{
+                        "type": "text",
+                        "text": { "content": "Title" },
+                        "plain_text": "Title"
+                    }]
+                }
+            },
+            {
+                "type": "paragraph",
+                "paragraph": {
+                    "rich_text": [
+                        {
+                            "type": "text",
+                            "text": { "content": "bold " },
+                            "annotations": {
+                                "bold": true,
+                                "italic": false,
+                                "strikethrough": false,
+                                "underline": false,
+                                "code": false,
+                                "color": "default"
+                            },
+                            "plain_text": "bold "
+                        },
+                        {
+                            "type": "text",
+                            "text": {
+                                "content": "<link>",
+                                "link": { "url": "javascript:alert(1)" }
+                            },
+                            "plain_text": "<link>"
+                        }
+                    ]
+                }
+            },
+            {
+                "type": "numbered_list_item",
+                "numbered_list_item": {
+                    "rich_text": [{
+                        "type": "text",
+                        "text": { "content": "first" },
+                        "plain_text": "first"
+                    }],
+                    "color": "default",
+                    "children": [{
+                        "type": "bulleted_list_item",
+                        "bulleted_list_item": {
+                            "rich_text": [{
+                                "type": "text",
+                                "text": { "content": "nested" },
+                                "plain_text": "nested"
+                            }],
+                            "color": "default"
+                        }
+                    }]
+                }
+            },
+            {
+                "type": "numbered_list_item",
+                "numbered_list_item": {
+                    "rich_text": [{
+                        "type": "text",
+                        "text": { "content": "second" },
+                        "plain_text": "second"
+                    }],
+                    "color": "default"
+                }
+            },
+            {
+                "type": "code",
+                "code": {
+                    "caption": [],
+                    "rich_text": [{
+                        "type": "text",
+                        "text": { "content": "fn main() {}" },
+                        "plain_text": "fn main() {}"
+                    }],
+                    "language": "rust"
+                }
+            },
+            {
+                "type": "table",
+                "table": {
+                    "table_width": 2,
+                    "has_column_header": true,
+                    "has_row_header": false,
+                    "children": [
+                        {
+                            "type": "table_row",
+                            "table_row": { "cells": [
+                                [{ "type": "text", "text": { "content": "a" } }],
+                                [{ "type": "text", "text": { "content": "b" } }]
+                            ] }
+                        },
+                        {
+                            "type": "table_row",
+                            "table_row": { "cells": [
+                                [{ "type": "text", "text": { "content": "1" } }],
+                                [{ "type": "text", "text": { "content": "2|3" } }]
+                            ] }
+                        }
+                    ]
+                }
+            }
+        ]))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_render_markdown() {
+        // Arrange
+        let blocks = blocks();
+
+        // Act
+        let markdown = markdown::render(&blocks);
+
+        // Assert
+        assert_eq!(
+            markdown,
+            "# Title\n\n\
+             **bold** \\<link\\>\n\n\
+             1. first\n   - nested\n\
+             2. second\n\n\
+             ```rust\nfn main() {}\n```\n\n\
+             | a | b |\n| --- | --- |\n| 1 | 2\\|3 |\n"
+        );
+    }
+
+    #[test]
+    fn test_render_html() {
+        // Arrange
+        let blocks = blocks();
+
+        // Act
+        let html = html::render(&blocks);
+
+        // Assert
+        assert_eq!(
+            html,
+            "<h1>Title</h1>\n\
+             <p><strong>bold </strong>&lt;link&gt;</p>\n\
+             <ol>\n<li>first\n<ul>\n<li>nested</li>\n</ul>\n</li>\n\
+             <li>second</li>\n</ol>\n\
+             <pre><code class=\"language-rust\">fn main() {}</code></pre>\n\
+             <table>\n<thead>\n<tr><th>a</th><th>b</th></tr>\n</thead>\n\
+             <tbody>\n<tr><td>1</td><td>2|3</td></tr>\n</tbody>\n</table>\n"
+        );
+    }
+}
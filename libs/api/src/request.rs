@@ -1,7 +1,15 @@
+use anyhow::{anyhow, Context};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine as _};
+use chrono::{DateTime, Utc};
+use entity::cursor::Cursor;
+use entity::post::Category;
 use serde::Deserialize;
 use serde_with::serde_as;
 use serde_with::DisplayFromStr;
-use utoipa::ToSchema;
+use utoipa::{IntoParams, ToSchema};
+
+const DEFAULT_LIMIT: u64 = 20;
+const MAX_LIMIT: u64 = 100;
 
 #[serde_as]
 #[derive(Deserialize, ToSchema)]
@@ -11,3 +19,101 @@ pub struct Pagination {
     #[serde_as(as = "DisplayFromStr")]
     pub offset: u64,
 }
+
+#[serde_as]
+#[derive(Deserialize, ToSchema)]
+pub struct CursorPagination {
+    pub cursor: Option<String>,
+    #[serde_as(as = "Option<DisplayFromStr>")]
+    #[serde(default)]
+    pub limit: Option<u64>,
+}
+
+impl CursorPagination {
+    pub fn limit(&self) -> u64 {
+        self.limit.unwrap_or(DEFAULT_LIMIT).clamp(1, MAX_LIMIT)
+    }
+
+    pub fn cursor(&self) -> anyhow::Result<Option<Cursor>> {
+        self.cursor.as_deref().map(decode_cursor).transpose()
+    }
+}
+
+// Cursors are opaque to clients; they only echo back `next_cursor`.
+pub fn encode_cursor(cursor: &Cursor) -> String {
+    URL_SAFE_NO_PAD.encode(format!(
+        "{}|{}",
+        cursor.created_at.to_rfc3339(),
+        cursor.id
+    ))
+}
+
+fn decode_cursor(cursor: &str) -> anyhow::Result<Cursor> {
+    let bytes = URL_SAFE_NO_PAD
+        .decode(cursor)
+        .context("failed to decode cursor")?;
+    let cursor = String::from_utf8(bytes).context("failed to read cursor")?;
+    let (created_at, id) = cursor
+        .split_once('|')
+        .ok_or_else(|| anyhow!("failed to split cursor"))?;
+
+    Ok(Cursor {
+        created_at: DateTime::parse_from_rfc3339(created_at)
+            .context("failed to parse cursor")?
+            .with_timezone(&Utc),
+        id: id.to_string(),
+    })
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct DateRange {
+    #[schema(value_type = Option<String>, format = DateTime)]
+    pub from: Option<DateTime<Utc>>,
+    #[schema(value_type = Option<String>, format = DateTime)]
+    pub to: Option<DateTime<Utc>>,
+}
+
+#[derive(Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum PostCategory {
+    Page,
+    Event,
+}
+
+impl From<PostCategory> for Category {
+    fn from(value: PostCategory) -> Self {
+        match value {
+            PostCategory::Page => Category::Page,
+            PostCategory::Event => Category::Event,
+        }
+    }
+}
+
+#[derive(Deserialize, ToSchema, IntoParams)]
+pub struct PreviewParam {
+    pub preview: Option<String>,
+}
+
+#[cfg(test)]
+mod test {
+    use chrono::{TimeZone, Utc};
+    use entity::cursor::Cursor;
+
+    use super::{decode_cursor, encode_cursor};
+
+    #[test]
+    fn test_cursor() {
+        // Arrange
+        let cursor = Cursor {
+            created_at: Utc.with_ymd_and_hms(2024, 5, 1, 0, 0, 0).unwrap(),
+            id: "3|4".to_string(),
+        };
+
+        // Act
+        let decoded = decode_cursor(&encode_cursor(&cursor));
+
+        // Assert
+        assert_eq!(decoded.unwrap(), cursor);
+        assert!(decode_cursor("invalid").is_err());
+    }
+}
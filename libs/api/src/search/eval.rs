@@ -0,0 +1,417 @@
+use std::sync::Arc;
+
+use anyhow::Context;
+use axum::{extract::State, http::StatusCode, Json};
+use cloudflare::models::text_generation::ModelParameters;
+use langfuse::{
+    apis::{
+        dataset_run_items_api::dataset_run_items_create,
+        datasets_api::datasets_get, score_api::score_create,
+    },
+    models::{
+        CreateDatasetRunItemRequest, CreateScoreRequest, DatasetItem,
+        DatasetStatus,
+    },
+    trace::{Observe, Trace},
+};
+use tokio::{join, sync::mpsc};
+use tracing::{error, info};
+
+use crate::agent::with_result;
+use crate::response::{ApiResponse, IntoApiResponse};
+use crate::ApiState;
+
+use super::request::{EvalParam, SearchParam};
+use super::response::EvalResp;
+use super::{make_answer, retrieve};
+
+const RECALL_SCORE: &str = "page-recall";
+const GRADE_SCORE: &str = "answer-grade";
+
+#[derive(Debug, PartialEq)]
+struct Case {
+    item_id: String,
+    prompt: String,
+    answer: Option<String>,
+    page_ids: Vec<String>,
+}
+
+/// Run a Langfuse dataset through the search and score the answers
+#[utoipa::path(
+    post,
+    path = "/search/eval",
+    request_body = EvalParam,
+    responses(
+        (status = 202, description = "Start evaluation successfully", body = EvalResp)
+    )
+)]
+pub async fn post_eval(
+    State(state): State<Arc<ApiState>>,
+    Json(params): Json<EvalParam>,
+) -> ApiResponse<(StatusCode, Json<EvalResp>)> {
+    let dataset = datasets_get(&state.langfuse, &params.dataset)
+        .await
+        .context("failed to get dataset")
+        .into_response("502-032")?;
+
+    let run_name = params.run_name.unwrap_or_else(|| {
+        format!(
+            "{}-{}",
+            params.dataset,
+            chrono::Utc::now().format("%Y%m%d%H%M%S")
+        )
+    });
+
+    let cases = dataset
+        .items
+        .iter()
+        .filter(|item| item.status == DatasetStatus::Active)
+        .filter_map(case_from_item)
+        .collect::<Vec<_>>();
+    let items = cases.len();
+
+    // Each item takes several model calls, so the run goes on in the
+    // background and its results are read in Langfuse.
+    let _state = state.clone();
+    let _run_name = run_name.clone();
+    tokio::spawn(async move {
+        for case in cases {
+            let result = evaluate(&_state, &_run_name, &case).await;
+            if let Err(e) = result {
+                error!(
+                    task = "evaluate",
+                    item_id = case.item_id,
+                    error = e.to_string(),
+                );
+            }
+        }
+        info!(task = "evaluate", run_name = _run_name, "finished");
+    });
+
+    Ok((StatusCode::ACCEPTED, Json(EvalResp { run_name, items })))
+}
+
+// Items hold either the prompt itself or `{"prompt": ..}` as input, and
+// either the expected answer or `{"answer": .., "page_ids": [..]}` as
+// expected output.
+fn case_from_item(item: &DatasetItem) -> Option<Case> {
+    let input = item.input.clone().flatten()?;
+    let prompt = match &input {
+        serde_json::Value::String(prompt) => prompt.as_str(),
+        input => input.get("prompt")?.as_str()?,
+    };
+    if prompt.trim().is_empty() {
+        return None;
+    }
+
+    let expected = item.expected_output.clone().flatten();
+    let (answer, page_ids) = match &expected {
+        Some(serde_json::Value::String(answer)) => {
+            (Some(answer.to_string()), vec![])
+        }
+        Some(expected) => (
+            expected
+                .get("answer")
+                .and_then(|answer| answer.as_str())
+                .map(str::to_string),
+            expected
+                .get("page_ids")
+                .and_then(|page_ids| page_ids.as_array())
+                .map(|page_ids| {
+                    page_ids
+                        .iter()
+                        .filter_map(|id| id.as_str())
+                        .map(str::to_string)
+                        .collect()
+                })
+                .unwrap_or_default(),
+        ),
+        None => (None, vec![]),
+    };
+
+    Some(Case {
+        item_id: item.id.clone(),
+        prompt: prompt.to_string(),
+        answer,
+        page_ids,
+    })
+}
+
+async fn evaluate(
+    state: &Arc<ApiState>,
+    run_name: &str,
+    case: &Case,
+) -> anyhow::Result<()> {
+    let params = SearchParam {
+        prompt: case.prompt.clone(),
+        history: vec![],
+        session: None,
+    };
+
+    let trace = Trace::new("eval")
+        .input(case.prompt.clone())
+        .metadata(serde_json::json!({
+            "run_name": run_name,
+            "dataset_item_id": case.item_id,
+        }))
+        .tags(vec![state.env.clone(), "eval".to_string()]);
+
+    let retrieval = retrieve(&params, state, &trace).await?;
+
+    let (message_tx, mut message_rx) = mpsc::channel(100);
+    let (result, answer) = join!(
+        make_answer(
+            &params,
+            state,
+            &retrieval.context,
+            message_tx,
+            trace.clone()
+        ),
+        async {
+            let mut answer = String::new();
+            while let Some(message) = message_rx.recv().await {
+                answer.push_str(&message);
+            }
+            answer
+        }
+    );
+    result.context("failed to make answer")?;
+
+    let grade = match &case.answer {
+        Some(expected) => {
+            Some(judge(state, &trace, &case.prompt, expected, &answer).await?)
+        }
+        None => None,
+    };
+
+    let trace = trace.output(answer);
+    let trace_id = trace.id();
+    state.ingestion.send(trace.finish()).await;
+    // The run item refers to the trace, so it has to be sent first.
+    state.ingestion.flush().await?;
+
+    dataset_run_items_create(
+        &state.langfuse,
+        CreateDatasetRunItemRequest {
+            trace_id: Some(Some(trace_id.clone())),
+            ..CreateDatasetRunItemRequest::new(
+                run_name.to_string(),
+                case.item_id.clone(),
+            )
+        },
+    )
+    .await
+    .context("failed to create dataset run item")?;
+
+    if let Some(recall) = page_recall(&case.page_ids, &retrieval.page_ids) {
+        score_create(
+            &state.langfuse,
+            CreateScoreRequest::new(
+                trace_id.clone(),
+                RECALL_SCORE.to_string(),
+                recall,
+            ),
+        )
+        .await
+        .context("failed to create recall score")?;
+    }
+
+    if let Some((grade, reason)) = grade {
+        score_create(
+            &state.langfuse,
+            CreateScoreRequest {
+                comment: Some(Some(reason)),
+                ..CreateScoreRequest::new(
+                    trace_id,
+                    GRADE_SCORE.to_string(),
+                    grade,
+                )
+            },
+        )
+        .await
+        .context("failed to create grade score")?;
+    }
+
+    Ok(())
+}
+
+// Share of the expected pages found by the retrieval. Notion ids are
+// compared without hyphens since both forms are in use.
+fn page_recall(expected: &[String], retrieved: &[String]) -> Option<f64> {
+    if expected.is_empty() {
+        return None;
+    }
+
+    let normalize = |id: &str| id.replace('-', "").to_lowercase();
+    let retrieved =
+        retrieved.iter().map(|id| normalize(id)).collect::<Vec<_>>();
+    let found = expected
+        .iter()
+        .filter(|id| retrieved.contains(&normalize(id)))
+        .count();
+
+    Some(found as f64 / expected.len() as f64)
+}
+
+// Asks the model to grade the answer against the expected one from 1 to 5.
+async fn judge(
+    state: &Arc<ApiState>,
+    trace: &Trace,
+    prompt: &str,
+    expected: &str,
+    answer: &str,
+) -> anyhow::Result<(f64, String)> {
+    let system_prompt = state.prompts.get("answer-judge-system").await?;
+    let user_prompt = state.prompts.get("answer-judge-user").await?;
+    let messages = system_prompt
+        .messages("system", &[])
+        .into_iter()
+        .chain(user_prompt.messages(
+            "user",
+            &[
+                ("{{prompt}}", prompt),
+                ("{{expected}}", expected),
+                ("{{answer}}", answer),
+            ],
+        ))
+        .collect::<Vec<_>>();
+    let model = state.llm.task("answer-judge")?;
+    let model_parameters = Some(ModelParameters {
+        temperature: Some(0),
+        ..Default::default()
+    });
+
+    let generation = trace
+        .generation("answer judge")
+        .model(model.name())
+        .model_parameters(&model_parameters)
+        .input(serde_json::json!(messages));
+    let response = model.chat(messages, model_parameters).await;
+    generation.end_with(&response, with_result);
+    let response = response.context("failed to judge answer")?;
+
+    let response = response.response.unwrap_or_default();
+    let grade = parse_grade(&response)
+        .with_context(|| format!("failed to parse grade: {}", response))?;
+
+    Ok((grade, response))
+}
+
+// Reads the grade from a reply that is either `{"grade": n}` or ends with a
+// `Grade: n` line. Anything else is no grade, rather than a guess from the
+// first digit in the reply.
+fn parse_grade(response: &str) -> Option<f64> {
+    let response = response.trim();
+    let grade = match serde_json::from_str::<serde_json::Value>(response) {
+        Ok(value) => value.get("grade")?.as_u64()?,
+        Err(_) => response
+            .lines()
+            .last()?
+            .trim()
+            .strip_prefix("Grade:")?
+            .trim()
+            .parse::<u64>()
+            .ok()?,
+    };
+
+    (1..=5).contains(&grade).then_some(grade as f64)
+}
+
+#[cfg(test)]
+mod test {
+    use langfuse::models::{DatasetItem, DatasetStatus};
+    use serde_json::json;
+
+    use super::{case_from_item, page_recall, parse_grade, Case};
+
+    #[test]
+    fn test_case_from_item() {
+        // Arrange
+        let item = |input, expected_output| DatasetItem {
+            input: Some(Some(input)),
+            expected_output,
+            ..DatasetItem::new(
+                "item".to_string(),
+                DatasetStatus::Active,
+                "dataset".to_string(),
+                "dataset".to_string(),
+                String::new(),
+                String::new(),
+            )
+        };
+
+        // Act
+        let plain = case_from_item(&item(
+            json!("What is axum?"),
+            Some(Some(json!("A web framework"))),
+        ));
+        let structured = case_from_item(&item(
+            json!({"prompt": "What is axum?"}),
+            Some(Some(json!({"page_ids": ["page"]}))),
+        ));
+        let empty = case_from_item(&item(json!(" "), None));
+
+        // Assert
+        assert_eq!(
+            plain,
+            Some(Case {
+                item_id: "item".to_string(),
+                prompt: "What is axum?".to_string(),
+                answer: Some("A web framework".to_string()),
+                page_ids: vec![],
+            })
+        );
+        assert_eq!(
+            structured,
+            Some(Case {
+                item_id: "item".to_string(),
+                prompt: "What is axum?".to_string(),
+                answer: None,
+                page_ids: vec!["page".to_string()],
+            })
+        );
+        assert_eq!(empty, None);
+    }
+
+    #[test]
+    fn test_page_recall() {
+        // Arrange
+        let expected = vec![
+            "0a1b2c3d-0000-0000-0000-000000000000".to_string(),
+            "ffffffff000000000000000000000000".to_string(),
+        ];
+        let retrieved = vec!["0A1B2C3D000000000000000000000000".to_string()];
+
+        // Act
+        let recall = page_recall(&expected, &retrieved);
+        let nothing_expected = page_recall(&[], &retrieved);
+
+        // Assert
+        assert_eq!(recall, Some(0.5));
+        assert_eq!(nothing_expected, None);
+    }
+
+    #[test]
+    fn test_parse_grade() {
+        // Arrange
+        let graded = "The answer misses a detail.\nGrade: 4";
+        let json = r#"{"grade": 5}"#;
+        let ungraded = "I cannot grade this.";
+        let scale = "On a scale of 1 to 5, I would give it a 4";
+        let out_of_ten = "10/10";
+
+        // Act
+        let graded = parse_grade(graded);
+        let json = parse_grade(json);
+        let ungraded = parse_grade(ungraded);
+        let scale = parse_grade(scale);
+        let out_of_ten = parse_grade(out_of_ten);
+
+        // Assert
+        assert_eq!(graded, Some(4.0));
+        assert_eq!(json, Some(5.0));
+        assert_eq!(ungraded, None);
+        assert_eq!(scale, None);
+        assert_eq!(out_of_ten, None);
+    }
+}
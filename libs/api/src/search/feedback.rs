@@ -0,0 +1,132 @@
+use std::sync::Arc;
+
+use anyhow::{anyhow, Context};
+use axum::{extract::State, http::StatusCode, Extension, Json};
+use entity::prelude::*;
+use langfuse::{apis::score_api::score_create, models::CreateScoreRequest};
+
+use crate::auth::Claims;
+use crate::response::{ApiResponse, IntoApiResponse};
+use crate::ApiState;
+
+use super::request::{FeedbackParam, Rating, Thumb};
+
+const MAX_SCORE: f64 = 5.0;
+const MIN_SCORE: f64 = 1.0;
+
+/// Send feedback on an answer of the search
+#[utoipa::path(
+    post,
+    path = "/search/feedback",
+    request_body = FeedbackParam,
+    responses(
+        (status = 204, description = "Send feedback successfully"),
+        (status = 404, description = "Answer was not found")
+    )
+)]
+pub async fn post_feedback(
+    Extension(ref claims): Extension<Claims>,
+    State(state): State<Arc<ApiState>>,
+    Json(params): Json<FeedbackParam>,
+) -> ApiResponse<StatusCode> {
+    let (name, value) = score(&params.rating).into_response("400-002")?;
+    let user_id = claims
+        .user_id
+        .context("failed to get user id")
+        .into_response("502-030")?;
+
+    let prompt = state
+        .repo
+        .prompt
+        .find_by_trace_id(&params.trace_id)
+        .await
+        .into_response("502-030")?;
+    let Some(prompt) = prompt else {
+        return Ok(StatusCode::NOT_FOUND);
+    };
+
+    // Only the user who asked can rate the answer.
+    let session = state
+        .repo
+        .prompt_session
+        .find_by_id_and_user_id(&prompt.prompt_session_id, user_id)
+        .await
+        .into_response("502-030")?;
+    if session.is_none() {
+        return Ok(StatusCode::NOT_FOUND);
+    }
+
+    let comment = params
+        .comment
+        .map(|comment| comment.trim().to_string())
+        .filter(|comment| !comment.is_empty());
+
+    state
+        .repo
+        .feedback
+        .save(FeedbackEntity {
+            prompt_id: prompt.id,
+            user_id,
+            trace_id: params.trace_id.clone(),
+            name: name.to_string(),
+            value,
+            comment: comment.clone(),
+            ..Default::default()
+        })
+        .await
+        .into_response("502-030")?;
+
+    // The score id is stable per user and trace, so Langfuse replaces the
+    // score the same way the stored feedback is replaced.
+    score_create(
+        &state.langfuse,
+        CreateScoreRequest {
+            id: Some(Some(format!("{}-{}", params.trace_id, user_id))),
+            comment: Some(comment),
+            ..CreateScoreRequest::new(params.trace_id, name.to_string(), value)
+        },
+    )
+    .await
+    .context("failed to create score")
+    .into_response("502-031")?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+fn score(rating: &Rating) -> anyhow::Result<(&'static str, f64)> {
+    match rating {
+        Rating::Thumb(Thumb::Up) => Ok(("thumbs", 1.0)),
+        Rating::Thumb(Thumb::Down) => Ok(("thumbs", 0.0)),
+        Rating::Score(score) if (MIN_SCORE..=MAX_SCORE).contains(score) => {
+            Ok(("rating", *score))
+        }
+        Rating::Score(score) => Err(anyhow!("rating out of range: {}", score)),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::score;
+    use crate::search::request::{Rating, Thumb};
+
+    #[test]
+    fn test_score() {
+        // Arrange
+        let up = Rating::Thumb(Thumb::Up);
+        let down = Rating::Thumb(Thumb::Down);
+        let rating = Rating::Score(4.0);
+        let out_of_range = Rating::Score(6.0);
+
+        // Act
+        let up = score(&up).unwrap();
+        let down = score(&down).unwrap();
+        let rating = score(&rating).unwrap();
+        let out_of_range = score(&out_of_range);
+
+        // Assert
+        assert_eq!(up, ("thumbs", 1.0));
+        assert_eq!(down, ("thumbs", 0.0));
+        assert_eq!(rating, ("rating", 4.0));
+        assert!(out_of_range.is_err());
+    }
+}
@@ -0,0 +1,240 @@
+use std::sync::Arc;
+
+use anyhow::Context;
+use cloudflare::models::text_generation::Message;
+use entity::prelude::*;
+use langfuse::trace::{Observe, Trace};
+use tracing::error;
+
+use crate::agent::with_result;
+use crate::ApiState;
+
+// Rough budget for the history sent along with every agent prompt. Turns
+// that don't fit are folded into a single summary turn.
+const MAX_HISTORY_CHARS: usize = 6000;
+const SUMMARY_QUESTION: &str = "What have we talked about so far?";
+
+#[derive(Debug, Default, PartialEq, Clone)]
+struct Turn {
+    context: String,
+    user: String,
+    assistant: String,
+}
+
+// Resolves the session the prompt belongs to and the history to send with
+// it. A session of another user is treated as a new one. Stored prompts
+// take precedence over the history sent by the client.
+pub async fn resolve(
+    state: &Arc<ApiState>,
+    user_id: i32,
+    session: Option<&str>,
+    history: &[Message],
+    trace: &Trace,
+) -> anyhow::Result<(Option<String>, Vec<Message>)> {
+    let session = match session {
+        Some(session) => state
+            .repo
+            .prompt_session
+            .find_by_id_and_user_id(session, user_id)
+            .await
+            .context("failed to find session")?,
+        None => None,
+    };
+
+    let turns = match &session {
+        Some(session) => state
+            .repo
+            .prompt
+            .find_by_session_id(&session.id)
+            .await
+            .context("failed to find prompts")?
+            .into_iter()
+            .map(|(prompt, _)| turn_from_prompt(prompt))
+            .collect(),
+        None => turns_from_messages(history),
+    };
+
+    let (old, mut recent) = split_to_fit(turns, MAX_HISTORY_CHARS);
+    if !old.is_empty() {
+        match summarize(state, &old, trace).await {
+            Ok(summary) => recent.insert(
+                0,
+                Turn {
+                    context: String::new(),
+                    user: SUMMARY_QUESTION.to_string(),
+                    assistant: summary,
+                },
+            ),
+            Err(e) => {
+                error!(task = "summarize history", error = e.to_string());
+            }
+        }
+    }
+
+    Ok((session.map(|session| session.id), to_messages(recent)))
+}
+
+fn turn_from_prompt(prompt: PromptEntity) -> Turn {
+    Turn {
+        context: prompt.tools_prompt,
+        user: prompt.user_prompt,
+        assistant: prompt.assistant_prompt,
+    }
+}
+
+// Agents pair the n-th user message with the n-th assistant and system
+// message, so only complete turns are kept from what the client sent.
+fn turns_from_messages(messages: &[Message]) -> Vec<Turn> {
+    let mut turns = vec![];
+    let mut turn = Turn::default();
+    let mut has_user = false;
+    for message in messages {
+        match message.role.as_str() {
+            "system" if !has_user => {
+                turn.context.clone_from(&message.content);
+            }
+            "user" => {
+                turn.user.clone_from(&message.content);
+                has_user = true;
+            }
+            "assistant" if has_user => {
+                turn.assistant.clone_from(&message.content);
+                turns.push(std::mem::take(&mut turn));
+                has_user = false;
+            }
+            _ => {}
+        }
+    }
+
+    turns
+}
+
+fn to_messages(turns: Vec<Turn>) -> Vec<Message> {
+    turns
+        .into_iter()
+        .flat_map(|turn| {
+            [
+                Message {
+                    role: "system".to_string(),
+                    content: turn.context,
+                },
+                Message {
+                    role: "user".to_string(),
+                    content: turn.user,
+                },
+                Message {
+                    role: "assistant".to_string(),
+                    content: turn.assistant,
+                },
+            ]
+        })
+        .collect()
+}
+
+// Keeps the latest turns within `budget` characters and returns the older
+// ones separately.
+fn split_to_fit(mut turns: Vec<Turn>, budget: usize) -> (Vec<Turn>, Vec<Turn>) {
+    let mut used = 0;
+    let mut split = turns.len();
+    for (i, turn) in turns.iter().enumerate().rev() {
+        used += turn.context.len() + turn.user.len() + turn.assistant.len();
+        if used > budget {
+            break;
+        }
+        split = i;
+    }
+
+    let recent = turns.split_off(split);
+    (turns, recent)
+}
+
+async fn summarize(
+    state: &Arc<ApiState>,
+    turns: &[Turn],
+    trace: &Trace,
+) -> anyhow::Result<String> {
+    let transcript = turns
+        .iter()
+        .map(|turn| {
+            format!("user: {}\nassistant: {}", turn.user, turn.assistant)
+        })
+        .collect::<Vec<_>>()
+        .join("\n");
+
+    let system_prompt = state.prompts.get("history-summarizer-system").await?;
+    let user_prompt = state.prompts.get("history-summarizer-user").await?;
+    let model = state.llm.task("history-summarizer")?;
+    let messages = system_prompt
+        .messages("system", &[])
+        .into_iter()
+        .chain(user_prompt.messages("user", &[("{{transcript}}", &transcript)]))
+        .collect::<Vec<_>>();
+
+    let generation = trace
+        .generation("history summarizer")
+        .model(model.name())
+        .input(serde_json::json!(messages));
+    let response = model.chat(messages, None).await;
+    generation.end_with(&response, with_result);
+    let response = response.context("failed to summarize history")?;
+
+    response
+        .response
+        .filter(|summary| !summary.is_empty())
+        .context("failed to get summary")
+}
+
+#[cfg(test)]
+mod test {
+    use cloudflare::models::text_generation::Message;
+
+    use super::{split_to_fit, turns_from_messages, Turn};
+
+    #[test]
+    fn test_turns_from_messages() {
+        // Arrange
+        let message = |role: &str, content: &str| Message {
+            role: role.to_string(),
+            content: content.to_string(),
+        };
+        let messages = vec![
+            message("system", "context"),
+            message("user", "hello"),
+            message("assistant", "hi"),
+            message("assistant", "dangling"),
+            message("tool", "unknown"),
+            message("user", "unanswered"),
+        ];
+
+        // Act
+        let turns = turns_from_messages(&messages);
+
+        // Assert
+        assert_eq!(
+            turns,
+            vec![Turn {
+                context: "context".to_string(),
+                user: "hello".to_string(),
+                assistant: "hi".to_string(),
+            }]
+        );
+    }
+
+    #[test]
+    fn test_split_to_fit() {
+        // Arrange
+        let turn = |user: &str| Turn {
+            context: String::new(),
+            user: user.to_string(),
+            assistant: "12345".to_string(),
+        };
+        let turns = vec![turn("first"), turn("second"), turn("third")];
+
+        // Act
+        let (old, recent) = split_to_fit(turns, 21);
+
+        // Assert
+        assert_eq!(old, vec![turn("first")]);
+        assert_eq!(recent, vec![turn("second"), turn("third")]);
+    }
+}
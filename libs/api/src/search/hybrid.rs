@@ -0,0 +1,306 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use anyhow::Context;
+use axum::{
+    extract::{Query, State},
+    Json,
+};
+use entity::prelude::*;
+use futures_util::join;
+use langfuse::trace::{Observe, Trace};
+use tracing::error;
+use vector_store::{CollectionModel, Document, Hit, VectorStore};
+
+use crate::render::page_title;
+use crate::response::{ApiResponse, IntoApiResponse};
+use crate::ApiState;
+
+use super::highlight;
+use super::request::SearchPagesParam;
+use super::response::{HybridPageResp, HybridSearchResp};
+
+// Damps the weight of top ranks so that neither list dominates the fusion.
+const RRF_K: f32 = 60.0;
+// Hits taken from each retriever before fusing them.
+const CANDIDATE_LIMIT: u64 = 30;
+const VECTOR_SCORE_THRESHOLD: f32 = 0.6;
+const MAX_CHUNKS: usize = 3;
+
+struct VectorHit {
+    page_id: String,
+    chunks: Vec<String>,
+}
+
+/// Keyword and vector search over published pages fused by rank
+#[utoipa::path(
+    get,
+    path = "/search/hybrid",
+    responses(
+        (status = 200, description = "Search pages successfully", body = HybridSearchResp)
+    ),
+    params(
+        SearchPagesParam
+    )
+)]
+pub async fn search_hybrid(
+    State(state): State<Arc<ApiState>>,
+    Query(params): Query<SearchPagesParam>,
+) -> ApiResponse<Json<HybridSearchResp>> {
+    let query = params.q.trim();
+    if query.is_empty() {
+        return Ok(Json(HybridSearchResp { pages: vec![] }));
+    }
+
+    let trace = Trace::new("hybrid search")
+        .input(query)
+        .tags(vec![state.env.clone()]);
+    let (keyword_hits, vector_hits) = join!(
+        state.repo.page.search(query, CANDIDATE_LIMIT),
+        vector_search(&state, query, &trace),
+    );
+    state.ingestion.send(trace.finish()).await;
+
+    // One retriever failing degrades the results instead of failing them.
+    let (keyword_hits, vector_hits) = match (keyword_hits, vector_hits) {
+        (Err(e), Err(_)) => return Err(e).into_response("502-026"),
+        (keyword_hits, vector_hits) => {
+            let keyword_hits = keyword_hits.unwrap_or_else(|e| {
+                error!(task = "keyword search", error = e.to_string());
+                vec![]
+            });
+            let vector_hits = vector_hits.unwrap_or_else(|e| {
+                error!(task = "vector search", error = e.to_string());
+                vec![]
+            });
+            (keyword_hits, vector_hits)
+        }
+    };
+
+    let keyword_ranking = keyword_hits
+        .iter()
+        .map(|hit| hit.notion_page_id.clone())
+        .collect::<Vec<_>>();
+    let vector_ranking = vector_hits
+        .iter()
+        .map(|hit| hit.page_id.clone())
+        .collect::<Vec<_>>();
+
+    let mut fused = fuse(&[keyword_ranking.clone(), vector_ranking.clone()]);
+
+    let ids = fused.iter().map(|(id, _)| id.clone()).collect::<Vec<_>>();
+    let pages = state
+        .repo
+        .page
+        .find_published_by_ids(&ids)
+        .await
+        .into_response("502-026")?
+        .into_iter()
+        .map(|page| (page.notion_page_id.clone(), page))
+        .collect::<HashMap<_, _>>();
+
+    // The vector index may still hold pages deleted or turned into drafts,
+    // which are dropped before the limit so that they don't shorten it
+    fused.retain(|(id, _)| pages.contains_key(id));
+    fused.truncate(params.limit() as usize);
+
+    let mut keyword_hits = keyword_hits
+        .into_iter()
+        .map(|hit| (hit.notion_page_id.clone(), hit))
+        .collect::<HashMap<_, _>>();
+    let mut vector_hits = vector_hits
+        .into_iter()
+        .map(|hit| (hit.page_id.clone(), hit))
+        .collect::<HashMap<_, _>>();
+
+    let mut results = vec![];
+    for (id, score) in fused {
+        let Some(page) = pages.get(&id) else {
+            continue;
+        };
+
+        let keyword_hit = keyword_hits.remove(&id);
+        let title = match &keyword_hit {
+            Some(hit) => hit.title.clone(),
+            None => page_title(page),
+        };
+
+        results.push(HybridPageResp {
+            keyword_rank: rank(&keyword_ranking, &id),
+            vector_rank: rank(&vector_ranking, &id),
+            snippet: keyword_hit.map(|hit| highlight(&hit.snippet)),
+            chunks: vector_hits
+                .remove(&id)
+                .map(|hit| hit.chunks)
+                .unwrap_or_default(),
+            title,
+            score,
+            id,
+        });
+    }
+
+    Ok(Json(HybridSearchResp { pages: results }))
+}
+
+async fn vector_search(
+    state: &ApiState,
+    query: &str,
+    trace: &Trace,
+) -> anyhow::Result<Vec<VectorHit>> {
+    let (vector, model) =
+        embed_query(state, query, trace, "embed query").await?;
+
+    let hits = state
+        .store
+        .search(
+            &vector_store::Query::new(vector, model, CANDIDATE_LIMIT as usize)
+                .score_threshold(VECTOR_SCORE_THRESHOLD),
+        )
+        .await?;
+
+    Ok(group_by_page(hits))
+}
+
+/// Embeds `query` with the model of the stored vectors, and returns the
+/// vector with the model to search it by.
+pub(super) async fn embed_query(
+    state: &ApiState,
+    query: &str,
+    observer: &impl Observe,
+    name: &str,
+) -> anyhow::Result<(Vec<f32>, CollectionModel)> {
+    let served = state
+        .store
+        .model()
+        .await
+        .context("failed to find the embedding model of the vectors")?;
+    let model = state.llm.task("embedding")?.with_name(&served.model);
+
+    let generation = observer.generation(name).model(model.name()).input(query);
+    let embedding = model.embed(vec![query.to_string()]).await;
+    generation.end_with(&embedding, |generation, vectors| {
+        generation.output(serde_json::json!({
+            "shape": [vectors.len(), vectors.first().map_or(0, Vec::len)],
+        }))
+    });
+    let vector = embedding
+        .context("failed to embed query")?
+        .into_iter()
+        .next()
+        .context("failed to get vectors")?;
+
+    Ok((vector, served))
+}
+
+// Points come best first, so a page ranks by its best point. Block points
+// are the matched chunks; page points only hold the title and summary.
+fn group_by_page(points: Vec<Hit>) -> Vec<VectorHit> {
+    let mut hits: Vec<VectorHit> = vec![];
+    for point in points {
+        let page_id = &point.document.page_id;
+        let index = match hits.iter().position(|hit| hit.page_id == *page_id) {
+            Some(index) => index,
+            None => {
+                hits.push(VectorHit {
+                    page_id: page_id.clone(),
+                    chunks: vec![],
+                });
+                hits.len() - 1
+            }
+        };
+
+        if point.document.document_type != DocumentTypeEntity::Block {
+            continue;
+        }
+
+        let chunks = &mut hits[index].chunks;
+        if chunks.len() < MAX_CHUNKS {
+            chunks.push(cited_document(&point.document));
+        }
+    }
+
+    hits
+}
+
+// The document of a chunk led by the headings of its section, so that an
+// answer can cite where it came from.
+pub(super) fn cited_document(document: &Document) -> String {
+    let text = document.text.trim();
+    match &document.chunk {
+        Some(chunk) if !chunk.heading_path.is_empty() => {
+            format!("[{}] {}", chunk.heading_path.join(" > "), text)
+        }
+        _ => text.to_string(),
+    }
+}
+
+// Reciprocal rank fusion of page ids ordered best first. Each list adds
+// 1 / (k + rank) to the pages it contains.
+fn fuse<S: AsRef<str>>(rankings: &[Vec<S>]) -> Vec<(String, f32)> {
+    let mut scores: HashMap<&str, f32> = HashMap::new();
+    for ranking in rankings {
+        for (index, id) in ranking.iter().map(AsRef::as_ref).enumerate() {
+            *scores.entry(id).or_default() +=
+                1.0 / (RRF_K + index as f32 + 1.0);
+        }
+    }
+
+    let mut fused = scores
+        .into_iter()
+        .map(|(id, score)| (id.to_string(), score))
+        .collect::<Vec<_>>();
+    fused.sort_by(|a, b| b.1.total_cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+    fused
+}
+
+fn rank(ranking: &[String], id: &str) -> Option<usize> {
+    ranking.iter().position(|r| *r == id).map(|index| index + 1)
+}
+
+#[cfg(test)]
+mod test {
+    use entity::prelude::DocumentTypeEntity;
+    use vector_store::{ChunkMetadata, Document};
+
+    use super::{cited_document, fuse};
+
+    #[test]
+    fn test_fuse() {
+        // Arrange
+        let keyword = vec!["a", "b", "c"];
+        let vector = vec!["c", "d", "a"];
+
+        // Act
+        let fused = fuse(&[keyword, vector]);
+
+        // Assert
+        let ids = fused.iter().map(|(id, _)| id.as_str()).collect::<Vec<_>>();
+        assert_eq!(ids, vec!["a", "c", "b", "d"]);
+        assert!((fused[0].1 - (1.0 / 61.0 + 1.0 / 63.0)).abs() < f32::EPSILON);
+    }
+
+    #[test]
+    fn test_cited_document() {
+        // Arrange
+        let document = |heading_path: Vec<&str>| Document {
+            page_id: "page".to_string(),
+            document_type: DocumentTypeEntity::Block,
+            text: " Run it. ".to_string(),
+            chunk: Some(ChunkMetadata {
+                heading_path: heading_path
+                    .into_iter()
+                    .map(str::to_string)
+                    .collect(),
+                ..Default::default()
+            }),
+        };
+
+        // Act
+        let cited = cited_document(&document(vec!["Setup", "Linux"]));
+        let uncited = cited_document(&document(vec![]));
+
+        // Assert
+        assert_eq!(cited, "[Setup > Linux] Run it.");
+        assert_eq!(uncited, "Run it.");
+    }
+}
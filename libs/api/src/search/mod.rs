@@ -1,57 +1,101 @@
-use crate::agent::{
-    get_template, question_and_answer::QuestionAnswerAgent, Agent,
-};
+use crate::agent::{question_and_answer::QuestionAnswerAgent, Agent};
+use anyhow::Context;
 use async_stream::stream;
 use axum::{
-    extract::State,
+    extract::{Query, State},
     response::{sse::Event, Sse},
     Extension, Json,
 };
-use cloudflare::models::{
-    text_embeddings::{StringOrArray, TextEmbeddings, TextEmbeddingsRequest},
-    text_generation::{
-        Function, ModelParameters, Parameters, PropertyType,
-        TextGenerationJsonResult, Tool, ToolCall, LLAMA_3_8B_INSTRUCT,
-    },
+use cloudflare::models::text_generation::{
+    Function, ModelParameters, Parameters, PropertyType, StreamEvent,
+    TextGenerationJsonResult, Tool, ToolCall,
 };
 use entity::prelude::*;
+use entity::search::{HIGHLIGHT_END, HIGHLIGHT_START};
 use futures_util::{join, Stream};
-use langfuse::{
-    apis::ingestion_api::ingestion_batch,
-    models::{
-        ingestion_event_one_of, ingestion_event_one_of_2,
-        ingestion_event_one_of_4, CreateGenerationBody, CreateSpanBody,
-        IngestionBatchRequest, IngestionEvent, IngestionEventOneOf,
-        IngestionEventOneOf2, IngestionEventOneOf4, TraceBody,
-    },
-};
+use langfuse::trace::{Observe, Span, Trace};
 use notion_client::objects::{
     block::Block,
     page::{Page, PageProperty},
 };
-use qdrant_client::qdrant::{
-    condition::ConditionOneOf, r#match::MatchValue,
-    with_payload_selector::SelectorOptions, Condition, FieldCondition, Filter,
-    Match, PayloadIncludeSelector, ScoredPoint, SearchPoints,
-    WithPayloadSelector,
-};
-use rpc_router::CallResponse;
-use uuid::Uuid;
+use rpc::RpcObserver;
+use rpc_router::{CallResponse, Resources};
 
 use serde_json::json;
 use std::{collections::HashMap, convert::Infallible, sync::Arc};
-use tokio::{
-    select,
-    sync::{mpsc, oneshot},
-};
+use tokio::{select, sync::mpsc};
 use tokio_stream::StreamExt as _;
 use tracing::error;
+use vector_store::{Hit, VectorStore};
 
+use crate::response::{ApiResponse, IntoApiResponse};
+use crate::xml::escape;
 use crate::{agent::function_call::FunctionCallAgent, auth::Claims, ApiState};
 
-use self::request::SearchParam;
+use self::hybrid::{cited_document, embed_query};
+use self::request::{SearchPagesParam, SearchParam};
+use self::response::{SearchPageResp, SearchPagesResp};
 
+pub mod eval;
+pub mod feedback;
+mod history;
+pub mod hybrid;
 pub mod request;
+pub mod response;
+
+/// Keyword search over published pages
+#[utoipa::path(
+    get,
+    path = "/search",
+    responses(
+        (status = 200, description = "Search pages successfully", body = SearchPagesResp)
+    ),
+    params(
+        SearchPagesParam
+    )
+)]
+pub async fn search_pages(
+    State(state): State<Arc<ApiState>>,
+    Query(params): Query<SearchPagesParam>,
+) -> ApiResponse<Json<SearchPagesResp>> {
+    let query = params.q.trim();
+    if query.is_empty() {
+        return Ok(Json(SearchPagesResp { pages: vec![] }));
+    }
+
+    let hits = state
+        .repo
+        .page
+        .search(query, params.limit())
+        .await
+        .into_response("502-025")?;
+
+    Ok(Json(SearchPagesResp {
+        pages: hits
+            .into_iter()
+            .map(|hit| SearchPageResp {
+                id: hit.notion_page_id,
+                title: hit.title,
+                snippet: highlight(&hit.snippet),
+                rank: hit.rank,
+                created_at: hit.created_at.to_rfc3339(),
+            })
+            .collect(),
+    }))
+}
+
+pub(crate) fn highlight(snippet: &str) -> String {
+    snippet
+        .split(HIGHLIGHT_START)
+        .map(|part| {
+            part.split(HIGHLIGHT_END)
+                .map(escape)
+                .collect::<Vec<_>>()
+                .join("</mark>")
+        })
+        .collect::<Vec<_>>()
+        .join("<mark>")
+}
 
 pub async fn search_text_with_sse(
     Extension(claims): Extension<Claims>,
@@ -60,53 +104,52 @@ pub async fn search_text_with_sse(
 ) -> Sse<impl Stream<Item = Result<Event, Infallible>>> {
     let stream = stream! {
 
-        let result = generate_keyword(&params,&state).await;
-        let Ok((keyword_response,keyword_log)) = result else {
+        let trace = Trace::new("search")
+            .user_id(claims.user_id.unwrap())
+            .input(params.prompt.clone())
+            .tags(vec![state.env.clone()])
+            .public(true);
+
+        let mut params = params;
+        let result = history::resolve(
+            &state,
+            claims.user_id.unwrap(),
+            params.session.as_deref(),
+            &params.history,
+            &trace,
+        )
+        .await;
+        let Ok((session, history)) = result else {
             error!(
-                task = "keyword",
+                task = "resolve history",
                 error = result.unwrap_err().to_string(),
             );
             return;
         };
+        params.session = session;
+        params.history = history;
 
-        let keywords = keyword_response.response.clone().unwrap_or_default();
-
-        let vector_search = vector_search(&state,&keywords.clone()).await;
-        let Ok((vector_search_result,vector_page_ids,vector_log)) = vector_search else {
-            error!(
-                task = "vector search",
-                error = vector_search.unwrap_err().to_string(),
-            );
-            return;
-        };
-
-        let result = function_call(&params,&state,&keywords.clone()).await;
-        let Ok((function_call_response,observations,function_page_ids,function_call_log,observation_log)) = result else {
+        let retrieval = retrieve(&params, &state, &trace).await;
+        let Ok(retrieval) = retrieval else {
             error!(
-                task = "function call",
-                error = result.unwrap_err().to_string(),
+                task = "retrieve",
+                error = retrieval.unwrap_err().to_string(),
             );
             return;
         };
 
-
-        let all_page_ids = vector_page_ids.into_iter().chain(function_page_ids.into_iter()).collect::<Vec<_>>();
-
+        let all_page_ids = retrieval.page_ids.clone();
         let (message_tx, mut message_rx) = mpsc::channel(100);
         let (page_tx, mut page_rx) = mpsc::channel(1);
-        let (qa_log_tx, qa_log_rx) = oneshot::channel();
-        let context = format!(
-            "{}\n{}",
-            vector_search_result.join("\n"),
-            observations.join("\n")
-        );
+        let context = retrieval.context.clone();
 
         let _params = params.clone();
         let _context = context.clone();
         let _state = state.clone();
+        let _trace = trace.clone();
         tokio::spawn(async move {
            let (_,_) = join!(
-                make_answer(&_params,&_state,&_context,message_tx,qa_log_tx),
+                make_answer(&_params,&_state,&_context,message_tx,_trace),
                 get_pages_by_ids(&_state,&all_page_ids,page_tx),
             );
         });
@@ -144,13 +187,16 @@ pub async fn search_text_with_sse(
                 }
                 else => {
 
+                    let trace_id = trace.id();
+
                     let session = save_prompt(
                         &state,
                         &params,
                         claims.user_id.unwrap(),
                         &all_messages,
-                        &function_call_response.tool_calls,
+                        &retrieval.tool_calls,
                         page_ids,
+                        &trace_id,
                     ).await;
 
 
@@ -174,8 +220,6 @@ pub async fn search_text_with_sse(
 
                     yield event;
 
-                    let trace_id = Uuid::new_v4().to_string();
-
                     let event =  Event::default().json_data(json!({"debug": {"context":&context, "traceId":trace_id.clone()}}));
                     let Ok(event) = event else {
                        error!(
@@ -186,24 +230,12 @@ pub async fn search_text_with_sse(
                     };
                     yield event;
 
-
-                    let qa_log = qa_log_rx.await;
-                    let Ok(qa_log) = qa_log else {
-                        error!(
-                            task = "qa log",
-                            error = qa_log.unwrap_err().to_string(),
-                        );
-                        break;
-                    };
-                    let log = log_langfuse(&claims,&params,&state,&session,trace_id,keyword_log,vector_log,function_call_log,observation_log,qa_log).await;
-
-                    let Ok(_) = log else {
-                        error!(
-                            task = "ingestion",
-                            error = log.unwrap_err().to_string(),
-                        );
-                        break;
-                    };
+                    // The answer generation has ended once the message
+                    // channel is closed.
+                    let trace = trace
+                        .session_id(&session)
+                        .output(all_messages.clone());
+                    state.ingestion.send(trace.finish()).await;
 
                     break;
                 }
@@ -214,6 +246,50 @@ pub async fn search_text_with_sse(
     Sse::new(stream.map(Ok))
 }
 
+#[derive(Debug)]
+struct Retrieval {
+    // Vector search results and observations of the function calls
+    context: String,
+    page_ids: Vec<String>,
+    tool_calls: Option<Vec<ToolCall>>,
+}
+
+// Gathers the context of the answer: keywords, vector search and function
+// calls. Each step is recorded on `trace`.
+async fn retrieve(
+    params: &SearchParam,
+    state: &Arc<ApiState>,
+    trace: &Trace,
+) -> anyhow::Result<Retrieval> {
+    let keyword_response = generate_keyword(params, state, trace)
+        .await
+        .context("failed to generate keyword")?;
+    let keywords = keyword_response.response.unwrap_or_default();
+
+    let (vector_search_result, vector_page_ids) =
+        vector_search(state, &keywords, trace)
+            .await
+            .context("failed to search vectors")?;
+
+    let (function_call_response, observations, function_page_ids) =
+        function_call(params, state, &keywords, trace)
+            .await
+            .context("failed to call functions")?;
+
+    Ok(Retrieval {
+        context: format!(
+            "{}\n{}",
+            vector_search_result.join("\n"),
+            observations.join("\n")
+        ),
+        page_ids: vector_page_ids
+            .into_iter()
+            .chain(function_page_ids)
+            .collect(),
+        tool_calls: function_call_response.tool_calls,
+    })
+}
+
 async fn save_prompt(
     state: &Arc<ApiState>,
     params: &SearchParam,
@@ -221,6 +297,7 @@ async fn save_prompt(
     answer: &str,
     tool_calls: &Option<Vec<ToolCall>>,
     page_ids: Vec<String>,
+    trace_id: &str,
 ) -> anyhow::Result<String> {
     let mut tools_prompt = None;
     if let Some(tool_calls) = tool_calls {
@@ -253,6 +330,7 @@ async fn save_prompt(
                 user_prompt: params.prompt.to_string(),
                 assistant_prompt: answer.to_string(),
                 tools_prompt: tools_prompt.unwrap_or_default().to_string(),
+                trace_id: Some(trace_id.to_string()),
                 ..Default::default()
             },
             page_ids,
@@ -265,100 +343,42 @@ async fn save_prompt(
 async fn retriever(
     state: &Arc<ApiState>,
     prompt: &str,
-) -> anyhow::Result<(Vec<ScoredPoint>, Vec<ScoredPoint>)> {
-    let embedding = state
-        .cloudflare
-        .bge_small_en_v1_5(TextEmbeddingsRequest {
-            text: StringOrArray::from(prompt),
-        })
-        .await?;
-
-    let Some(vector) = embedding.result.data.first() else {
-        return Err(anyhow::anyhow!("No vectors found"));
-    };
-
-    let page_param = SearchPoints {
-        collection_name: state.config.qdrant.collection.clone(),
-        vector: vector.clone(),
-        limit: 5,
-        with_payload: Some(WithPayloadSelector {
-            selector_options: Some(SelectorOptions::Include(
-                PayloadIncludeSelector {
-                    fields: vec!["document".to_string(), "page_id".to_string()],
-                },
-            )),
-        }),
-        filter: Some(Filter {
-            must: vec![Condition {
-                condition_one_of: Some(ConditionOneOf::Field(FieldCondition {
-                    key: "type".to_string(),
-                    r#match: Some(Match {
-                        match_value: Some(MatchValue::Keyword(
-                            serde_json::to_string(&DocumentTypeEntity::Page)
-                                .unwrap(),
-                        )),
-                    }),
-                    ..Default::default()
-                })),
-            }],
-            ..Default::default()
-        }),
-        score_threshold: Some(0.6),
-        ..Default::default()
-    };
-
-    let block_param = SearchPoints {
-        collection_name: state.config.qdrant.collection.clone(),
-        vector: vector.clone(),
-        limit: 5,
-        with_payload: Some(WithPayloadSelector {
-            selector_options: Some(SelectorOptions::Include(
-                PayloadIncludeSelector {
-                    fields: vec!["document".to_string(), "page_id".to_string()],
-                },
-            )),
-        }),
-        filter: Some(Filter {
-            must: vec![Condition {
-                condition_one_of: Some(ConditionOneOf::Field(FieldCondition {
-                    key: "type".to_string(),
-                    r#match: Some(Match {
-                        match_value: Some(MatchValue::Keyword(
-                            serde_json::to_string(&DocumentTypeEntity::Block)
-                                .unwrap(),
-                        )),
-                    }),
-                    ..Default::default()
-                })),
-            }],
-            ..Default::default()
-        }),
-        score_threshold: Some(0.7),
-        ..Default::default()
-    };
+    span: &Span,
+) -> anyhow::Result<(Vec<Hit>, Vec<Hit>)> {
+    let (vector, model) =
+        embed_query(state, prompt, span, "embed keyword").await?;
+
+    let page_query = vector_store::Query::new(vector, model, 5);
+    let block_query = page_query
+        .clone()
+        .document_type(DocumentTypeEntity::Block)
+        .score_threshold(0.7);
+    let page_query = page_query
+        .document_type(DocumentTypeEntity::Page)
+        .score_threshold(0.6);
 
     let (page_search_result, block_search_result) = join!(
-        state.qdrant.search_points(&page_param),
-        state.qdrant.search_points(&block_param),
+        state.store.search(&page_query),
+        state.store.search(&block_query),
     );
 
-    let page_search_result = page_search_result?;
-    let block_search_result = block_search_result?;
-
-    Ok((page_search_result.result, block_search_result.result))
+    Ok((page_search_result?, block_search_result?))
 }
 
 async fn generate_keyword(
     params: &SearchParam,
     state: &Arc<ApiState>,
-) -> anyhow::Result<(TextGenerationJsonResult, CreateGenerationBody)> {
-    let system_prompt =
-        get_template(&state.langfuse, "keyword-generator-system").await?;
+    trace: &Trace,
+) -> anyhow::Result<TextGenerationJsonResult> {
+    let system = state
+        .prompts
+        .get("keyword-generator-system")
+        .await?
+        .messages("system", &[]);
 
     let keyword_generator = QuestionAnswerAgent::new(
-        state.cloudflare.clone(),
-        "keyword generator".to_string(),
-        system_prompt,
+        state.llm.task("keyword-generator")?,
+        system,
         params.history.clone(),
         Some(ModelParameters {
             max_tokens: Some(20),
@@ -367,39 +387,34 @@ async fn generate_keyword(
     );
 
     let user_prompt_template =
-        get_template(&state.langfuse, "keyword-generator-user").await?;
-
-    let (keyword_result, log) = keyword_generator
-        .prompt(&user_prompt_template, &params.prompt, None)
+        state.prompts.get("keyword-generator-user").await?;
+
+    let keyword_result = keyword_generator
+        .prompt(
+            trace.generation("keyword generator"),
+            &user_prompt_template,
+            &params.prompt,
+            None,
+        )
         .await?;
 
-    Ok((keyword_result[0].clone(), log))
+    Ok(keyword_result[0].clone())
 }
 
 async fn vector_search(
     state: &Arc<ApiState>,
     keywords: &str,
-) -> anyhow::Result<(Vec<String>, Vec<String>, CreateSpanBody)> {
+    trace: &Trace,
+) -> anyhow::Result<(Vec<String>, Vec<String>)> {
     let mut vector_result = vec![];
     let mut all_page_ids = vec![];
-    let keywords = keywords.split(',');
-
-    let mut span = CreateSpanBody {
-        id: Some(Some(Uuid::new_v4().to_string())),
-        name: Some(Some("search vectors".to_string())),
-        start_time: Some(Some(chrono::Utc::now().to_rfc3339())),
-        end_time: Some(Some(chrono::Utc::now().to_rfc3339())),
-        input: Some(Some(serde_json::Value::String(
-            keywords.clone().collect::<Vec<_>>().join(","),
-        ))),
-        ..Default::default()
-    };
+    let span = trace.span("search vectors").input(keywords);
 
-    for keyword in keywords {
+    for keyword in keywords.split(',') {
         if keyword.is_empty() {
             continue;
         }
-        let result = retriever(state, keyword).await;
+        let result = retriever(state, keyword, &span).await;
         let Ok((page_points, block_points)) = result else {
             error!(
                 task = "get context by retriever",
@@ -418,14 +433,13 @@ async fn vector_search(
 
         // title and summary
         let mut documents = vec![];
-        for point in page_points.iter() {
-            let page_id = point.payload.get("page_id").unwrap().to_string();
-            let document = point.payload.get("document").unwrap().to_string();
-            if all_page_ids.contains(&page_id) {
+        for point in page_points {
+            let document = point.document;
+            if all_page_ids.contains(&document.page_id) {
                 continue;
             }
-            all_page_ids.push(page_id);
-            documents.push(document);
+            all_page_ids.push(document.page_id);
+            documents.push(document.text);
         }
 
         if !documents.is_empty() {
@@ -440,16 +454,15 @@ async fn vector_search(
             ));
         }
 
-        // chunk
+        // chunk, cited by its section
         let mut documents = vec![];
-        for point in block_points.iter() {
-            let page_id = point.payload.get("page_id").unwrap().to_string();
-            let document = point.payload.get("document").unwrap().to_string();
-            if all_page_ids.contains(&page_id) {
+        for point in block_points {
+            let document = point.document;
+            if all_page_ids.contains(&document.page_id) {
                 continue;
             }
-            all_page_ids.push(page_id);
-            documents.push(document);
+            documents.push(cited_document(&document));
+            all_page_ids.push(document.page_id);
         }
 
         if !documents.is_empty() {
@@ -465,41 +478,22 @@ async fn vector_search(
         }
     }
 
-    span.end_time = Some(Some(chrono::Utc::now().to_rfc3339()));
-    span.output = Some(Some(serde_json::Value::Array(
-        vector_result
-            .iter()
-            .map(|t| {
-                serde_json::json!({
-                    "role": "observation",
-                    "content": t,
-                })
-            })
-            .collect(),
-    )));
-    span.metadata = Some(Some(serde_json::json!({
-        "page_ids": all_page_ids,
-    })));
+    span.output(observations_json(&vector_result))
+        .metadata(json!({ "page_ids": all_page_ids }))
+        .end();
 
-    Ok((vector_result, all_page_ids, span))
+    Ok((vector_result, all_page_ids))
 }
 
-#[allow(clippy::too_many_arguments)]
 async fn function_call(
     params: &SearchParam,
     state: &Arc<ApiState>,
     keywords: &str,
-) -> anyhow::Result<(
-    TextGenerationJsonResult,
-    Vec<String>,
-    Vec<String>,
-    CreateGenerationBody,
-    CreateSpanBody,
-)> {
+    trace: &Trace,
+) -> anyhow::Result<(TextGenerationJsonResult, Vec<String>, Vec<String>)> {
     let function_call_agent = FunctionCallAgent::new(
-        state.cloudflare.clone(),
-        &state.langfuse,
-        "function call".to_string(),
+        state.llm.task("function-call")?,
+        &state.prompts,
         vec![
         Tool {
             r#type: "function".to_string(),
@@ -586,46 +580,43 @@ async fn function_call(
 
     let context = format!("## Possible search keywords\n{}", keywords);
 
-    let user_prompt_template =
-        get_template(&state.langfuse, "function-calls-user").await?;
+    let user_prompt_template = state.prompts.get("function-calls-user").await?;
 
-    let (tool_calls_response, log) = function_call_agent
-        .prompt(&user_prompt_template, &params.prompt, Some(&context))
+    let tool_calls_response = function_call_agent
+        .prompt(
+            trace.generation("function call"),
+            &user_prompt_template,
+            &params.prompt,
+            Some(&context),
+        )
         .await?;
 
-    let mut span = CreateSpanBody {
-        id: Some(Some(Uuid::new_v4().to_string())),
-        name: Some(Some("get observations".to_string())),
-        start_time: Some(Some(chrono::Utc::now().to_rfc3339())),
-        end_time: Some(Some(chrono::Utc::now().to_rfc3339())),
-        ..Default::default()
-    };
-
     let Some(tool_calls) = &tool_calls_response.tool_calls else {
-        return Ok((tool_calls_response, vec![], vec![], log, span));
+        return Ok((tool_calls_response, vec![], vec![]));
     };
 
     let tool_calls = tool_calls.clone().into_iter().take(3);
 
-    span.input = Some(Some(serde_json::Value::Array(
+    let span = trace.span("get observations").input(
         tool_calls
             .clone()
-            .map(|t| {
-                serde_json::json!({
-                    "role": "tool_call",
-                    "content": t,
-                })
-            })
-            .collect(),
-    )));
+            .map(|t| json!({ "role": "tool_call", "content": t }))
+            .collect::<Vec<_>>(),
+    );
 
     let mut observations = vec![];
     let mut page_ids = vec![];
     for tool_call in tool_calls.clone() {
         let params = json!(&tool_call.arguments);
+        let observer = RpcObserver(Arc::new(span.span(&tool_call.name)));
         let response = state
             .rpc
-            .call_route(None, tool_call.clone().name, Some(params.clone()))
+            .call_route_with_resources(
+                None,
+                tool_call.clone().name,
+                Some(params.clone()),
+                Resources::builder().append(observer).build(),
+            )
             .await;
         let Ok(CallResponse {
             id: _,
@@ -828,20 +819,16 @@ async fn function_call(
         }
     }
 
-    span.end_time = Some(Some(chrono::Utc::now().to_rfc3339()));
-    span.output = Some(Some(serde_json::Value::Array(
-        observations
-            .iter()
-            .map(|t| {
-                serde_json::json!({
-                    "role": "observation",
-                    "content": t,
-                })
-            })
-            .collect(),
-    )));
+    span.output(observations_json(&observations)).end();
+
+    Ok((tool_calls_response, observations, page_ids))
+}
 
-    Ok((tool_calls_response, observations, page_ids, log, span))
+fn observations_json(observations: &[String]) -> serde_json::Value {
+    observations
+        .iter()
+        .map(|t| json!({ "role": "observation", "content": t }))
+        .collect()
 }
 
 async fn make_answer(
@@ -849,47 +836,34 @@ async fn make_answer(
     state: &Arc<ApiState>,
     context: &str,
     message_tx: mpsc::Sender<String>,
-    log_tx: oneshot::Sender<CreateGenerationBody>,
+    trace: Trace,
 ) -> anyhow::Result<()> {
-    let system_prompt =
-        get_template(&state.langfuse, "answer-generator-system").await?;
-    let question_answer_agent = QuestionAnswerAgent::new(
-        state.cloudflare.clone(),
-        "answer generator".to_string(),
-        system_prompt,
-        params.history.clone(),
-        None,
-    );
+    let system = state
+        .prompts
+        .get("answer-generator-system")
+        .await?
+        .messages("system", &[]);
+    let model = state.llm.task("answer-generator")?;
+    let model_name = model.name().to_string();
+    let question_answer_agent =
+        QuestionAnswerAgent::new(model, system, params.history.clone(), None);
 
     let prompt = &params.prompt;
 
     let user_prompt_template =
-        get_template(&state.langfuse, "answer-generator-user").await?;
+        state.prompts.get("answer-generator-user").await?;
 
     let (qa_message, mut question_answer) = question_answer_agent
         .prompt_with_stream(&user_prompt_template, prompt, Some(context))
         .await;
 
-    let mut log = CreateGenerationBody {
-        id: Some(Some(Uuid::new_v4().to_string())),
-        name: Some(Some("answer generator".to_string())),
-        model: Some(Some(LLAMA_3_8B_INSTRUCT.to_string())),
-        start_time: Some(Some(chrono::Utc::now().to_rfc3339())),
-        input: Some(Some(serde_json::Value::Array(
-            qa_message
-                .iter()
-                .map(|t| {
-                    serde_json::json!({
-                        "role": t.role,
-                        "content": t.content,
-                    })
-                })
-                .collect(),
-        ))),
-        ..Default::default()
-    };
+    let mut generation = trace
+        .generation("answer generator")
+        .model(&model_name)
+        .input(json!(qa_message));
 
     let mut output = String::new();
+    let mut usage = None;
 
     loop {
         let data = question_answer.next().await.transpose();
@@ -900,28 +874,30 @@ async fn make_answer(
             );
             break;
         };
-        if let Some(data) = data {
-            for d in data {
-                if let Some(response) = d.response {
-                    output.push_str(&response);
-                    let result = message_tx.send(response).await;
-                    if let Err(err) = result {
-                        error!(
-                            task = "send message event",
-                            error = err.to_string()
-                        );
-                    }
+        match data {
+            Some(StreamEvent::Delta(response)) => {
+                generation.completion_started();
+                output.push_str(&response);
+                let result = message_tx.send(response).await;
+                if let Err(err) = result {
+                    error!(
+                        task = "send message event",
+                        error = err.to_string()
+                    );
                 }
             }
-        } else {
+            Some(StreamEvent::Usage(u)) => usage = Some(u),
             // Finish answer
-            break;
+            None => break,
         }
     }
 
-    log.output = Some(Some(serde_json::Value::String(output)));
-    log.end_time = Some(Some(chrono::Utc::now().to_rfc3339()));
-    let _ = log_tx.send(log);
+    generation = generation.output(output);
+    if let Some(usage) = usage {
+        generation =
+            generation.usage(usage.prompt_tokens, usage.completion_tokens);
+    }
+    generation.end();
 
     Ok(())
 }
@@ -933,7 +909,7 @@ async fn get_pages_by_ids(
 ) {
     let mut pages = vec![];
     for id in page_ids {
-        let result = state.repo.page.find_by_id(id).await;
+        let result = state.repo.page.find_published_by_id(id).await;
         let Ok(page) = result else {
             error!(
                 task = "get page by notion client",
@@ -965,123 +941,163 @@ async fn get_pages_by_ids(
     }
 }
 
-#[allow(clippy::too_many_arguments)]
-async fn log_langfuse(
-    claims: &Claims,
-    params: &SearchParam,
-    state: &Arc<ApiState>,
-    session_id: &str,
-    trace_id: String,
-    mut keyword_log: CreateGenerationBody,
-    mut vector_log: CreateSpanBody,
-    mut tool_calls_log: CreateGenerationBody,
-    mut observation_log: CreateSpanBody,
-    mut qa_log: CreateGenerationBody,
-) -> anyhow::Result<()> {
-    let env = state.env.clone();
-    keyword_log.trace_id = Some(Some(trace_id.clone()));
-    vector_log.trace_id = Some(Some(trace_id.clone()));
-    tool_calls_log.trace_id = Some(Some(trace_id.clone()));
-    observation_log.trace_id = Some(Some(trace_id.clone()));
-    qa_log.trace_id = Some(Some(trace_id.clone()));
-    let response = ingestion_batch(
-        &state.langfuse,
-        IngestionBatchRequest {
-            batch: vec![
-                // create trace
-                IngestionEvent::IngestionEventOneOf(Box::new(
-                    IngestionEventOneOf::new(
-                        TraceBody {
-                            id: Some(Some(trace_id.clone())),
-                            timestamp: Some(Some(
-                                chrono::Utc::now().to_rfc3339(),
-                            )),
-                            user_id: Some(Some(
-                                claims.user_id.unwrap().to_string(),
-                            )),
-                            input: Some(Some(serde_json::Value::String(
-                                params.prompt.clone(),
-                            ))),
-                            output: Some(Some(serde_json::Value::String(
-                                qa_log
-                                    .clone()
-                                    .output
-                                    .unwrap()
-                                    .unwrap()
-                                    .to_string(),
-                            ))),
-                            session_id: Some(Some(session_id.to_string())),
-                            tags: Some(Some(vec![env])),
-                            public: Some(Some(true)),
-                            ..Default::default()
-                        },
-                        trace_id.clone(),
-                        chrono::Utc::now().to_rfc3339(),
-                        ingestion_event_one_of::Type::TraceCreate,
-                    ),
-                )),
-                // keywords result
-                IngestionEvent::IngestionEventOneOf4(Box::new(
-                    IngestionEventOneOf4::new(
-                        keyword_log,
-                        Uuid::new_v4().to_string(),
-                        chrono::Utc::now().to_rfc3339(),
-                        ingestion_event_one_of_4::Type::GenerationCreate,
-                    ),
-                )),
-                // keywords result
-                IngestionEvent::IngestionEventOneOf2(Box::new(
-                    IngestionEventOneOf2::new(
-                        vector_log,
-                        Uuid::new_v4().to_string(),
-                        chrono::Utc::now().to_rfc3339(),
-                        ingestion_event_one_of_2::Type::SpanCreate,
-                    ),
-                )),
-                // function calls result
-                IngestionEvent::IngestionEventOneOf4(Box::new(
-                    IngestionEventOneOf4::new(
-                        tool_calls_log,
-                        Uuid::new_v4().to_string(),
-                        chrono::Utc::now().to_rfc3339(),
-                        ingestion_event_one_of_4::Type::GenerationCreate,
-                    ),
-                )),
-                // observation result
-                IngestionEvent::IngestionEventOneOf2(Box::new(
-                    IngestionEventOneOf2::new(
-                        observation_log,
-                        Uuid::new_v4().to_string(),
-                        chrono::Utc::now().to_rfc3339(),
-                        ingestion_event_one_of_2::Type::SpanCreate,
-                    ),
-                )),
-                // answer result
-                IngestionEvent::IngestionEventOneOf4(Box::new(
-                    IngestionEventOneOf4::new(
-                        qa_log,
-                        Uuid::new_v4().to_string(),
-                        chrono::Utc::now().to_rfc3339(),
-                        ingestion_event_one_of_4::Type::GenerationCreate,
-                    ),
-                )),
-            ],
-            metadata: None,
-        },
-    )
-    .await;
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
 
-    let Ok(response) = response else {
-        error!(
-            task = "ingestion",
-            error = response.unwrap_err().to_string(),
-        );
-        return Err(anyhow::anyhow!("Failed to log langfuse"));
+    use axum::{extract::State, response::IntoResponse, Extension, Json};
+    use cloudflare::{
+        fake::{FakeCloudflare, Reply},
+        models::text_generation::TextGenerationJsonResult,
+    };
+    use entity::prelude::DocumentTypeEntity;
+    use langfuse::trace::{Observe, Trace};
+    use llm::{Model, Provider};
+    use rpc::RpcObserver;
+    use rpc_router::Resources;
+    use vector_store::{
+        CollectionModel, Document, MemoryStore, Point, Store, VectorStore,
     };
 
-    for error in response.errors {
-        error!(task = "ingestion", error = error.message);
+    use super::{highlight, request::SearchParam, search_text_with_sse};
+    use crate::{auth::Claims, ApiState};
+
+    const CHAT: &str = "@cf/meta/llama-3-8b-instruct-awq";
+    const FUNCTION_CALL: &str = "@hf/nousresearch/hermes-2-pro-mistral-7b";
+    const EMBEDDING: &str = "@cf/baai/bge-small-en-v1.5";
+
+    fn result(response: &str) -> TextGenerationJsonResult {
+        TextGenerationJsonResult {
+            response: Some(response.to_string()),
+            tool_calls: None,
+            usage: None,
+        }
     }
 
-    Ok(())
+    #[tokio::test]
+    async fn test_search_text_with_sse() {
+        // Arrange
+        let fake = FakeCloudflare::start().await.unwrap();
+        fake.reply(CHAT, Reply::Generation(result("axum")))
+            .reply(CHAT, Reply::Stream(vec![result("Use "), result("axum.")]))
+            .reply(FUNCTION_CALL, Reply::Generation(result("No calls.")))
+            .reply(EMBEDDING, Reply::Embeddings { dimensions: 64 });
+        let text = "Axum\nRouting with axum";
+        let vector = Model::new(Provider::Cloudflare(fake.models()), EMBEDDING)
+            .embed(vec![text.to_string()])
+            .await
+            .unwrap()
+            .remove(0);
+        let store = Store::Memory(MemoryStore::default());
+        store
+            .upsert(
+                &CollectionModel::new(EMBEDDING),
+                vec![Point {
+                    id: "axum".to_string(),
+                    vector,
+                    document: Document {
+                        page_id: "axum".to_string(),
+                        document_type: DocumentTypeEntity::Page,
+                        text: text.to_string(),
+                        chunk: None,
+                    },
+                }],
+            )
+            .await
+            .unwrap();
+        let dir = tempfile::tempdir().unwrap();
+        let state = ApiState::fake(&fake, store, dir.path()).unwrap();
+        let claims = Claims {
+            sub: "user".to_string(),
+            user_id: Some(1),
+        };
+        let params = SearchParam {
+            prompt: "How do I route with axum?".to_string(),
+            history: vec![],
+            session: None,
+        };
+
+        // Act
+        let response = search_text_with_sse(
+            Extension(claims),
+            State(Arc::new(state)),
+            Json(params),
+        )
+        .await
+        .into_response();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+
+        // Assert
+        let body = String::from_utf8(body.to_vec()).unwrap();
+        assert!(body.contains(r#"data: {"message":"Use "}"#));
+        assert!(body.contains(r#"data: {"message":"axum."}"#));
+        assert!(body.contains(r#"data: {"pages":[]}"#));
+        let answer = fake
+            .requests()
+            .into_iter()
+            .find(|request| request.body["stream"] == true)
+            .unwrap();
+        let messages = answer.body["messages"].as_array().unwrap();
+        let question = messages.last().unwrap()["content"].as_str().unwrap();
+        assert!(question.contains(text));
+        assert!(question.contains("How do I route with axum?"));
+    }
+
+    #[tokio::test]
+    async fn test_rpc_records_query_embedding() {
+        // Arrange
+        let fake = FakeCloudflare::start().await.unwrap();
+        fake.reply(EMBEDDING, Reply::Embeddings { dimensions: 64 });
+        let store = Store::Memory(MemoryStore::new(EMBEDDING));
+        let dir = tempfile::tempdir().unwrap();
+        let state = ApiState::fake(&fake, store, dir.path()).unwrap();
+        let trace = Trace::new("test");
+        let span = trace.span("get observations");
+
+        // Act
+        let response = state
+            .rpc
+            .call_route_with_resources(
+                None,
+                "get_article_summary",
+                Some(serde_json::json!({ "query": "axum" })),
+                Resources::builder()
+                    .append(RpcObserver(Arc::new(
+                        span.span("get_article_summary"),
+                    )))
+                    .build(),
+            )
+            .await;
+        drop(span);
+
+        // Assert
+        assert!(response.is_ok());
+        let events = serde_json::to_value(trace.finish()).unwrap();
+        let generation = events
+            .as_array()
+            .unwrap()
+            .iter()
+            .find(|event| event["body"]["name"] == "embed query")
+            .unwrap();
+        assert_eq!(generation["body"]["model"], EMBEDDING);
+        assert_eq!(generation["body"]["input"], "axum");
+        assert_eq!(fake.requests()[0].body["text"][0], "axum");
+    }
+
+    #[test]
+    fn test_highlight() {
+        // Arrange
+        let snippet = "use \u{2}axum\u{3} & <b>\u{2}tokio\u{3}</b>";
+
+        // Act
+        let html = highlight(snippet);
+
+        // Assert
+        assert_eq!(
+            html,
+            "use <mark>axum</mark> &amp; &lt;b&gt;<mark>tokio</mark>&lt;/b&gt;"
+        );
+    }
 }
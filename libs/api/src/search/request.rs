@@ -1,10 +1,62 @@
 use cloudflare::models::text_generation::Message;
 use serde::Deserialize;
+use serde_with::{serde_as, DisplayFromStr};
 use utoipa::{IntoParams, ToSchema};
 
+const DEFAULT_SEARCH_LIMIT: u64 = 10;
+const MAX_SEARCH_LIMIT: u64 = 50;
+
 #[derive(Deserialize, ToSchema, IntoParams, Clone)]
 pub struct SearchParam {
     pub prompt: String,
     pub history: Vec<Message>,
     pub session: Option<String>,
 }
+
+#[serde_as]
+#[derive(Deserialize, ToSchema, IntoParams)]
+pub struct SearchPagesParam {
+    pub q: String,
+    #[serde_as(as = "Option<DisplayFromStr>")]
+    #[serde(default)]
+    pub limit: Option<u64>,
+}
+
+impl SearchPagesParam {
+    pub fn limit(&self) -> u64 {
+        self.limit
+            .unwrap_or(DEFAULT_SEARCH_LIMIT)
+            .clamp(1, MAX_SEARCH_LIMIT)
+    }
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct FeedbackParam {
+    /// Trace id emitted in the `debug` event of the search stream
+    pub trace_id: String,
+    pub rating: Rating,
+    pub comment: Option<String>,
+}
+
+/// Either `"up"`/`"down"` or a number from 1 to 5
+#[derive(Deserialize, ToSchema)]
+#[serde(untagged)]
+pub enum Rating {
+    Thumb(Thumb),
+    Score(f64),
+}
+
+#[derive(Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum Thumb {
+    Up,
+    Down,
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct EvalParam {
+    /// Name of the Langfuse dataset
+    pub dataset: String,
+    /// Defaults to the dataset name with the current time
+    pub run_name: Option<String>,
+}
@@ -0,0 +1,45 @@
+use serde::Serialize;
+use utoipa::ToSchema;
+
+#[derive(Serialize, ToSchema)]
+pub struct SearchPageResp {
+    pub id: String,
+    pub title: String,
+    /// Html escaped text with matched words wrapped in `<mark>`
+    pub snippet: String,
+    pub rank: f32,
+    pub created_at: String,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct SearchPagesResp {
+    pub pages: Vec<SearchPageResp>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct HybridPageResp {
+    pub id: String,
+    pub title: String,
+    /// Reciprocal rank fusion score
+    pub score: f32,
+    /// 1-based rank in the keyword results
+    pub keyword_rank: Option<usize>,
+    /// 1-based rank in the vector results
+    pub vector_rank: Option<usize>,
+    /// Html escaped text with matched words wrapped in `<mark>`
+    pub snippet: Option<String>,
+    /// Body chunks that matched the query by vector
+    pub chunks: Vec<String>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct HybridSearchResp {
+    pub pages: Vec<HybridPageResp>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct EvalResp {
+    pub run_name: String,
+    /// Number of dataset items to be evaluated
+    pub items: usize,
+}
@@ -0,0 +1,235 @@
+use std::collections::HashSet;
+use std::fmt::Write as _;
+use std::sync::Arc;
+
+use anyhow::Context;
+use axum::{
+    extract::{Path, State},
+    http::{header, StatusCode},
+    response::{IntoResponse, Response},
+};
+use chrono::{DateTime, SecondsFormat, Utc};
+use entity::page::ParentType;
+
+use crate::response::{ApiResponse, IntoApiResponse};
+use crate::xml::escape;
+use crate::{ApiState, Site};
+
+// The protocol caps a single sitemap at 50,000 urls.
+const MAX_URLS: usize = 50_000;
+
+struct Url {
+    loc: String,
+    lastmod: Option<DateTime<Utc>>,
+}
+
+/// Sitemap of published pages
+#[utoipa::path(
+    get,
+    path = "/sitemap.xml",
+    responses(
+        (status = 200, description = "Get sitemap successfully", body = String, content_type = "application/xml")
+    )
+)]
+pub async fn get_sitemap(
+    State(state): State<Arc<ApiState>>,
+) -> ApiResponse<Response> {
+    let urls = find_urls(&state).await.into_response("502-022")?;
+
+    let site = &state.config.site;
+    let xml = if urls.len() > MAX_URLS {
+        sitemap_index(site, &urls)
+    } else {
+        urlset(&urls)
+    };
+
+    Ok((
+        [(header::CONTENT_TYPE, "application/xml; charset=utf-8")],
+        xml,
+    )
+        .into_response())
+}
+
+/// One part of a sitemap split by the sitemap index
+#[utoipa::path(
+    get,
+    path = "/sitemaps/:file",
+    responses(
+        (status = 200, description = "Get sitemap successfully", body = String, content_type = "application/xml"),
+        (status = 404, description = "Sitemap was not found")
+    ),
+    params(
+        ("file", description = "sitemap file name like 1.xml"),
+    )
+)]
+pub async fn get_sitemap_part(
+    State(state): State<Arc<ApiState>>,
+    Path(file): Path<String>,
+) -> ApiResponse<Response> {
+    let Some(index) = file
+        .strip_suffix(".xml")
+        .and_then(|index| index.parse::<usize>().ok())
+        .filter(|index| *index > 0)
+    else {
+        return Ok(StatusCode::NOT_FOUND.into_response());
+    };
+
+    let urls = find_urls(&state).await.into_response("502-022")?;
+
+    let Some(urls) = urls.chunks(MAX_URLS).nth(index - 1) else {
+        return Ok(StatusCode::NOT_FOUND.into_response());
+    };
+
+    Ok((
+        [(header::CONTENT_TYPE, "application/xml; charset=utf-8")],
+        urlset(urls),
+    )
+        .into_response())
+}
+
+/// robots.txt pointing crawlers at the sitemap
+#[utoipa::path(
+    get,
+    path = "/robots.txt",
+    responses(
+        (status = 200, description = "Get robots.txt successfully", body = String, content_type = "text/plain")
+    )
+)]
+pub async fn get_robots(State(state): State<Arc<ApiState>>) -> Response {
+    (
+        [(header::CONTENT_TYPE, "text/plain; charset=utf-8")],
+        robots(&state.config.site),
+    )
+        .into_response()
+}
+
+async fn find_urls(state: &ApiState) -> anyhow::Result<Vec<Url>> {
+    let static_page_ids = state
+        .repo
+        .static_page
+        .find_all()
+        .await
+        .context("failed to find static pages")?
+        .into_iter()
+        .map(|page| page.notion_page_id)
+        .collect::<HashSet<_>>();
+
+    let pages = state
+        .repo
+        .page
+        .find_published()
+        .await
+        .context("failed to find pages")?;
+
+    let site = &state.config.site;
+    let mut urls = vec![Url {
+        loc: format!("{}/", site.url),
+        lastmod: None,
+    }];
+    urls.extend(
+        pages
+            .into_iter()
+            .filter(|page| {
+                page.parent_type == ParentType::Database
+                    || static_page_ids.contains(&page.notion_page_id)
+            })
+            .map(|page| Url {
+                loc: format!("{}/pages/{}", site.url, page.notion_page_id),
+                lastmod: Some(page.updated_at.unwrap_or(page.created_at)),
+            }),
+    );
+
+    Ok(urls)
+}
+
+fn urlset(urls: &[Url]) -> String {
+    let mut xml = String::new();
+    xml.push_str("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    xml.push_str(
+        "<urlset xmlns=\"http://www.sitemaps.org/schemas/sitemap/0.9\">\n",
+    );
+    for url in urls {
+        xml.push_str("<url>");
+        let _ = write!(xml, "<loc>{}</loc>", escape(&url.loc));
+        if let Some(lastmod) = url.lastmod {
+            let _ = write!(xml, "<lastmod>{}</lastmod>", w3c_datetime(lastmod));
+        }
+        xml.push_str("</url>\n");
+    }
+    xml.push_str("</urlset>\n");
+    xml
+}
+
+fn sitemap_index(site: &Site, urls: &[Url]) -> String {
+    let mut xml = String::new();
+    xml.push_str("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    xml.push_str(
+        "<sitemapindex xmlns=\"http://www.sitemaps.org/schemas/sitemap/0.9\">\n",
+    );
+    for (i, urls) in urls.chunks(MAX_URLS).enumerate() {
+        xml.push_str("<sitemap>");
+        let _ = write!(
+            xml,
+            "<loc>{}</loc>",
+            escape(&format!("{}/sitemaps/{}.xml", site.url, i + 1))
+        );
+        let lastmod = urls.iter().filter_map(|url| url.lastmod).max();
+        if let Some(lastmod) = lastmod {
+            let _ = write!(xml, "<lastmod>{}</lastmod>", w3c_datetime(lastmod));
+        }
+        xml.push_str("</sitemap>\n");
+    }
+    xml.push_str("</sitemapindex>\n");
+    xml
+}
+
+fn robots(site: &Site) -> String {
+    format!(
+        "User-agent: *\nAllow: /\n\nSitemap: {}/sitemap.xml\n",
+        site.url
+    )
+}
+
+fn w3c_datetime(datetime: DateTime<Utc>) -> String {
+    datetime.to_rfc3339_opts(SecondsFormat::Secs, true)
+}
+
+#[cfg(test)]
+mod test {
+    use chrono::{TimeZone, Utc};
+
+    use super::{sitemap_index, urlset, Url, MAX_URLS};
+    use crate::Site;
+
+    #[test]
+    fn test_sitemap_index() {
+        // Arrange
+        let site = Site {
+            url: "https://example.com".to_string(),
+            title: "example".to_string(),
+            description: "example".to_string(),
+        };
+        let date = Utc.with_ymd_and_hms(2024, 5, 1, 0, 0, 0).unwrap();
+        let urls = (0..MAX_URLS + 1)
+            .map(|i| Url {
+                loc: format!("https://example.com/pages/{}?a&b", i),
+                lastmod: Some(date),
+            })
+            .collect::<Vec<_>>();
+
+        // Act
+        let index = sitemap_index(&site, &urls);
+        let urlset = urlset(&urls[..1]);
+
+        // Assert
+        assert_eq!(index.matches("<sitemap>").count(), 2);
+        assert!(index.contains(
+            "<loc>https://example.com/sitemaps/2.xml</loc>\
+             <lastmod>2024-05-01T00:00:00Z</lastmod>"
+        ));
+        assert!(urlset.contains(
+            "<url><loc>https://example.com/pages/0?a&amp;b</loc>\
+             <lastmod>2024-05-01T00:00:00Z</lastmod></url>"
+        ));
+    }
+}
@@ -1,16 +1,29 @@
+use std::collections::HashMap;
 use std::sync::Arc;
 
 use anyhow::anyhow;
-use axum::{extract::State, Extension, Json};
+use anyhow::Context;
+use axum::{
+    extract::{Path, Query, State},
+    http::StatusCode,
+    Extension, Json,
+};
 use entity::user::User;
 
 use crate::{
     auth::Claims,
+    render::page_title,
+    request::encode_cursor,
     response::{ApiResponse, IntoApiResponse},
     ApiState,
 };
 
-use self::response::{GetUserResp, UserResp};
+use self::request::GetSessionsParam;
+use self::response::{
+    CitedPageResp, GetSessionResp, GetSessionsResp, GetUserResp, PromptResp,
+    SessionDetailResp, SessionResp, UserResp,
+};
+pub mod request;
 pub mod response;
 
 /// Get user
@@ -59,3 +72,156 @@ pub async fn get_user(
         user: UserResp::from(user),
     }))
 }
+
+/// List prompt sessions of the user
+#[utoipa::path(
+    get,
+    path = "/user/sessions",
+    responses(
+        (status = 200, description = "List sessions successfully", body = GetSessionsResp)
+    ),
+    params(
+        GetSessionsParam
+    )
+)]
+pub async fn get_sessions(
+    Extension(ref claims): Extension<Claims>,
+    State(state): State<Arc<ApiState>>,
+    Query(params): Query<GetSessionsParam>,
+) -> ApiResponse<Json<GetSessionsResp>> {
+    let cursor = params.pagination.cursor().into_response("400-001")?;
+    let user_id = claims
+        .user_id
+        .context("failed to get user id")
+        .into_response("502-027")?;
+
+    let (sessions, next_cursor) = state
+        .repo
+        .prompt_session
+        .find_by_user_id(user_id, params.pagination.limit(), cursor)
+        .await
+        .into_response("502-027")?;
+
+    Ok(Json(GetSessionsResp {
+        sessions: sessions.into_iter().map(SessionResp::from).collect(),
+        next_cursor: next_cursor.as_ref().map(encode_cursor),
+    }))
+}
+
+/// Get a prompt session of the user with its prompts
+#[utoipa::path(
+    get,
+    path = "/user/sessions/:id",
+    responses(
+        (status = 200, description = "Get session successfully", body = GetSessionResp)
+    ),
+    params(
+        ("id", description = "session id"),
+    )
+)]
+pub async fn get_session(
+    Extension(ref claims): Extension<Claims>,
+    State(state): State<Arc<ApiState>>,
+    Path(id): Path<String>,
+) -> ApiResponse<Json<GetSessionResp>> {
+    let user_id = claims
+        .user_id
+        .context("failed to get user id")
+        .into_response("502-028")?;
+
+    let session = state
+        .repo
+        .prompt_session
+        .find_by_id_and_user_id(&id, user_id)
+        .await
+        .into_response("502-028")?;
+
+    let Some(session) = session else {
+        return Ok(Json(GetSessionResp { session: None }));
+    };
+
+    let prompts = state
+        .repo
+        .prompt
+        .find_by_session_id(&session.id)
+        .await
+        .into_response("502-028")?;
+
+    let mut page_ids = prompts
+        .iter()
+        .flat_map(|(_, page_ids)| page_ids.clone())
+        .collect::<Vec<_>>();
+    page_ids.sort();
+    page_ids.dedup();
+
+    // Cited pages that were deleted or turned into drafts are left out.
+    let titles = state
+        .repo
+        .page
+        .find_published_by_ids(&page_ids)
+        .await
+        .into_response("502-028")?
+        .into_iter()
+        .map(|page| (page.notion_page_id.clone(), page_title(&page)))
+        .collect::<HashMap<_, _>>();
+
+    let prompts = prompts
+        .into_iter()
+        .map(|(prompt, page_ids)| PromptResp {
+            id: prompt.id,
+            user_prompt: prompt.user_prompt,
+            assistant_prompt: prompt.assistant_prompt,
+            pages: page_ids
+                .into_iter()
+                .filter_map(|id| {
+                    let title = titles.get(&id)?.clone();
+                    Some(CitedPageResp { id, title })
+                })
+                .collect(),
+            created_at: prompt.created_at.to_string(),
+        })
+        .collect();
+
+    Ok(Json(GetSessionResp {
+        session: Some(SessionDetailResp {
+            session: SessionResp::from(session),
+            prompts,
+        }),
+    }))
+}
+
+/// Delete a prompt session of the user with its prompts
+#[utoipa::path(
+    delete,
+    path = "/user/sessions/:id",
+    responses(
+        (status = 204, description = "Delete session successfully"),
+        (status = 404, description = "Session was not found")
+    ),
+    params(
+        ("id", description = "session id"),
+    )
+)]
+pub async fn delete_session(
+    Extension(ref claims): Extension<Claims>,
+    State(state): State<Arc<ApiState>>,
+    Path(id): Path<String>,
+) -> ApiResponse<StatusCode> {
+    let user_id = claims
+        .user_id
+        .context("failed to get user id")
+        .into_response("502-029")?;
+
+    let deleted = state
+        .repo
+        .prompt_session
+        .delete_by_user_id(&id, user_id)
+        .await
+        .into_response("502-029")?;
+
+    if !deleted {
+        return Ok(StatusCode::NOT_FOUND);
+    }
+
+    Ok(StatusCode::NO_CONTENT)
+}
@@ -0,0 +1,10 @@
+use serde::Deserialize;
+use utoipa::{IntoParams, ToSchema};
+
+use crate::request::CursorPagination;
+
+#[derive(Deserialize, ToSchema, IntoParams)]
+pub struct GetSessionsParam {
+    #[serde(flatten)]
+    pub pagination: CursorPagination,
+}
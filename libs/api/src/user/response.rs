@@ -21,3 +21,53 @@ impl From<UserEntity> for UserResp {
         }
     }
 }
+
+#[derive(Serialize, ToSchema)]
+pub struct SessionResp {
+    pub id: String,
+    pub created_at: String,
+    pub updated_at: String,
+}
+
+impl From<PromptSessionEntity> for SessionResp {
+    fn from(value: PromptSessionEntity) -> Self {
+        Self {
+            id: value.id,
+            created_at: value.created_at.to_string(),
+            updated_at: value.updated_at.to_string(),
+        }
+    }
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct GetSessionsResp {
+    pub sessions: Vec<SessionResp>,
+    pub next_cursor: Option<String>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct CitedPageResp {
+    pub id: String,
+    pub title: String,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct PromptResp {
+    pub id: i32,
+    pub user_prompt: String,
+    pub assistant_prompt: String,
+    pub pages: Vec<CitedPageResp>,
+    pub created_at: String,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct SessionDetailResp {
+    #[serde(flatten)]
+    pub session: SessionResp,
+    pub prompts: Vec<PromptResp>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct GetSessionResp {
+    pub session: Option<SessionDetailResp>,
+}
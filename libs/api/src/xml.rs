@@ -0,0 +1,16 @@
+pub fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            // XML 1.0 doesn't allow most control characters even escaped.
+            c if c.is_control() && !matches!(c, '\n' | '\r' | '\t') => {}
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
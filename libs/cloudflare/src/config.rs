@@ -0,0 +1,151 @@
+use std::{collections::HashMap, time::Duration};
+
+use anyhow::Context;
+use rand::Rng;
+use toml::{map::Map, Value};
+
+/// Timeouts, retries and the concurrency limit of [`crate::models::Models`].
+#[derive(Debug, Clone)]
+pub struct ClientConfig {
+    pub timeout: Duration,
+    // Overrides of `timeout` by model
+    pub timeouts: HashMap<String, Duration>,
+    pub max_retries: u32,
+    pub retry_base_delay: Duration,
+    // Also the longest Retry-After waited for, beyond which a request fails
+    pub retry_max_delay: Duration,
+    // Requests in flight at once across every clone of the client
+    pub max_concurrency: usize,
+}
+
+impl Default for ClientConfig {
+    fn default() -> Self {
+        Self {
+            timeout: Duration::from_secs(60),
+            timeouts: HashMap::new(),
+            max_retries: 3,
+            retry_base_delay: Duration::from_millis(500),
+            retry_max_delay: Duration::from_secs(20),
+            max_concurrency: 8,
+        }
+    }
+}
+
+impl ClientConfig {
+    // Builds the config from the `[cloudflare]` table of the config. Keys
+    // left out keep their default.
+    pub fn from_config(config: &Map<String, Value>) -> anyhow::Result<Self> {
+        let mut client_config = Self::default();
+        let integer = |key: &str| -> anyhow::Result<Option<u64>> {
+            config
+                .get(key)
+                .map(|value| {
+                    value
+                        .as_integer()
+                        .and_then(|value| u64::try_from(value).ok())
+                        .with_context(|| format!("invalid {}", key))
+                })
+                .transpose()
+        };
+
+        if let Some(secs) = integer("timeout_secs")? {
+            client_config.timeout = Duration::from_secs(secs);
+        }
+        if let Some(retries) = integer("max_retries")? {
+            client_config.max_retries = retries as u32;
+        }
+        if let Some(millis) = integer("retry_base_delay_ms")? {
+            client_config.retry_base_delay = Duration::from_millis(millis);
+        }
+        if let Some(secs) = integer("retry_max_delay_secs")? {
+            client_config.retry_max_delay = Duration::from_secs(secs);
+        }
+        if let Some(concurrency) = integer("max_concurrency")? {
+            client_config.max_concurrency = concurrency as usize;
+        }
+        if let Some(table) = config.get("timeouts").and_then(Value::as_table) {
+            for (model, secs) in table {
+                let secs = secs
+                    .as_integer()
+                    .and_then(|secs| u64::try_from(secs).ok())
+                    .with_context(|| format!("invalid timeout of {}", model))?;
+                client_config
+                    .timeouts
+                    .insert(model.to_string(), Duration::from_secs(secs));
+            }
+        }
+
+        Ok(client_config)
+    }
+
+    pub fn timeout(&self, model: &str) -> Duration {
+        self.timeouts.get(model).copied().unwrap_or(self.timeout)
+    }
+
+    // A random delay up to the exponential backoff of `attempt`, so that
+    // clients limited at the same time don't retry at the same time either.
+    pub fn backoff(&self, attempt: u32) -> Duration {
+        let ceiling = self
+            .retry_base_delay
+            .saturating_mul(2_u32.saturating_pow(attempt))
+            .min(self.retry_max_delay);
+
+        ceiling.mul_f64(rand::thread_rng().gen_range(0.0..=1.0))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use super::ClientConfig;
+
+    #[test]
+    fn test_from_config() {
+        // Arrange
+        let config = toml::from_str::<toml::Table>(
+            r#"
+            timeout_secs = 30
+            max_retries = 5
+            max_concurrency = 2
+
+            [timeouts]
+            "@cf/image" = 90
+            "#,
+        )
+        .unwrap();
+
+        // Act
+        let config = ClientConfig::from_config(&config).unwrap();
+
+        // Assert
+        assert_eq!(config.timeout("@cf/text"), Duration::from_secs(30));
+        assert_eq!(config.timeout("@cf/image"), Duration::from_secs(90));
+        assert_eq!(config.max_retries, 5);
+        assert_eq!(config.max_concurrency, 2);
+        assert_eq!(
+            config.retry_base_delay,
+            ClientConfig::default().retry_base_delay
+        );
+    }
+
+    #[test]
+    fn test_backoff() {
+        // Arrange
+        let config = ClientConfig {
+            retry_base_delay: Duration::from_millis(100),
+            retry_max_delay: Duration::from_millis(1000),
+            ..Default::default()
+        };
+
+        // Act
+        let delays = (0..8).map(|attempt| config.backoff(attempt));
+
+        // Assert
+        for (attempt, delay) in delays.enumerate() {
+            let ceiling = Duration::from_millis(100 << attempt)
+                .min(Duration::from_millis(1000));
+            assert!(delay <= ceiling, "{:?} > {:?}", delay, ceiling);
+        }
+    }
+}
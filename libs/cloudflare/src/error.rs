@@ -0,0 +1,153 @@
+use std::time::{Duration, SystemTime};
+
+use reqwest::{header::RETRY_AFTER, Response, StatusCode};
+use serde::Deserialize;
+
+// An entry of `errors` in the `{success, errors[]}` envelope of the API
+#[derive(Debug, Clone, Deserialize, PartialEq)]
+pub struct ApiError {
+    pub code: i64,
+    pub message: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub(crate) struct Envelope {
+    #[serde(default = "success")]
+    pub success: bool,
+    #[serde(default)]
+    pub errors: Vec<ApiError>,
+}
+
+fn success() -> bool {
+    true
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum CloudflareError {
+    #[error("status code: {status}, errors: {}", messages(.errors))]
+    Api {
+        status: StatusCode,
+        errors: Vec<ApiError>,
+        retry_after: Option<Duration>,
+    },
+    // A failure without the envelope, e.g. from a proxy in between
+    #[error("status code: {status}, response: {body}")]
+    Status {
+        status: StatusCode,
+        body: String,
+        retry_after: Option<Duration>,
+    },
+    #[error("timed out after {0:?}")]
+    Timeout(Duration),
+    #[error("request error: {0}")]
+    Request(#[from] reqwest::Error),
+}
+
+fn messages(errors: &[ApiError]) -> String {
+    errors
+        .iter()
+        .map(|error| format!("{} ({})", error.message, error.code))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+impl CloudflareError {
+    pub(crate) async fn from_response(response: Response) -> Self {
+        let status = response.status();
+        let retry_after = response
+            .headers()
+            .get(RETRY_AFTER)
+            .and_then(|value| value.to_str().ok())
+            .and_then(parse_retry_after);
+        let body = match response.text().await {
+            Ok(body) => body,
+            Err(e) => return Self::Request(e),
+        };
+
+        match serde_json::from_str::<Envelope>(&body) {
+            Ok(envelope) if !envelope.errors.is_empty() => Self::Api {
+                status,
+                errors: envelope.errors,
+                retry_after,
+            },
+            _ => Self::Status {
+                status,
+                body,
+                retry_after,
+            },
+        }
+    }
+
+    pub fn status(&self) -> Option<StatusCode> {
+        match self {
+            Self::Api { status, .. } | Self::Status { status, .. } => {
+                Some(*status)
+            }
+            Self::Timeout(_) => None,
+            Self::Request(e) => e.status(),
+        }
+    }
+
+    // Rate limits, server errors and requests that never got an answer are
+    // worth another try.
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            Self::Api { status, .. } | Self::Status { status, .. } => {
+                *status == StatusCode::TOO_MANY_REQUESTS
+                    || status.is_server_error()
+            }
+            Self::Timeout(_) => true,
+            Self::Request(e) => e.is_timeout() || e.is_connect(),
+        }
+    }
+
+    pub fn retry_after(&self) -> Option<Duration> {
+        match self {
+            Self::Api { retry_after, .. }
+            | Self::Status { retry_after, .. } => *retry_after,
+            _ => None,
+        }
+    }
+}
+
+// `Retry-After` is either a number of seconds or an HTTP date.
+fn parse_retry_after(value: &str) -> Option<Duration> {
+    if let Ok(seconds) = value.trim().parse::<u64>() {
+        return Some(Duration::from_secs(seconds));
+    }
+    let date = httpdate::parse_http_date(value).ok()?;
+
+    Some(
+        date.duration_since(SystemTime::now())
+            .unwrap_or(Duration::ZERO),
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::{Duration, SystemTime};
+
+    use super::parse_retry_after;
+
+    #[test]
+    fn test_parse_retry_after() {
+        // Arrange
+        let date = httpdate::fmt_http_date(
+            SystemTime::now() + Duration::from_secs(120),
+        );
+
+        // Act
+        let seconds = parse_retry_after("7");
+        let date = parse_retry_after(&date).unwrap();
+        let past = parse_retry_after("Wed, 21 Oct 2015 07:28:00 GMT");
+        let invalid = parse_retry_after("soon");
+
+        // Assert
+        assert_eq!(seconds, Some(Duration::from_secs(7)));
+        assert!(
+            date > Duration::from_secs(110) && date <= Duration::from_secs(120)
+        );
+        assert_eq!(past, Some(Duration::ZERO));
+        assert_eq!(invalid, None);
+    }
+}
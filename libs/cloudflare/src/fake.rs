@@ -0,0 +1,425 @@
+//! An in-process stand-in for the Workers AI REST API so that code running
+//! models can be tested offline.
+//!
+//! Replies are scripted per model with [`FakeCloudflare::reply`] and the
+//! requests received are kept for assertions.
+
+use std::{
+    collections::{HashMap, VecDeque},
+    convert::Infallible,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use axum::{
+    body::Body,
+    extract::{Path, State},
+    http::{header, StatusCode},
+    response::{IntoResponse, Response},
+    routing::post,
+    Json, Router,
+};
+use bytes::Bytes;
+use serde_json::{json, Value};
+use tokio::{net::TcpListener, task::JoinHandle};
+
+use crate::models::{text_generation::TextGenerationJsonResult, Models};
+
+#[derive(Clone, Debug)]
+pub enum Reply {
+    Generation(TextGenerationJsonResult),
+    // Each result is sent as its own `data:` event, followed by `[DONE]`
+    Stream(Vec<TextGenerationJsonResult>),
+    // Bag-of-words vectors so that texts sharing words are close
+    Embeddings {
+        dimensions: usize,
+    },
+    Image(Bytes),
+    Error {
+        status: u16,
+        message: String,
+        retry_after: Option<u64>,
+    },
+    Delay {
+        delay: Duration,
+        reply: Box<Reply>,
+    },
+}
+
+#[derive(Clone, Debug)]
+pub struct Request {
+    pub model: String,
+    pub body: Value,
+}
+
+#[derive(Default)]
+struct Script {
+    replies: HashMap<String, VecDeque<Reply>>,
+    requests: Vec<Request>,
+    in_flight: usize,
+    max_in_flight: usize,
+}
+
+type SharedScript = Arc<Mutex<Script>>;
+
+pub struct FakeCloudflare {
+    base_url: String,
+    script: SharedScript,
+    server: JoinHandle<()>,
+}
+
+impl FakeCloudflare {
+    pub async fn start() -> anyhow::Result<Self> {
+        let script = SharedScript::default();
+        let app = Router::new()
+            .route("/*model", post(run))
+            .with_state(script.clone());
+
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let base_url = format!("http://{}", listener.local_addr()?);
+        let server = tokio::spawn(async move {
+            let _ = axum::serve(listener, app).await;
+        });
+
+        Ok(Self {
+            base_url,
+            script,
+            server,
+        })
+    }
+
+    pub fn base_url(&self) -> &str {
+        &self.base_url
+    }
+
+    pub fn models(&self) -> Models {
+        Models::with_base_url(&self.base_url, "token")
+    }
+
+    /// Queues a reply for `model`. The last reply queued for a model is
+    /// repeated for every later request.
+    pub fn reply(&self, model: &str, reply: Reply) -> &Self {
+        if let Ok(mut script) = self.script.lock() {
+            script
+                .replies
+                .entry(model.to_string())
+                .or_default()
+                .push_back(reply);
+        }
+        self
+    }
+
+    pub fn requests(&self) -> Vec<Request> {
+        self.script
+            .lock()
+            .map(|script| script.requests.clone())
+            .unwrap_or_default()
+    }
+
+    // The most requests that were being answered at once
+    pub fn max_in_flight(&self) -> usize {
+        self.script
+            .lock()
+            .map(|script| script.max_in_flight)
+            .unwrap_or_default()
+    }
+}
+
+impl Drop for FakeCloudflare {
+    fn drop(&mut self) {
+        self.server.abort();
+    }
+}
+
+// Counts a request out when it's answered or the client gives up on it.
+struct InFlight(SharedScript);
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        if let Ok(mut script) = self.0.lock() {
+            script.in_flight -= 1;
+        }
+    }
+}
+
+async fn run(
+    State(script): State<SharedScript>,
+    Path(model): Path<String>,
+    body: Bytes,
+) -> Response {
+    let body = serde_json::from_slice::<Value>(&body).unwrap_or_default();
+    let reply = {
+        let Ok(mut script) = script.lock() else {
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        };
+        script.requests.push(Request {
+            model: model.clone(),
+            body: body.clone(),
+        });
+        script.in_flight += 1;
+        script.max_in_flight = script.max_in_flight.max(script.in_flight);
+        script.replies.get_mut(&model).and_then(|replies| {
+            if replies.len() > 1 {
+                replies.pop_front()
+            } else {
+                replies.front().cloned()
+            }
+        })
+    };
+
+    let _in_flight = InFlight(script);
+
+    let mut delayed = reply.as_ref();
+    while let Some(Reply::Delay { delay, reply }) = delayed {
+        tokio::time::sleep(*delay).await;
+        delayed = Some(reply);
+    }
+
+    respond(reply, &model, &body)
+}
+
+fn respond(reply: Option<Reply>, model: &str, body: &Value) -> Response {
+    match reply {
+        Some(Reply::Generation(result)) => success(json!(result)),
+        Some(Reply::Stream(results)) => {
+            let events = results
+                .iter()
+                .map(|result| format!("data: {}\n\n", json!(result)))
+                .chain(["data: [DONE]\n\n".to_string()])
+                .map(Ok::<_, Infallible>)
+                .collect::<Vec<_>>();
+            (
+                [(header::CONTENT_TYPE, "text/event-stream")],
+                Body::from_stream(futures_util::stream::iter(events)),
+            )
+                .into_response()
+        }
+        Some(Reply::Embeddings { dimensions }) => {
+            let texts = match &body["text"] {
+                Value::String(text) => vec![text.as_str()],
+                Value::Array(texts) => {
+                    texts.iter().filter_map(Value::as_str).collect()
+                }
+                _ => vec![],
+            };
+            let data = texts
+                .iter()
+                .map(|text| embed(text, dimensions))
+                .collect::<Vec<_>>();
+            success(json!({
+                "shape": [data.len(), dimensions],
+                "data": data,
+            }))
+        }
+        Some(Reply::Image(bytes)) => {
+            ([(header::CONTENT_TYPE, "image/png")], bytes).into_response()
+        }
+        Some(Reply::Error {
+            status,
+            message,
+            retry_after,
+        }) => {
+            let mut response = failure(
+                StatusCode::from_u16(status)
+                    .unwrap_or(StatusCode::INTERNAL_SERVER_ERROR),
+                &message,
+            );
+            if let Some(retry_after) = retry_after {
+                response
+                    .headers_mut()
+                    .insert(header::RETRY_AFTER, retry_after.into());
+            }
+            response
+        }
+        // Waited out by `run`
+        Some(Reply::Delay { reply, .. }) => respond(Some(*reply), model, body),
+        None => failure(
+            StatusCode::BAD_REQUEST,
+            &format!("No route for that URI: {}", model),
+        ),
+    }
+}
+
+fn success(result: Value) -> Response {
+    Json(json!({
+        "result": result,
+        "success": true,
+        "errors": [],
+        "messages": [],
+    }))
+    .into_response()
+}
+
+fn failure(status: StatusCode, message: &str) -> Response {
+    (
+        status,
+        Json(json!({
+            "result": null,
+            "success": false,
+            "errors": [{ "code": status.as_u16(), "message": message }],
+            "messages": [],
+        })),
+    )
+        .into_response()
+}
+
+// Hashes each word into a bucket and normalizes the counts.
+fn embed(text: &str, dimensions: usize) -> Vec<f32> {
+    let dimensions = dimensions.max(1);
+    let mut vector = vec![0.0; dimensions];
+    for word in text.split_whitespace() {
+        let word = word.to_lowercase();
+        let hash = word.bytes().fold(0xcbf29ce484222325_u64, |hash, byte| {
+            (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+        });
+        vector[(hash % dimensions as u64) as usize] += 1.0;
+    }
+
+    let norm = vector.iter().map(|v| v * v).sum::<f32>().sqrt();
+    if norm > 0.0 {
+        vector.iter_mut().for_each(|v| *v /= norm);
+    }
+    vector
+}
+
+#[cfg(test)]
+mod test {
+    use bytes::Bytes;
+    use futures_util::StreamExt;
+
+    use super::{FakeCloudflare, Reply};
+    use crate::models::{
+        text_embeddings::{TextEmbeddings, TextEmbeddingsRequest},
+        text_generation::{
+            MessageRequest, StreamEvent, TextGeneration,
+            TextGenerationJsonResult, TextGenerationRequest,
+            TextGenerationResponse,
+        },
+        text_to_image::{TextToImage, TextToImageRequest},
+    };
+
+    fn result(response: &str) -> TextGenerationJsonResult {
+        TextGenerationJsonResult {
+            response: Some(response.to_string()),
+            tool_calls: None,
+            usage: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_text_generation() {
+        // Arrange
+        let fake = FakeCloudflare::start().await.unwrap();
+        fake.reply("@cf/model", Reply::Generation(result("first")))
+            .reply("@cf/model", Reply::Generation(result("second")));
+        let models = fake.models();
+        let request =
+            || TextGenerationRequest::Message(MessageRequest::default());
+
+        // Act
+        let first = models.text_generation("@cf/model", request()).await;
+        let second = models.text_generation("@cf/model", request()).await;
+        let repeated = models.text_generation("@cf/model", request()).await;
+        let missing = models.text_generation("@cf/missing", request()).await;
+
+        // Assert
+        let response = |r: anyhow::Result<TextGenerationResponse>| {
+            r.unwrap().result.response
+        };
+        assert_eq!(response(first), Some("first".to_string()));
+        assert_eq!(response(second), Some("second".to_string()));
+        assert_eq!(response(repeated), Some("second".to_string()));
+        assert!(missing.is_err());
+        let requests = fake.requests();
+        assert_eq!(requests.len(), 4);
+        assert_eq!(requests[0].model, "@cf/model");
+        assert_eq!(requests[3].model, "@cf/missing");
+    }
+
+    #[tokio::test]
+    async fn test_text_generation_with_stream() {
+        // Arrange
+        let fake = FakeCloudflare::start().await.unwrap();
+        fake.reply(
+            "@cf/model",
+            Reply::Stream(vec![result("Hel"), result("lo")]),
+        );
+
+        // Act
+        let stream = fake.models().text_generation_with_stream(
+            "@cf/model".to_string(),
+            TextGenerationRequest::Message(MessageRequest {
+                stream: Some(true),
+                ..Default::default()
+            }),
+        );
+        let events = Box::pin(stream).collect::<Vec<_>>().await;
+
+        // Assert
+        let events = events
+            .into_iter()
+            .map(|event| event.unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            events,
+            vec![
+                StreamEvent::Delta("Hel".to_string()),
+                StreamEvent::Delta("lo".to_string()),
+            ]
+        );
+        assert_eq!(fake.requests()[0].body["stream"], true);
+    }
+
+    #[tokio::test]
+    async fn test_text_embeddings() {
+        // Arrange
+        let fake = FakeCloudflare::start().await.unwrap();
+        fake.reply("@cf/bge", Reply::Embeddings { dimensions: 8 });
+        let request = TextEmbeddingsRequest {
+            text: vec!["rust axum", "rust axum", ""].into(),
+        };
+
+        // Act
+        let response = fake
+            .models()
+            .text_embeddings("@cf/bge", request)
+            .await
+            .unwrap();
+
+        // Assert
+        assert_eq!(response.result.shape, vec![3.0, 8.0]);
+        assert_eq!(response.result.data[0], response.result.data[1]);
+        let norm = response.result.data[0].iter().map(|v| v * v).sum::<f32>();
+        assert!((norm - 1.0).abs() < 1e-6);
+        assert_eq!(response.result.data[2], vec![0.0; 8]);
+    }
+
+    #[tokio::test]
+    async fn test_text_to_image() {
+        // Arrange
+        let fake = FakeCloudflare::start().await.unwrap();
+        fake.reply("@cf/image", Reply::Image(Bytes::from_static(b"png")))
+            .reply(
+                "@cf/failing",
+                Reply::Error {
+                    status: 400,
+                    message: "Invalid input".to_string(),
+                    retry_after: None,
+                },
+            );
+        let request = || TextToImageRequest {
+            prompt: "a cat".to_string(),
+            ..Default::default()
+        };
+
+        // Act
+        let image = fake.models().text_to_image("@cf/image", request()).await;
+        let failing =
+            fake.models().text_to_image("@cf/failing", request()).await;
+
+        // Assert
+        assert_eq!(image.unwrap(), Bytes::from_static(b"png"));
+        assert!(failing.unwrap_err().to_string().contains("400"));
+        assert_eq!(fake.requests()[0].body["prompt"], "a cat");
+    }
+}
@@ -1 +1,6 @@
+pub mod config;
+pub mod error;
+#[cfg(any(test, feature = "fake"))]
+pub mod fake;
 pub mod models;
+pub mod sse;
@@ -1,8 +1,17 @@
-use anyhow::ensure;
+use std::sync::Arc;
+
+use anyhow::Context;
 use bytes::Bytes;
+use futures_util::StreamExt;
 use reqwest::{
     header::{HeaderMap, HeaderValue},
-    Body, Client,
+    Body, Client, Response,
+};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+use crate::{
+    config::ClientConfig,
+    error::{CloudflareError, Envelope},
 };
 
 pub mod text_embeddings;
@@ -14,14 +23,25 @@ pub mod translation;
 pub struct Models {
     base_url: String,
     client: Client,
+    config: Arc<ClientConfig>,
+    limiter: Arc<Semaphore>,
 }
 
 impl Models {
     pub fn new(account_id: &str, token: &str) -> Self {
-        let base_url = format!(
-            "https://api.cloudflare.com/client/v4/accounts/{}/ai/run",
-            account_id
-        );
+        Self::with_base_url(
+            &format!(
+                "https://api.cloudflare.com/client/v4/accounts/{}/ai/run",
+                account_id
+            ),
+            token,
+        )
+    }
+
+    // Runs models under `base_url` instead of the Cloudflare API, e.g. a
+    // gateway or a fake server in tests.
+    pub fn with_base_url(base_url: &str, token: &str) -> Self {
+        let base_url = base_url.trim_end_matches('/').to_string();
         let mut headers = HeaderMap::new();
         headers.insert("Accept", HeaderValue::from_str("*/*").unwrap());
         headers.insert(
@@ -35,7 +55,75 @@ impl Models {
             .build()
             .unwrap();
 
-        Self { base_url, client }
+        let config = ClientConfig::default();
+        Self {
+            base_url,
+            client,
+            limiter: Arc::new(Semaphore::new(config.max_concurrency)),
+            config: Arc::new(config),
+        }
+    }
+
+    pub fn with_config(mut self, config: ClientConfig) -> Self {
+        self.limiter = Arc::new(Semaphore::new(config.max_concurrency.max(1)));
+        self.config = Arc::new(config);
+        self
+    }
+
+    // Posts `request` to `model` and retries rate limits, server errors and
+    // timeouts. The response comes with the permit of the limiter, which is
+    // to be held until the body is read.
+    async fn send<R: Into<Body>>(
+        &self,
+        request: R,
+        model: &str,
+    ) -> anyhow::Result<(Response, OwnedSemaphorePermit)> {
+        let body: Body = request.into();
+        let body = Bytes::copy_from_slice(
+            body.as_bytes().context("failed to buffer request")?,
+        );
+        let timeout = self.config.timeout(model);
+
+        let mut attempt = 0;
+        loop {
+            let permit = self
+                .limiter
+                .clone()
+                .acquire_owned()
+                .await
+                .context("failed to acquire permit")?;
+            let request = self
+                .client
+                .post(format!("{}/{}", self.base_url, model))
+                .body(body.clone())
+                .send();
+
+            let error = match tokio::time::timeout(timeout, request).await {
+                Ok(Ok(response)) if response.status().is_success() => {
+                    return Ok((response, permit));
+                }
+                Ok(Ok(response)) => {
+                    CloudflareError::from_response(response).await
+                }
+                Ok(Err(e)) => CloudflareError::Request(e),
+                Err(_) => CloudflareError::Timeout(timeout),
+            };
+            drop(permit);
+
+            if attempt >= self.config.max_retries || !error.is_retryable() {
+                return Err(error.into());
+            }
+            // A wait longer than any backoff isn't worth holding the caller
+            let delay = match error.retry_after() {
+                Some(delay) if delay > self.config.retry_max_delay => {
+                    return Err(error.into());
+                }
+                Some(delay) => delay,
+                None => self.config.backoff(attempt),
+            };
+            tokio::time::sleep(delay).await;
+            attempt += 1;
+        }
     }
 
     async fn string_response<R: Into<Body>>(
@@ -43,24 +131,27 @@ impl Models {
         request: R,
         model: &str,
     ) -> anyhow::Result<String> {
-        let response = self
-            .client
-            .post(format!("{}/{}", self.base_url, model))
-            .body(request)
-            .send()
-            .await?;
-
-        let status_code = response.status();
-        let text = response.text().await;
-
-        ensure!(
-            status_code.is_success(),
-            "status code: {}, response: {:?}",
-            status_code,
-            text
-        );
+        let (response, _permit) = self.send(request, model).await?;
+        let status = response.status();
+        let timeout = self.config.timeout(model);
+        let text = tokio::time::timeout(timeout, response.text())
+            .await
+            .map_err(|_| CloudflareError::Timeout(timeout))?
+            .map_err(CloudflareError::Request)?;
+
+        // The API may answer 200 with `success: false`
+        if let Ok(envelope) = serde_json::from_str::<Envelope>(&text) {
+            if !envelope.success {
+                return Err(CloudflareError::Api {
+                    status,
+                    errors: envelope.errors,
+                    retry_after: None,
+                }
+                .into());
+            }
+        }
 
-        Ok(text?)
+        Ok(text)
     }
 
     async fn binary_response<R: Into<Body>>(
@@ -68,21 +159,17 @@ impl Models {
         request: R,
         model: &str,
     ) -> anyhow::Result<Bytes> {
-        let response = self
-            .client
-            .post(format!("{}/{}", self.base_url, model))
-            .body(request)
-            .send()
-            .await?;
-
-        let status_code = response.status();
-        let bytes = response.bytes().await;
+        let (response, _permit) = self.send(request, model).await?;
+        let timeout = self.config.timeout(model);
+        let bytes = tokio::time::timeout(timeout, response.bytes())
+            .await
+            .map_err(|_| CloudflareError::Timeout(timeout))?
+            .map_err(CloudflareError::Request)?;
 
-        ensure!(status_code.is_success(), "status code: {}", status_code);
-
-        Ok(bytes?)
+        Ok(bytes)
     }
 
+    // The timeout covers waiting for the stream to start, not reading it.
     async fn stream_response<R: Into<Body>>(
         &self,
         request: R,
@@ -90,18 +177,209 @@ impl Models {
     ) -> anyhow::Result<
         impl futures_core::Stream<Item = Result<Bytes, reqwest::Error>>,
     > {
-        let response = self
-            .client
-            .post(format!("{}/{}", self.base_url, model))
-            .body(request)
-            .send()
-            .await?;
+        let (response, permit) = self.send(request, model).await?;
+
+        Ok(response.bytes_stream().map(move |bytes| {
+            let _permit = &permit;
+            bytes
+        }))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::{Duration, Instant};
+
+    use super::Models;
+    use crate::{
+        config::ClientConfig,
+        error::CloudflareError,
+        fake::{FakeCloudflare, Reply},
+        models::text_generation::{
+            MessageRequest, TextGeneration, TextGenerationJsonResult,
+            TextGenerationRequest,
+        },
+    };
 
-        let status_code = response.status();
-        let stream = response.bytes_stream();
+    fn models(fake: &FakeCloudflare, config: ClientConfig) -> Models {
+        fake.models().with_config(ClientConfig {
+            retry_base_delay: Duration::from_millis(1),
+            ..config
+        })
+    }
+
+    fn request() -> TextGenerationRequest {
+        TextGenerationRequest::Message(MessageRequest::default())
+    }
+
+    fn generation() -> Reply {
+        Reply::Generation(TextGenerationJsonResult {
+            response: Some("ok".to_string()),
+            tool_calls: None,
+            usage: None,
+        })
+    }
+
+    fn error(status: u16, retry_after: Option<u64>) -> Reply {
+        Reply::Error {
+            status,
+            message: "failed".to_string(),
+            retry_after,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_retry() {
+        // Arrange
+        let fake = FakeCloudflare::start().await.unwrap();
+        fake.reply("@cf/retried", error(429, Some(0)))
+            .reply("@cf/retried", error(503, None))
+            .reply("@cf/retried", generation())
+            .reply("@cf/invalid", error(400, None))
+            .reply("@cf/down", error(500, None));
+        let models = models(
+            &fake,
+            ClientConfig {
+                max_retries: 2,
+                ..Default::default()
+            },
+        );
+
+        // Act
+        let retried = models.text_generation("@cf/retried", request()).await;
+        let invalid = models.text_generation("@cf/invalid", request()).await;
+        let down = models.text_generation("@cf/down", request()).await;
+
+        // Assert
+        assert_eq!(retried.unwrap().result.response, Some("ok".to_string()));
+        let invalid = invalid.unwrap_err();
+        let Some(CloudflareError::Api { status, errors, .. }) =
+            invalid.downcast_ref::<CloudflareError>()
+        else {
+            panic!("expected an api error: {}", invalid);
+        };
+        assert_eq!(status.as_u16(), 400);
+        assert_eq!(errors[0].message, "failed");
+        let down = down.unwrap_err().downcast::<CloudflareError>().unwrap();
+        assert_eq!(down.status().map(|s| s.as_u16()), Some(500));
+        let count = |model: &str| {
+            fake.requests().iter().filter(|r| r.model == model).count()
+        };
+        assert_eq!(count("@cf/retried"), 3);
+        assert_eq!(count("@cf/invalid"), 1);
+        assert_eq!(count("@cf/down"), 3);
+    }
+
+    #[tokio::test]
+    async fn test_retry_after() {
+        // Arrange
+        let fake = FakeCloudflare::start().await.unwrap();
+        fake.reply("@cf/limited", error(429, Some(1)))
+            .reply("@cf/limited", generation())
+            .reply("@cf/blocked", error(429, Some(3600)));
+        let models = models(
+            &fake,
+            ClientConfig {
+                max_retries: 2,
+                retry_max_delay: Duration::from_secs(2),
+                ..Default::default()
+            },
+        );
+
+        // Act
+        let started = Instant::now();
+        let limited = models.text_generation("@cf/limited", request()).await;
+        let limited_elapsed = started.elapsed();
+        let started = Instant::now();
+        let blocked = models.text_generation("@cf/blocked", request()).await;
+        let blocked_elapsed = started.elapsed();
+
+        // Assert
+        assert!(limited.is_ok());
+        assert!(limited_elapsed >= Duration::from_secs(1));
+        let blocked =
+            blocked.unwrap_err().downcast::<CloudflareError>().unwrap();
+        assert_eq!(blocked.status().map(|s| s.as_u16()), Some(429));
+        assert!(blocked_elapsed < Duration::from_secs(1));
+        let count = |model: &str| {
+            fake.requests().iter().filter(|r| r.model == model).count()
+        };
+        assert_eq!(count("@cf/limited"), 2);
+        assert_eq!(count("@cf/blocked"), 1);
+    }
+
+    #[tokio::test]
+    async fn test_timeout() {
+        // Arrange
+        let fake = FakeCloudflare::start().await.unwrap();
+        let slow = Reply::Delay {
+            delay: Duration::from_secs(5),
+            reply: Box::new(generation()),
+        };
+        fake.reply("@cf/slow", slow.clone()).reply(
+            "@cf/patient",
+            Reply::Delay {
+                delay: Duration::from_millis(200),
+                reply: Box::new(generation()),
+            },
+        );
+        let mut config = ClientConfig {
+            timeout: Duration::from_millis(50),
+            max_retries: 0,
+            ..Default::default()
+        };
+        config
+            .timeouts
+            .insert("@cf/patient".to_string(), Duration::from_secs(5));
+        let models = models(&fake, config);
+
+        // Act
+        let started = Instant::now();
+        let slow = models.text_generation("@cf/slow", request()).await;
+        let elapsed = started.elapsed();
+        let patient = models.text_generation("@cf/patient", request()).await;
+
+        // Assert
+        let slow = slow.unwrap_err().downcast::<CloudflareError>().unwrap();
+        assert!(matches!(slow, CloudflareError::Timeout(_)));
+        assert!(elapsed < Duration::from_secs(1));
+        assert!(patient.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_concurrency_limit() {
+        // Arrange
+        let fake = FakeCloudflare::start().await.unwrap();
+        fake.reply(
+            "@cf/model",
+            Reply::Delay {
+                delay: Duration::from_millis(50),
+                reply: Box::new(generation()),
+            },
+        );
+        let models = models(
+            &fake,
+            ClientConfig {
+                max_concurrency: 2,
+                ..Default::default()
+            },
+        );
 
-        ensure!(status_code.is_success(), "status code: {}", status_code);
+        // Act
+        let results =
+            futures_util::future::join_all(
+                (0..6).map(|_| {
+                    let models = models.clone();
+                    async move {
+                        models.text_generation("@cf/model", request()).await
+                    }
+                }),
+            )
+            .await;
 
-        Ok(stream)
+        // Assert
+        assert!(results.iter().all(|result| result.is_ok()));
+        assert_eq!(fake.requests().len(), 6);
+        assert_eq!(fake.max_in_flight(), 2);
     }
 }
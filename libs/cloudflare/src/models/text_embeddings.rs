@@ -3,11 +3,21 @@ pub mod implementation;
 use reqwest::Body;
 use serde::{Deserialize, Serialize};
 
-static BGE_BASE_EN_V1_5: &str = "@cf/baai/bge-base-en-v1.5";
-static BGE_LARGE_EN_V1_5: &str = "@cf/baai/bge-large-en-v1.5";
-static BGE_SMALL_EN_V1_5: &str = "@cf/baai/bge-small-en-v1.5";
+pub static BGE_BASE_EN_V1_5: &str = "@cf/baai/bge-base-en-v1.5";
+pub static BGE_LARGE_EN_V1_5: &str = "@cf/baai/bge-large-en-v1.5";
+pub static BGE_SMALL_EN_V1_5: &str = "@cf/baai/bge-small-en-v1.5";
+
+// The most texts the models embed in one request
+pub const MAX_BATCH_SIZE: usize = 100;
 
 pub trait TextEmbeddings {
+    // Runs any text embeddings model by its name
+    fn text_embeddings(
+        &self,
+        model: &str,
+        request: TextEmbeddingsRequest,
+    ) -> impl std::future::Future<Output = anyhow::Result<TextEmbeddingsResponse>>
+           + Send;
     fn bge_base_en_v1_5(
         &self,
         request: TextEmbeddingsRequest,
@@ -61,6 +71,12 @@ impl From<&str> for StringOrArray {
     }
 }
 
+impl From<Vec<String>> for StringOrArray {
+    fn from(value: Vec<String>) -> Self {
+        Self::Array(value)
+    }
+}
+
 impl From<Vec<&str>> for StringOrArray {
     fn from(value: Vec<&str>) -> Self {
         Self::Array(value.iter().map(|s| s.to_string()).collect())
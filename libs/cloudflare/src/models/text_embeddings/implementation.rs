@@ -8,11 +8,12 @@ use crate::models::{
 use super::{TextEmbeddings, TextEmbeddingsRequest, TextEmbeddingsResponse};
 
 impl TextEmbeddings for Models {
-    async fn bge_base_en_v1_5(
+    async fn text_embeddings(
         &self,
+        model: &str,
         request: TextEmbeddingsRequest,
     ) -> anyhow::Result<TextEmbeddingsResponse> {
-        let text = self.string_response(request, BGE_BASE_EN_V1_5).await?;
+        let text = self.string_response(request, model).await?;
 
         let response =
             serde_json::from_str(&text).context("failed to parse response")?;
@@ -20,27 +21,24 @@ impl TextEmbeddings for Models {
         Ok(response)
     }
 
-    async fn bge_large_en_v1_5(
+    async fn bge_base_en_v1_5(
         &self,
         request: TextEmbeddingsRequest,
     ) -> anyhow::Result<TextEmbeddingsResponse> {
-        let text = self.string_response(request, BGE_LARGE_EN_V1_5).await?;
-
-        let response =
-            serde_json::from_str(&text).context("failed to parse response")?;
+        self.text_embeddings(BGE_BASE_EN_V1_5, request).await
+    }
 
-        Ok(response)
+    async fn bge_large_en_v1_5(
+        &self,
+        request: TextEmbeddingsRequest,
+    ) -> anyhow::Result<TextEmbeddingsResponse> {
+        self.text_embeddings(BGE_LARGE_EN_V1_5, request).await
     }
 
     async fn bge_small_en_v1_5(
         &self,
         request: TextEmbeddingsRequest,
     ) -> anyhow::Result<TextEmbeddingsResponse> {
-        let text = self.string_response(request, BGE_SMALL_EN_V1_5).await?;
-
-        let response =
-            serde_json::from_str(&text).context("failed to parse response")?;
-
-        Ok(response)
+        self.text_embeddings(BGE_SMALL_EN_V1_5, request).await
     }
 }
@@ -11,6 +11,22 @@ pub static HERMES_2_PRO_MISTRAL_7B: &str =
     "@hf/nousresearch/hermes-2-pro-mistral-7b";
 
 pub trait TextGeneration {
+    // Runs any text generation model by its name
+    fn text_generation(
+        &self,
+        model: &str,
+        request: TextGenerationRequest,
+    ) -> impl std::future::Future<Output = anyhow::Result<TextGenerationResponse>>
+           + Send;
+
+    // Streams the tokens of the response, then the usage when the model
+    // reports it
+    fn text_generation_with_stream(
+        self,
+        model: String,
+        request: TextGenerationRequest,
+    ) -> impl Stream<Item = anyhow::Result<StreamEvent>> + Send;
+
     fn llama_3_8b_instruct(
         &self,
         request: TextGenerationRequest,
@@ -20,7 +36,7 @@ pub trait TextGeneration {
     fn llama_3_8b_instruct_with_stream(
         self,
         request: TextGenerationRequest,
-    ) -> impl Stream<Item = anyhow::Result<Vec<TextGenerationJsonResult>>> + Send;
+    ) -> impl Stream<Item = anyhow::Result<StreamEvent>> + Send;
 
     fn hermes_2_pro_mistral_7b(
         &self,
@@ -52,11 +68,13 @@ pub struct MessageRequest {
     pub messages: Vec<Message>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub stream: Option<bool>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub tools: Vec<Tool>,
     #[serde(flatten, skip_serializing_if = "Option::is_none")]
     pub model_parameters: Option<ModelParameters>,
 }
 
-#[derive(Clone, Debug, Serialize, Deserialize, Default)]
+#[derive(Clone, Debug, Serialize, Deserialize, Default, PartialEq)]
 pub struct Message {
     pub role: String,
     pub content: String,
@@ -71,6 +89,38 @@ pub struct TextGenerationResponse {
 pub struct TextGenerationJsonResult {
     pub response: Option<String>,
     pub tool_calls: Option<Vec<ToolCall>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub usage: Option<Usage>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum StreamEvent {
+    Delta(String),
+    Usage(Usage),
+}
+
+impl TextGenerationJsonResult {
+    // A streamed frame holds a token, the usage or both
+    pub fn into_events(self) -> Vec<StreamEvent> {
+        let mut events = vec![];
+        if let Some(response) = self.response.filter(|r| !r.is_empty()) {
+            events.push(StreamEvent::Delta(response));
+        }
+        if let Some(usage) = self.usage {
+            events.push(StreamEvent::Usage(usage));
+        }
+        events
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Default, PartialEq)]
+pub struct Usage {
+    #[serde(default)]
+    pub prompt_tokens: i32,
+    #[serde(default)]
+    pub completion_tokens: i32,
+    #[serde(default)]
+    pub total_tokens: i32,
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize, Default)]
@@ -79,13 +129,13 @@ pub struct ToolCall {
     pub arguments: Option<HashMap<String, Option<String>>>,
 }
 
-#[derive(Serialize, Default)]
+#[derive(Debug, Serialize, Default)]
 pub struct Tool {
     pub r#type: String,
     pub function: Function,
 }
 
-#[derive(Serialize, Default)]
+#[derive(Debug, Serialize, Default)]
 pub struct Function {
     pub name: String,
     pub description: String,
@@ -93,7 +143,7 @@ pub struct Function {
     pub parameters: Option<Parameters>,
 }
 
-#[derive(Serialize, Default)]
+#[derive(Debug, Serialize, Default)]
 pub struct Parameters {
     pub r#type: String,
     pub properties: HashMap<String, PropertyType>,
@@ -118,7 +168,7 @@ pub struct ModelParameters {
     pub presence_penalty: Option<i32>, // from 0 to 2
 }
 
-#[derive(Serialize)]
+#[derive(Debug, Serialize)]
 #[serde(tag = "type", rename_all = "camelCase")]
 pub enum PropertyType {
     String,
@@ -1,49 +1,73 @@
-use async_stream::stream;
+use anyhow::Context;
+use async_stream::try_stream;
 use futures_core::Stream;
 
-use crate::models::{text_generation::LLAMA_3_8B_INSTRUCT, Models};
+use crate::{
+    models::{text_generation::LLAMA_3_8B_INSTRUCT, Models},
+    sse::data_stream,
+};
 use futures_util::StreamExt;
 
 use super::{
-    TextGeneration, TextGenerationJsonResult, HERMES_2_PRO_MISTRAL_7B,
+    StreamEvent, TextGeneration, TextGenerationJsonResult,
+    HERMES_2_PRO_MISTRAL_7B,
 };
 
 impl TextGeneration for Models {
-    async fn llama_3_8b_instruct(
+    async fn text_generation(
         &self,
+        model: &str,
         request: super::TextGenerationRequest,
     ) -> anyhow::Result<super::TextGenerationResponse> {
-        let text = self.string_response(request, LLAMA_3_8B_INSTRUCT).await?;
+        let text = self.string_response(request, model).await?;
 
         let response = serde_json::from_str(&text)?;
 
         Ok(response)
     }
 
-    fn llama_3_8b_instruct_with_stream(
+    fn text_generation_with_stream(
         self,
+        model: String,
         request: super::TextGenerationRequest,
-    ) -> impl Stream<Item = anyhow::Result<Vec<TextGenerationJsonResult>>> {
-        stream! {
-        let mut stream =
-        self.stream_response(request, LLAMA_3_8B_INSTRUCT).await?;
-                while let Some(s) = stream.next().await.transpose()? {
-                    let data:Vec<TextGenerationJsonResult> = String::from_utf8(s.to_vec())?.split("data: ").flat_map(serde_json::from_str).collect();
-                    yield Ok(data);
+    ) -> impl Stream<Item = anyhow::Result<StreamEvent>> {
+        try_stream! {
+            let stream = self.stream_response(request, &model).await?;
+            let mut stream = std::pin::pin!(data_stream(stream));
+            while let Some(data) = stream.next().await.transpose()? {
+                let result =
+                    serde_json::from_str::<TextGenerationJsonResult>(&data)
+                        .with_context(|| {
+                            format!("failed to parse event: {}", data)
+                        })?;
+                for event in result.into_events() {
+                    yield event;
                 }
             }
+        }
     }
 
-    async fn hermes_2_pro_mistral_7b(
+    async fn llama_3_8b_instruct(
         &self,
         request: super::TextGenerationRequest,
     ) -> anyhow::Result<super::TextGenerationResponse> {
-        let text = self
-            .string_response(request, HERMES_2_PRO_MISTRAL_7B)
-            .await?;
+        self.text_generation(LLAMA_3_8B_INSTRUCT, request).await
+    }
 
-        let response = serde_json::from_str(&text)?;
+    fn llama_3_8b_instruct_with_stream(
+        self,
+        request: super::TextGenerationRequest,
+    ) -> impl Stream<Item = anyhow::Result<StreamEvent>> {
+        self.text_generation_with_stream(
+            LLAMA_3_8B_INSTRUCT.to_string(),
+            request,
+        )
+    }
 
-        Ok(response)
+    async fn hermes_2_pro_mistral_7b(
+        &self,
+        request: super::TextGenerationRequest,
+    ) -> anyhow::Result<super::TextGenerationResponse> {
+        self.text_generation(HERMES_2_PRO_MISTRAL_7B, request).await
     }
 }
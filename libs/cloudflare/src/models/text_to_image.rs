@@ -4,10 +4,17 @@ use bytes::Bytes;
 use reqwest::Body;
 use serde::Serialize;
 
-static STABLE_DIFFUSION_XL_LIGHTNING: &str =
+pub static STABLE_DIFFUSION_XL_LIGHTNING: &str =
     "@cf/bytedance/stable-diffusion-xl-lightning";
 
 pub trait TextToImage {
+    // Runs any text to image model by its name
+    fn text_to_image(
+        &self,
+        model: &str,
+        request: TextToImageRequest,
+    ) -> impl std::future::Future<Output = anyhow::Result<Bytes>> + Send;
+
     fn stable_diffusion_xl_lightning(
         &self,
         request: TextToImageRequest,
@@ -5,14 +5,21 @@ use crate::models::Models;
 use super::{TextToImage, STABLE_DIFFUSION_XL_LIGHTNING};
 
 impl TextToImage for Models {
-    async fn stable_diffusion_xl_lightning(
+    async fn text_to_image(
         &self,
+        model: &str,
         request: super::TextToImageRequest,
     ) -> anyhow::Result<Bytes> {
-        let bytes = self
-            .binary_response(request, STABLE_DIFFUSION_XL_LIGHTNING)
-            .await?;
+        let bytes = self.binary_response(request, model).await?;
 
         Ok(bytes)
     }
+
+    async fn stable_diffusion_xl_lightning(
+        &self,
+        request: super::TextToImageRequest,
+    ) -> anyhow::Result<Bytes> {
+        self.text_to_image(STABLE_DIFFUSION_XL_LIGHTNING, request)
+            .await
+    }
 }
@@ -3,7 +3,7 @@ pub mod implementation;
 use reqwest::Body;
 use serde::{Deserialize, Serialize};
 
-static M2M100_1_2B: &str = "@cf/meta/m2m100-1.2b";
+pub static M2M100_1_2B: &str = "@cf/meta/m2m100-1.2b";
 
 pub trait Translation {
     fn m2m100_1_2b(
@@ -0,0 +1,249 @@
+//! Incremental decoding of server-sent events that arrive in chunks of any
+//! size.
+//!
+//! Bytes are buffered until a line is complete, so events split across
+//! chunks and multi-byte characters split at chunk edges decode the same as
+//! a stream read in one piece.
+
+use async_stream::try_stream;
+use bytes::Bytes;
+use futures_core::Stream;
+use futures_util::StreamExt;
+
+pub const DONE: &str = "[DONE]";
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Frame {
+    Data(String),
+    // The `[DONE]` sentinel ending a stream of model output
+    Done,
+}
+
+#[derive(Debug, Default)]
+pub struct Decoder {
+    buffer: Vec<u8>,
+    data: Vec<String>,
+    done: bool,
+}
+
+impl Decoder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn is_done(&self) -> bool {
+        self.done
+    }
+
+    /// Returns the events completed by `chunk`. Anything after `[DONE]` is
+    /// ignored.
+    pub fn decode(&mut self, chunk: &[u8]) -> Vec<Frame> {
+        if self.done {
+            return vec![];
+        }
+        self.buffer.extend_from_slice(chunk);
+
+        let mut frames = vec![];
+        let mut start = 0;
+        while let Some(end) =
+            self.buffer[start..].iter().position(|b| *b == b'\n')
+        {
+            let line = &self.buffer[start..start + end];
+            let line = line.strip_suffix(b"\r").unwrap_or(line);
+            // A line is complete here, so it can't end inside a character
+            let line = String::from_utf8_lossy(line).into_owned();
+            start += end + 1;
+
+            if let Some(frame) = self.line(&line) {
+                frames.push(frame);
+                if self.done {
+                    self.buffer.clear();
+                    return frames;
+                }
+            }
+        }
+        self.buffer.drain(..start);
+
+        frames
+    }
+
+    /// Returns the last event when the stream ends without a blank line
+    /// after it.
+    pub fn finish(&mut self) -> Vec<Frame> {
+        if self.done {
+            return vec![];
+        }
+        let line = String::from_utf8_lossy(&std::mem::take(&mut self.buffer))
+            .trim_end_matches('\r')
+            .to_string();
+
+        let mut frames = vec![];
+        if !line.is_empty() {
+            frames.extend(self.line(&line));
+        }
+        frames.extend(self.line(""));
+        frames
+    }
+
+    fn line(&mut self, line: &str) -> Option<Frame> {
+        if line.is_empty() {
+            return self.dispatch();
+        }
+        // Comments and fields other than `data` such as `event` and `id`
+        // carry nothing for model output
+        let value = line.strip_prefix("data")?;
+        let value = match value.strip_prefix(':') {
+            Some(value) => value.strip_prefix(' ').unwrap_or(value),
+            None if value.is_empty() => "",
+            None => return None,
+        };
+        self.data.push(value.to_string());
+
+        None
+    }
+
+    fn dispatch(&mut self) -> Option<Frame> {
+        if self.data.is_empty() {
+            return None;
+        }
+        let data = std::mem::take(&mut self.data).join("\n");
+        if data.trim() == DONE {
+            self.done = true;
+            return Some(Frame::Done);
+        }
+
+        Some(Frame::Data(data))
+    }
+}
+
+/// Decodes a body read in chunks into the data of its events, ending at
+/// `[DONE]` or with the body.
+pub fn data_stream<S, E>(
+    stream: S,
+) -> impl Stream<Item = anyhow::Result<String>>
+where
+    S: Stream<Item = Result<Bytes, E>>,
+    E: std::error::Error + Send + Sync + 'static,
+{
+    try_stream! {
+        let mut stream = std::pin::pin!(stream);
+        let mut decoder = Decoder::new();
+        let mut ended = false;
+        while !ended && !decoder.is_done() {
+            let frames = match stream.next().await.transpose()? {
+                Some(bytes) => decoder.decode(&bytes),
+                None => {
+                    ended = true;
+                    decoder.finish()
+                }
+            };
+            for frame in frames {
+                if let Frame::Data(data) = frame {
+                    yield data;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use proptest::prelude::*;
+
+    use super::{Decoder, Frame};
+
+    fn data(data: &str) -> Frame {
+        Frame::Data(data.to_string())
+    }
+
+    #[test]
+    fn test_decode() {
+        // Arrange
+        let mut decoder = Decoder::new();
+
+        // Act
+        let first = decoder.decode(b": keep-alive\n\ndata: {\"a\":");
+        let second = decoder.decode(b"1}\r\n\r\nevent: message\ndata: x\n");
+        let third = decoder.decode(b"data:y\n\ndata: [DONE]\n\ndata: z\n\n");
+        let after_done = decoder.decode(b"data: z\n\n");
+
+        // Assert
+        assert_eq!(first, vec![]);
+        assert_eq!(second, vec![data("{\"a\":1}")]);
+        assert_eq!(third, vec![data("x\ny"), Frame::Done]);
+        assert_eq!(after_done, vec![]);
+        assert!(decoder.is_done());
+    }
+
+    #[test]
+    fn test_split_character() {
+        // Arrange
+        let mut decoder = Decoder::new();
+        let bytes = "data: こんにちは\n\n".as_bytes();
+
+        // Act
+        let mut frames = decoder.decode(&bytes[..8]);
+        frames.extend(decoder.decode(&bytes[8..]));
+
+        // Assert
+        assert_eq!(frames, vec![data("こんにちは")]);
+    }
+
+    #[test]
+    fn test_finish() {
+        // Arrange
+        let mut decoder = Decoder::new();
+
+        // Act
+        let frames = decoder.decode(b"data: first\n\ndata: last");
+        let rest = decoder.finish();
+
+        // Assert
+        assert_eq!(frames, vec![data("first")]);
+        assert_eq!(rest, vec![data("last")]);
+        assert!(!decoder.is_done());
+    }
+
+    proptest! {
+        #[test]
+        fn test_any_chunk_boundaries(
+            payloads in prop::collection::vec(
+                "[^\r\n]+".prop_filter("not done", |p| p.trim() != "[DONE]"),
+                0..8,
+            ),
+            crlf in any::<bool>(),
+            cuts in prop::collection::vec(any::<prop::sample::Index>(), 0..16),
+        ) {
+            // Arrange
+            let newline = if crlf { "\r\n" } else { "\n" };
+            let mut stream = String::new();
+            for payload in &payloads {
+                stream.push_str(&format!("data: {}{}{}", payload, newline, newline));
+            }
+            stream.push_str(&format!("data: [DONE]{}{}", newline, newline));
+            let bytes = stream.as_bytes();
+            let mut cuts = cuts
+                .iter()
+                .map(|cut| cut.index(bytes.len() + 1))
+                .collect::<Vec<_>>();
+            cuts.extend([0, bytes.len()]);
+            cuts.sort_unstable();
+
+            // Act
+            let mut decoder = Decoder::new();
+            let mut frames = vec![];
+            for window in cuts.windows(2) {
+                frames.extend(decoder.decode(&bytes[window[0]..window[1]]));
+            }
+            frames.extend(decoder.finish());
+
+            // Assert
+            let mut expected = payloads
+                .iter()
+                .map(|payload| Frame::Data(payload.to_string()))
+                .collect::<Vec<_>>();
+            expected.push(Frame::Done);
+            prop_assert_eq!(frames, expected);
+        }
+    }
+}
@@ -0,0 +1,7 @@
+use chrono::{DateTime, Utc};
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Cursor {
+    pub created_at: DateTime<Utc>,
+    pub id: String,
+}
@@ -0,0 +1,14 @@
+use chrono::NaiveDateTime;
+
+#[derive(Debug, Default, PartialEq, Clone)]
+pub struct Feedback {
+    pub id: i32,
+    pub prompt_id: i32,
+    pub user_id: i32,
+    pub trace_id: String,
+    pub name: String,
+    pub value: f64,
+    pub comment: Option<String>,
+    pub created_at: NaiveDateTime,
+    pub updated_at: NaiveDateTime,
+}
@@ -1,6 +1,8 @@
 pub mod block;
+pub mod cursor;
 pub mod document_type;
 pub mod event;
+pub mod feedback;
 pub mod notion_database;
 pub mod nudge;
 pub mod page;
@@ -8,6 +10,7 @@ pub mod post;
 pub mod prelude;
 pub mod prompt;
 pub mod prompt_session;
+pub mod search;
 pub mod static_page;
 pub mod top;
 pub mod user;
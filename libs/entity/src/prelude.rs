@@ -1,11 +1,13 @@
 pub use super::block::Block as BlockEntity;
 pub use super::document_type::DocumentType as DocumentTypeEntity;
 pub use super::event::Event as EventEntity;
+pub use super::feedback::Feedback as FeedbackEntity;
 pub use super::notion_database::NotionDatabase as NotionDatabaseEntity;
 pub use super::nudge::Nudge as NudgeEntity;
 pub use super::page::Page as PageEntity;
 pub use super::post::Post as PostEntity;
 pub use super::prompt::Prompt as PromptEntity;
 pub use super::prompt_session::PromptSession as PromptSessionEntity;
+pub use super::search::SearchHit as SearchHitEntity;
 pub use super::static_page::StaticPage as StaticPageEntity;
 pub use super::user::User as UserEntity;
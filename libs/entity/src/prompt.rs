@@ -7,6 +7,7 @@ pub struct Prompt {
     pub user_prompt: String,
     pub assistant_prompt: String,
     pub tools_prompt: String,
+    pub trace_id: Option<String>,
     pub created_at: NaiveDateTime,
     pub updated_at: NaiveDateTime,
 }
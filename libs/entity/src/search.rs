@@ -0,0 +1,16 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+// Marks around the matched words in `SearchHit::snippet`. They are control
+// characters so that callers can escape the text before highlighting it.
+pub const HIGHLIGHT_START: &str = "\u{2}";
+pub const HIGHLIGHT_END: &str = "\u{3}";
+
+#[derive(Debug, Default, PartialEq, Clone, Serialize, Deserialize)]
+pub struct SearchHit {
+    pub notion_page_id: String,
+    pub title: String,
+    pub snippet: String,
+    pub rank: f32,
+    pub created_at: DateTime<Utc>,
+}
@@ -0,0 +1,518 @@
+use std::{
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use anyhow::Context;
+use reqwest::StatusCode;
+use tokio::{
+    select,
+    sync::{
+        mpsc::{self, error::TrySendError},
+        oneshot,
+    },
+    time::{interval, sleep, MissedTickBehavior},
+};
+use toml::{map::Map, Value};
+use tracing::{error, info, warn};
+use uuid::Uuid;
+
+use crate::{
+    apis::{
+        configuration::Configuration,
+        ingestion_api::{ingestion_batch, IngestionBatchError},
+        Error,
+    },
+    models::{IngestionBatchRequest, IngestionEvent},
+};
+
+const CHANNEL_SIZE: usize = 1000;
+// Batches waiting for delivery before more are spilled instead
+const DELIVERY_QUEUE_SIZE: usize = 16;
+const BASE_DELAY: Duration = Duration::from_millis(500);
+
+pub struct IngestionOptions {
+    pub batch_size: usize,
+    pub flush_interval: Duration,
+    pub max_retries: u32,
+    pub spill_dir: PathBuf,
+}
+
+enum Command {
+    Events(Vec<IngestionEvent>),
+    Flush(oneshot::Sender<()>),
+    Shutdown(oneshot::Sender<()>),
+}
+
+enum Delivery {
+    Batch(Vec<IngestionEvent>),
+    ResendSpilled,
+    // Acknowledged once the batches handed off before it are delivered
+    Done(oneshot::Sender<()>),
+}
+
+// Sends events to Langfuse in the background. Events are buffered and sent
+// in batches once `batch_size` is reached or every `flush_interval`.
+// Batches that can't be delivered after retries are spilled to disk and
+// sent again later. Events that can't even be queued are spilled at once,
+// so that a slow Langfuse never holds up the caller.
+#[derive(Clone)]
+pub struct Ingestion {
+    tx: mpsc::Sender<Command>,
+    spill_dir: PathBuf,
+}
+
+impl Ingestion {
+    pub fn spawn(
+        configuration: Configuration,
+        options: IngestionOptions,
+    ) -> Self {
+        let (tx, rx) = mpsc::channel(CHANNEL_SIZE);
+        let spill_dir = options.spill_dir.clone();
+        tokio::spawn(worker(configuration, options, rx));
+
+        Self { tx, spill_dir }
+    }
+
+    // Reads the `[langfuse]` table of the config. `spill_dir` is relative
+    // to the workspace.
+    pub fn from_config(
+        mut configuration: Configuration,
+        config: &Map<String, Value>,
+        workspace_dir: &Path,
+    ) -> anyhow::Result<Self> {
+        let integer = |key: &str| {
+            config
+                .get(key)
+                .and_then(Value::as_integer)
+                .with_context(|| format!("failed to find {}", key))
+        };
+        let spill_dir = config
+            .get("spill_dir")
+            .and_then(Value::as_str)
+            .context("failed to find spill_dir")?;
+
+        configuration.client = reqwest::Client::builder()
+            .timeout(Duration::from_secs(
+                integer("request_timeout_secs")? as u64
+            ))
+            .build()
+            .context("failed to build langfuse client")?;
+
+        Ok(Self::spawn(
+            configuration,
+            IngestionOptions {
+                batch_size: integer("batch_size")? as usize,
+                flush_interval: Duration::from_secs(integer(
+                    "flush_interval_secs",
+                )? as u64),
+                max_retries: integer("max_retries")? as u32,
+                spill_dir: workspace_dir.join(spill_dir),
+            },
+        ))
+    }
+
+    pub async fn send(&self, events: Vec<IngestionEvent>) {
+        match self.tx.try_send(Command::Events(events)) {
+            Ok(()) => {}
+            Err(TrySendError::Full(Command::Events(events))) => {
+                warn!(task = "send ingestion events", "queue is full");
+                spill_in_background(&self.spill_dir, events).await;
+            }
+            Err(e) => {
+                error!(task = "send ingestion events", error = e.to_string())
+            }
+        }
+    }
+
+    // Waits until the buffered events have been sent or spilled.
+    pub async fn flush(&self) -> anyhow::Result<()> {
+        let (ack_tx, ack_rx) = oneshot::channel();
+        self.tx
+            .send(Command::Flush(ack_tx))
+            .await
+            .context("failed to request flush")?;
+        ack_rx.await.context("failed to wait for flush")
+    }
+
+    // Flushes the buffered events and stops the worker. Events sent after
+    // this are dropped.
+    pub async fn shutdown(&self) -> anyhow::Result<()> {
+        let (ack_tx, ack_rx) = oneshot::channel();
+        self.tx
+            .send(Command::Shutdown(ack_tx))
+            .await
+            .context("failed to request shutdown")?;
+        ack_rx.await.context("failed to wait for shutdown")
+    }
+}
+
+// Buffers the events and hands batches off to `deliver`, so that receiving
+// never waits on Langfuse.
+async fn worker(
+    configuration: Configuration,
+    options: IngestionOptions,
+    mut rx: mpsc::Receiver<Command>,
+) {
+    let (deliveries, delivery_rx) = mpsc::channel(DELIVERY_QUEUE_SIZE);
+    tokio::spawn(deliver(
+        configuration,
+        options.max_retries,
+        options.spill_dir.clone(),
+        delivery_rx,
+    ));
+
+    let mut buffer = vec![];
+    let mut ticker = interval(options.flush_interval);
+    ticker.set_missed_tick_behavior(MissedTickBehavior::Skip);
+
+    loop {
+        select! {
+            command = rx.recv() => match command {
+                Some(Command::Events(events)) => {
+                    buffer.extend(events);
+                    if buffer.len() >= options.batch_size {
+                        hand_off(&deliveries, &options, &mut buffer).await;
+                    }
+                }
+                Some(Command::Flush(ack)) => {
+                    hand_off(&deliveries, &options, &mut buffer).await;
+                    let deliveries = deliveries.clone();
+                    tokio::spawn(async move {
+                        let _ = deliveries.send(Delivery::Done(ack)).await;
+                    });
+                }
+                Some(Command::Shutdown(ack)) => {
+                    hand_off(&deliveries, &options, &mut buffer).await;
+                    let _ = deliveries.send(Delivery::Done(ack)).await;
+                    info!(task = "ingestion", "shut down");
+                    return;
+                }
+                None => {
+                    hand_off(&deliveries, &options, &mut buffer).await;
+                    return;
+                }
+            },
+            _ = ticker.tick() => {
+                hand_off(&deliveries, &options, &mut buffer).await;
+                // Skipped while deliveries are backed up anyway
+                let _ = deliveries.try_send(Delivery::ResendSpilled);
+            }
+        }
+    }
+}
+
+// Queues the buffer in batches for delivery, spilling the batches that
+// don't fit.
+async fn hand_off(
+    deliveries: &mpsc::Sender<Delivery>,
+    options: &IngestionOptions,
+    buffer: &mut Vec<IngestionEvent>,
+) {
+    while !buffer.is_empty() {
+        let size = buffer.len().min(options.batch_size.max(1));
+        let batch = buffer.drain(..size).collect::<Vec<_>>();
+
+        match deliveries.try_send(Delivery::Batch(batch)) {
+            Ok(()) => {}
+            Err(TrySendError::Full(Delivery::Batch(batch))) => {
+                warn!(task = "ingestion", "delivery queue is full");
+                spill_in_background(&options.spill_dir, batch).await;
+            }
+            Err(_) => {
+                error!(task = "ingestion", "delivery stopped");
+                return;
+            }
+        }
+    }
+}
+
+async fn deliver(
+    configuration: Configuration,
+    max_retries: u32,
+    spill_dir: PathBuf,
+    mut rx: mpsc::Receiver<Delivery>,
+) {
+    while let Some(delivery) = rx.recv().await {
+        match delivery {
+            Delivery::Batch(batch) => {
+                let result =
+                    send_with_retry(&configuration, &batch, max_retries).await;
+                let Err(e) = result else {
+                    continue;
+                };
+
+                if !is_retryable(&e) {
+                    error!(
+                        task = "ingestion",
+                        events = batch.len(),
+                        error = e.to_string(),
+                        "dropped events",
+                    );
+                    continue;
+                }
+
+                spill_in_background(&spill_dir, batch).await;
+            }
+            Delivery::ResendSpilled => {
+                resend_spilled(&configuration, &spill_dir).await;
+            }
+            Delivery::Done(ack) => {
+                let _ = ack.send(());
+            }
+        }
+    }
+}
+
+async fn send_with_retry(
+    configuration: &Configuration,
+    batch: &[IngestionEvent],
+    max_retries: u32,
+) -> Result<(), Error<IngestionBatchError>> {
+    let mut attempt = 0;
+    loop {
+        let result = ingestion_batch(
+            configuration,
+            IngestionBatchRequest {
+                batch: batch.to_vec(),
+                metadata: None,
+            },
+        )
+        .await;
+
+        match result {
+            Ok(response) => {
+                for error in response.errors {
+                    error!(
+                        task = "ingestion",
+                        id = error.id,
+                        status = error.status,
+                        error = error.message.flatten().unwrap_or_default(),
+                    );
+                }
+                return Ok(());
+            }
+            Err(e) if attempt < max_retries && is_retryable(&e) => {
+                warn!(task = "ingestion", attempt, error = e.to_string());
+                sleep(BASE_DELAY * 2u32.pow(attempt)).await;
+                attempt += 1;
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+// Network errors and server side errors may pass on a later attempt; the
+// others would fail the same way again.
+fn is_retryable<T>(error: &Error<T>) -> bool {
+    match error {
+        Error::Reqwest(_) | Error::Io(_) => true,
+        Error::ResponseError(response) => {
+            response.status.is_server_error()
+                || response.status == StatusCode::TOO_MANY_REQUESTS
+        }
+        Error::Serde(_) => false,
+    }
+}
+
+async fn spill_in_background(dir: &Path, batch: Vec<IngestionEvent>) {
+    let dir = dir.to_path_buf();
+    let events = batch.len();
+    let result = tokio::task::spawn_blocking(move || spill(&dir, &batch))
+        .await
+        .context("failed to spill")
+        .and_then(|result| result);
+    if let Err(e) = result {
+        error!(
+            task = "spill ingestion events",
+            events,
+            error = e.to_string(),
+        );
+    }
+}
+
+// Written aside and renamed into place, so that a spill file is never read
+// half written.
+fn spill(dir: &Path, batch: &[IngestionEvent]) -> anyhow::Result<()> {
+    std::fs::create_dir_all(dir).context("failed to create spill dir")?;
+    let json = serde_json::to_string(batch).context("failed to serialize")?;
+    let id = Uuid::new_v4();
+    let temp = dir.join(format!("{}.json.tmp", id));
+    std::fs::write(&temp, json).context("failed to write spill file")?;
+    std::fs::rename(&temp, dir.join(format!("{}.json", id)))
+        .context("failed to rename spill file")?;
+
+    Ok(())
+}
+
+// A file that can't be read is moved aside to `.bad` so that it neither
+// holds up the others nor is read again.
+fn load_spilled(
+    dir: &Path,
+) -> anyhow::Result<Vec<(PathBuf, Vec<IngestionEvent>)>> {
+    if !dir.exists() {
+        return Ok(vec![]);
+    }
+
+    let mut spilled = vec![];
+    for entry in std::fs::read_dir(dir).context("failed to read spill dir")? {
+        let Ok(path) = entry.map(|entry| entry.path()) else {
+            continue;
+        };
+        if path.extension().and_then(|e| e.to_str()) != Some("json") {
+            continue;
+        }
+
+        let batch = std::fs::read_to_string(&path)
+            .context("failed to read")
+            .and_then(|json| {
+                serde_json::from_str::<Vec<IngestionEvent>>(&json)
+                    .context("failed to parse")
+            });
+        match batch {
+            Ok(batch) => spilled.push((path, batch)),
+            Err(e) => {
+                error!(
+                    task = "load spilled events",
+                    path = path.display().to_string(),
+                    error = e.to_string(),
+                );
+                if let Err(e) =
+                    std::fs::rename(&path, path.with_extension("bad"))
+                {
+                    error!(task = "move spill file", error = e.to_string());
+                }
+            }
+        }
+    }
+
+    Ok(spilled)
+}
+
+// Sends spilled batches once each. The rest is kept for the next tick when
+// Langfuse is still unreachable.
+async fn resend_spilled(configuration: &Configuration, dir: &Path) {
+    let path = dir.to_path_buf();
+    let spilled = tokio::task::spawn_blocking(move || load_spilled(&path))
+        .await
+        .context("failed to load spilled events")
+        .and_then(|result| result);
+    let Ok(spilled) = spilled else {
+        error!(
+            task = "load spilled events",
+            error = spilled.unwrap_err().to_string(),
+        );
+        return;
+    };
+
+    for (path, batch) in spilled {
+        if let Err(e) = send_with_retry(configuration, &batch, 0).await {
+            if is_retryable(&e) {
+                return;
+            }
+            error!(
+                task = "resend spilled events",
+                events = batch.len(),
+                error = e.to_string(),
+                "dropped events",
+            );
+        }
+
+        let result =
+            tokio::task::spawn_blocking(move || std::fs::remove_file(path))
+                .await;
+        if let Ok(Err(e)) = result {
+            error!(task = "remove spill file", error = e.to_string());
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use reqwest::StatusCode;
+    use tokio::sync::mpsc;
+
+    use super::{is_retryable, load_spilled, spill, Ingestion};
+    use crate::{
+        apis::{Error, ResponseContent},
+        models::{
+            ingestion_event_one_of, IngestionEvent, IngestionEventOneOf,
+            TraceBody,
+        },
+    };
+
+    #[test]
+    fn test_is_retryable() {
+        // Arrange
+        let response = |status| {
+            Error::<()>::ResponseError(ResponseContent {
+                status,
+                content: String::new(),
+                entity: None,
+            })
+        };
+
+        // Act
+        let unavailable = is_retryable(&response(StatusCode::BAD_GATEWAY));
+        let throttled = is_retryable(&response(StatusCode::TOO_MANY_REQUESTS));
+        let invalid = is_retryable(&response(StatusCode::BAD_REQUEST));
+
+        // Assert
+        assert!(unavailable);
+        assert!(throttled);
+        assert!(!invalid);
+    }
+
+    fn event() -> IngestionEvent {
+        IngestionEvent::IngestionEventOneOf(Box::new(IngestionEventOneOf::new(
+            TraceBody {
+                id: Some(Some("trace".to_string())),
+                ..Default::default()
+            },
+            "event".to_string(),
+            "2024-06-01T00:00:00Z".to_string(),
+            ingestion_event_one_of::Type::TraceCreate,
+        )))
+    }
+
+    #[test]
+    fn test_spill() {
+        // Arrange
+        let dir = tempfile::tempdir().unwrap();
+        let torn = dir.path().join("torn.json");
+        std::fs::write(&torn, "[{\"id\":").unwrap();
+
+        // Act
+        spill(dir.path(), &[event()]).unwrap();
+        let spilled = load_spilled(dir.path()).unwrap();
+        let reloaded = load_spilled(dir.path()).unwrap();
+
+        // Assert
+        assert_eq!(spilled.len(), 1);
+        assert_eq!(spilled[0].1, vec![event()]);
+        assert_eq!(reloaded.len(), 1);
+        assert!(!torn.exists());
+        assert!(dir.path().join("torn.bad").exists());
+    }
+
+    #[tokio::test]
+    async fn test_send_when_full() {
+        // Arrange
+        let dir = tempfile::tempdir().unwrap();
+        // Nothing receives, so the queue stays full after one command
+        let (tx, _rx) = mpsc::channel(1);
+        let ingestion = Ingestion {
+            tx,
+            spill_dir: dir.path().to_path_buf(),
+        };
+
+        // Act
+        ingestion.send(vec![event()]).await;
+        ingestion.send(vec![event(), event()]).await;
+
+        // Assert
+        let spilled = load_spilled(dir.path()).unwrap();
+        assert_eq!(spilled.len(), 1);
+        assert_eq!(spilled[0].1.len(), 2);
+    }
+}
@@ -7,4 +7,6 @@ extern crate serde_json;
 extern crate url;
 
 pub mod apis;
+pub mod ingestion;
 pub mod models;
+pub mod trace;
@@ -0,0 +1,446 @@
+//! Builders for a trace and its nested observations.
+//!
+//! Observations are recorded on the trace they belong to when they end or
+//! are dropped, and [`Trace::finish`] returns every event to ingest.
+
+use std::{
+    fmt::Display,
+    sync::{Arc, Mutex},
+};
+
+use serde::Serialize;
+use serde_json::Value;
+use uuid::Uuid;
+
+use crate::models::{
+    ingestion_event_one_of, ingestion_event_one_of_2, ingestion_event_one_of_4,
+    CreateGenerationBody, CreateSpanBody, IngestionEvent, IngestionEventOneOf,
+    IngestionEventOneOf2, IngestionEventOneOf4, IngestionUsage, ModelUsageUnit,
+    ObservationLevel, TraceBody, Usage,
+};
+
+fn now() -> String {
+    chrono::Utc::now().to_rfc3339()
+}
+
+#[derive(Clone, Default)]
+struct Recorder(Arc<Mutex<Vec<IngestionEvent>>>);
+
+impl Recorder {
+    fn record(&self, event: IngestionEvent) {
+        if let Ok(mut events) = self.0.lock() {
+            events.push(event);
+        }
+    }
+
+    fn take(&self) -> Vec<IngestionEvent> {
+        self.0
+            .lock()
+            .map(|mut events| std::mem::take(&mut *events))
+            .unwrap_or_default()
+    }
+}
+
+/// Starts observations under a trace or a span.
+pub trait Observe {
+    fn span(&self, name: &str) -> Span;
+    fn generation(&self, name: &str) -> Generation;
+}
+
+#[derive(Clone)]
+pub struct Trace {
+    body: TraceBody,
+    recorder: Recorder,
+}
+
+impl Trace {
+    pub fn new(name: &str) -> Self {
+        Self {
+            body: TraceBody {
+                id: Some(Some(Uuid::new_v4().to_string())),
+                name: Some(Some(name.to_string())),
+                timestamp: Some(Some(now())),
+                ..Default::default()
+            },
+            recorder: Recorder::default(),
+        }
+    }
+
+    pub fn id(&self) -> String {
+        self.body.id.clone().flatten().unwrap_or_default()
+    }
+
+    pub fn user_id(mut self, user_id: impl ToString) -> Self {
+        self.body.user_id = Some(Some(user_id.to_string()));
+        self
+    }
+
+    pub fn session_id(mut self, session_id: &str) -> Self {
+        self.body.session_id = Some(Some(session_id.to_string()));
+        self
+    }
+
+    pub fn input(mut self, input: impl Into<Value>) -> Self {
+        self.body.input = Some(Some(input.into()));
+        self
+    }
+
+    pub fn output(mut self, output: impl Into<Value>) -> Self {
+        self.body.output = Some(Some(output.into()));
+        self
+    }
+
+    pub fn metadata(mut self, metadata: Value) -> Self {
+        self.body.metadata = Some(Some(metadata));
+        self
+    }
+
+    pub fn tags(mut self, tags: Vec<String>) -> Self {
+        self.body.tags = Some(Some(tags));
+        self
+    }
+
+    pub fn public(mut self, public: bool) -> Self {
+        self.body.public = Some(Some(public));
+        self
+    }
+
+    /// Returns the trace followed by the observations ended so far.
+    pub fn finish(self) -> Vec<IngestionEvent> {
+        let id = self.id();
+        let mut events = vec![IngestionEvent::IngestionEventOneOf(Box::new(
+            IngestionEventOneOf::new(
+                self.body,
+                id,
+                now(),
+                ingestion_event_one_of::Type::TraceCreate,
+            ),
+        ))];
+        events.extend(self.recorder.take());
+        events
+    }
+}
+
+impl Observe for Trace {
+    fn span(&self, name: &str) -> Span {
+        Span::start(name, self.id(), None, self.recorder.clone())
+    }
+
+    fn generation(&self, name: &str) -> Generation {
+        Generation::start(name, self.id(), None, self.recorder.clone())
+    }
+}
+
+pub struct Span {
+    body: Option<CreateSpanBody>,
+    recorder: Recorder,
+}
+
+impl Span {
+    fn start(
+        name: &str,
+        trace_id: String,
+        parent_id: Option<String>,
+        recorder: Recorder,
+    ) -> Self {
+        Self {
+            body: Some(CreateSpanBody {
+                id: Some(Some(Uuid::new_v4().to_string())),
+                trace_id: Some(Some(trace_id)),
+                parent_observation_id: parent_id.map(Some),
+                name: Some(Some(name.to_string())),
+                start_time: Some(Some(now())),
+                ..Default::default()
+            }),
+            recorder,
+        }
+    }
+
+    fn body(&mut self) -> &mut CreateSpanBody {
+        self.body.get_or_insert_with(Default::default)
+    }
+
+    pub fn id(&self) -> String {
+        self.body
+            .as_ref()
+            .and_then(|body| body.id.clone().flatten())
+            .unwrap_or_default()
+    }
+
+    fn trace_id(&self) -> String {
+        self.body
+            .as_ref()
+            .and_then(|body| body.trace_id.clone().flatten())
+            .unwrap_or_default()
+    }
+
+    pub fn input(mut self, input: impl Into<Value>) -> Self {
+        self.body().input = Some(Some(input.into()));
+        self
+    }
+
+    pub fn output(mut self, output: impl Into<Value>) -> Self {
+        self.body().output = Some(Some(output.into()));
+        self
+    }
+
+    pub fn metadata(mut self, metadata: Value) -> Self {
+        self.body().metadata = Some(Some(metadata));
+        self
+    }
+
+    pub fn error(mut self, message: impl ToString) -> Self {
+        self.body().level = Some(ObservationLevel::Error);
+        self.body().status_message = Some(Some(message.to_string()));
+        self
+    }
+
+    /// Records the span with the current time as its end.
+    pub fn end(self) {}
+}
+
+impl Observe for Span {
+    fn span(&self, name: &str) -> Span {
+        Span::start(
+            name,
+            self.trace_id(),
+            Some(self.id()),
+            self.recorder.clone(),
+        )
+    }
+
+    fn generation(&self, name: &str) -> Generation {
+        Generation::start(
+            name,
+            self.trace_id(),
+            Some(self.id()),
+            self.recorder.clone(),
+        )
+    }
+}
+
+impl Drop for Span {
+    fn drop(&mut self) {
+        let Some(mut body) = self.body.take() else {
+            return;
+        };
+        body.end_time = Some(Some(now()));
+        self.recorder
+            .record(IngestionEvent::IngestionEventOneOf2(Box::new(
+                IngestionEventOneOf2::new(
+                    body,
+                    Uuid::new_v4().to_string(),
+                    now(),
+                    ingestion_event_one_of_2::Type::SpanCreate,
+                ),
+            )));
+    }
+}
+
+pub struct Generation {
+    body: Option<CreateGenerationBody>,
+    recorder: Recorder,
+}
+
+impl Generation {
+    fn start(
+        name: &str,
+        trace_id: String,
+        parent_id: Option<String>,
+        recorder: Recorder,
+    ) -> Self {
+        Self {
+            body: Some(CreateGenerationBody {
+                id: Some(Some(Uuid::new_v4().to_string())),
+                trace_id: Some(Some(trace_id)),
+                parent_observation_id: parent_id.map(Some),
+                name: Some(Some(name.to_string())),
+                start_time: Some(Some(now())),
+                ..Default::default()
+            }),
+            recorder,
+        }
+    }
+
+    fn body(&mut self) -> &mut CreateGenerationBody {
+        self.body.get_or_insert_with(Default::default)
+    }
+
+    pub fn id(&self) -> String {
+        self.body
+            .as_ref()
+            .and_then(|body| body.id.clone().flatten())
+            .unwrap_or_default()
+    }
+
+    pub fn model(mut self, model: &str) -> Self {
+        self.body().model = Some(Some(model.to_string()));
+        self
+    }
+
+    /// Parameters that don't serialize to a map of values are left out.
+    pub fn model_parameters(mut self, parameters: &impl Serialize) -> Self {
+        let parameters = serde_json::to_value(parameters)
+            .and_then(serde_json::from_value)
+            .ok();
+        self.body().model_parameters = Some(parameters);
+        self
+    }
+
+    pub fn input(mut self, input: impl Into<Value>) -> Self {
+        self.body().input = Some(Some(input.into()));
+        self
+    }
+
+    pub fn output(mut self, output: impl Into<Value>) -> Self {
+        self.body().output = Some(Some(output.into()));
+        self
+    }
+
+    pub fn metadata(mut self, metadata: Value) -> Self {
+        self.body().metadata = Some(Some(metadata));
+        self
+    }
+
+    pub fn usage(mut self, input_tokens: i32, output_tokens: i32) -> Self {
+        self.body().usage =
+            Some(Box::new(IngestionUsage::Usage(Box::new(Usage {
+                input: Some(Some(input_tokens)),
+                output: Some(Some(output_tokens)),
+                total: Some(Some(input_tokens + output_tokens)),
+                unit: Some(ModelUsageUnit::Tokens),
+                ..Default::default()
+            }))));
+        self
+    }
+
+    /// Marks the arrival of the first token of a streamed completion. Only
+    /// the first call counts.
+    pub fn completion_started(&mut self) {
+        let body = self.body();
+        if body.completion_start_time.is_none() {
+            body.completion_start_time = Some(Some(now()));
+        }
+    }
+
+    pub fn error(mut self, message: impl ToString) -> Self {
+        self.body().level = Some(ObservationLevel::Error);
+        self.body().status_message = Some(Some(message.to_string()));
+        self
+    }
+
+    /// Records the generation with the current time as its end.
+    pub fn end(self) {}
+
+    /// Ends the generation with the outcome of the call to the model. `f`
+    /// fills in the output and usage of a successful response.
+    pub fn end_with<T, E: Display>(
+        self,
+        result: &Result<T, E>,
+        f: impl FnOnce(Self, &T) -> Self,
+    ) {
+        match result {
+            Ok(response) => f(self, response).end(),
+            Err(e) => self.error(e).end(),
+        }
+    }
+}
+
+impl Drop for Generation {
+    fn drop(&mut self) {
+        let Some(mut body) = self.body.take() else {
+            return;
+        };
+        body.end_time = Some(Some(now()));
+        self.recorder
+            .record(IngestionEvent::IngestionEventOneOf4(Box::new(
+                IngestionEventOneOf4::new(
+                    body,
+                    Uuid::new_v4().to_string(),
+                    now(),
+                    ingestion_event_one_of_4::Type::GenerationCreate,
+                ),
+            )));
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Observe, Trace};
+    use crate::models::{IngestionEvent, ObservationLevel};
+
+    #[test]
+    fn test_nested_observations() {
+        // Arrange
+        let trace = Trace::new("search").user_id(1).input("question");
+        let span = trace.span("retrieve").input("question");
+        let generation = span
+            .generation("keyword generator")
+            .model("model")
+            .usage(10, 2);
+
+        // Act
+        generation.output("keywords").end();
+        let span_id = span.id();
+        span.end();
+        let trace_id = trace.id();
+        let events = trace.finish();
+
+        // Assert
+        assert_eq!(events.len(), 3);
+        let IngestionEvent::IngestionEventOneOf(trace) = &events[0] else {
+            panic!("expected a trace first");
+        };
+        assert_eq!(trace.body.id, Some(Some(trace_id.clone())));
+        let IngestionEvent::IngestionEventOneOf4(generation) = &events[1]
+        else {
+            panic!("expected the generation to end first");
+        };
+        assert_eq!(generation.body.trace_id, Some(Some(trace_id.clone())));
+        assert_eq!(
+            generation.body.parent_observation_id,
+            Some(Some(span_id.clone()))
+        );
+        assert!(generation.body.end_time.is_some());
+        let IngestionEvent::IngestionEventOneOf2(span) = &events[2] else {
+            panic!("expected the span last");
+        };
+        assert_eq!(span.body.id, Some(Some(span_id)));
+        assert_eq!(span.body.parent_observation_id, None);
+    }
+
+    #[test]
+    fn test_end_with() {
+        // Arrange
+        let trace = Trace::new("summarize");
+        let ok: Result<&str, String> = Ok("summary");
+        let err: Result<&str, String> = Err("status code: 429".to_string());
+
+        // Act
+        trace
+            .generation("summarizer")
+            .end_with(&ok, |generation, response| generation.output(*response));
+        trace
+            .generation("summarizer")
+            .end_with(&err, |generation, response| {
+                generation.output(*response)
+            });
+        let events = trace.finish();
+
+        // Assert
+        let IngestionEvent::IngestionEventOneOf4(ok) = &events[1] else {
+            panic!("expected a generation");
+        };
+        assert_eq!(ok.body.output, Some(Some("summary".into())));
+        assert_eq!(ok.body.level, None);
+        let IngestionEvent::IngestionEventOneOf4(err) = &events[2] else {
+            panic!("expected a generation");
+        };
+        assert_eq!(err.body.output, None);
+        assert_eq!(err.body.level, Some(ObservationLevel::Error));
+        assert_eq!(
+            err.body.status_message,
+            Some(Some("status code: 429".to_string()))
+        );
+    }
+}
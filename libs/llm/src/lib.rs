@@ -0,0 +1,303 @@
+//! Chat, embedding and image models behind one interface so that each task
+//! can run on the provider and model set in the config.
+
+use std::{collections::HashMap, future::Future, pin::Pin};
+
+use anyhow::Context;
+use bytes::Bytes;
+use cloudflare::models::text_generation::{
+    Message, ModelParameters, StreamEvent, TextGenerationJsonResult, Tool,
+};
+use futures_core::Stream;
+use toml::{map::Map, Value};
+
+pub use self::openai::OpenAi;
+
+pub mod openai;
+mod workers_ai;
+
+pub type ChatStream =
+    Pin<Box<dyn Stream<Item = anyhow::Result<StreamEvent>> + Send>>;
+
+pub trait Llm {
+    // Models that support function calling may answer with calls of `tools`
+    // in `tool_calls`.
+    fn chat(
+        &self,
+        model: &str,
+        messages: Vec<Message>,
+        tools: Vec<Tool>,
+        parameters: Option<ModelParameters>,
+    ) -> impl Future<Output = anyhow::Result<TextGenerationJsonResult>> + Send;
+
+    // Streams the tokens of the response, then the usage when the provider
+    // reports it.
+    fn chat_stream(
+        &self,
+        model: &str,
+        messages: Vec<Message>,
+        tools: Vec<Tool>,
+        parameters: Option<ModelParameters>,
+    ) -> ChatStream;
+
+    fn embed(
+        &self,
+        model: &str,
+        texts: Vec<String>,
+    ) -> impl Future<Output = anyhow::Result<Vec<Vec<f32>>>> + Send;
+
+    fn text_to_image(
+        &self,
+        model: &str,
+        prompt: &str,
+    ) -> impl Future<Output = anyhow::Result<Bytes>> + Send;
+}
+
+#[derive(Clone, Debug)]
+pub enum Provider {
+    Cloudflare(cloudflare::models::Models),
+    OpenAi(OpenAi),
+}
+
+impl Llm for Provider {
+    async fn chat(
+        &self,
+        model: &str,
+        messages: Vec<Message>,
+        tools: Vec<Tool>,
+        parameters: Option<ModelParameters>,
+    ) -> anyhow::Result<TextGenerationJsonResult> {
+        match self {
+            Provider::Cloudflare(client) => {
+                client.chat(model, messages, tools, parameters).await
+            }
+            Provider::OpenAi(client) => {
+                client.chat(model, messages, tools, parameters).await
+            }
+        }
+    }
+
+    fn chat_stream(
+        &self,
+        model: &str,
+        messages: Vec<Message>,
+        tools: Vec<Tool>,
+        parameters: Option<ModelParameters>,
+    ) -> ChatStream {
+        match self {
+            Provider::Cloudflare(client) => {
+                client.chat_stream(model, messages, tools, parameters)
+            }
+            Provider::OpenAi(client) => {
+                client.chat_stream(model, messages, tools, parameters)
+            }
+        }
+    }
+
+    async fn embed(
+        &self,
+        model: &str,
+        texts: Vec<String>,
+    ) -> anyhow::Result<Vec<Vec<f32>>> {
+        match self {
+            Provider::Cloudflare(client) => client.embed(model, texts).await,
+            Provider::OpenAi(client) => client.embed(model, texts).await,
+        }
+    }
+
+    async fn text_to_image(
+        &self,
+        model: &str,
+        prompt: &str,
+    ) -> anyhow::Result<Bytes> {
+        match self {
+            Provider::Cloudflare(client) => {
+                client.text_to_image(model, prompt).await
+            }
+            Provider::OpenAi(client) => {
+                client.text_to_image(model, prompt).await
+            }
+        }
+    }
+}
+
+/// A model of a provider chosen for a task.
+#[derive(Clone, Debug)]
+pub struct Model {
+    provider: Provider,
+    name: String,
+}
+
+impl Model {
+    pub fn new(provider: Provider, name: &str) -> Self {
+        Self {
+            provider,
+            name: name.to_string(),
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// The same provider running the model `name` instead.
+    pub fn with_name(&self, name: &str) -> Self {
+        Self::new(self.provider.clone(), name)
+    }
+
+    pub async fn chat(
+        &self,
+        messages: Vec<Message>,
+        parameters: Option<ModelParameters>,
+    ) -> anyhow::Result<TextGenerationJsonResult> {
+        self.chat_with_tools(messages, vec![], parameters).await
+    }
+
+    pub async fn chat_with_tools(
+        &self,
+        messages: Vec<Message>,
+        tools: Vec<Tool>,
+        parameters: Option<ModelParameters>,
+    ) -> anyhow::Result<TextGenerationJsonResult> {
+        self.provider
+            .chat(&self.name, messages, tools, parameters)
+            .await
+    }
+
+    pub fn chat_stream(
+        &self,
+        messages: Vec<Message>,
+        parameters: Option<ModelParameters>,
+    ) -> ChatStream {
+        self.provider
+            .chat_stream(&self.name, messages, vec![], parameters)
+    }
+
+    pub async fn embed(
+        &self,
+        texts: Vec<String>,
+    ) -> anyhow::Result<Vec<Vec<f32>>> {
+        self.provider.embed(&self.name, texts).await
+    }
+
+    pub async fn text_to_image(&self, prompt: &str) -> anyhow::Result<Bytes> {
+        self.provider.text_to_image(&self.name, prompt).await
+    }
+}
+
+/// Models per task read from the `[llm]` table of the config.
+#[derive(Clone, Debug, Default)]
+pub struct Llms {
+    tasks: HashMap<String, Model>,
+}
+
+impl Llms {
+    // Providers are declared under `[llm.providers.<name>]` with a `kind` of
+    // either "cloudflare" or "openai", and each entry of `[llm.tasks]` names
+    // a provider and a model. The key of an OpenAI-compatible server is read
+    // from the environment variable in `api_key_env`, if any.
+    pub fn from_config(
+        config: &Map<String, Value>,
+        cloudflare: cloudflare::models::Models,
+    ) -> anyhow::Result<Self> {
+        let mut providers = HashMap::new();
+        let table = config
+            .get("providers")
+            .and_then(Value::as_table)
+            .context("failed to find providers")?;
+        for (name, provider) in table {
+            let kind = provider
+                .get("kind")
+                .and_then(Value::as_str)
+                .with_context(|| format!("failed to find kind of {}", name))?;
+            let provider = match kind {
+                "cloudflare" => Provider::Cloudflare(cloudflare.clone()),
+                "openai" => {
+                    let base_url = provider
+                        .get("base_url")
+                        .and_then(Value::as_str)
+                        .with_context(|| {
+                            format!("failed to find base_url of {}", name)
+                        })?;
+                    let api_key = provider
+                        .get("api_key_env")
+                        .and_then(Value::as_str)
+                        .and_then(|key| std::env::var(key).ok());
+                    Provider::OpenAi(OpenAi::new(base_url, api_key.as_deref())?)
+                }
+                kind => anyhow::bail!("unknown provider kind: {}", kind),
+            };
+            providers.insert(name.to_string(), provider);
+        }
+
+        let mut tasks = HashMap::new();
+        let table = config
+            .get("tasks")
+            .and_then(Value::as_table)
+            .context("failed to find tasks")?;
+        for (task, model) in table {
+            let field = |key: &str| {
+                model.get(key).and_then(Value::as_str).with_context(|| {
+                    format!("failed to find {} of {}", key, task)
+                })
+            };
+            let provider = providers
+                .get(field("provider")?)
+                .with_context(|| format!("unknown provider of {}", task))?;
+            tasks.insert(
+                task.to_string(),
+                Model::new(provider.clone(), field("model")?),
+            );
+        }
+
+        Ok(Self { tasks })
+    }
+
+    pub fn task(&self, name: &str) -> anyhow::Result<Model> {
+        self.tasks
+            .get(name)
+            .cloned()
+            .with_context(|| format!("failed to find model for {}", name))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Llms, Provider};
+
+    #[test]
+    fn test_from_config() {
+        // Arrange
+        let config = toml::from_str::<toml::Table>(
+            r#"
+            [providers.cloudflare]
+            kind = "cloudflare"
+
+            [providers.local]
+            kind = "openai"
+            base_url = "http://localhost:11434/v1"
+
+            [tasks]
+            summarizer = { provider = "cloudflare", model = "@cf/model" }
+            answer = { provider = "local", model = "llama3" }
+            "#,
+        )
+        .unwrap();
+        let cloudflare = cloudflare::models::Models::new("account", "token");
+
+        // Act
+        let llms = Llms::from_config(&config, cloudflare).unwrap();
+
+        // Assert
+        let summarizer = llms.task("summarizer").unwrap();
+        assert_eq!(summarizer.name(), "@cf/model");
+        assert!(matches!(summarizer.provider, Provider::Cloudflare(_)));
+        let answer = llms.task("answer").unwrap();
+        assert_eq!(answer.name(), "llama3");
+        assert!(matches!(answer.provider, Provider::OpenAi(_)));
+        assert!(llms.task("missing").is_err());
+        let renamed = answer.with_name("llama3:70b");
+        assert_eq!(renamed.name(), "llama3:70b");
+        assert!(matches!(renamed.provider, Provider::OpenAi(_)));
+    }
+}
@@ -0,0 +1,487 @@
+use anyhow::{ensure, Context};
+use async_stream::try_stream;
+use base64::{engine::general_purpose::STANDARD, Engine as _};
+use bytes::Bytes;
+use std::collections::HashMap;
+
+use cloudflare::{
+    models::text_generation::{
+        Message, ModelParameters, StreamEvent, TextGenerationJsonResult, Tool,
+        ToolCall, Usage,
+    },
+    sse::data_stream,
+};
+use futures_util::StreamExt;
+use reqwest::{
+    header::{HeaderMap, HeaderValue},
+    Client, Response,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{ChatStream, Llm};
+
+// A server speaking the OpenAI API, such as llama.cpp server, vLLM or
+// Ollama. `base_url` includes the version, e.g. `http://localhost:8080/v1`.
+#[derive(Clone, Debug)]
+pub struct OpenAi {
+    base_url: String,
+    client: Client,
+}
+
+#[derive(Serialize)]
+struct ChatRequest<'a> {
+    model: &'a str,
+    messages: Vec<Message>,
+    stream: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    stream_options: Option<StreamOptions>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    tools: Vec<Tool>,
+    #[serde(flatten)]
+    parameters: Parameters,
+}
+
+#[derive(Serialize)]
+struct StreamOptions {
+    include_usage: bool,
+}
+
+#[derive(Serialize, Default, Debug, PartialEq)]
+struct Parameters {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    max_tokens: Option<i32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    temperature: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    top_p: Option<f32>,
+    // Not part of the OpenAI API but accepted by llama.cpp and vLLM
+    #[serde(skip_serializing_if = "Option::is_none")]
+    top_k: Option<i32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    frequency_penalty: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    presence_penalty: Option<f32>,
+}
+
+impl From<ModelParameters> for Parameters {
+    fn from(parameters: ModelParameters) -> Self {
+        Self {
+            max_tokens: parameters.max_tokens,
+            temperature: parameters.temperature.map(|t| t as f32),
+            top_p: parameters.top_p.map(|p| p as f32),
+            top_k: parameters.top_k,
+            frequency_penalty: parameters.frequency_penalty.map(|p| p as f32),
+            presence_penalty: parameters.presence_penalty.map(|p| p as f32),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct ChatCompletion {
+    #[serde(default)]
+    choices: Vec<Choice>,
+    usage: Option<Usage>,
+}
+
+// Responses hold `message` and stream chunks hold `delta`.
+#[derive(Deserialize)]
+struct Choice {
+    message: Option<ChoiceMessage>,
+    delta: Option<ChoiceMessage>,
+}
+
+#[derive(Deserialize)]
+struct ChoiceMessage {
+    content: Option<String>,
+    #[serde(default)]
+    tool_calls: Vec<ChoiceToolCall>,
+}
+
+// Stream chunks carry pieces of a call, so every field may be missing.
+#[derive(Deserialize, Default)]
+#[serde(default)]
+struct ChoiceToolCall {
+    function: ChoiceFunction,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default)]
+struct ChoiceFunction {
+    name: String,
+    // A JSON object encoded as a string
+    arguments: String,
+}
+
+impl From<ChoiceToolCall> for ToolCall {
+    fn from(call: ChoiceToolCall) -> Self {
+        let arguments = serde_json::from_str::<
+            HashMap<String, serde_json::Value>,
+        >(&call.function.arguments)
+        .ok()
+        .map(|arguments| {
+            arguments
+                .into_iter()
+                .map(|(key, value)| {
+                    let value = match value {
+                        serde_json::Value::Null => None,
+                        serde_json::Value::String(value) => Some(value),
+                        value => Some(value.to_string()),
+                    };
+                    (key, value)
+                })
+                .collect()
+        });
+
+        Self {
+            name: call.function.name,
+            arguments,
+        }
+    }
+}
+
+impl From<ChatCompletion> for TextGenerationJsonResult {
+    fn from(completion: ChatCompletion) -> Self {
+        let message = completion
+            .choices
+            .into_iter()
+            .next()
+            .and_then(|choice| choice.message.or(choice.delta));
+        let (response, tool_calls) = match message {
+            Some(message) => (message.content, message.tool_calls),
+            None => (None, vec![]),
+        };
+
+        Self {
+            response,
+            // None rather than empty, so callers can tell no calls were made
+            tool_calls: (!tool_calls.is_empty())
+                .then(|| tool_calls.into_iter().map(ToolCall::from).collect()),
+            usage: completion.usage,
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct EmbeddingsRequest<'a> {
+    model: &'a str,
+    input: Vec<String>,
+}
+
+#[derive(Deserialize)]
+struct Embeddings {
+    data: Vec<Embedding>,
+}
+
+#[derive(Deserialize)]
+struct Embedding {
+    index: usize,
+    embedding: Vec<f32>,
+}
+
+#[derive(Serialize)]
+struct ImageRequest<'a> {
+    model: &'a str,
+    prompt: &'a str,
+    response_format: &'a str,
+}
+
+#[derive(Deserialize)]
+struct Images {
+    data: Vec<Image>,
+}
+
+#[derive(Deserialize)]
+struct Image {
+    b64_json: String,
+}
+
+impl OpenAi {
+    pub fn new(base_url: &str, api_key: Option<&str>) -> anyhow::Result<Self> {
+        let mut headers = HeaderMap::new();
+        if let Some(api_key) = api_key {
+            headers.insert(
+                "Authorization",
+                HeaderValue::from_str(&format!("Bearer {}", api_key))
+                    .context("invalid api key")?,
+            );
+        }
+
+        let client = reqwest::ClientBuilder::new()
+            .default_headers(headers)
+            .build()
+            .context("failed to build client")?;
+
+        Ok(Self {
+            base_url: base_url.trim_end_matches('/').to_string(),
+            client,
+        })
+    }
+
+    async fn post<T: Serialize>(
+        &self,
+        path: &str,
+        body: &T,
+    ) -> anyhow::Result<Response> {
+        let response = self
+            .client
+            .post(format!("{}/{}", self.base_url, path))
+            .json(body)
+            .send()
+            .await?;
+
+        let status_code = response.status();
+        if !status_code.is_success() {
+            let text = response.text().await;
+            anyhow::bail!("status code: {}, response: {:?}", status_code, text);
+        }
+
+        Ok(response)
+    }
+}
+
+impl Llm for OpenAi {
+    async fn chat(
+        &self,
+        model: &str,
+        messages: Vec<Message>,
+        tools: Vec<Tool>,
+        parameters: Option<ModelParameters>,
+    ) -> anyhow::Result<TextGenerationJsonResult> {
+        let request = ChatRequest {
+            model,
+            messages,
+            stream: false,
+            stream_options: None,
+            tools,
+            parameters: parameters.map(Parameters::from).unwrap_or_default(),
+        };
+
+        let completion = self
+            .post("chat/completions", &request)
+            .await?
+            .json::<ChatCompletion>()
+            .await
+            .context("failed to parse response")?;
+
+        Ok(completion.into())
+    }
+
+    fn chat_stream(
+        &self,
+        model: &str,
+        messages: Vec<Message>,
+        tools: Vec<Tool>,
+        parameters: Option<ModelParameters>,
+    ) -> ChatStream {
+        let client = self.clone();
+        let model = model.to_string();
+        Box::pin(try_stream! {
+            let request = ChatRequest {
+                model: &model,
+                messages,
+                stream: true,
+                stream_options: Some(StreamOptions { include_usage: true }),
+                tools,
+                parameters: parameters.map(Parameters::from).unwrap_or_default(),
+            };
+            let stream = client
+                .post("chat/completions", &request)
+                .await?
+                .bytes_stream();
+            let mut stream = std::pin::pin!(data_stream(stream));
+            while let Some(data) = stream.next().await.transpose()? {
+                for event in events(&data)? {
+                    yield event;
+                }
+            }
+        })
+    }
+
+    async fn embed(
+        &self,
+        model: &str,
+        texts: Vec<String>,
+    ) -> anyhow::Result<Vec<Vec<f32>>> {
+        let mut embeddings = self
+            .post(
+                "embeddings",
+                &EmbeddingsRequest {
+                    model,
+                    input: texts,
+                },
+            )
+            .await?
+            .json::<Embeddings>()
+            .await
+            .context("failed to parse response")?
+            .data;
+        embeddings.sort_by_key(|embedding| embedding.index);
+
+        Ok(embeddings
+            .into_iter()
+            .map(|embedding| embedding.embedding)
+            .collect())
+    }
+
+    async fn text_to_image(
+        &self,
+        model: &str,
+        prompt: &str,
+    ) -> anyhow::Result<Bytes> {
+        let images = self
+            .post(
+                "images/generations",
+                &ImageRequest {
+                    model,
+                    prompt,
+                    response_format: "b64_json",
+                },
+            )
+            .await?
+            .json::<Images>()
+            .await
+            .context("failed to parse response")?;
+
+        let image = images.data.first().context("failed to get image")?;
+        let bytes = STANDARD
+            .decode(&image.b64_json)
+            .context("failed to decode image")?;
+        ensure!(!bytes.is_empty(), "empty image");
+
+        Ok(Bytes::from(bytes))
+    }
+}
+
+// Stream chunks carry a `delta` and the last one the usage.
+fn events(data: &str) -> anyhow::Result<Vec<StreamEvent>> {
+    let completion = serde_json::from_str::<ChatCompletion>(data)
+        .with_context(|| format!("failed to parse event: {}", data))?;
+
+    Ok(TextGenerationJsonResult::from(completion).into_events())
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::{Arc, Mutex};
+
+    use axum::{extract::State, routing::post, Json, Router};
+    use cloudflare::{
+        models::text_generation::{
+            Function, Message, StreamEvent, Tool, Usage,
+        },
+        sse::{Decoder, Frame},
+    };
+    use serde_json::{json, Value};
+    use tokio::net::TcpListener;
+
+    use super::{events, OpenAi};
+    use crate::Llm;
+
+    #[tokio::test]
+    async fn test_chat_with_tool_calls() {
+        // Arrange
+        let requests = Arc::new(Mutex::new(vec![]));
+        let app = Router::new()
+            .route(
+                "/v1/chat/completions",
+                post(
+                    |State(requests): State<Arc<Mutex<Vec<Value>>>>,
+                     Json(body): Json<Value>| async move {
+                        requests.lock().unwrap().push(body);
+                        Json(json!({
+                            "choices": [{
+                                "message": {
+                                    "role": "assistant",
+                                    "content": null,
+                                    "tool_calls": [{
+                                        "id": "call_1",
+                                        "type": "function",
+                                        "function": {
+                                            "name": "get_article_summary",
+                                            "arguments": "{\"query\":\"axum\",\"limit\":3}"
+                                        }
+                                    }]
+                                }
+                            }]
+                        }))
+                    },
+                ),
+            )
+            .with_state(requests.clone());
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let base_url = format!("http://{}/v1", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await });
+        let client = OpenAi::new(&base_url, None).unwrap();
+        let tools = vec![Tool {
+            r#type: "function".to_string(),
+            function: Function {
+                name: "get_article_summary".to_string(),
+                ..Default::default()
+            },
+        }];
+        let messages = vec![Message {
+            role: "user".to_string(),
+            content: "axum?".to_string(),
+        }];
+
+        // Act
+        let response =
+            client.chat("llama3", messages, tools, None).await.unwrap();
+
+        // Assert
+        assert_eq!(response.response, None);
+        let tool_calls = response.tool_calls.unwrap();
+        assert_eq!(tool_calls.len(), 1);
+        assert_eq!(tool_calls[0].name, "get_article_summary");
+        let arguments = tool_calls[0].arguments.as_ref().unwrap();
+        assert_eq!(arguments["query"], Some("axum".to_string()));
+        assert_eq!(arguments["limit"], Some("3".to_string()));
+        let requests = requests.lock().unwrap();
+        assert_eq!(
+            requests[0]["tools"][0]["function"]["name"],
+            "get_article_summary"
+        );
+    }
+
+    #[test]
+    fn test_events() {
+        // Arrange
+        let mut decoder = Decoder::new();
+        let mut frames = decoder.decode(
+            concat!(
+            "data: {\"choices\":[{\"delta\":{\"role\":\"assistant\"}}]}\n\n",
+            "data: {\"choices\":[{\"delta\":{\"content\":\"Hel\"}}]}\n\n",
+            "data: {\"choices\":[],\"usage\":{\"prompt_tokens\":5,",
+        )
+            .as_bytes(),
+        );
+        frames.extend(decoder.decode(
+            b"\"completion_tokens\":2,\"total_tokens\":7}}\n\ndata: [DONE]\n\n",
+        ));
+
+        // Act
+        let decoded = frames
+            .iter()
+            .filter_map(|frame| match frame {
+                Frame::Data(data) => Some(events(data).unwrap()),
+                Frame::Done => None,
+            })
+            .flatten()
+            .collect::<Vec<_>>();
+
+        // Assert
+        assert_eq!(
+            decoded,
+            vec![
+                StreamEvent::Delta("Hel".to_string()),
+                StreamEvent::Usage(Usage {
+                    prompt_tokens: 5,
+                    completion_tokens: 2,
+                    total_tokens: 7,
+                }),
+            ]
+        );
+        assert_eq!(frames.last(), Some(&Frame::Done));
+        assert!(events("not json").is_err());
+    }
+}
@@ -0,0 +1,177 @@
+use bytes::Bytes;
+use cloudflare::models::{
+    text_embeddings::{StringOrArray, TextEmbeddings, TextEmbeddingsRequest},
+    text_generation::{
+        Message, MessageRequest, ModelParameters, TextGeneration,
+        TextGenerationJsonResult, TextGenerationRequest, Tool,
+    },
+    text_to_image::{TextToImage, TextToImageRequest},
+    Models,
+};
+
+use crate::{ChatStream, Llm};
+
+impl Llm for Models {
+    async fn chat(
+        &self,
+        model: &str,
+        messages: Vec<Message>,
+        tools: Vec<Tool>,
+        parameters: Option<ModelParameters>,
+    ) -> anyhow::Result<TextGenerationJsonResult> {
+        let response = self
+            .text_generation(
+                model,
+                TextGenerationRequest::Message(MessageRequest {
+                    messages,
+                    tools,
+                    model_parameters: parameters,
+                    ..Default::default()
+                }),
+            )
+            .await?;
+
+        Ok(response.result)
+    }
+
+    fn chat_stream(
+        &self,
+        model: &str,
+        messages: Vec<Message>,
+        tools: Vec<Tool>,
+        parameters: Option<ModelParameters>,
+    ) -> ChatStream {
+        Box::pin(self.clone().text_generation_with_stream(
+            model.to_string(),
+            TextGenerationRequest::Message(MessageRequest {
+                messages,
+                stream: Some(true),
+                tools,
+                model_parameters: parameters,
+            }),
+        ))
+    }
+
+    async fn embed(
+        &self,
+        model: &str,
+        texts: Vec<String>,
+    ) -> anyhow::Result<Vec<Vec<f32>>> {
+        let response = self
+            .text_embeddings(
+                model,
+                TextEmbeddingsRequest {
+                    text: StringOrArray::Array(texts),
+                },
+            )
+            .await?;
+
+        Ok(response.result.data)
+    }
+
+    async fn text_to_image(
+        &self,
+        model: &str,
+        prompt: &str,
+    ) -> anyhow::Result<Bytes> {
+        TextToImage::text_to_image(
+            self,
+            model,
+            TextToImageRequest {
+                prompt: prompt.to_string(),
+                ..Default::default()
+            },
+        )
+        .await
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use cloudflare::{
+        fake::{FakeCloudflare, Reply},
+        models::text_generation::{
+            Message, StreamEvent, TextGenerationJsonResult, Usage,
+        },
+    };
+    use futures_util::StreamExt;
+
+    use crate::{Model, Provider};
+
+    fn result(
+        response: &str,
+        usage: Option<Usage>,
+    ) -> TextGenerationJsonResult {
+        TextGenerationJsonResult {
+            response: Some(response.to_string()),
+            tool_calls: None,
+            usage,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_chat() {
+        // Arrange
+        let fake = FakeCloudflare::start().await.unwrap();
+        let usage = Usage {
+            prompt_tokens: 3,
+            completion_tokens: 1,
+            total_tokens: 4,
+        };
+        fake.reply("@cf/chat", Reply::Generation(result("hi", None)))
+            .reply(
+                "@cf/chat",
+                Reply::Stream(vec![
+                    result("h", None),
+                    result("i", Some(usage.clone())),
+                ]),
+            );
+        let model = Model::new(Provider::Cloudflare(fake.models()), "@cf/chat");
+        let messages = vec![Message {
+            role: "user".to_string(),
+            content: "hello".to_string(),
+        }];
+
+        // Act
+        let response = model.chat(messages.clone(), None).await.unwrap();
+        let events = model
+            .chat_stream(messages, None)
+            .collect::<Vec<_>>()
+            .await
+            .into_iter()
+            .map(|event| event.unwrap())
+            .collect::<Vec<_>>();
+
+        // Assert
+        assert_eq!(response.response, Some("hi".to_string()));
+        assert_eq!(
+            events,
+            vec![
+                StreamEvent::Delta("h".to_string()),
+                StreamEvent::Delta("i".to_string()),
+                StreamEvent::Usage(usage),
+            ]
+        );
+        let requests = fake.requests();
+        assert_eq!(requests[0].body["messages"][0]["content"], "hello");
+        assert_eq!(requests[1].body["stream"], true);
+    }
+
+    #[tokio::test]
+    async fn test_embed() {
+        // Arrange
+        let fake = FakeCloudflare::start().await.unwrap();
+        fake.reply("@cf/bge", Reply::Embeddings { dimensions: 4 });
+        let model = Model::new(Provider::Cloudflare(fake.models()), "@cf/bge");
+
+        // Act
+        let embeddings = model
+            .embed(vec!["a".to_string(), "b".to_string()])
+            .await
+            .unwrap();
+
+        // Assert
+        assert_eq!(embeddings.len(), 2);
+        assert!(embeddings.iter().all(|vector| vector.len() == 4));
+    }
+}
@@ -23,6 +23,9 @@ mod m20240528_141124_add_tools_prompt;
 mod m20240529_121200_add_draft_column;
 mod m20240529_132201_create_nudge_table;
 mod m20240529_134720_add_page_id_column;
+mod m20240601_093012_add_search_vector_column;
+mod m20240602_041530_add_trace_id_column;
+mod m20240602_041845_create_feedback_table;
 
 pub struct Migrator;
 
@@ -55,6 +58,9 @@ impl MigratorTrait for Migrator {
             Box::new(m20240529_121200_add_draft_column::Migration),
             Box::new(m20240529_132201_create_nudge_table::Migration),
             Box::new(m20240529_134720_add_page_id_column::Migration),
+            Box::new(m20240601_093012_add_search_vector_column::Migration),
+            Box::new(m20240602_041530_add_trace_id_column::Migration),
+            Box::new(m20240602_041845_create_feedback_table::Migration),
         ]
     }
 }
@@ -0,0 +1,114 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+// Pages are weighted by title then summary, and blocks contribute every
+// rich text `plain_text` of the page body. Both are derived from the
+// stored notion json, so the sync needs no change to keep them fresh. A
+// page without a title is still found by its summary.
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        let db = manager.get_connection();
+
+        db.execute_unprepared(
+            r#"ALTER TABLE "page" ADD COLUMN "search_vector" tsvector
+            GENERATED ALWAYS AS (
+                setweight(to_tsvector('simple', coalesce("title", '')), 'A')
+                || setweight(to_tsvector('simple', jsonb_path_query_array(
+                    "contents"::jsonb,
+                    'lax $.properties.summary.rich_text[*].plain_text'
+                )), 'B')
+            ) STORED"#,
+        )
+        .await?;
+
+        db.execute_unprepared(
+            r#"ALTER TABLE "block" ADD COLUMN "search_vector" tsvector
+            GENERATED ALWAYS AS (
+                to_tsvector('simple', jsonb_path_query_array(
+                    "contents"::jsonb,
+                    'strict $.** ? (exists(@.plain_text)).plain_text'
+                ))
+            ) STORED"#,
+        )
+        .await?;
+
+        manager
+            .create_index(
+                Index::create()
+                    .table(Page::Table)
+                    .name("idx_page_search_vector")
+                    .col(Page::SearchVector)
+                    .index_type(IndexType::Custom(SeaRc::new(Alias::new(
+                        "GIN",
+                    ))))
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_index(
+                Index::create()
+                    .table(Block::Table)
+                    .name("idx_block_search_vector")
+                    .col(Block::SearchVector)
+                    .index_type(IndexType::Custom(SeaRc::new(Alias::new(
+                        "GIN",
+                    ))))
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_index(
+                Index::drop()
+                    .table(Block::Table)
+                    .name("idx_block_search_vector")
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .drop_index(
+                Index::drop()
+                    .table(Page::Table)
+                    .name("idx_page_search_vector")
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Block::Table)
+                    .drop_column(Block::SearchVector)
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Page::Table)
+                    .drop_column(Page::SearchVector)
+                    .to_owned(),
+            )
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum Page {
+    Table,
+    SearchVector,
+}
+
+#[derive(DeriveIden)]
+enum Block {
+    Table,
+    SearchVector,
+}
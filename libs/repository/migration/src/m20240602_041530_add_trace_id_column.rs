@@ -0,0 +1,50 @@
+use sea_orm_migration::prelude::*;
+
+use crate::m20240517_085142_create_prompt_table::Prompt;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Prompt::Table)
+                    .add_column(ColumnDef::new(Alias::new("trace_id")).string())
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_index(
+                Index::create()
+                    .table(Prompt::Table)
+                    .name("idx_prompt_trace_id")
+                    .col(Alias::new("trace_id"))
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_index(
+                Index::drop()
+                    .table(Prompt::Table)
+                    .name("idx_prompt_trace_id")
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Prompt::Table)
+                    .drop_column(Alias::new("trace_id"))
+                    .to_owned(),
+            )
+            .await
+    }
+}
@@ -0,0 +1,76 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(Feedback::Table)
+                    .if_not_exists()
+                    .col(
+                        ColumnDef::new(Feedback::Id)
+                            .integer()
+                            .not_null()
+                            .auto_increment()
+                            .primary_key(),
+                    )
+                    .col(
+                        ColumnDef::new(Feedback::PromptId).integer().not_null(),
+                    )
+                    .col(ColumnDef::new(Feedback::UserId).integer().not_null())
+                    .col(ColumnDef::new(Feedback::TraceId).string().not_null())
+                    .col(ColumnDef::new(Feedback::Name).string().not_null())
+                    .col(ColumnDef::new(Feedback::Value).double().not_null())
+                    .col(ColumnDef::new(Feedback::Comment).string())
+                    .col(
+                        ColumnDef::new(Feedback::CreatedAt)
+                            .date_time()
+                            .not_null(),
+                    )
+                    .col(
+                        ColumnDef::new(Feedback::UpdatedAt)
+                            .date_time()
+                            .not_null(),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+
+        // A user keeps one feedback per answer; sending again replaces it.
+        manager
+            .create_index(
+                Index::create()
+                    .table(Feedback::Table)
+                    .name("idx_feedback_prompt_id_user_id")
+                    .col(Feedback::PromptId)
+                    .col(Feedback::UserId)
+                    .unique()
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(Feedback::Table).to_owned())
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+pub enum Feedback {
+    Table,
+    Id,
+    PromptId,
+    UserId,
+    TraceId,
+    Name,
+    Value,
+    Comment,
+    CreatedAt,
+    UpdatedAt,
+}
@@ -4,6 +4,7 @@ pub mod prelude;
 
 pub mod block;
 pub mod event;
+pub mod feedback;
 pub mod notion_database;
 pub mod nudge;
 pub mod page;
@@ -2,6 +2,7 @@
 
 pub use super::block::Entity as Block;
 pub use super::event::Entity as Event;
+pub use super::feedback::Entity as Feedback;
 pub use super::notion_database::Entity as NotionDatabase;
 pub use super::nudge::Entity as Nudge;
 pub use super::page::Entity as Page;
@@ -13,6 +13,7 @@ pub struct Model {
     pub created_at: DateTime,
     pub updated_at: DateTime,
     pub tools_prompt: String,
+    pub trace_id: Option<String>,
 }
 
 #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
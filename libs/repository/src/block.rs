@@ -4,10 +4,13 @@ use sea_orm::{
     IntoActiveValue, Iterable,
 };
 
-use sea_orm::QueryFilter;
+use sea_orm::{JoinType, QueryFilter, QueryOrder, QuerySelect};
 
 use crate::active_models::{prelude::*, *};
+use crate::cursor::{after, between, paginate};
+use chrono::DateTime;
 use chrono::TimeZone;
+use entity::cursor::Cursor;
 use entity::prelude::*;
 
 use self::block::Column;
@@ -52,6 +55,58 @@ impl BlockRepository {
         Ok(blocks.into_iter().map(BlockEntity::from).collect())
     }
 
+    pub async fn find(
+        &self,
+        limit: u64,
+        cursor: Option<Cursor>,
+        from: Option<DateTime<Utc>>,
+        to: Option<DateTime<Utc>>,
+    ) -> anyhow::Result<(Vec<BlockEntity>, Option<Cursor>)> {
+        // Blocks are ordered by the creation of the page they belong to.
+        let mut query = Block::find()
+            .join(
+                JoinType::InnerJoin,
+                block::Entity::belongs_to(page::Entity)
+                    .from(Column::NotionPageId)
+                    .to(page::Column::NotionPageId)
+                    .into(),
+            )
+            .filter(page::Column::Draft.eq(false))
+            .filter(between(page::Column::CreatedAt, from, to))
+            .order_by_desc(page::Column::CreatedAt)
+            .order_by_desc(page::Column::NotionPageId);
+
+        if let Some(cursor) = cursor {
+            query = query.filter(after(
+                &cursor,
+                page::Column::CreatedAt,
+                page::Column::NotionPageId,
+            ));
+        }
+
+        let blocks = query
+            .select_also(page::Entity)
+            .limit(limit + 1)
+            .all(&self.db)
+            .await?;
+        let (blocks, next) =
+            paginate(blocks, Some(limit), |(block, page)| Cursor {
+                created_at: page
+                    .as_ref()
+                    .map(|page| page.created_at.and_utc())
+                    .unwrap_or_default(),
+                id: block.notion_page_id.clone(),
+            });
+
+        Ok((
+            blocks
+                .into_iter()
+                .map(|(block, _)| BlockEntity::from(block))
+                .collect(),
+            next,
+        ))
+    }
+
     pub async fn find_by_notion_page_id(
         &self,
         id: &str,
@@ -0,0 +1,51 @@
+use chrono::{DateTime, Utc};
+use entity::cursor::Cursor;
+use sea_orm::{ColumnTrait, Condition};
+
+// Rows are ordered by (created_at, id) descending, so the next page starts
+// strictly after the last row of the previous one.
+pub fn after<C: ColumnTrait>(
+    cursor: &Cursor,
+    created_at: C,
+    id: C,
+) -> Condition {
+    let created_at_value = cursor.created_at.naive_utc();
+    Condition::any().add(created_at.lt(created_at_value)).add(
+        Condition::all()
+            .add(created_at.eq(created_at_value))
+            .add(id.lt(cursor.id.clone())),
+    )
+}
+
+pub fn between<C: ColumnTrait>(
+    created_at: C,
+    from: Option<DateTime<Utc>>,
+    to: Option<DateTime<Utc>>,
+) -> Condition {
+    let mut condition = Condition::all();
+    if let Some(from) = from {
+        condition = condition.add(created_at.gte(from.naive_utc()));
+    }
+    if let Some(to) = to {
+        condition = condition.add(created_at.lt(to.naive_utc()));
+    }
+    condition
+}
+
+// Queries fetch one extra row to know whether another page exists.
+pub fn paginate<T>(
+    mut rows: Vec<T>,
+    limit: Option<u64>,
+    key: impl Fn(&T) -> Cursor,
+) -> (Vec<T>, Option<Cursor>) {
+    let Some(limit) = limit else {
+        return (rows, None);
+    };
+    if rows.len() as u64 <= limit {
+        return (rows, None);
+    }
+
+    rows.truncate(limit as usize);
+    let next = rows.last().map(key);
+    (rows, next)
+}
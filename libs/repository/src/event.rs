@@ -7,7 +7,11 @@ use sea_orm::{
 use sea_orm::{ColumnTrait, QueryFilter};
 
 use crate::active_models::{prelude::*, *};
+use crate::cursor::{after, between, paginate};
+use chrono::DateTime;
+use entity::cursor::Cursor;
 use entity::prelude::*;
+use sea_orm::sea_query::Expr;
 
 use self::event::Column;
 
@@ -50,17 +54,48 @@ impl From<EventEntity> for event::ActiveModel {
 impl EventRepository {
     pub async fn find(
         &self,
-        offset: u64,
         limit: u64,
-    ) -> anyhow::Result<Vec<EventEntity>> {
-        let events = Event::find()
+        cursor: Option<Cursor>,
+        repo: Option<String>,
+        r#type: Option<String>,
+        from: Option<DateTime<Utc>>,
+        to: Option<DateTime<Utc>>,
+    ) -> anyhow::Result<(Vec<EventEntity>, Option<Cursor>)> {
+        let mut query = Event::find()
+            .filter(between(event::Column::CreatedAt, from, to))
             .order_by_desc(event::Column::CreatedAt)
-            .offset(offset)
-            .limit(limit)
-            .all(&self.db)
-            .await?;
+            .order_by_desc(event::Column::GithubEventId);
+
+        if let Some(cursor) = cursor {
+            query = query.filter(after(
+                &cursor,
+                event::Column::CreatedAt,
+                event::Column::GithubEventId,
+            ));
+        }
 
-        Ok(events.into_iter().map(EventEntity::from).collect())
+        // Contents hold the raw GitHub event json.
+        if let Some(repo) = repo {
+            query = query.filter(Expr::cust_with_values(
+                "(contents::jsonb -> 'repo' ->> 'name') = $1",
+                [repo],
+            ));
+        }
+
+        if let Some(r#type) = r#type {
+            query = query.filter(Expr::cust_with_values(
+                "(contents::jsonb ->> 'type') = $1",
+                [r#type],
+            ));
+        }
+
+        let events = query.limit(limit + 1).all(&self.db).await?;
+        let (events, next) = paginate(events, Some(limit), |event| Cursor {
+            created_at: event.created_at.and_utc(),
+            id: event.github_event_id.clone(),
+        });
+
+        Ok((events.into_iter().map(EventEntity::from).collect(), next))
     }
 
     pub async fn find_all(&self) -> anyhow::Result<Vec<EventEntity>> {
@@ -0,0 +1,83 @@
+use chrono::{NaiveDateTime, Utc};
+use sea_orm::{sea_query, ActiveValue, DatabaseConnection, EntityTrait};
+
+use crate::active_models::{prelude::*, *};
+use entity::prelude::*;
+
+#[derive(Clone, Debug)]
+pub struct FeedbackRepository {
+    db: DatabaseConnection,
+}
+
+impl FeedbackRepository {
+    pub fn new(db: DatabaseConnection) -> Self {
+        Self { db }
+    }
+}
+
+impl From<feedback::Model> for FeedbackEntity {
+    fn from(value: feedback::Model) -> Self {
+        FeedbackEntity {
+            id: value.id,
+            prompt_id: value.prompt_id,
+            user_id: value.user_id,
+            trace_id: value.trace_id,
+            name: value.name,
+            value: value.value,
+            comment: value.comment,
+            created_at: value.created_at,
+            updated_at: value.updated_at,
+        }
+    }
+}
+
+impl From<FeedbackEntity> for feedback::ActiveModel {
+    fn from(value: FeedbackEntity) -> Self {
+        Self {
+            id: if value.id == i32::default() {
+                ActiveValue::not_set()
+            } else {
+                ActiveValue::Set(value.id)
+            },
+            prompt_id: ActiveValue::Set(value.prompt_id),
+            user_id: ActiveValue::Set(value.user_id),
+            trace_id: ActiveValue::Set(value.trace_id),
+            name: ActiveValue::Set(value.name),
+            value: ActiveValue::Set(value.value),
+            comment: ActiveValue::Set(value.comment),
+            created_at: if value.created_at == NaiveDateTime::default() {
+                ActiveValue::Set(Utc::now().naive_utc())
+            } else {
+                ActiveValue::Set(value.created_at)
+            },
+            updated_at: ActiveValue::Set(Utc::now().naive_utc()),
+        }
+    }
+}
+
+impl FeedbackRepository {
+    // A user has one feedback per prompt, so sending it again replaces it.
+    pub async fn save(&self, feedback: FeedbackEntity) -> anyhow::Result<()> {
+        let feedback = feedback::ActiveModel::from(feedback);
+
+        Feedback::insert(feedback)
+            .on_conflict(
+                sea_query::OnConflict::columns([
+                    feedback::Column::PromptId,
+                    feedback::Column::UserId,
+                ])
+                .update_columns([
+                    feedback::Column::TraceId,
+                    feedback::Column::Name,
+                    feedback::Column::Value,
+                    feedback::Column::Comment,
+                    feedback::Column::UpdatedAt,
+                ])
+                .to_owned(),
+            )
+            .exec(&self.db)
+            .await?;
+
+        Ok(())
+    }
+}
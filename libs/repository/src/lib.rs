@@ -2,6 +2,7 @@ use std::time::Duration;
 
 use block::BlockRepository;
 use event::EventRepository;
+use feedback::FeedbackRepository;
 use migration::Migrator;
 use migration::MigratorTrait;
 use notion_database::NotionDatabaseRepository;
@@ -10,7 +11,7 @@ use page::PageRepository;
 use post::PostRepository;
 use prompt::PromptRepository;
 use prompt_session::PromptSessionRepository;
-use sea_orm::{ConnectOptions, Database};
+use sea_orm::{ConnectOptions, Database, DatabaseConnection};
 use session::SessionRepository;
 use shuttle_persist::PersistInstance;
 use static_page::StaticPageRepository;
@@ -19,7 +20,9 @@ use user::UserRepository;
 
 mod active_models;
 pub mod block;
+mod cursor;
 pub mod event;
+pub mod feedback;
 pub mod notion_database;
 pub mod nudge;
 pub mod page;
@@ -43,6 +46,7 @@ pub struct Repository {
     pub notion_database_id: NotionDatabaseRepository,
     pub static_page: StaticPageRepository,
     pub nudge: NudgeRepository,
+    pub feedback: FeedbackRepository,
     pub top: Option<TopRepository>,
     pub session: Option<SessionRepository>,
 }
@@ -60,7 +64,13 @@ impl Repository {
 
         Migrator::up(&db, None).await?;
 
-        Ok(Self {
+        Ok(Self::from_connection(db))
+    }
+
+    // Repositories on `db` as it is, without migrating it, for tests
+    // without a database.
+    pub fn from_connection(db: DatabaseConnection) -> Self {
+        Self {
             post: PostRepository::new(db.clone()),
             page: PageRepository::new(db.clone()),
             static_page: StaticPageRepository::new(db.clone()),
@@ -71,9 +81,10 @@ impl Repository {
             prompt_session: PromptSessionRepository::new(db.clone()),
             prompt: PromptRepository::new(db.clone()),
             nudge: NudgeRepository::new(db.clone()),
+            feedback: FeedbackRepository::new(db.clone()),
             top: None,
             session: None,
-        })
+        }
     }
 
     pub fn with_cache(self, cache: PersistInstance) -> Self {
@@ -1,8 +1,9 @@
 use chrono::{TimeZone, Utc};
 use entity::page::ParentType;
+use entity::search::{HIGHLIGHT_END, HIGHLIGHT_START};
 use sea_orm::{
-    sea_query, ActiveValue, DatabaseConnection, EntityTrait, Iterable,
-    QueryFilter, QueryOrder, QuerySelect,
+    sea_query, ActiveValue, DatabaseConnection, DbBackend, EntityTrait,
+    FromQueryResult, Iterable, QueryFilter, QueryOrder, QuerySelect, Statement,
 };
 
 use sea_orm::ColumnTrait;
@@ -55,6 +56,85 @@ impl From<PageEntity> for page::ActiveModel {
     }
 }
 
+// Ranks published pages by their own vector and their blocks' vector, and
+// builds the snippet from the summary and body text of the top hits only.
+const SEARCH_SQL: &str = r#"
+WITH "query" AS (
+    SELECT websearch_to_tsquery('simple', $1) AS "q"
+), "hit" AS (
+    SELECT
+        "page"."notion_page_id",
+        "page"."title",
+        "page"."contents" AS "page_contents",
+        "block"."contents" AS "block_contents",
+        "page"."created_at",
+        ts_rank(
+            "page"."search_vector"
+                || coalesce("block"."search_vector", ''::tsvector),
+            "query"."q"
+        ) AS "rank"
+    FROM "page"
+    LEFT JOIN "block"
+        ON "block"."notion_page_id" = "page"."notion_page_id"
+    CROSS JOIN "query"
+    WHERE "page"."draft" = false
+        AND (
+            "page"."search_vector" @@ "query"."q"
+            OR "block"."search_vector" @@ "query"."q"
+        )
+    ORDER BY "rank" DESC, "page"."created_at" DESC
+    LIMIT $2
+)
+SELECT
+    "hit"."notion_page_id",
+    coalesce((
+        SELECT string_agg("text", '')
+        FROM jsonb_array_elements_text(jsonb_path_query_array(
+            "hit"."page_contents"::jsonb,
+            'lax $.properties.title.title[*].plain_text'
+        )) AS "text"
+    ), "hit"."title") AS "title",
+    ts_headline('simple', concat_ws(' ', (
+        SELECT string_agg("text", ' ')
+        FROM jsonb_array_elements_text(jsonb_path_query_array(
+            "hit"."page_contents"::jsonb,
+            'lax $.properties.summary.rich_text[*].plain_text'
+        )) AS "text"
+    ), (
+        SELECT string_agg("text", ' ')
+        FROM jsonb_array_elements_text(jsonb_path_query_array(
+            "hit"."block_contents"::jsonb,
+            'strict $.** ? (exists(@.plain_text)).plain_text'
+        )) AS "text"
+    )), "query"."q", $3) AS "snippet",
+    "hit"."rank",
+    "hit"."created_at"
+FROM "hit"
+CROSS JOIN "query"
+ORDER BY "hit"."rank" DESC, "hit"."created_at" DESC
+"#;
+
+#[derive(FromQueryResult)]
+struct SearchRow {
+    notion_page_id: String,
+    title: String,
+    snippet: String,
+    rank: f32,
+    created_at: sea_orm::prelude::DateTime,
+}
+
+impl From<SearchRow> for SearchHitEntity {
+    fn from(value: SearchRow) -> Self {
+        Self {
+            notion_page_id: value.notion_page_id,
+            title: value.title,
+            snippet: value.snippet,
+            rank: value.rank,
+            created_at: value.created_at.and_utc(),
+        }
+    }
+}
+
 impl PageRepository {
     pub async fn find(
         &self,
@@ -69,7 +149,9 @@ impl PageRepository {
             .one(&self.db)
             .await?;
 
-        let mut query = Page::find().order_by_desc(page::Column::CreatedAt);
+        let mut query = Page::find()
+            .filter(page::Column::Draft.eq(false))
+            .order_by_desc(page::Column::CreatedAt);
 
         if let Some(database) = database {
             query = query.filter(page::Column::NotionParentId.eq(database.id));
@@ -98,6 +180,16 @@ impl PageRepository {
         Ok(pages.into_iter().map(PageEntity::from).collect())
     }
 
+    pub async fn find_published(&self) -> anyhow::Result<Vec<PageEntity>> {
+        let pages = Page::find()
+            .filter(page::Column::Draft.eq(false))
+            .order_by_desc(page::Column::CreatedAt)
+            .all(&self.db)
+            .await?;
+
+        Ok(pages.into_iter().map(PageEntity::from).collect())
+    }
+
     pub async fn find_by_id(
         &self,
         id: &str,
@@ -110,6 +202,32 @@ impl PageRepository {
         Ok(page.map(PageEntity::from))
     }
 
+    pub async fn find_published_by_id(
+        &self,
+        id: &str,
+    ) -> anyhow::Result<Option<PageEntity>> {
+        let page = page::Entity::find()
+            .filter(Column::NotionPageId.eq(id))
+            .filter(Column::Draft.eq(false))
+            .one(&self.db)
+            .await?;
+
+        Ok(page.map(PageEntity::from))
+    }
+
+    pub async fn find_published_by_ids(
+        &self,
+        ids: &[String],
+    ) -> anyhow::Result<Vec<PageEntity>> {
+        let pages = page::Entity::find()
+            .filter(Column::NotionPageId.is_in(ids))
+            .filter(Column::Draft.eq(false))
+            .all(&self.db)
+            .await?;
+
+        Ok(pages.into_iter().map(PageEntity::from).collect())
+    }
+
     pub async fn find_by_word(
         &self,
         word: &str,
@@ -122,6 +240,29 @@ impl PageRepository {
         Ok(page.map(PageEntity::from))
     }
 
+    pub async fn search(
+        &self,
+        query: &str,
+        limit: u64,
+    ) -> anyhow::Result<Vec<SearchHitEntity>> {
+        let options = format!(
+            "StartSel={}, StopSel={}, MaxWords=35, MinWords=15, \
+             MaxFragments=2, FragmentDelimiter=\" ... \"",
+            HIGHLIGHT_START, HIGHLIGHT_END
+        );
+
+        let rows =
+            SearchRow::find_by_statement(Statement::from_sql_and_values(
+                DbBackend::Postgres,
+                SEARCH_SQL,
+                [query.into(), (limit as i64).into(), options.into()],
+            ))
+            .all(&self.db)
+            .await?;
+
+        Ok(rows.into_iter().map(SearchHitEntity::from).collect())
+    }
+
     pub async fn save(&self, page: PageEntity) -> anyhow::Result<()> {
         let mut page = page::ActiveModel::from(page);
         page.updated_at = ActiveValue::set(Some(Utc::now().naive_utc()));
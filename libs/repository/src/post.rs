@@ -2,10 +2,13 @@ use sea_orm::{
     sea_query, strum::IntoEnumIterator as _, ActiveValue, DatabaseConnection,
     EntityTrait, QueryFilter,
 };
-use sea_orm::{ColumnTrait, QueryOrder};
+use sea_orm::{ColumnTrait, QueryOrder, QuerySelect};
 use std::collections::HashMap;
 
 use crate::active_models::{prelude::*, *};
+use crate::cursor::{after, between, paginate};
+use chrono::{DateTime, Utc};
+use entity::cursor::Cursor;
 use entity::prelude::*;
 use sea_orm::IntoActiveValue;
 use strum::IntoEnumIterator as _;
@@ -36,10 +39,49 @@ impl From<post::Model> for PostEntity {
 
 impl PostRepository {
     pub async fn find_all(&self) -> anyhow::Result<Vec<PostEntity>> {
-        let posts = Post::find()
+        let (posts, _) = self.find(None, None, None, None, None).await?;
+
+        Ok(posts)
+    }
+
+    pub async fn find(
+        &self,
+        limit: Option<u64>,
+        cursor: Option<Cursor>,
+        category: Option<entity::post::Category>,
+        from: Option<DateTime<Utc>>,
+        to: Option<DateTime<Utc>>,
+    ) -> anyhow::Result<(Vec<PostEntity>, Option<Cursor>)> {
+        let drafts = sea_query::Query::select()
+            .column(page::Column::NotionPageId)
+            .from(Page)
+            .and_where(page::Column::Draft.eq(true))
+            .to_owned();
+        let mut query = Post::find()
+            .filter(post::Column::Id.not_in_subquery(drafts))
+            .filter(between(post::Column::CreatedAt, from, to))
             .order_by_desc(post::Column::CreatedAt)
-            .all(&self.db)
-            .await?;
+            .order_by_desc(post::Column::Id);
+
+        if let Some(cursor) = cursor {
+            query = query.filter(after(
+                &cursor,
+                post::Column::CreatedAt,
+                post::Column::Id,
+            ));
+        }
+
+        if let Some(category) = category {
+            query = query
+                .filter(post::Column::Category.eq(Category::from(category)));
+        }
+
+        let posts = query.limit(limit.map(|l| l + 1)).all(&self.db).await?;
+        let (posts, next) = paginate(posts, limit, |post| Cursor {
+            created_at: post.created_at.and_utc(),
+            id: post.id.clone(),
+        });
+
         let event_ids: Vec<_> = posts
             .iter()
             .filter(|x| x.category == Category::Event)
@@ -80,7 +122,7 @@ impl PostRepository {
             });
         }
 
-        Ok(results)
+        Ok((results, next))
     }
 
     pub async fn save(&self, post: PostEntity) -> anyhow::Result<()> {
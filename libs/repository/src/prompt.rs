@@ -1,5 +1,10 @@
+use std::collections::HashMap;
+
 use chrono::{NaiveDateTime, Utc};
-use sea_orm::{ActiveModelTrait, ActiveValue, DatabaseConnection, EntityTrait};
+use sea_orm::{
+    ActiveModelTrait, ActiveValue, ColumnTrait, DatabaseConnection,
+    EntityTrait, QueryFilter, QueryOrder,
+};
 
 use crate::active_models::{prelude::*, *};
 use entity::prelude::*;
@@ -23,6 +28,7 @@ impl From<prompt::Model> for PromptEntity {
             user_prompt: value.user_prompt,
             assistant_prompt: value.assistant_prompt,
             tools_prompt: value.tools_prompt,
+            trace_id: value.trace_id,
             created_at: value.created_at,
             updated_at: value.updated_at,
         }
@@ -41,6 +47,7 @@ impl From<PromptEntity> for prompt::ActiveModel {
             user_prompt: ActiveValue::Set(value.user_prompt),
             assistant_prompt: ActiveValue::Set(value.assistant_prompt),
             tools_prompt: ActiveValue::Set(value.tools_prompt),
+            trace_id: ActiveValue::Set(value.trace_id),
             created_at: if value.created_at == NaiveDateTime::default() {
                 ActiveValue::Set(Utc::now().naive_utc())
             } else {
@@ -58,6 +65,57 @@ impl PromptRepository {
         Ok(prompts.into_iter().map(PromptEntity::from).collect())
     }
 
+    // Prompts of the session in the order they were asked, each with the
+    // ids of the pages cited in its answer.
+    pub async fn find_by_session_id(
+        &self,
+        prompt_session_id: &str,
+    ) -> anyhow::Result<Vec<(PromptEntity, Vec<String>)>> {
+        let prompts = Prompt::find()
+            .filter(prompt::Column::PromptSessionId.eq(prompt_session_id))
+            .order_by_asc(prompt::Column::CreatedAt)
+            .order_by_asc(prompt::Column::Id)
+            .all(&self.db)
+            .await?;
+
+        let prompt_pages = prompt_page::Entity::find()
+            .filter(
+                prompt_page::Column::PromptId
+                    .is_in(prompts.iter().map(|prompt| prompt.id)),
+            )
+            .order_by_asc(prompt_page::Column::Id)
+            .all(&self.db)
+            .await?;
+
+        let mut page_ids: HashMap<i32, Vec<String>> = HashMap::new();
+        for prompt_page in prompt_pages {
+            page_ids
+                .entry(prompt_page.prompt_id)
+                .or_default()
+                .push(prompt_page.page_id);
+        }
+
+        Ok(prompts
+            .into_iter()
+            .map(|prompt| {
+                let page_ids = page_ids.remove(&prompt.id).unwrap_or_default();
+                (PromptEntity::from(prompt), page_ids)
+            })
+            .collect())
+    }
+
+    pub async fn find_by_trace_id(
+        &self,
+        trace_id: &str,
+    ) -> anyhow::Result<Option<PromptEntity>> {
+        let prompt = Prompt::find()
+            .filter(prompt::Column::TraceId.eq(trace_id))
+            .one(&self.db)
+            .await?;
+
+        Ok(prompt.map(PromptEntity::from))
+    }
+
     pub async fn save(
         &self,
         prompt: PromptEntity,
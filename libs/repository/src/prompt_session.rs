@@ -1,10 +1,13 @@
 use chrono::{NaiveDateTime, Utc};
 use sea_orm::{
-    prelude::Uuid, sea_query, ActiveValue, DatabaseConnection, EntityTrait,
-    Iterable,
+    prelude::Uuid, sea_query, ActiveValue, ColumnTrait, DatabaseConnection,
+    EntityTrait, Iterable, QueryFilter, QueryOrder, QuerySelect,
+    TransactionTrait,
 };
 
 use crate::active_models::{prelude::*, *};
+use crate::cursor::{after, paginate};
+use entity::cursor::Cursor;
 use entity::prelude::*;
 
 #[derive(Clone, Debug)]
@@ -58,6 +61,58 @@ impl PromptSessionRepository {
             .collect())
     }
 
+    // Sessions are listed by their last activity, so the cursor carries
+    // `updated_at` in place of the creation time.
+    pub async fn find_by_user_id(
+        &self,
+        user_id: i32,
+        limit: u64,
+        cursor: Option<Cursor>,
+    ) -> anyhow::Result<(Vec<PromptSessionEntity>, Option<Cursor>)> {
+        let mut query = PromptSession::find()
+            .filter(prompt_session::Column::UserId.eq(user_id))
+            .order_by_desc(prompt_session::Column::UpdatedAt)
+            .order_by_desc(prompt_session::Column::Id);
+
+        if let Some(cursor) = cursor {
+            query = query.filter(after(
+                &cursor,
+                prompt_session::Column::UpdatedAt,
+                prompt_session::Column::Id,
+            ));
+        }
+
+        let prompt_sessions = query.limit(limit + 1).all(&self.db).await?;
+
+        let (prompt_sessions, next) =
+            paginate(prompt_sessions, Some(limit), |prompt_session| Cursor {
+                created_at: prompt_session.updated_at.and_utc(),
+                id: prompt_session.id.clone(),
+            });
+
+        Ok((
+            prompt_sessions
+                .into_iter()
+                .map(PromptSessionEntity::from)
+                .collect(),
+            next,
+        ))
+    }
+
+    pub async fn find_by_id_and_user_id(
+        &self,
+        id: &str,
+        user_id: i32,
+    ) -> anyhow::Result<Option<PromptSessionEntity>> {
+        let prompt_session = PromptSession::find()
+            .filter(prompt_session::Column::Id.eq(id))
+            .filter(prompt_session::Column::UserId.eq(user_id))
+            .one(&self.db)
+            .await?;
+
+        Ok(prompt_session.map(PromptSessionEntity::from))
+    }
+
     pub async fn save(
         &self,
         prompt_session: PromptSessionEntity,
@@ -89,4 +144,50 @@ impl PromptSessionRepository {
 
         Ok(())
     }
+
+    // Removes the session with its prompts, their cited pages and feedback.
+    // Returns false when the user has no such session.
+    pub async fn delete_by_user_id(
+        &self,
+        prompt_session_id: &str,
+        user_id: i32,
+    ) -> anyhow::Result<bool> {
+        let txn = self.db.begin().await?;
+
+        let result = prompt_session::Entity::delete_many()
+            .filter(prompt_session::Column::Id.eq(prompt_session_id))
+            .filter(prompt_session::Column::UserId.eq(user_id))
+            .exec(&txn)
+            .await?;
+        if result.rows_affected == 0 {
+            return Ok(false);
+        }
+
+        let prompt_ids = Prompt::find()
+            .select_only()
+            .column(prompt::Column::Id)
+            .filter(prompt::Column::PromptSessionId.eq(prompt_session_id))
+            .into_tuple::<i32>()
+            .all(&txn)
+            .await?;
+
+        prompt_page::Entity::delete_many()
+            .filter(prompt_page::Column::PromptId.is_in(prompt_ids.clone()))
+            .exec(&txn)
+            .await?;
+
+        feedback::Entity::delete_many()
+            .filter(feedback::Column::PromptId.is_in(prompt_ids))
+            .exec(&txn)
+            .await?;
+
+        prompt::Entity::delete_many()
+            .filter(prompt::Column::PromptSessionId.eq(prompt_session_id))
+            .exec(&txn)
+            .await?;
+
+        txn.commit().await?;
+
+        Ok(true)
+    }
 }
@@ -2,43 +2,28 @@ use std::sync::Arc;
 
 use anyhow::Context;
 use chrono::{DateTime, Utc};
-use cloudflare::models::text_embeddings::{
-    StringOrArray, TextEmbeddings, TextEmbeddingsRequest,
-};
 use entity::prelude::*;
-use qdrant_client::{
-    client::QdrantClient,
-    qdrant::{
-        condition::ConditionOneOf, r#match::MatchValue,
-        with_payload_selector::SelectorOptions, Condition, FieldCondition,
-        Filter, Match, PayloadIncludeSelector, ScoredPoint, SearchPoints,
-        WithPayloadSelector,
-    },
-};
+use langfuse::trace::{Observe, Span};
+use llm::{Llms, Model};
 use repository::Repository;
 use rpc_router::{
     router_builder, Router, RpcHandlerError, RpcParams, RpcResource,
 };
 use serde::{Deserialize, Serialize};
 use util::load_config;
+use vector_store::{Hit, Query, Store, VectorStore};
 
 #[derive(Clone, RpcResource)]
 pub struct RpcState {
-    config: Config,
     repo: Repository,
-    qdrant: Arc<QdrantClient>,
-    cloudflare: cloudflare::models::Models,
-}
-
-#[derive(Clone)]
-pub struct Config {
-    pub qdrant: Qdrant,
+    store: Store,
+    embedder: Model,
 }
 
-#[derive(Clone)]
-pub struct Qdrant {
-    pub collection: String,
-}
+/// The span of the tool call, passed with each call, under which the
+/// embedding of the query is recorded.
+#[derive(Clone, RpcResource)]
+pub struct RpcObserver(pub Arc<Span>);
 
 #[derive(Debug, thiserror::Error, RpcHandlerError)]
 pub enum RpcError {
@@ -51,20 +36,22 @@ pub enum RpcError {
 pub fn serve(
     config_name: &str,
     repository: Repository,
-    qdrant: QdrantClient,
+    store: Store,
     cloudflare: cloudflare::models::Models,
 ) -> Result<Router, RpcError> {
     let config = load_config(config_name)?;
+    let embedder = Llms::from_config(
+        config["llm"]
+            .as_table()
+            .context("failed to find llm config")?,
+        cloudflare,
+    )?
+    .task("embedding")?;
 
     // Build the Router with the handlers and common resources
     let rpc_router = router_builder!(
         handlers: [get_article_summary,get_article_detail,get_current_datetime,get_article_title_list,get_information_about_this_site],         // will be turned into routes
-        resources: [RpcState {config:Config{qdrant:Qdrant {
-            collection: config["qdrant"]["collection"]
-                .as_str()
-                .unwrap()
-                .to_string(),
-        }} ,repo:repository,qdrant:Arc::new(qdrant),cloudflare}] // common resources for all calls
+        resources: [RpcState {repo:repository,store,embedder}] // common resources for all calls
     )
     .build();
 
@@ -77,9 +64,11 @@ pub struct ParamsFindByWord {
 }
 pub async fn get_article_summary(
     state: RpcState,
+    observer: RpcObserver,
     params: ParamsFindByWord,
 ) -> Result<Option<PageEntity>, RpcError> {
-    let results = retrieve_from_vector_db(&state, params.query).await?;
+    let results =
+        retrieve_from_vector_db(&state, &observer, params.query).await?;
 
     let mut page: Option<PageEntity> = None;
     for result in results.iter() {
@@ -87,15 +76,8 @@ pub async fn get_article_summary(
             continue;
         }
 
-        // Take page id
-        let Some(page_id) = result.payload.get("page_id") else {
-            continue;
-        };
-        let Some(page_id) = page_id.as_str() else {
-            continue;
-        };
-
-        page = state.repo.page.find_by_id(page_id).await?;
+        let page_id = &result.document.page_id;
+        page = state.repo.page.find_published_by_id(page_id).await?;
 
         if page.is_some() {
             break;
@@ -111,9 +93,11 @@ pub struct ParamsGetArticleFullTextsByTitle {
 }
 pub async fn get_article_detail(
     state: RpcState,
+    observer: RpcObserver,
     params: ParamsGetArticleFullTextsByTitle,
 ) -> Result<Option<BlockEntity>, RpcError> {
-    let results = retrieve_from_vector_db(&state, params.query).await?;
+    let results =
+        retrieve_from_vector_db(&state, &observer, params.query).await?;
 
     let mut block = None;
     for result in results.iter() {
@@ -121,13 +105,11 @@ pub async fn get_article_detail(
             continue;
         }
 
-        // Take page id
-        let Some(page_id) = result.payload.get("page_id") else {
-            continue;
-        };
-        let Some(page_id) = page_id.as_str() else {
+        let page_id = &result.document.page_id;
+        let page = state.repo.page.find_published_by_id(page_id).await?;
+        if page.is_none() {
             continue;
-        };
+        }
 
         block = state.repo.block.find_by_notion_page_id(page_id).await?;
 
@@ -176,54 +158,35 @@ pub async fn get_current_datetime() -> Result<DateTime<Utc>, RpcError> {
 
 async fn retrieve_from_vector_db(
     state: &RpcState,
+    observer: &RpcObserver,
     text: String,
-) -> Result<Vec<ScoredPoint>, RpcError> {
-    let embedding = state
-        .cloudflare
-        .bge_small_en_v1_5(TextEmbeddingsRequest {
-            text: StringOrArray::String(text),
-        })
-        .await?;
-
-    let Some(vector) = embedding.result.data.first() else {
+) -> Result<Vec<Hit>, RpcError> {
+    // Queries are embedded with the model of the stored vectors
+    let served = state.store.model().await?;
+    let model = state.embedder.with_name(&served.model);
+
+    let generation = observer
+        .0
+        .generation("embed query")
+        .model(model.name())
+        .input(text.as_str());
+    let embedding = model.embed(vec![text]).await;
+    generation.end_with(&embedding, |generation, vectors| {
+        generation.output(serde_json::json!({
+            "shape": [vectors.len(), vectors.first().map_or(0, Vec::len)],
+        }))
+    });
+    let vectors = embedding?;
+
+    let Some(vector) = vectors.into_iter().next() else {
         return Ok(vec![]);
     };
 
-    let search_result = state
-        .qdrant
-        .search_points(&SearchPoints {
-            collection_name: state.config.qdrant.collection.clone(),
-            vector: vector.clone(),
-            limit: 5,
-            with_payload: Some(WithPayloadSelector {
-                selector_options: Some(SelectorOptions::Include(
-                    PayloadIncludeSelector {
-                        fields: vec!["page_id".to_string()],
-                    },
-                )),
-            }),
-            filter: Some(Filter {
-                must: vec![Condition {
-                    condition_one_of: Some(ConditionOneOf::Field(
-                        FieldCondition {
-                            key: "type".to_string(),
-                            r#match: Some(Match {
-                                match_value: Some(MatchValue::Keyword(
-                                    serde_json::to_string(
-                                        &DocumentTypeEntity::Page,
-                                    )
-                                    .unwrap(),
-                                )),
-                            }),
-                            ..Default::default()
-                        },
-                    )),
-                }],
-                ..Default::default()
-            }),
-            ..Default::default()
-        })
-        .await?;
-
-    Ok(search_result.result)
+    Ok(state
+        .store
+        .search(
+            &Query::new(vector, served, 5)
+                .document_type(DocumentTypeEntity::Page),
+        )
+        .await?)
 }
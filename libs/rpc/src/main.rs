@@ -1,3 +1,4 @@
+use anyhow::Context;
 use repository::Repository;
 use rpc::serve;
 use rpc_router::{CallResponse, Request};
@@ -27,23 +28,33 @@ async fn main() -> anyhow::Result<()> {
     let cloudflare = cloudflare::models::Models::new(
         cloudflare_account_id,
         cloudflare_token,
-    );
+    )
+    .with_config(cloudflare::config::ClientConfig::from_config(
+        config["cloudflare"]
+            .as_table()
+            .context("failed to find cloudflare config")?,
+    )?);
 
     let rpc_router = serve(
         config_name,
         repository,
-        qdrant_client::client::QdrantClient::from_url(
-            config
-                .get("qdrant")
-                .unwrap()
-                .get("base_url")
-                .unwrap()
-                .as_str()
-                .unwrap(),
-        )
-        .with_api_key(secrets.get("QDRANT_API_KEY").unwrap().as_str().unwrap())
-        .build()
-        .unwrap(),
+        vector_store::Store::from_config(
+            &config,
+            qdrant_client::client::QdrantClient::from_url(
+                config
+                    .get("qdrant")
+                    .unwrap()
+                    .get("base_url")
+                    .unwrap()
+                    .as_str()
+                    .unwrap(),
+            )
+            .with_api_key(
+                secrets.get("QDRANT_API_KEY").unwrap().as_str().unwrap(),
+            )
+            .build()
+            .unwrap(),
+        )?,
         cloudflare,
     )?;
 
@@ -0,0 +1,691 @@
+//! Splits the blocks of a page into chunks to embed.
+//!
+//! Chunks never cross a heading, and a list, table or code block starts a
+//! new chunk rather than being split when it fits in one. Consecutive chunks
+//! of a section share up to `overlap_tokens` of text.
+
+use anyhow::Context as _;
+use notion_client::objects::{
+    block::{Block, BlockType},
+    rich_text::RichText,
+};
+use toml::{map::Map, Value};
+use uuid::Uuid;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ChunkerConfig {
+    pub max_tokens: usize,
+    pub overlap_tokens: usize,
+}
+
+impl ChunkerConfig {
+    pub fn from_config(config: &Map<String, Value>) -> anyhow::Result<Self> {
+        let integer = |key: &str| {
+            config
+                .get(key)
+                .and_then(Value::as_integer)
+                .and_then(|value| usize::try_from(value).ok())
+                .with_context(|| format!("failed to find {}", key))
+        };
+        let max_tokens = integer("max_tokens")?;
+        let overlap_tokens = integer("overlap_tokens")?;
+        anyhow::ensure!(
+            overlap_tokens < max_tokens,
+            "overlap_tokens must be less than max_tokens"
+        );
+
+        Ok(Self {
+            max_tokens,
+            overlap_tokens,
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Chunk {
+    pub text: String,
+    pub heading_path: Vec<String>,
+    pub block_ids: Vec<String>,
+    pub block_types: Vec<String>,
+    pub index: usize,
+}
+
+// Text kept whole unless it's longer than a chunk
+#[derive(Debug)]
+struct Unit {
+    text: String,
+    tokens: usize,
+    block_id: Option<String>,
+    block_type: String,
+}
+
+// The items of a list, the rows of a table, the lines of code or a single
+// block of text
+type Group = Vec<Unit>;
+
+struct Section {
+    heading_path: Vec<String>,
+    groups: Vec<Group>,
+}
+
+/// Point ids of `chunks` derived from the page and the text of each chunk,
+/// so that a chunk keeps its id until its text changes. Repeated text is
+/// told apart by the count of earlier chunks with the same text.
+pub fn point_ids(page_id: &str, chunks: &[Chunk]) -> Vec<Uuid> {
+    let mut seen: Vec<Uuid> = vec![];
+    chunks
+        .iter()
+        .map(|chunk| {
+            let hash =
+                Uuid::new_v5(&Uuid::NAMESPACE_OID, chunk.text.as_bytes());
+            let occurrence = seen.iter().filter(|h| **h == hash).count();
+            seen.push(hash);
+            Uuid::new_v5(
+                &Uuid::NAMESPACE_OID,
+                format!("{}/{}/{}", page_id, hash, occurrence).as_bytes(),
+            )
+        })
+        .collect()
+}
+
+pub fn chunk(blocks: &[Block], config: &ChunkerConfig) -> Vec<Chunk> {
+    let mut chunks = vec![];
+    for section in sections(blocks, config) {
+        let mut packer = Packer::new(config);
+        for group in &section.groups {
+            packer.push_group(group);
+        }
+
+        for units in packer.finish() {
+            let mut block_ids = vec![];
+            let mut block_types = vec![];
+            for unit in &units {
+                if let Some(id) = &unit.block_id {
+                    if !block_ids.contains(id) {
+                        block_ids.push(id.clone());
+                    }
+                }
+                if !block_types.contains(&unit.block_type) {
+                    block_types.push(unit.block_type.clone());
+                }
+            }
+
+            chunks.push(Chunk {
+                text: units
+                    .iter()
+                    .map(|unit| unit.text.as_str())
+                    .collect::<Vec<_>>()
+                    .join("\n"),
+                heading_path: section.heading_path.clone(),
+                block_ids,
+                block_types,
+                index: chunks.len(),
+            });
+        }
+    }
+
+    chunks
+}
+
+fn sections(blocks: &[Block], config: &ChunkerConfig) -> Vec<Section> {
+    let mut headings: Vec<(u8, String)> = vec![];
+    let mut sections = vec![Section {
+        heading_path: vec![],
+        groups: vec![],
+    }];
+    let mut in_list = false;
+
+    for block in blocks {
+        let block_type = block_type_name(&block.block_type);
+        let unit = |text: String| Unit {
+            tokens: count_tokens(&text),
+            text,
+            block_id: block.id.clone(),
+            block_type: block_type.clone(),
+        };
+
+        let heading = match &block.block_type {
+            BlockType::Heading1 { heading_1 } => Some((1, heading_1)),
+            BlockType::Heading2 { heading_2 } => Some((2, heading_2)),
+            BlockType::Heading3 { heading_3 } => Some((3, heading_3)),
+            _ => None,
+        };
+        if let Some((level, heading)) = heading {
+            let text = rich_text(&heading.rich_text);
+            headings.retain(|(l, _)| *l < level);
+            headings.push((level, text.clone()));
+            sections.push(Section {
+                heading_path: headings.iter().map(|(_, h)| h.clone()).collect(),
+                groups: vec![],
+            });
+            in_list = false;
+            if !text.is_empty() {
+                sections.last_mut().unwrap().groups.push(vec![unit(text)]);
+            }
+            continue;
+        }
+
+        let groups = &mut sections.last_mut().unwrap().groups;
+        match &block.block_type {
+            BlockType::BulletedListItem { .. }
+            | BlockType::NumberedListItem { .. }
+            | BlockType::ToDo { .. } => {
+                let text = list_item(block, 0);
+                if text.trim().is_empty() {
+                    continue;
+                }
+                let units = split(unit(text), config.max_tokens);
+                match groups.last_mut() {
+                    Some(group) if in_list => group.extend(units),
+                    _ => groups.push(units),
+                }
+                in_list = true;
+            }
+            BlockType::Table { table } => {
+                let rows = table
+                    .children
+                    .iter()
+                    .flatten()
+                    .filter_map(|row| match &row.block_type {
+                        BlockType::TableRow { table_row } => Some(
+                            table_row
+                                .cells
+                                .iter()
+                                .map(|cell| rich_text(cell))
+                                .collect::<Vec<_>>()
+                                .join(" | "),
+                        ),
+                        _ => None,
+                    })
+                    .flat_map(|row| split(unit(row), config.max_tokens))
+                    .collect::<Vec<_>>();
+                if !rows.is_empty() {
+                    groups.push(rows);
+                }
+                in_list = false;
+            }
+            BlockType::Code { code } => {
+                let text = rich_text(&code.rich_text);
+                in_list = false;
+                if text.trim().is_empty() {
+                    continue;
+                }
+                let units = if count_tokens(&text) <= config.max_tokens {
+                    vec![unit(text)]
+                } else {
+                    // Long code is split between lines
+                    pack(text.split_inclusive('\n'), config.max_tokens)
+                        .into_iter()
+                        .map(|lines| unit(lines.trim_end().to_string()))
+                        .flat_map(|unit| split(unit, config.max_tokens))
+                        .collect()
+                };
+                groups.push(units);
+            }
+            block_type => {
+                let text = block_type
+                    .plain_text()
+                    .into_iter()
+                    .flatten()
+                    .collect::<Vec<_>>()
+                    .join("");
+                in_list = false;
+                if text.trim().is_empty() {
+                    continue;
+                }
+                groups.push(split(unit(text), config.max_tokens));
+            }
+        }
+    }
+
+    sections
+        .into_iter()
+        .filter(|section| !section.groups.is_empty())
+        .collect()
+}
+
+// Fills chunks with units, moving a group that would be split to a new
+// chunk when it fits in one.
+struct Packer<'a> {
+    config: &'a ChunkerConfig,
+    chunks: Vec<Vec<&'a Unit>>,
+    current: Vec<&'a Unit>,
+    // Units at the start of `current` repeated from the previous chunk
+    overlap: usize,
+}
+
+impl<'a> Packer<'a> {
+    fn new(config: &'a ChunkerConfig) -> Self {
+        Self {
+            config,
+            chunks: vec![],
+            current: vec![],
+            overlap: 0,
+        }
+    }
+
+    fn tokens(&self) -> usize {
+        self.current.iter().map(|unit| unit.tokens).sum()
+    }
+
+    fn push_group(&mut self, group: &'a Group) {
+        let tokens = group.iter().map(|unit| unit.tokens).sum::<usize>();
+        if group.len() > 1 && tokens <= self.config.max_tokens {
+            if self.tokens() + tokens > self.config.max_tokens {
+                self.flush();
+            }
+            // Keep the group whole rather than the overlap
+            self.drop_overlap(tokens);
+        }
+        for unit in group {
+            self.push(unit);
+        }
+    }
+
+    fn push(&mut self, unit: &'a Unit) {
+        if self.tokens() + unit.tokens > self.config.max_tokens
+            && self.current.len() > self.overlap
+        {
+            self.flush();
+        }
+        self.drop_overlap(unit.tokens);
+        self.current.push(unit);
+    }
+
+    // Drops as much of the overlap as `tokens` more need to fit
+    fn drop_overlap(&mut self, tokens: usize) {
+        while self.overlap > 0
+            && self.tokens() + tokens > self.config.max_tokens
+        {
+            self.current.remove(0);
+            self.overlap -= 1;
+        }
+    }
+
+    fn flush(&mut self) {
+        if self.current.len() <= self.overlap {
+            return;
+        }
+        let chunk = std::mem::take(&mut self.current);
+
+        let mut tokens = 0;
+        let mut overlap = vec![];
+        for unit in chunk.iter().skip(1).rev() {
+            if tokens + unit.tokens > self.config.overlap_tokens {
+                break;
+            }
+            tokens += unit.tokens;
+            overlap.insert(0, *unit);
+        }
+        self.overlap = overlap.len();
+        self.current = overlap;
+        self.chunks.push(chunk);
+    }
+
+    fn finish(mut self) -> Vec<Vec<&'a Unit>> {
+        self.flush();
+        self.chunks
+    }
+}
+
+// Splits a unit longer than a chunk between words, or between characters
+// for text without spaces.
+fn split(unit: Unit, max_tokens: usize) -> Vec<Unit> {
+    if unit.tokens <= max_tokens {
+        return vec![unit];
+    }
+
+    let words = unit.text.split_inclusive(char::is_whitespace).flat_map(
+        |word| -> Vec<&str> {
+            if count_tokens(word) <= max_tokens {
+                return vec![word];
+            }
+            word.char_indices()
+                .map(|(i, c)| &word[i..i + c.len_utf8()])
+                .collect()
+        },
+    );
+    pack(words, max_tokens)
+        .into_iter()
+        .map(|text| {
+            let text = text.trim().to_string();
+            Unit {
+                tokens: count_tokens(&text),
+                text,
+                block_id: unit.block_id.clone(),
+                block_type: unit.block_type.clone(),
+            }
+        })
+        .filter(|unit| !unit.text.is_empty())
+        .collect()
+}
+
+fn pack<'a>(
+    pieces: impl Iterator<Item = &'a str>,
+    max_tokens: usize,
+) -> Vec<String> {
+    let mut packed = vec![];
+    let mut current = String::new();
+    for piece in pieces {
+        if !current.is_empty()
+            && count_tokens(&current) + count_tokens(piece) > max_tokens
+        {
+            packed.push(std::mem::take(&mut current));
+        }
+        current.push_str(piece);
+    }
+    if !current.is_empty() {
+        packed.push(current);
+    }
+    packed
+}
+
+// An estimate of the tokens of a WordPiece tokenizer: a token per four
+// letters of a word and one per other character but whitespace.
+pub fn count_tokens(text: &str) -> usize {
+    let mut tokens = 0;
+    let mut word = 0_usize;
+    for c in text.chars() {
+        if c.is_ascii_alphanumeric() {
+            word += 1;
+            continue;
+        }
+        tokens += word.div_ceil(4);
+        word = 0;
+        if !c.is_whitespace() {
+            tokens += 1;
+        }
+    }
+
+    tokens + word.div_ceil(4)
+}
+
+fn rich_text(rich_text: &[RichText]) -> String {
+    rich_text.iter().filter_map(RichText::plain_text).collect()
+}
+
+// Renders a list item and its nested items, indented by `depth`.
+fn list_item(block: &Block, depth: usize) -> String {
+    let (marker, text, children) = match &block.block_type {
+        BlockType::BulletedListItem { bulleted_list_item } => (
+            "-".to_string(),
+            &bulleted_list_item.rich_text,
+            &bulleted_list_item.children,
+        ),
+        BlockType::NumberedListItem { numbered_list_item } => (
+            "1.".to_string(),
+            &numbered_list_item.rich_text,
+            &numbered_list_item.children,
+        ),
+        BlockType::ToDo { to_do } => (
+            match to_do.checked {
+                Some(true) => "- [x]".to_string(),
+                _ => "- [ ]".to_string(),
+            },
+            &to_do.rich_text,
+            &to_do.children,
+        ),
+        block_type => {
+            return format!(
+                "{}{}",
+                "  ".repeat(depth),
+                block_type
+                    .plain_text()
+                    .into_iter()
+                    .flatten()
+                    .collect::<String>()
+            )
+        }
+    };
+
+    let mut lines = vec![format!(
+        "{}{} {}",
+        "  ".repeat(depth),
+        marker,
+        rich_text(text)
+    )];
+    for child in children.iter().flatten() {
+        lines.push(list_item(child, depth + 1));
+    }
+    lines.join("\n")
+}
+
+fn block_type_name(block_type: &BlockType) -> String {
+    serde_json::to_value(block_type)
+        .ok()
+        .and_then(|value| value["type"].as_str().map(str::to_string))
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod test {
+    use notion_client::objects::block::Block;
+    use serde_json::json;
+
+    use super::{chunk, count_tokens, point_ids, ChunkerConfig};
+
+    fn text(content: &str) -> serde_json::Value {
+        json!([{
+            "type": "text",
+            "text": { "content": content },
+            "plain_text": content,
+        }])
+    }
+
+    fn block(id: &str, block_type: &str, content: &str) -> Block {
+        serde_json::from_value(json!({
+            "id": id,
+            "type": block_type,
+            block_type: {
+                "rich_text": text(content),
+                "color": "default",
+                "caption": [],
+                "language": "rust",
+            },
+        }))
+        .unwrap()
+    }
+
+    fn table(id: &str, rows: &[[&str; 2]]) -> Block {
+        let rows = rows
+            .iter()
+            .map(|row| {
+                json!({
+                    "type": "table_row",
+                    "table_row": {
+                        "cells": row.iter().map(|cell| text(cell)).collect::<Vec<_>>(),
+                    },
+                })
+            })
+            .collect::<Vec<_>>();
+        serde_json::from_value(json!({
+            "id": id,
+            "type": "table",
+            "table": {
+                "table_width": 2,
+                "has_column_header": true,
+                "has_row_header": false,
+                "children": rows,
+            },
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_count_tokens() {
+        // Arrange
+        let texts = ["", "a chunk", "embeddings, please!", "見出し"];
+
+        // Act
+        let tokens = texts.map(count_tokens);
+
+        // Assert
+        assert_eq!(tokens, [0, 3, 7, 3]);
+    }
+
+    #[test]
+    fn test_chunk_follows_headings() {
+        // Arrange
+        let blocks = vec![
+            block("p0", "paragraph", "Intro"),
+            block("h1", "heading_1", "Setup"),
+            block("p1", "paragraph", "Install it."),
+            block("h2", "heading_2", "Linux"),
+            block("l1", "bulleted_list_item", "apt"),
+            block("l2", "bulleted_list_item", "dnf"),
+            block("h3", "heading_1", "Usage"),
+            table("t1", &[["name", "value"], ["size", "256"]]),
+        ];
+        let config = ChunkerConfig {
+            max_tokens: 64,
+            overlap_tokens: 8,
+        };
+
+        // Act
+        let chunks = chunk(&blocks, &config);
+
+        // Assert
+        let summary = chunks
+            .iter()
+            .map(|chunk| (chunk.heading_path.join(" > "), chunk.text.as_str()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            summary,
+            vec![
+                ("".to_string(), "Intro"),
+                ("Setup".to_string(), "Setup\nInstall it."),
+                ("Setup > Linux".to_string(), "Linux\n- apt\n- dnf"),
+                ("Usage".to_string(), "Usage\nname | value\nsize | 256"),
+            ]
+        );
+        assert_eq!(chunks[2].block_ids, vec!["h2", "l1", "l2"]);
+        assert_eq!(
+            chunks[2].block_types,
+            vec!["heading_2", "bulleted_list_item"]
+        );
+        assert_eq!(chunks[3].block_ids, vec!["h3", "t1"]);
+        assert_eq!(
+            chunks.iter().map(|chunk| chunk.index).collect::<Vec<_>>(),
+            vec![0, 1, 2, 3]
+        );
+    }
+
+    #[test]
+    fn test_chunk_size_and_overlap() {
+        // Arrange
+        let blocks = (0..6)
+            .map(|i| {
+                block(
+                    &format!("p{}", i),
+                    "paragraph",
+                    &format!("word{} word word word", i),
+                )
+            })
+            .chain([block("l1", "numbered_list_item", "first step")])
+            .chain([block("l2", "numbered_list_item", "second step")])
+            .collect::<Vec<_>>();
+        let config = ChunkerConfig {
+            max_tokens: 12,
+            overlap_tokens: 5,
+        };
+
+        // Act
+        let chunks = chunk(&blocks, &config);
+
+        // Assert
+        assert!(chunks
+            .iter()
+            .all(|chunk| count_tokens(&chunk.text) <= config.max_tokens));
+        // Each chunk starts with the last paragraph of the previous one
+        for pair in chunks.windows(2).take(4) {
+            let last = pair[0].text.lines().last().unwrap();
+            assert!(pair[1].text.starts_with(last), "{:?}", pair);
+        }
+        // The list is kept together in a chunk of its own
+        let list = chunks.last().unwrap();
+        assert_eq!(list.text, "1. first step\n1. second step");
+        assert_eq!(list.block_ids, vec!["l1", "l2"]);
+    }
+
+    #[test]
+    fn test_chunk_splits_long_blocks() {
+        // Arrange
+        let long = (0..40).map(|i| format!("w{}", i)).collect::<Vec<_>>();
+        let code = (0..12)
+            .map(|i| format!("let x{} = {};", i, i))
+            .collect::<Vec<_>>()
+            .join("\n");
+        let blocks = vec![
+            block("p1", "paragraph", &long.join(" ")),
+            block("c1", "code", &code),
+        ];
+        let config = ChunkerConfig {
+            max_tokens: 32,
+            overlap_tokens: 0,
+        };
+
+        // Act
+        let chunks = chunk(&blocks, &config);
+
+        // Assert
+        assert!(chunks.len() > 2);
+        assert!(chunks
+            .iter()
+            .all(|chunk| count_tokens(&chunk.text) <= config.max_tokens));
+        let text = chunks
+            .iter()
+            .filter(|chunk| chunk.block_ids == vec!["p1"])
+            .map(|chunk| chunk.text.clone())
+            .collect::<Vec<_>>()
+            .join(" ");
+        assert_eq!(text, long.join(" "));
+        // Code is split between lines
+        assert!(chunks
+            .iter()
+            .filter(|chunk| chunk.block_ids.contains(&"c1".to_string()))
+            .flat_map(|chunk| chunk.text.lines())
+            .filter(|line| line.starts_with("let"))
+            .all(|line| line.ends_with(';')));
+    }
+
+    #[test]
+    fn test_point_ids() {
+        // Arrange
+        let config = ChunkerConfig {
+            max_tokens: 8,
+            overlap_tokens: 0,
+        };
+        let before = chunk(
+            &[
+                block("p1", "paragraph", "Keep this text"),
+                block("h1", "heading_1", "Repeated"),
+                block("h2", "heading_1", "Repeated"),
+            ],
+            &config,
+        );
+        let after = chunk(
+            &[
+                block("p0", "paragraph", "A new paragraph first"),
+                block("p1", "paragraph", "Keep this text"),
+                block("h1", "heading_1", "Repeated"),
+                block("h2", "heading_1", "Repeated"),
+            ],
+            &config,
+        );
+
+        // Act
+        let before = point_ids("page", &before);
+        let after = point_ids("page", &after);
+        let other_page = point_ids(
+            "other",
+            &chunk(&[block("p1", "paragraph", "Keep this text")], &config),
+        );
+
+        // Assert
+        assert_eq!(after.len(), 4);
+        assert_eq!(&after[1..], &before[..]);
+        assert!(!before.contains(&after[0]));
+        assert_ne!(before[1], before[2]);
+        assert_ne!(other_page[0], before[0]);
+    }
+}
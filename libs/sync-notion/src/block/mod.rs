@@ -1,16 +1,11 @@
-use crate::State;
+pub mod chunker;
+
+use crate::{embedding, State};
 use anyhow::Context;
 use async_recursion::async_recursion;
-use cloudflare::models::text_embeddings::{
-    TextEmbeddings, TextEmbeddingsRequest,
-};
 use entity::prelude::*;
+use langfuse::trace::{Observe, Trace};
 use notion_client::objects::block::{Block, BlockType};
-use qdrant_client::{
-    client::Payload,
-    qdrant::{Condition, FieldCondition, Filter, Match, PointStruct, Value},
-};
-
 use std::{collections::HashMap, sync::Arc, time::Duration};
 use tokio::{
     join,
@@ -19,10 +14,13 @@ use tokio::{
     time::sleep,
 };
 use tracing::error;
-use uuid::Uuid;
+use vector_store::{
+    ChunkMetadata, CollectionModel, Document, Point, VectorStore,
+};
 
 struct Message {
     parent_id: String,
+    draft: bool,
     blocks: Vec<Block>,
 }
 
@@ -56,13 +54,13 @@ fn sender(
 
                 let mut children = vec![];
                 for _child in _children {
-                    let child = scan_block(state.clone(), _child).await;
-                    children.push(child);
+                    children.extend(scan_block(state.clone(), _child).await);
                 }
 
                 let result = tx
                     .send(Message {
                         parent_id: page.notion_page_id.to_owned(),
+                        draft: page.draft,
                         blocks: children,
                     })
                     .await;
@@ -79,25 +77,31 @@ fn sender(
 }
 
 #[async_recursion]
-async fn scan_block(state: Arc<State>, mut block: Block) -> Block {
+async fn scan_block(state: Arc<State>, block: Block) -> Vec<Block> {
     let Some(id) = &block.id else {
-        return block;
+        return vec![block];
     };
     let Some(has_children) = block.has_children else {
-        return block;
+        return vec![block];
     };
     if !has_children {
-        return block;
+        return vec![block];
     }
 
     let _children = get_children(state.clone(), id).await;
 
     let mut children = vec![];
     for _child in _children {
-        let child = scan_block(state.clone(), _child).await;
-        children.push(child);
+        children.extend(scan_block(state.clone(), _child).await);
     }
 
+    with_children(block, children)
+}
+
+// Puts `children` in the block, or right after it for callouts and columns,
+// whose notion types have no place for them, so that their text is still
+// rendered and chunked.
+fn with_children(mut block: Block, children: Vec<Block>) -> Vec<Block> {
     block.block_type = match block.block_type {
         BlockType::BulletedListItem {
             mut bulleted_list_item,
@@ -127,10 +131,15 @@ async fn scan_block(state: Arc<State>, mut block: Block) -> Block {
             toggle.children = Some(children);
             BlockType::Toggle { toggle }
         }
+        BlockType::Callout { .. }
+        | BlockType::ColumnList { .. }
+        | BlockType::Column { .. } => {
+            return std::iter::once(block).chain(children).collect();
+        }
         t => t,
     };
 
-    return block;
+    vec![block]
 }
 
 async fn get_children(state: Arc<State>, parent_block_id: &str) -> Vec<Block> {
@@ -251,16 +260,27 @@ fn receiver(
                 ..Default::default()
             };
 
-            let (save_result, store_result) = join!(
-                state.repository.block.save(model),
-                store_vectors(
-                    &state.cloudflare,
-                    &state.qdrant,
-                    state.collention.clone(),
-                    message.blocks,
-                    parent_id,
-                )
-            );
+            let draft = message.draft;
+            let _page = state.lock_page(parent_id).await;
+            let (save_result, store_result) =
+                join!(state.repository.block.save(model), async {
+                    // Drafts are kept out of the vector index.
+                    if draft {
+                        return Ok(());
+                    }
+                    let trace = Trace::new("store block vectors")
+                        .metadata(serde_json::json!({ "page_id": parent_id }))
+                        .tags(vec!["sync".to_string()]);
+                    let result = store_vectors(
+                        &state,
+                        message.blocks,
+                        parent_id,
+                        &trace,
+                    )
+                    .await;
+                    state.ingestion.send(trace.finish()).await;
+                    result
+                });
 
             if let Err(e) = save_result {
                 error!(
@@ -281,89 +301,343 @@ fn receiver(
     })
 }
 
-async fn store_vectors(
-    cloudflare: &cloudflare::models::Models,
-    qdrant: &qdrant_client::client::QdrantClient,
-    collection: String,
+// Embeds the chunks of a page that aren't stored yet and deletes the stored
+// chunks that no longer exist once the new ones are in, so that the page is
+// searchable throughout.
+pub async fn store_vectors(
+    state: &State,
     blocks: Vec<Block>,
     page_id: &str,
+    trace: &Trace,
 ) -> anyhow::Result<()> {
-    qdrant.delete_points(
-        collection.clone(),
-        None,
-        &qdrant_client::qdrant::PointsSelector {
-            points_selector_one_of: Some(qdrant_client::qdrant::points_selector::PointsSelectorOneOf::Filter(Filter{
-                must:vec![Condition{
-                   condition_one_of:Some(qdrant_client::qdrant::condition::ConditionOneOf::Field(FieldCondition{
-                    key:"page_id".to_string(),
-                    r#match:Some(Match{match_value:Some(qdrant_client::qdrant::r#match::MatchValue::Keyword(page_id.to_string()))}),
-                    ..Default::default()
-                   }))
-                },Condition{
-                    condition_one_of:Some(qdrant_client::qdrant::condition::ConditionOneOf::Field(FieldCondition{
-                     key:"type".to_string(),
-                     r#match:Some(Match{match_value:Some(qdrant_client::qdrant::r#match::MatchValue::Keyword(serde_json::to_string(&DocumentTypeEntity::Block).unwrap()))}),
-                     ..Default::default()
-                    }))
-                 }],
-                ..Default::default()
-            })),
-        },
-        None,
-    ).await.context("failed to delete")?;
+    let model = CollectionModel::new(state.embedder.name());
+    let chunks = chunker::chunk(&blocks, &state.chunker);
+    let ids = chunker::point_ids(page_id, &chunks);
+    let stored = state
+        .store
+        .page_points(&model, page_id, DocumentTypeEntity::Block)
+        .await?;
+    let documents = ids
+        .into_iter()
+        .zip(chunks)
+        .map(|(id, chunk)| {
+            (id.hyphenated().to_string(), document(page_id, chunk))
+        })
+        .collect();
+    let Diff {
+        mut points,
+        unembedded,
+        stale,
+    } = diff(documents, stored);
+
+    let texts = unembedded
+        .iter()
+        .map(|(_, document)| document.text.clone())
+        .collect::<Vec<_>>();
+    let vectors = embedding::embed(
+        &state.embedder,
+        &texts,
+        &state.embedding,
+        trace,
+        "embed chunks",
+    )
+    .await?;
+    let reused = points.len();
+    for ((id, document), vector) in unembedded.into_iter().zip(vectors) {
+        points.push(Point {
+            id,
+            vector,
+            document,
+        });
+    }
 
-    let mut blocks = blocks
+    let span = trace.span("replace chunks").input(serde_json::json!({
+        "embedded": texts.len(),
+        "reused": reused,
+        "stale": stale.len(),
+    }));
+
+    state
+        .store
+        .upsert(&model, points)
+        .await
+        .context("failed to upsert")?;
+    state
+        .store
+        .delete(stale)
+        .await
+        .context("failed to delete")?;
+    span.end();
+
+    Ok(())
+}
+
+#[derive(Debug, Default, PartialEq)]
+struct Diff {
+    // Stored points to upsert as they are or with their new place
+    points: Vec<Point>,
+    // Documents without a stored point, to embed
+    unembedded: Vec<(String, Document)>,
+    // Stored points of chunks that no longer exist
+    stale: Vec<String>,
+}
+
+// Compares the documents of the chunks of a page, by point id, with the
+// points stored for it. Unchanged points are left out.
+fn diff(documents: Vec<(String, Document)>, stored: Vec<Point>) -> Diff {
+    let mut stored = stored
         .into_iter()
-        .flat_map(|block| block.block_type.plain_text())
-        .flatten();
+        .map(|point| (point.id.clone(), point))
+        .collect::<HashMap<_, _>>();
+
+    let mut diff = Diff::default();
+    for (id, document) in documents {
+        match stored.remove(&id) {
+            Some(point) if point.document == document => {}
+            // The text is the same, only its place in the page moved
+            Some(point) => diff.points.push(Point { document, ..point }),
+            None => diff.unembedded.push((id, document)),
+        }
+    }
+    diff.stale = stored.into_keys().collect();
+    diff.stale.sort();
 
-    loop {
-        let mut texts = String::new();
-        loop {
-            let Some(text) = blocks.next() else {
-                return Ok(());
-            };
-            if text.is_empty() {
-                continue;
-            }
+    diff
+}
 
-            texts.push_str(&format!(" {}", text.as_str()));
+fn document(page_id: &str, chunk: chunker::Chunk) -> Document {
+    Document {
+        page_id: page_id.to_string(),
+        document_type: DocumentTypeEntity::Block,
+        text: chunk.text,
+        chunk: Some(ChunkMetadata {
+            heading_path: chunk.heading_path,
+            block_ids: chunk.block_ids,
+            block_types: chunk.block_types,
+            index: chunk.index,
+        }),
+    }
+}
 
-            if texts.len() > 600 {
-                break;
-            }
+#[cfg(test)]
+mod test {
+    use cloudflare::{
+        fake::{FakeCloudflare, Reply},
+        models::text_embeddings::BGE_SMALL_EN_V1_5,
+    };
+    use entity::prelude::DocumentTypeEntity;
+    use langfuse::trace::Trace;
+    use llm::{Model, Provider};
+    use notion_client::objects::block::Block;
+    use serde_json::json;
+    use vector_store::{
+        ChunkMetadata, CollectionModel, Document, MemoryStore, Point, Store,
+        VectorStore,
+    };
+
+    use super::{diff, store_vectors, with_children, Diff};
+    use crate::State;
+
+    fn chunk(text: &str, index: usize) -> Document {
+        Document {
+            page_id: "page".to_string(),
+            document_type: DocumentTypeEntity::Block,
+            text: text.to_string(),
+            chunk: Some(ChunkMetadata {
+                index,
+                ..Default::default()
+            }),
         }
+    }
 
-        let embedding = cloudflare
-            .bge_small_en_v1_5(TextEmbeddingsRequest {
-                text: texts.as_str().into(),
-            })
-            .await
-            .context(format!("failed to embed. {}", texts))?;
+    fn point(id: &str, document: Document) -> Point {
+        Point {
+            id: id.to_string(),
+            vector: vec![1.0],
+            document,
+        }
+    }
 
-        let Some(vectors) = embedding.result.data.first() else {
-            continue;
+    #[test]
+    fn test_with_children() {
+        // Arrange
+        let block = |value: serde_json::Value| {
+            serde_json::from_value::<Block>(value).unwrap()
         };
+        let paragraph = |text: &str| {
+            block(json!({
+                "type": "paragraph",
+                "paragraph": {
+                    "rich_text": [{
+                        "type": "text",
+                        "text": { "content": text },
+                        "plain_text": text,
+                    }],
+                    "color": "default",
+                },
+            }))
+        };
+        let callout = block(json!({
+            "type": "callout",
+            "callout": {
+                "rich_text": [],
+                "icon": { "type": "emoji", "emoji": "💡" },
+                "color": "default",
+            },
+        }));
+        let column_list =
+            block(json!({ "type": "column_list", "column_list": {} }));
+        let column = block(json!({ "type": "column", "column": {} }));
+        let toggle = block(json!({
+            "type": "toggle",
+            "toggle": { "rich_text": [], "color": "default" },
+        }));
+
+        // Act
+        let callout = with_children(callout.clone(), vec![paragraph("tip")]);
+        let columns = with_children(
+            column_list.clone(),
+            with_children(column.clone(), vec![paragraph("left")]),
+        );
+        let toggle = with_children(toggle, vec![paragraph("hidden")]);
+
+        // Assert
+        assert_eq!(callout[1], paragraph("tip"));
+        assert_eq!(columns, vec![column_list, column, paragraph("left")]);
+        assert_eq!(toggle.len(), 1);
+        let toggle = serde_json::to_value(&toggle[0]).unwrap();
+        assert_eq!(
+            toggle["toggle"]["children"][0]["paragraph"]["rich_text"][0]
+                ["plain_text"],
+            "hidden"
+        );
+    }
 
-        let mut map = HashMap::new();
-        map.insert("page_id".to_string(), Value::from(page_id));
-        map.insert("document".to_string(), Value::from(texts.clone()));
-        map.insert(
-            "type".to_string(),
-            Value::from(
-                serde_json::to_string(&DocumentTypeEntity::Block).unwrap(),
-            ),
+    #[test]
+    fn test_diff() {
+        // Arrange
+        let stored = vec![
+            point("unchanged", chunk("Intro", 0)),
+            point("moved", chunk("Setup", 1)),
+            point("edited", chunk("Run it.", 2)),
+            point("removed", chunk("Old", 3)),
+        ];
+        let documents = vec![
+            ("unchanged".to_string(), chunk("Intro", 0)),
+            ("added".to_string(), chunk("New", 1)),
+            ("moved".to_string(), chunk("Setup", 2)),
+            ("rewritten".to_string(), chunk("Run it twice.", 3)),
+        ];
+
+        // Act
+        let diff = diff(documents, stored);
+
+        // Assert
+        assert_eq!(
+            diff,
+            Diff {
+                points: vec![point("moved", chunk("Setup", 2))],
+                unembedded: vec![
+                    ("added".to_string(), chunk("New", 1)),
+                    ("rewritten".to_string(), chunk("Run it twice.", 3)),
+                ],
+                stale: vec!["edited".to_string(), "removed".to_string()],
+            }
         );
+    }
+
+    fn block(id: &str, block_type: &str, content: &str) -> Block {
+        serde_json::from_value(json!({
+            "id": id,
+            "type": block_type,
+            block_type: {
+                "rich_text": [{
+                    "type": "text",
+                    "text": { "content": content },
+                    "plain_text": content,
+                }],
+                "color": "default",
+            },
+        }))
+        .unwrap()
+    }
+
+    fn blocks(usage: Option<&str>) -> Vec<Block> {
+        let mut blocks = vec![
+            block("h1", "heading_1", "Setup"),
+            block("p1", "paragraph", "Install it."),
+        ];
+        if let Some(usage) = usage {
+            blocks.extend([
+                block("h2", "heading_1", "Usage"),
+                block("p2", "paragraph", usage),
+            ]);
+        }
+        blocks
+    }
+
+    async fn texts(store: &Store) -> Vec<String> {
+        let mut texts = store
+            .page_points(
+                &CollectionModel::new(BGE_SMALL_EN_V1_5),
+                "page",
+                DocumentTypeEntity::Block,
+            )
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|point| point.document.text)
+            .collect::<Vec<_>>();
+        texts.sort();
+        texts
+    }
 
-        let points = vec![PointStruct::new(
-            Uuid::new_v4().hyphenated().to_string(),
-            vectors.clone(),
-            Payload::new_from_hashmap(map),
-        )];
+    #[tokio::test]
+    async fn test_store_vectors() {
+        // Arrange
+        let fake = FakeCloudflare::start().await.unwrap();
+        fake.reply(BGE_SMALL_EN_V1_5, Reply::Embeddings { dimensions: 8 });
+        let store = Store::Memory(MemoryStore::default());
+        let dir = tempfile::tempdir().unwrap();
+        let state = State::fake(
+            Model::new(Provider::Cloudflare(fake.models()), BGE_SMALL_EN_V1_5),
+            store.clone(),
+            dir.path(),
+        );
+        let trace = Trace::new("test");
+        let embedded = |from: usize| {
+            fake.requests()
+                .into_iter()
+                .skip(from)
+                .flat_map(|request| {
+                    request.body["text"].as_array().cloned().unwrap_or_default()
+                })
+                .filter_map(|text| text.as_str().map(str::to_string))
+                .collect::<Vec<_>>()
+        };
 
-        qdrant
-            .upsert_points(collection.clone(), None, points, None)
+        // Act
+        store_vectors(&state, blocks(Some("Run it.")), "page", &trace)
+            .await
+            .unwrap();
+        let created = texts(&store).await;
+        let requests = fake.requests().len();
+        store_vectors(&state, blocks(Some("Run it twice.")), "page", &trace)
+            .await
+            .unwrap();
+        let edited = texts(&store).await;
+        let edited_embedded = embedded(requests);
+        let requests = fake.requests().len();
+        store_vectors(&state, blocks(None), "page", &trace)
             .await
-            .context(format!("failed to upsert. {}", texts))?;
+            .unwrap();
+        let removed = texts(&store).await;
+
+        // Assert
+        assert_eq!(created, vec!["Setup\nInstall it.", "Usage\nRun it."]);
+        assert_eq!(edited, vec!["Setup\nInstall it.", "Usage\nRun it twice."]);
+        assert_eq!(edited_embedded, vec!["Usage\nRun it twice."]);
+        assert_eq!(removed, vec!["Setup\nInstall it."]);
+        assert_eq!(fake.requests().len(), requests);
     }
 }
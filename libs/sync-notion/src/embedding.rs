@@ -0,0 +1,124 @@
+use anyhow::Context as _;
+use cloudflare::models::text_embeddings::MAX_BATCH_SIZE;
+use langfuse::trace::{Observe, Trace};
+use llm::Model;
+use toml::{map::Map, Value};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct EmbeddingConfig {
+    // Texts per embedding request
+    pub batch_size: usize,
+    // Points per upsert to qdrant
+    pub upsert_batch_size: usize,
+}
+
+impl EmbeddingConfig {
+    pub fn from_config(config: &Map<String, Value>) -> anyhow::Result<Self> {
+        let integer = |key: &str| {
+            config
+                .get(key)
+                .and_then(Value::as_integer)
+                .and_then(|value| usize::try_from(value).ok())
+                .filter(|value| *value > 0)
+                .with_context(|| format!("failed to find {}", key))
+        };
+
+        Ok(Self {
+            batch_size: integer("batch_size")?.min(MAX_BATCH_SIZE),
+            upsert_batch_size: integer("upsert_batch_size")?,
+        })
+    }
+}
+
+/// Embeds `texts` with a request per `batch_size` texts and returns their
+/// vectors in the same order.
+pub async fn embed(
+    model: &Model,
+    texts: &[String],
+    config: &EmbeddingConfig,
+    trace: &Trace,
+    name: &str,
+) -> anyhow::Result<Vec<Vec<f32>>> {
+    let mut vectors = Vec::with_capacity(texts.len());
+    for batch in texts.chunks(config.batch_size.clamp(1, MAX_BATCH_SIZE)) {
+        let generation =
+            trace.generation(name).model(model.name()).input(batch);
+        let embedding = model.embed(batch.to_vec()).await;
+        generation.end_with(&embedding, |generation, vectors| {
+            generation.output(serde_json::json!({
+                "shape": [vectors.len(), vectors.first().map_or(0, Vec::len)],
+            }))
+        });
+        let data = embedding
+            .with_context(|| format!("failed to embed. {:?}", batch))?;
+
+        anyhow::ensure!(
+            data.len() == batch.len(),
+            "got {} vectors for {} texts",
+            data.len(),
+            batch.len()
+        );
+        vectors.extend(data);
+    }
+
+    Ok(vectors)
+}
+
+#[cfg(test)]
+mod test {
+    use cloudflare::{
+        fake::{FakeCloudflare, Reply},
+        models::text_embeddings::BGE_SMALL_EN_V1_5,
+    };
+    use langfuse::trace::Trace;
+    use llm::{Model, Provider};
+
+    use super::{embed, EmbeddingConfig};
+
+    #[tokio::test]
+    async fn test_embed() {
+        // Arrange
+        let fake = FakeCloudflare::start().await.unwrap();
+        fake.reply(BGE_SMALL_EN_V1_5, Reply::Embeddings { dimensions: 8 });
+        let texts = ["a", "b", "c", "a", "e"].map(str::to_string);
+        let config = EmbeddingConfig {
+            batch_size: 2,
+            upsert_batch_size: 10,
+        };
+
+        // Act
+        let model =
+            Model::new(Provider::Cloudflare(fake.models()), BGE_SMALL_EN_V1_5);
+        let vectors =
+            embed(&model, &texts, &config, &Trace::new("test"), "embed")
+                .await
+                .unwrap();
+
+        // Assert
+        let batches = fake
+            .requests()
+            .iter()
+            .map(|request| request.body["text"].as_array().unwrap().len())
+            .collect::<Vec<_>>();
+        assert_eq!(batches, vec![2, 2, 1]);
+        assert_eq!(vectors.len(), 5);
+        assert_eq!(vectors[0], vectors[3]);
+        assert_ne!(vectors[0], vectors[1]);
+    }
+
+    #[test]
+    fn test_from_config() {
+        // Arrange
+        let config = toml::from_str::<toml::Table>(
+            "batch_size = 500\nupsert_batch_size = 64",
+        )
+        .unwrap();
+
+        // Act
+        let config = EmbeddingConfig::from_config(&config).unwrap();
+
+        // Assert
+        assert_eq!(config.batch_size, 100);
+        assert_eq!(config.upsert_batch_size, 64);
+    }
+}
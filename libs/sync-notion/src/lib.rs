@@ -1,51 +1,118 @@
-use std::sync::Arc;
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex, PoisonError},
+};
 
 use anyhow::Context as _;
+use block::chunker::ChunkerConfig;
+use embedding::EmbeddingConfig;
+use langfuse::{ingestion::Ingestion, trace::Trace};
+use llm::{Llms, Model};
 use notion_client::endpoints::Client;
-use qdrant_client::qdrant::{
-    vectors_config::Config, CreateCollection, VectorParams, VectorsConfig,
-};
 use repository::Repository;
-use tokio::task::JoinHandle;
+use tokio::{sync::OwnedMutexGuard, task::JoinHandle};
 use tracing::info;
+use vector_store::{collection, Store};
 
 mod block;
+mod embedding;
 mod page;
+mod reindex;
 
 pub struct State {
     repository: Repository,
     client: Client,
-    cloudflare: cloudflare::models::Models,
-    qdrant: qdrant_client::client::QdrantClient,
+    embedder: Model,
+    // Where the vectors of `embedder` go, which may not be searched yet
+    store: Store,
+    ingestion: Ingestion,
     pause_secs: u64,
-    collention: String,
+    chunker: ChunkerConfig,
+    embedding: EmbeddingConfig,
+    page_locks: Mutex<HashMap<String, Arc<tokio::sync::Mutex<()>>>>,
 }
 
 impl State {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         repository: Repository,
         client: Client,
-        cloudflare: cloudflare::models::Models,
-        qdrant: qdrant_client::client::QdrantClient,
+        embedder: Model,
+        store: Store,
+        ingestion: Ingestion,
         pause_secs: u64,
-        collention: String,
+        chunker: ChunkerConfig,
+        embedding: EmbeddingConfig,
     ) -> Self {
         Self {
             repository,
             client,
-            cloudflare,
-            qdrant,
+            embedder,
+            store,
+            ingestion,
             pause_secs,
-            collention,
+            chunker,
+            embedding,
+            page_locks: Mutex::default(),
         }
     }
+
+    // Held while the blocks of `page_id` are saved and their vectors stored,
+    // so that a reindex reading an older copy can't store it over a newer one
+    async fn lock_page(&self, page_id: &str) -> OwnedMutexGuard<()> {
+        let lock = self
+            .page_locks
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .entry(page_id.to_string())
+            .or_default()
+            .clone();
+        lock.lock_owned().await
+    }
+}
+
+#[cfg(test)]
+impl State {
+    // A state embedding with `embedder` into `store`, for tests that reach
+    // neither the database nor Notion.
+    pub(crate) fn fake(
+        embedder: Model,
+        store: Store,
+        spill_dir: &std::path::Path,
+    ) -> Self {
+        Self::new(
+            Repository::from_connection(Default::default()),
+            Client::new("token".to_string()).unwrap(),
+            embedder,
+            store,
+            Ingestion::spawn(
+                Default::default(),
+                langfuse::ingestion::IngestionOptions {
+                    batch_size: 100,
+                    flush_interval: std::time::Duration::from_secs(60),
+                    max_retries: 0,
+                    spill_dir: spill_dir.to_path_buf(),
+                },
+            ),
+            0,
+            ChunkerConfig {
+                max_tokens: 64,
+                overlap_tokens: 0,
+            },
+            EmbeddingConfig {
+                batch_size: 2,
+                upsert_batch_size: 100,
+            },
+        )
+    }
 }
 
 pub async fn serve(
     repository: Repository,
     client: notion_client::endpoints::Client,
     cloudflare: cloudflare::models::Models,
-    qdrant: qdrant_client::client::QdrantClient,
+    store: Store,
+    ingestion: Ingestion,
     config_name: &str,
 ) -> anyhow::Result<Vec<JoinHandle<anyhow::Result<()>>>> {
     info!(task = "start notion sync");
@@ -60,48 +127,96 @@ pub async fn serve(
         .as_integer()
         .context("failed to parse pause_secs config")?;
 
-    let collection_name = config
-        .get("qdrant")
-        .unwrap()
-        .get("collection")
-        .unwrap()
-        .as_str()
-        .unwrap()
-        .to_string();
-
-    if !qdrant
-        .collection_exists(collection_name.clone())
-        .await
-        .unwrap()
-    {
-        qdrant
-            .create_collection(&CreateCollection {
-                collection_name: collection_name.clone(),
-                vectors_config: Some(VectorsConfig {
-                    config: Some(Config::Params(VectorParams {
-                        size: 384,
-                        distance: 1,
-                        on_disk: Some(true),
-                        ..Default::default()
-                    })),
-                }),
-                ..Default::default()
-            })
-            .await
-            .unwrap();
-    }
+    let chunker = ChunkerConfig::from_config(
+        config
+            .get("notion")
+            .and_then(|notion| notion.get("chunker"))
+            .and_then(|chunker| chunker.as_table())
+            .context("failed to load chunker config")?,
+    )?;
+
+    let embedding = EmbeddingConfig::from_config(
+        config
+            .get("embedding")
+            .and_then(|embedding| embedding.as_table())
+            .context("failed to load embedding config")?,
+    )?;
+
+    let embedder = Llms::from_config(
+        config
+            .get("llm")
+            .and_then(|llm| llm.as_table())
+            .context("failed to load llm config")?,
+        cloudflare,
+    )?
+    .task("embedding")?;
+
+    // The size of the vectors is whatever the model returns
+    let trace = Trace::new("probe embedding dimensions")
+        .metadata(serde_json::json!({ "model": embedder.name() }))
+        .tags(vec!["sync".to_string()]);
+    let probe = embedding::embed(
+        &embedder,
+        &["dimensions".to_string()],
+        &embedding,
+        &trace,
+        "embed probe",
+    )
+    .await;
+    ingestion.send(trace.finish()).await;
+    let dimensions = probe
+        .context("failed to embed with the embedding model")?
+        .first()
+        .map(Vec::len)
+        .context("failed to get vectors of the embedding model")?;
+
+    // A qdrant collection is only filled for a new model, and resumed until
+    // the alias is switched to it, while memory starts out empty
+    let (store, alias, reindex) = match store {
+        Store::Qdrant(qdrant) => {
+            let alias = qdrant.collection().to_string();
+            let name = collection::versioned_name(&alias, embedder.name());
+            let target =
+                collection::alias_target(qdrant.client(), &alias).await?;
+            let reindex = collection::needs_reindex(
+                &alias,
+                &name,
+                target.as_deref(),
+                qdrant.client().collection_exists(&alias).await?,
+            )?;
+            collection::create(
+                qdrant.client(),
+                &name,
+                embedder.name(),
+                dimensions as u64,
+            )
+            .await?;
+
+            let qdrant = qdrant
+                .with_collection(&name)
+                .with_upsert_batch_size(embedding.upsert_batch_size);
+            (Store::Qdrant(qdrant), Some(alias), reindex)
+        }
+        Store::Memory(memory) => (Store::Memory(memory), None, true),
+    };
 
     let state = Arc::new(State::new(
         repository,
         client,
-        cloudflare,
-        qdrant,
+        embedder,
+        store,
+        ingestion,
         pause_secs as u64,
-        collection_name,
+        chunker,
+        embedding,
     ));
 
-    let page_handles = page::spawn_service_to_get_pages(state.clone());
-    let block_handles = block::spawn_service_to_get_blocks(state.clone());
+    let mut handles = page::spawn_service_to_get_pages(state.clone());
+    handles.extend(block::spawn_service_to_get_blocks(state.clone()));
+    if reindex {
+        info!(task = "reindex", model = state.embedder.name());
+        handles.push(reindex::spawn_reindex(state.clone(), alias));
+    }
 
-    Ok(page_handles.into_iter().chain(block_handles).collect())
+    Ok(handles)
 }
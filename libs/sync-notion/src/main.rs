@@ -1,5 +1,6 @@
 use anyhow::Context as _;
 use futures::future::join_all;
+use langfuse::{apis::configuration::Configuration, ingestion::Ingestion};
 use repository::Repository;
 use std::fs::OpenOptions;
 use sync_notion::serve;
@@ -43,7 +44,12 @@ async fn main() -> anyhow::Result<()> {
             .as_str()
             .unwrap(),
         secrets.get("CLOUDFLARE_TOKEN").unwrap().as_str().unwrap(),
-    );
+    )
+    .with_config(cloudflare::config::ClientConfig::from_config(
+        config["cloudflare"]
+            .as_table()
+            .context("failed to find cloudflare config")?,
+    )?);
 
     let qdrant = qdrant_client::client::QdrantClient::from_url(
         config
@@ -57,10 +63,53 @@ async fn main() -> anyhow::Result<()> {
     .with_api_key(secrets.get("QDRANT_API_KEY").unwrap().as_str().unwrap())
     .build()
     .unwrap();
+    let store = vector_store::Store::from_config(&config, qdrant)?;
+
+    let langfuse = Configuration {
+        base_path: config
+            .get("langfuse")
+            .unwrap()
+            .get("base_url")
+            .unwrap()
+            .as_str()
+            .unwrap()
+            .to_string(),
+        basic_auth: Some((
+            secrets
+                .get("LANGFUSE_PUBLIC_KEY")
+                .unwrap()
+                .as_str()
+                .unwrap()
+                .to_string(),
+            Some(
+                secrets
+                    .get("LANGFUSE_SECRET_KEY")
+                    .unwrap()
+                    .as_str()
+                    .unwrap()
+                    .to_string(),
+            ),
+        )),
+        ..Default::default()
+    };
+    let ingestion = Ingestion::from_config(
+        langfuse,
+        config
+            .get("langfuse")
+            .and_then(|langfuse| langfuse.as_table())
+            .context("failed to find langfuse config")?,
+        &util::workspace_dir(),
+    )?;
 
-    let handles =
-        serve(repository, notion_client, cloudflare, qdrant, config_name)
-            .await?;
+    let handles = serve(
+        repository,
+        notion_client,
+        cloudflare,
+        store,
+        ingestion,
+        config_name,
+    )
+    .await?;
 
     let _ = join_all(handles).await;
 
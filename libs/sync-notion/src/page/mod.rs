@@ -1,10 +1,8 @@
-use crate::State;
+use crate::{embedding, State};
 use anyhow::{anyhow, Context};
-use cloudflare::models::text_embeddings::{
-    TextEmbeddings, TextEmbeddingsRequest,
-};
 use entity::{page::ParentType, post::Category, prelude::*};
 use futures::future::join_all;
+use langfuse::trace::Trace;
 use notion_client::objects::page::Page;
 use notion_client::{
     endpoints::databases::query::request::{
@@ -12,24 +10,14 @@ use notion_client::{
     },
     objects::{page::PageProperty, parent::Parent},
 };
-use qdrant_client::{
-    client::Payload,
-    qdrant::{
-        Condition, FieldCondition, Filter, Match, PointId, PointStruct, Value,
-    },
-};
-use std::{
-    collections::{HashMap, HashSet},
-    sync::Arc,
-    time::Duration,
-    vec,
-};
+use std::{collections::HashSet, sync::Arc, time::Duration, vec};
 use tokio::{join, task::JoinHandle};
 use tokio::{
     sync::mpsc::{self, Receiver, Sender},
     time::sleep,
 };
 use tracing::{error, info};
+use vector_store::{CollectionModel, Document, Point, VectorStore};
 
 enum Message {
     Save { pages: Vec<Page> },
@@ -221,6 +209,8 @@ fn receiver(
         loop {
             match rx.recv().await {
                 Some(Message::Save { pages }) => {
+                    // Embedded together after the loop
+                    let mut unembedded = vec![];
                     for page in pages {
                         let json = serde_json::to_string_pretty(&page).unwrap();
                         let parent_id = match page.parent {
@@ -243,7 +233,38 @@ fn receiver(
                             continue;
                         };
 
-                        let need_update = match stored {
+                        let draft = page
+                            .properties
+                            .get("draft")
+                            .map(|t| {
+                                let PageProperty::Checkbox { id: _, checkbox } =
+                                    t
+                                else {
+                                    return false;
+                                };
+
+                                *checkbox
+                            })
+                            .unwrap_or_default();
+
+                        // A published draft has no block vectors yet, so
+                        // drop its blocks to let the block sync embed them.
+                        if stored.as_ref().is_some_and(|s| s.draft && !draft) {
+                            let result = state
+                                .repository
+                                .block
+                                .delete_by_page_id(&page.id)
+                                .await;
+                            if let Err(e) = result {
+                                error!(
+                                    task = "delete block",
+                                    page_id = page.id,
+                                    error = e.to_string()
+                                );
+                            }
+                        }
+
+                        let need_update = match &stored {
                             None => true,
                             Some(stored) => {
                                 let stored_contents =
@@ -266,6 +287,23 @@ fn receiver(
                             }
                         };
 
+                        // Drafts stay out of the vector index, including
+                        // the ones indexed before they became drafts. An
+                        // unchanged draft has nothing left to delete.
+                        let became_draft =
+                            stored.as_ref().map_or(true, |s| !s.draft);
+                        if draft && (became_draft || need_update) {
+                            let result =
+                                state.store.delete_page(&page.id).await;
+                            if let Err(e) = result {
+                                error!(
+                                    task = "delete vector",
+                                    page_id = page.id,
+                                    error = e.to_string()
+                                );
+                            }
+                        }
+
                         if !need_update {
                             continue;
                         }
@@ -282,15 +320,6 @@ fn receiver(
                                 .join("")
                         });
 
-                        let draft = page.properties.get("draft").map(|t| {
-                            let PageProperty::Checkbox { id: _, checkbox } = t
-                            else {
-                                return false;
-                            };
-
-                            *checkbox
-                        });
-
                         let _page = page.clone();
                         let page_model = PageEntity {
                             notion_page_id: _page.id.clone(),
@@ -303,7 +332,7 @@ fn receiver(
                                 _ => ParentType::Database,
                             },
                             title: title.unwrap_or_default().to_lowercase(),
-                            draft: draft.unwrap_or_default(),
+                            draft,
                             contents: json,
                             created_at: _page.created_time,
                             updated_at: None,
@@ -316,20 +345,13 @@ fn receiver(
                             created_at: _page.created_time,
                         };
 
-                        let (
-                            save_page_result,
-                            save_post_result,
-                            store_vectors_result,
-                        ) = join!(
+                        let (save_page_result, save_post_result) = join!(
                             state.repository.page.save(page_model.clone()),
                             state.repository.post.save(post_model.clone()),
-                            store_vectors(
-                                &state.cloudflare,
-                                &state.qdrant,
-                                state.collention.clone(),
-                                page.clone()
-                            ),
                         );
+                        if !draft {
+                            unembedded.push(page);
+                        }
 
                         if let Err(e) = save_page_result {
                             error!(
@@ -346,14 +368,27 @@ fn receiver(
                                 error = e.to_string()
                             );
                         }
+                    }
 
-                        if let Err(e) = store_vectors_result {
-                            error!(
-                                task = "store vectors",
-                                model = format!("{:?}", page),
-                                error = e.to_string()
-                            );
-                        }
+                    if unembedded.is_empty() {
+                        continue;
+                    }
+                    let page_ids = unembedded
+                        .iter()
+                        .map(|page| page.id.clone())
+                        .collect::<Vec<_>>();
+                    let trace = Trace::new("store page vectors")
+                        .metadata(serde_json::json!({ "page_ids": page_ids }))
+                        .tags(vec!["sync".to_string()]);
+                    let result =
+                        store_vectors(&state, &unembedded, &trace).await;
+                    state.ingestion.send(trace.finish()).await;
+                    if let Err(e) = result {
+                        error!(
+                            task = "store vectors",
+                            page_ids = format!("{:?}", page_ids),
+                            error = e.to_string()
+                        );
                     }
                 }
                 Some(Message::Delete { page_ids }) => {
@@ -365,11 +400,7 @@ fn receiver(
                         ) = join!(
                             state.repository.block.delete_by_page_id(&page_id),
                             state.repository.page.delete(&page_id),
-                            delete_vectors(
-                                &state.qdrant,
-                                state.collention.clone(),
-                                &page_id,
-                            )
+                            state.store.delete_page(&page_id)
                         );
 
                         if let Err(e) = delete_block_result {
@@ -403,46 +434,65 @@ fn receiver(
     })
 }
 
-async fn delete_vectors(
-    qdrant: &qdrant_client::client::QdrantClient,
-    collection: String,
-    page_id: &str,
+// Embeds the title and summary of `pages` in batches, one point per page.
+pub async fn store_vectors(
+    state: &State,
+    pages: &[Page],
+    trace: &Trace,
 ) -> anyhow::Result<()> {
-    qdrant.delete_points(
-        collection.clone(),
-        None,
-        &qdrant_client::qdrant::PointsSelector {
-            points_selector_one_of: Some(qdrant_client::qdrant::points_selector::PointsSelectorOneOf::Filter(Filter{
-                must:vec![Condition{
-                   condition_one_of:Some(qdrant_client::qdrant::condition::ConditionOneOf::Field(FieldCondition{
-                    key:"page_id".to_string(),
-                    r#match:Some(Match{match_value:Some(qdrant_client::qdrant::r#match::MatchValue::Keyword(page_id.to_string()))}),
-                    ..Default::default()
-                   }))
-                },Condition{
-                    condition_one_of:Some(qdrant_client::qdrant::condition::ConditionOneOf::Field(FieldCondition{
-                     key:"type".to_string(),
-                     r#match:Some(Match{match_value:Some(qdrant_client::qdrant::r#match::MatchValue::Keyword(serde_json::to_string(&DocumentTypeEntity::Page).unwrap()))}),
-                     ..Default::default()
-                    }))
-                 }],
-                ..Default::default()
-            })),
-        },
-        None,
-    ).await?;
+    let mut page_ids = vec![];
+    let mut documents = vec![];
+    for page in pages {
+        match title_summary(page) {
+            Ok(Some(document)) => {
+                page_ids.push(page.id.clone());
+                documents.push(document);
+            }
+            Ok(None) => {}
+            Err(e) => error!(
+                task = "get title and summary",
+                page_id = page.id,
+                error = e.to_string()
+            ),
+        }
+    }
+
+    let vectors = embedding::embed(
+        &state.embedder,
+        &documents,
+        &state.embedding,
+        trace,
+        "embed titles and summaries",
+    )
+    .await?;
+
+    let points = page_ids
+        .into_iter()
+        .zip(documents)
+        .zip(vectors)
+        .map(|((page_id, text), vector)| Point {
+            id: page_id.clone(),
+            vector,
+            document: Document {
+                page_id,
+                document_type: DocumentTypeEntity::Page,
+                text,
+                chunk: None,
+            },
+        })
+        .collect::<Vec<_>>();
+
+    state
+        .store
+        .upsert(&CollectionModel::new(state.embedder.name()), points)
+        .await
+        .context("failed to upsert")?;
 
     Ok(())
 }
 
-async fn store_vectors(
-    cloudflare: &cloudflare::models::Models,
-    qdrant: &qdrant_client::client::QdrantClient,
-    collection: String,
-    page: Page,
-) -> anyhow::Result<()> {
-    let page_id = page.id;
-
+// The text embedded for a page, or none for a page without a summary.
+fn title_summary(page: &Page) -> anyhow::Result<Option<String>> {
     let title = page
         .properties
         .get("title")
@@ -458,7 +508,7 @@ async fn store_vectors(
         .join("");
 
     let Some(summary) = page.properties.get("summary") else {
-        return Ok(());
+        return Ok(None);
     };
     let PageProperty::RichText { id: _, rich_text } = summary else {
         return Err(anyhow!("failed to get summary"));
@@ -471,40 +521,103 @@ async fn store_vectors(
         .join("");
 
     if summary.is_empty() {
-        return Ok(());
+        return Ok(None);
     }
 
-    let title_summary = format!("{}\n{}", title, summary);
-
-    let embedding = cloudflare
-        .bge_small_en_v1_5(TextEmbeddingsRequest {
-            text: title_summary.as_str().into(),
-        })
-        .await
-        .context(format!("failed to embed. {}", title_summary))?;
+    Ok(Some(format!("{}\n{}", title, summary)))
+}
 
-    let Some(vectors) = embedding.result.data.first() else {
-        return Err(anyhow!("failed to get vectors. {}", title_summary));
+#[cfg(test)]
+mod test {
+    use cloudflare::{
+        fake::{FakeCloudflare, Reply},
+        models::text_embeddings::BGE_SMALL_EN_V1_5,
+    };
+    use langfuse::trace::Trace;
+    use llm::{Model, Provider};
+    use notion_client::objects::page::Page;
+    use serde_json::json;
+    use vector_store::{
+        CollectionModel, MemoryStore, Query, Store, VectorStore,
     };
 
-    let mut map = HashMap::new();
-    map.insert("page_id".to_string(), Value::from(page_id.clone()));
-    map.insert("document".to_string(), Value::from(title_summary.clone()));
-    map.insert(
-        "type".to_string(),
-        Value::from(serde_json::to_string(&DocumentTypeEntity::Page).unwrap()),
-    );
-
-    let points = vec![PointStruct::new(
-        PointId::from(page_id),
-        vectors.clone(),
-        Payload::new_from_hashmap(map),
-    )];
-
-    qdrant
-        .upsert_points(collection.clone(), None, points, None)
-        .await
-        .context(format!("failed to upsert. {}", title_summary))?;
+    use super::store_vectors;
+    use crate::State;
 
-    Ok(())
+    fn text(content: &str) -> serde_json::Value {
+        json!([{
+            "type": "text",
+            "text": { "content": content },
+            "plain_text": content,
+        }])
+    }
+
+    fn page(id: &str, title: &str, summary: Option<&str>) -> Page {
+        let mut properties = json!({
+            "title": { "type": "title", "id": "title", "title": text(title) },
+        });
+        if let Some(summary) = summary {
+            properties["summary"] = json!({
+                "type": "rich_text",
+                "id": "summary",
+                "rich_text": text(summary),
+            });
+        }
+        let user = json!({ "object": "user", "id": "user" });
+        serde_json::from_value(json!({
+            "id": id,
+            "created_time": "2024-05-01T00:00:00Z",
+            "created_by": user,
+            "last_edited_time": "2024-05-01T00:00:00Z",
+            "last_edited_by": user,
+            "archived": false,
+            "properties": properties,
+            "parent": { "type": "database_id", "database_id": "database" },
+            "url": format!("https://www.notion.so/{}", id),
+        }))
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_store_vectors() {
+        // Arrange
+        let fake = FakeCloudflare::start().await.unwrap();
+        fake.reply(BGE_SMALL_EN_V1_5, Reply::Embeddings { dimensions: 8 });
+        let store = Store::Memory(MemoryStore::default());
+        let dir = tempfile::tempdir().unwrap();
+        let model =
+            Model::new(Provider::Cloudflare(fake.models()), BGE_SMALL_EN_V1_5);
+        let state = State::fake(model.clone(), store.clone(), dir.path());
+        let pages = vec![
+            page("axum", "Axum", Some("Routing with axum")),
+            page("qdrant", "Qdrant", Some("Vectors in qdrant")),
+            page("draft", "Draft", None),
+        ];
+
+        // Act
+        store_vectors(&state, &pages, &Trace::new("test"))
+            .await
+            .unwrap();
+        let vector = model
+            .embed(vec!["routing axum".to_string()])
+            .await
+            .unwrap()
+            .remove(0);
+        let hits = store
+            .search(&Query::new(
+                vector,
+                CollectionModel::new(BGE_SMALL_EN_V1_5),
+                5,
+            ))
+            .await
+            .unwrap();
+
+        // Assert
+        let page_ids = hits
+            .iter()
+            .map(|hit| hit.document.page_id.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(page_ids, vec!["axum", "qdrant"]);
+        assert_eq!(hits[0].document.text, "Axum\nRouting with axum");
+    }
 }
@@ -0,0 +1,103 @@
+use std::sync::Arc;
+
+use anyhow::Context as _;
+use langfuse::trace::Trace;
+use notion_client::objects::{block::Block, page::Page};
+use tokio::task::JoinHandle;
+use tracing::{error, info};
+use vector_store::{collection, Store};
+
+use crate::{block, page, State};
+
+// Fills the store with every published page and its blocks from the
+// database. A qdrant collection is then put behind `alias`, which search keeps
+// reading the previous collection through until then, and a failed reindex
+// leaves the alias alone to be retried on the next start.
+pub fn spawn_reindex(
+    state: Arc<State>,
+    alias: Option<String>,
+) -> JoinHandle<anyhow::Result<()>> {
+    tokio::spawn(async move {
+        let result = reindex(&state).await;
+        if let Err(e) = result {
+            error!(
+                task = "reindex",
+                model = state.embedder.name(),
+                error = e.to_string()
+            );
+            return Err(e);
+        }
+
+        let (Some(alias), Store::Qdrant(qdrant)) = (alias, &state.store) else {
+            return Ok(());
+        };
+        let result = collection::switch_alias(
+            qdrant.client(),
+            &alias,
+            qdrant.collection(),
+        )
+        .await;
+        if let Err(e) = result {
+            error!(
+                task = "switch alias",
+                alias,
+                collection = qdrant.collection(),
+                error = e.to_string()
+            );
+            return Err(e);
+        }
+        info!(
+            task = "switch alias",
+            alias,
+            collection = qdrant.collection()
+        );
+
+        Ok(())
+    })
+}
+
+async fn reindex(state: &State) -> anyhow::Result<()> {
+    let pages = state
+        .repository
+        .page
+        .find_published()
+        .await
+        .context("failed to find pages")?
+        .into_iter()
+        .map(|page| serde_json::from_str::<Page>(&page.contents))
+        .collect::<Result<Vec<_>, _>>()
+        .context("failed to deserialize pages")?;
+
+    let trace = Trace::new("reindex page vectors")
+        .tags(vec!["sync".to_string(), "reindex".to_string()]);
+    let result = page::store_vectors(state, &pages, &trace).await;
+    state.ingestion.send(trace.finish()).await;
+    result?;
+
+    for page in pages {
+        // Read under the lock, so that blocks saved meanwhile aren't
+        // replaced by the copy read before them
+        let _page = state.lock_page(&page.id).await;
+        let Some(blocks) = state
+            .repository
+            .block
+            .find_by_notion_page_id(&page.id)
+            .await
+            .context("failed to find blocks")?
+        else {
+            continue;
+        };
+        let blocks = serde_json::from_str::<Vec<Block>>(&blocks.contents)
+            .context("failed to deserialize blocks")?;
+
+        let trace = Trace::new("reindex block vectors")
+            .metadata(serde_json::json!({ "page_id": page.id }))
+            .tags(vec!["sync".to_string(), "reindex".to_string()]);
+        let result =
+            block::store_vectors(state, blocks, &page.id, &trace).await;
+        state.ingestion.send(trace.finish()).await;
+        result.with_context(|| format!("failed to reindex {}", page.id))?;
+    }
+
+    Ok(())
+}
@@ -0,0 +1,346 @@
+//! Versioned qdrant collections served through an alias.
+//!
+//! A collection holds the vectors of one embedding model in a vector named
+//! after the model. Search looks up the collection behind the alias, embeds
+//! its query with the model of that collection and searches it, so a new
+//! model is only searched once its collection is filled and the alias is
+//! switched to it.
+
+use anyhow::Context as _;
+use qdrant_client::{
+    client::QdrantClient,
+    qdrant::{
+        alias_operations::Action, vectors_config::Config, AliasOperations,
+        ChangeAliases, CollectionInfo, CreateAlias, CreateCollection,
+        DeleteAlias, Distance, VectorParams, VectorParamsMap, VectorsConfig,
+    },
+};
+
+// The model of the collection made before models were configurable, whose
+// vector has no name
+pub const LEGACY_MODEL: &str = "@cf/baai/bge-small-en-v1.5";
+
+/// The embedding model of a collection and the name of its vector.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CollectionModel {
+    pub model: String,
+    pub vector_name: Option<String>,
+    // The collection the model was read from behind an alias, which search
+    // goes to so that a switch of the alias in between can't mismatch them
+    pub collection: Option<String>,
+}
+
+impl CollectionModel {
+    /// The model of a collection made by [`create`], whose vector is named
+    /// after it.
+    pub fn new(model: &str) -> Self {
+        Self {
+            model: model.to_string(),
+            vector_name: Some(model.to_string()),
+            collection: None,
+        }
+    }
+}
+
+/// The collection for the vectors of `model` behind `alias`.
+pub fn versioned_name(alias: &str, model: &str) -> String {
+    let slug = model
+        .to_lowercase()
+        .split(|c: char| !c.is_ascii_alphanumeric())
+        .filter(|part| !part.is_empty())
+        .collect::<Vec<_>>()
+        .join("-");
+
+    format!("{}-{}", alias, slug)
+}
+
+/// Creates the collection for `size` dimensional vectors of `model` unless
+/// it exists.
+pub async fn create(
+    qdrant: &QdrantClient,
+    name: &str,
+    model: &str,
+    size: u64,
+) -> anyhow::Result<()> {
+    if qdrant.collection_exists(name).await? {
+        return Ok(());
+    }
+
+    qdrant
+        .create_collection(&CreateCollection {
+            collection_name: name.to_string(),
+            vectors_config: Some(VectorsConfig {
+                config: Some(Config::ParamsMap(VectorParamsMap {
+                    map: [(
+                        model.to_string(),
+                        VectorParams {
+                            size,
+                            distance: Distance::Cosine.into(),
+                            on_disk: Some(true),
+                            ..Default::default()
+                        },
+                    )]
+                    .into(),
+                })),
+            }),
+            ..Default::default()
+        })
+        .await
+        .with_context(|| format!("failed to create {}", name))?;
+
+    Ok(())
+}
+
+/// The collection `alias` points to, if it's an alias.
+pub async fn alias_target(
+    qdrant: &QdrantClient,
+    alias: &str,
+) -> anyhow::Result<Option<String>> {
+    let aliases = qdrant.list_aliases().await?.aliases;
+
+    Ok(aliases
+        .into_iter()
+        .find(|description| description.alias_name == alias)
+        .map(|description| description.collection_name))
+}
+
+/// The model of the collection behind `name`, which is either an alias or a
+/// collection. A `legacy` collection is read until the alias is made.
+pub async fn model_of(
+    qdrant: &QdrantClient,
+    name: &str,
+    legacy: Option<&str>,
+) -> anyhow::Result<CollectionModel> {
+    let target = alias_target(qdrant, name).await?;
+    let collection = searched_collection(name, target, legacy);
+    let info = qdrant
+        .collection_info(&collection)
+        .await?
+        .result
+        .with_context(|| format!("failed to find {}", collection))?;
+
+    let model = model_from_info(&info)
+        .with_context(|| format!("failed to find vectors of {}", collection))?;
+
+    Ok(CollectionModel {
+        collection: Some(collection),
+        ..model
+    })
+}
+
+// The target of the alias `name`, else the legacy collection that served
+// search before the alias was first switched, else `name` as a collection
+fn searched_collection(
+    name: &str,
+    target: Option<String>,
+    legacy: Option<&str>,
+) -> String {
+    target
+        .or_else(|| legacy.map(str::to_string))
+        .unwrap_or_else(|| name.to_string())
+}
+
+/// Whether the collection `name` of the model still has to be filled
+/// before `alias` is pointed at it. An unswitched one is filled again on
+/// top of what it has, since points are upserted by id. A collection named
+/// like the alias, made before there were versions, can't be turned into
+/// one, so sync refuses to start rather than never switching.
+pub fn needs_reindex(
+    alias: &str,
+    name: &str,
+    target: Option<&str>,
+    alias_is_collection: bool,
+) -> anyhow::Result<bool> {
+    if target.is_none() && alias_is_collection {
+        anyhow::bail!(
+            "{} is a collection, not an alias. Set `collection` of [qdrant] \
+             to a new alias name and `legacy_collection` to {}",
+            alias,
+            alias
+        );
+    }
+
+    Ok(target != Some(name))
+}
+
+fn model_from_info(info: &CollectionInfo) -> Option<CollectionModel> {
+    let config = info
+        .config
+        .as_ref()?
+        .params
+        .as_ref()?
+        .vectors_config
+        .as_ref()?
+        .config
+        .as_ref()?;
+
+    match config {
+        Config::Params(_) => Some(CollectionModel {
+            model: LEGACY_MODEL.to_string(),
+            vector_name: None,
+            collection: None,
+        }),
+        Config::ParamsMap(params) => {
+            let name = params.map.keys().next()?;
+            Some(CollectionModel::new(name))
+        }
+    }
+}
+
+/// Points `alias` at `collection` in a single update, so that searches see
+/// either collection and never none. A collection named like the alias,
+/// made before there were versions, is left alone since deleting it first
+/// would leave searches with nothing. See [`needs_reindex`].
+pub async fn switch_alias(
+    qdrant: &QdrantClient,
+    alias: &str,
+    collection: &str,
+) -> anyhow::Result<()> {
+    let mut actions = vec![];
+    match alias_target(qdrant, alias).await? {
+        Some(target) if target == collection => return Ok(()),
+        Some(_) => actions.push(AliasOperations {
+            action: Some(Action::DeleteAlias(DeleteAlias {
+                alias_name: alias.to_string(),
+            })),
+        }),
+        None => {
+            if qdrant.collection_exists(alias).await? {
+                anyhow::bail!(
+                    "{} is a collection, not an alias. Set `collection` of \
+                     [qdrant] to a new alias name to search {}, then delete \
+                     {}",
+                    alias,
+                    collection,
+                    alias
+                );
+            }
+        }
+    }
+    actions.push(AliasOperations {
+        action: Some(Action::CreateAlias(CreateAlias {
+            collection_name: collection.to_string(),
+            alias_name: alias.to_string(),
+        })),
+    });
+
+    qdrant
+        .update_aliases(ChangeAliases {
+            actions,
+            timeout: None,
+        })
+        .await
+        .with_context(|| {
+            format!("failed to point {} at {}", alias, collection)
+        })?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use qdrant_client::qdrant::{
+        vectors_config::Config, CollectionConfig, CollectionInfo,
+        CollectionParams, VectorParams, VectorParamsMap, VectorsConfig,
+    };
+
+    use super::{
+        model_from_info, needs_reindex, searched_collection, versioned_name,
+        LEGACY_MODEL,
+    };
+
+    fn info(config: Config) -> CollectionInfo {
+        CollectionInfo {
+            config: Some(CollectionConfig {
+                params: Some(CollectionParams {
+                    vectors_config: Some(VectorsConfig {
+                        config: Some(config),
+                    }),
+                    ..Default::default()
+                }),
+                ..Default::default()
+            }),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_versioned_name() {
+        // Arrange
+        let model = "@cf/baai/bge-base-en-v1.5";
+
+        // Act
+        let name = versioned_name("notion", model);
+
+        // Assert
+        assert_eq!(name, "notion-cf-baai-bge-base-en-v1-5");
+        assert_ne!(
+            name,
+            versioned_name("notion", "@cf/baai/bge-large-en-v1.5")
+        );
+    }
+
+    #[test]
+    fn test_model_from_info() {
+        // Arrange
+        let legacy = info(Config::Params(VectorParams::default()));
+        let versioned = info(Config::ParamsMap(VectorParamsMap {
+            map: [("@cf/model".to_string(), VectorParams::default())].into(),
+        }));
+
+        // Act
+        let legacy = model_from_info(&legacy).unwrap();
+        let versioned = model_from_info(&versioned).unwrap();
+
+        // Assert
+        assert_eq!(legacy.model, LEGACY_MODEL);
+        assert_eq!(legacy.vector_name, None);
+        assert_eq!(versioned.model, "@cf/model");
+        assert_eq!(versioned.vector_name.as_deref(), Some("@cf/model"));
+    }
+
+    #[test]
+    fn test_searched_collection() {
+        // Arrange
+        let switched = Some("notion-search-cf-model".to_string());
+
+        // Act
+        let before_switch =
+            searched_collection("notion-search", None, Some("notion"));
+        let after_switch = searched_collection(
+            "notion-search",
+            switched.clone(),
+            Some("notion"),
+        );
+        let collection = searched_collection("notion", None, None);
+
+        // Assert
+        assert_eq!(before_switch, "notion");
+        assert_eq!(after_switch, "notion-search-cf-model");
+        assert_eq!(collection, "notion");
+    }
+
+    #[test]
+    fn test_needs_reindex() {
+        // Arrange
+        let name = versioned_name("notion-search", "@cf/model");
+
+        // Act
+        let legacy_alias = needs_reindex("notion", &name, None, true);
+        let first_start = needs_reindex("notion-search", &name, None, false);
+        let resumed = needs_reindex(
+            "notion-search",
+            &name,
+            Some("notion-search-cf-other"),
+            false,
+        );
+        let switched =
+            needs_reindex("notion-search", &name, Some(&name), false);
+
+        // Assert
+        assert!(legacy_alias.is_err());
+        assert!(first_start.unwrap());
+        assert!(resumed.unwrap());
+        assert!(!switched.unwrap());
+    }
+}
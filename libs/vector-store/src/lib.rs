@@ -0,0 +1,280 @@
+//! Page and block vectors behind one interface so that search and the sync
+//! run against qdrant or, for tests and local development, in memory.
+
+use std::future::Future;
+
+use anyhow::Context as _;
+use entity::prelude::DocumentTypeEntity;
+use qdrant_client::client::QdrantClient;
+use toml::{map::Map, Value};
+
+pub use self::collection::CollectionModel;
+pub use self::memory::MemoryStore;
+pub use self::qdrant::QdrantStore;
+
+pub mod collection;
+mod memory;
+mod qdrant;
+
+/// What a point was embedded from.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Document {
+    pub page_id: String,
+    pub document_type: DocumentTypeEntity,
+    pub text: String,
+    // Only block chunks have one
+    pub chunk: Option<ChunkMetadata>,
+}
+
+/// Where a chunk of blocks sits in its page.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct ChunkMetadata {
+    pub heading_path: Vec<String>,
+    pub block_ids: Vec<String>,
+    pub block_types: Vec<String>,
+    pub index: usize,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Point {
+    pub id: String,
+    pub vector: Vec<f32>,
+    pub document: Document,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Hit {
+    pub id: String,
+    pub score: f32,
+    pub document: Document,
+}
+
+/// A search for the points closest to `vector`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Query {
+    pub vector: Vec<f32>,
+    // The model that embedded `vector`
+    pub model: CollectionModel,
+    pub document_type: Option<DocumentTypeEntity>,
+    pub page_id: Option<String>,
+    pub limit: usize,
+    pub score_threshold: Option<f32>,
+}
+
+impl Query {
+    pub fn new(vector: Vec<f32>, model: CollectionModel, limit: usize) -> Self {
+        Self {
+            vector,
+            model,
+            document_type: None,
+            page_id: None,
+            limit,
+            score_threshold: None,
+        }
+    }
+
+    pub fn document_type(mut self, document_type: DocumentTypeEntity) -> Self {
+        self.document_type = Some(document_type);
+        self
+    }
+
+    pub fn page_id(mut self, page_id: impl Into<String>) -> Self {
+        self.page_id = Some(page_id.into());
+        self
+    }
+
+    pub fn score_threshold(mut self, score_threshold: f32) -> Self {
+        self.score_threshold = Some(score_threshold);
+        self
+    }
+}
+
+pub trait VectorStore {
+    /// The embedding model of the stored vectors, which queries have to be
+    /// embedded with.
+    fn model(
+        &self,
+    ) -> impl Future<Output = anyhow::Result<CollectionModel>> + Send;
+
+    /// Inserts `points` embedded with `model`, replacing the ones with the
+    /// same ids.
+    fn upsert(
+        &self,
+        model: &CollectionModel,
+        points: Vec<Point>,
+    ) -> impl Future<Output = anyhow::Result<()>> + Send;
+
+    /// The points of `page_id` of `document_type`, with their vectors of
+    /// `model`.
+    fn page_points(
+        &self,
+        model: &CollectionModel,
+        page_id: &str,
+        document_type: DocumentTypeEntity,
+    ) -> impl Future<Output = anyhow::Result<Vec<Point>>> + Send;
+
+    fn delete(
+        &self,
+        ids: Vec<String>,
+    ) -> impl Future<Output = anyhow::Result<()>> + Send;
+
+    /// Deletes the page point and the block points of `page_id`.
+    fn delete_page(
+        &self,
+        page_id: &str,
+    ) -> impl Future<Output = anyhow::Result<()>> + Send;
+
+    /// The points matching the filters of `query`, best first.
+    fn search(
+        &self,
+        query: &Query,
+    ) -> impl Future<Output = anyhow::Result<Vec<Hit>>> + Send;
+}
+
+#[derive(Clone)]
+pub enum Store {
+    Qdrant(QdrantStore),
+    Memory(MemoryStore),
+}
+
+impl Store {
+    /// Picks the backend set in `[vector_store]` of `config`, the whole
+    /// config. A qdrant store reads `collection` and the optional
+    /// `legacy_collection` of `[qdrant]` and a memory store the embedding
+    /// model of `[llm.tasks]`.
+    pub fn from_config(
+        config: &Map<String, Value>,
+        qdrant: QdrantClient,
+    ) -> anyhow::Result<Self> {
+        let backend = config
+            .get("vector_store")
+            .and_then(|store| store.get("backend"))
+            .and_then(Value::as_str)
+            .context("failed to find vector_store backend")?;
+
+        match backend {
+            "qdrant" => {
+                let config = config
+                    .get("qdrant")
+                    .context("failed to find qdrant config")?;
+                let collection = config
+                    .get("collection")
+                    .and_then(Value::as_str)
+                    .context("failed to find qdrant collection")?;
+                let store = QdrantStore::new(qdrant, collection);
+                match config.get("legacy_collection").and_then(Value::as_str) {
+                    Some(legacy) => {
+                        Ok(Self::Qdrant(store.with_legacy_collection(legacy)))
+                    }
+                    None => Ok(Self::Qdrant(store)),
+                }
+            }
+            "memory" => {
+                let model = config
+                    .get("llm")
+                    .and_then(|llm| llm.get("tasks"))
+                    .and_then(|tasks| tasks.get("embedding"))
+                    .and_then(|embedding| embedding.get("model"))
+                    .and_then(Value::as_str)
+                    .context("failed to find the embedding model")?;
+                Ok(Self::Memory(MemoryStore::new(model)))
+            }
+            backend => anyhow::bail!("unknown vector store: {}", backend),
+        }
+    }
+}
+
+impl VectorStore for Store {
+    async fn model(&self) -> anyhow::Result<CollectionModel> {
+        match self {
+            Store::Qdrant(store) => store.model().await,
+            Store::Memory(store) => store.model().await,
+        }
+    }
+
+    async fn upsert(
+        &self,
+        model: &CollectionModel,
+        points: Vec<Point>,
+    ) -> anyhow::Result<()> {
+        match self {
+            Store::Qdrant(store) => store.upsert(model, points).await,
+            Store::Memory(store) => store.upsert(model, points).await,
+        }
+    }
+
+    async fn page_points(
+        &self,
+        model: &CollectionModel,
+        page_id: &str,
+        document_type: DocumentTypeEntity,
+    ) -> anyhow::Result<Vec<Point>> {
+        match self {
+            Store::Qdrant(store) => {
+                store.page_points(model, page_id, document_type).await
+            }
+            Store::Memory(store) => {
+                store.page_points(model, page_id, document_type).await
+            }
+        }
+    }
+
+    async fn delete(&self, ids: Vec<String>) -> anyhow::Result<()> {
+        match self {
+            Store::Qdrant(store) => store.delete(ids).await,
+            Store::Memory(store) => store.delete(ids).await,
+        }
+    }
+
+    async fn delete_page(&self, page_id: &str) -> anyhow::Result<()> {
+        match self {
+            Store::Qdrant(store) => store.delete_page(page_id).await,
+            Store::Memory(store) => store.delete_page(page_id).await,
+        }
+    }
+
+    async fn search(&self, query: &Query) -> anyhow::Result<Vec<Hit>> {
+        match self {
+            Store::Qdrant(store) => store.search(query).await,
+            Store::Memory(store) => store.search(query).await,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Store, VectorStore};
+
+    #[tokio::test]
+    async fn test_from_config() {
+        // Arrange
+        let config = |backend: &str| {
+            toml::from_str::<toml::Table>(&format!(
+                "[vector_store]\nbackend = \"{}\"\n[qdrant]\ncollection = \"notion\"\n[llm.tasks]\nembedding = {{ provider = \"cloudflare\", model = \"@cf/bge\" }}",
+                backend
+            ))
+            .unwrap()
+        };
+        let qdrant = || {
+            qdrant_client::client::QdrantClient::from_url(
+                "http://localhost:6334",
+            )
+            .build()
+            .unwrap()
+        };
+
+        // Act
+        let qdrant_store = Store::from_config(&config("qdrant"), qdrant());
+        let memory_store = Store::from_config(&config("memory"), qdrant());
+        let unknown_store = Store::from_config(&config("pinecone"), qdrant());
+
+        // Assert
+        assert!(
+            matches!(qdrant_store, Ok(Store::Qdrant(store)) if store.collection() == "notion")
+        );
+        let memory_store = memory_store.unwrap();
+        assert!(matches!(memory_store, Store::Memory(_)));
+        assert_eq!(memory_store.model().await.unwrap().model, "@cf/bge");
+        assert!(unknown_store.is_err());
+    }
+}
@@ -0,0 +1,298 @@
+use std::{
+    collections::BTreeMap,
+    sync::{Arc, RwLock},
+};
+
+use anyhow::anyhow;
+use entity::prelude::DocumentTypeEntity;
+
+use crate::{CollectionModel, Hit, Point, Query, VectorStore};
+
+/// Points kept in the process and searched by brute force, for tests and
+/// local development without a qdrant cluster. Clones share the points.
+#[derive(Clone, Default)]
+pub struct MemoryStore {
+    inner: Arc<RwLock<Memory>>,
+}
+
+#[derive(Default)]
+struct Memory {
+    // The configured model, otherwise that of the first points upserted
+    model: Option<CollectionModel>,
+    points: BTreeMap<String, Point>,
+}
+
+impl MemoryStore {
+    /// An empty store for vectors of `model`, which search embeds its
+    /// queries with before anything is stored.
+    pub fn new(model: &str) -> Self {
+        Self {
+            inner: Arc::new(RwLock::new(Memory {
+                model: Some(CollectionModel::new(model)),
+                points: BTreeMap::new(),
+            })),
+        }
+    }
+
+    fn read<T>(&self, f: impl FnOnce(&Memory) -> T) -> anyhow::Result<T> {
+        let memory = self
+            .inner
+            .read()
+            .map_err(|_| anyhow!("failed to read the memory store"))?;
+        Ok(f(&memory))
+    }
+
+    fn write<T>(&self, f: impl FnOnce(&mut Memory) -> T) -> anyhow::Result<T> {
+        let mut memory = self
+            .inner
+            .write()
+            .map_err(|_| anyhow!("failed to write the memory store"))?;
+        Ok(f(&mut memory))
+    }
+}
+
+impl Memory {
+    // Vectors of another model can't be compared with the stored ones.
+    fn ensure_model(&self, model: &CollectionModel) -> anyhow::Result<()> {
+        match &self.model {
+            Some(stored) if stored != model => Err(anyhow!(
+                "the vectors are of {}, not {}",
+                stored.model,
+                model.model
+            )),
+            _ => Ok(()),
+        }
+    }
+}
+
+impl VectorStore for MemoryStore {
+    async fn model(&self) -> anyhow::Result<CollectionModel> {
+        self.read(|memory| memory.model.clone())?
+            .ok_or_else(|| anyhow!("failed to find the model of no vectors"))
+    }
+
+    async fn upsert(
+        &self,
+        model: &CollectionModel,
+        points: Vec<Point>,
+    ) -> anyhow::Result<()> {
+        self.write(|memory| {
+            memory.ensure_model(model)?;
+            let dimensions = memory
+                .points
+                .values()
+                .chain(&points)
+                .next()
+                .map(|point| point.vector.len());
+            if let Some(point) = points
+                .iter()
+                .find(|point| Some(point.vector.len()) != dimensions)
+            {
+                anyhow::bail!(
+                    "expected {:?} dimensions for {}, got {}",
+                    dimensions,
+                    point.id,
+                    point.vector.len()
+                );
+            }
+
+            if points.is_empty() {
+                return Ok(());
+            }
+            memory.model = Some(model.clone());
+            memory.points.extend(
+                points.into_iter().map(|point| (point.id.clone(), point)),
+            );
+            Ok(())
+        })?
+    }
+
+    async fn page_points(
+        &self,
+        model: &CollectionModel,
+        page_id: &str,
+        document_type: DocumentTypeEntity,
+    ) -> anyhow::Result<Vec<Point>> {
+        self.read(|memory| {
+            memory.ensure_model(model)?;
+            Ok(memory
+                .points
+                .values()
+                .filter(|point| {
+                    point.document.page_id == page_id
+                        && point.document.document_type == document_type
+                })
+                .cloned()
+                .collect())
+        })?
+    }
+
+    async fn delete(&self, ids: Vec<String>) -> anyhow::Result<()> {
+        self.write(|memory| {
+            for id in ids {
+                memory.points.remove(&id);
+            }
+        })
+    }
+
+    async fn delete_page(&self, page_id: &str) -> anyhow::Result<()> {
+        self.write(|memory| {
+            memory
+                .points
+                .retain(|_, point| point.document.page_id != page_id)
+        })
+    }
+
+    async fn search(&self, query: &Query) -> anyhow::Result<Vec<Hit>> {
+        self.read(|memory| {
+            memory.ensure_model(&query.model)?;
+
+            let mut hits = memory
+                .points
+                .values()
+                .filter(|point| {
+                    query.document_type.map_or(true, |document_type| {
+                        point.document.document_type == document_type
+                    }) && query.page_id.as_ref().map_or(true, |page_id| {
+                        point.document.page_id == *page_id
+                    })
+                })
+                .map(|point| Hit {
+                    id: point.id.clone(),
+                    score: cosine(&query.vector, &point.vector),
+                    document: point.document.clone(),
+                })
+                .filter(|hit| {
+                    query
+                        .score_threshold
+                        .map_or(true, |threshold| hit.score >= threshold)
+                })
+                .collect::<Vec<_>>();
+
+            hits.sort_by(|a, b| b.score.total_cmp(&a.score));
+            hits.truncate(query.limit);
+            Ok(hits)
+        })?
+    }
+}
+
+// The distance of the qdrant collections, 0 for a zero vector.
+fn cosine(a: &[f32], b: &[f32]) -> f32 {
+    let norm = |v: &[f32]| v.iter().map(|x| x * x).sum::<f32>().sqrt();
+    let (norm_a, norm_b) = (norm(a), norm(b));
+    if norm_a == 0.0 || norm_b == 0.0 {
+        return 0.0;
+    }
+
+    a.iter().zip(b).map(|(x, y)| x * y).sum::<f32>() / (norm_a * norm_b)
+}
+
+#[cfg(test)]
+mod test {
+    use entity::prelude::DocumentTypeEntity;
+
+    use super::MemoryStore;
+    use crate::{CollectionModel, Document, Point, Query, VectorStore};
+
+    fn point(
+        id: &str,
+        page_id: &str,
+        document_type: DocumentTypeEntity,
+        vector: Vec<f32>,
+    ) -> Point {
+        Point {
+            id: id.to_string(),
+            vector,
+            document: Document {
+                page_id: page_id.to_string(),
+                document_type,
+                text: id.to_string(),
+                chunk: None,
+            },
+        }
+    }
+
+    #[tokio::test]
+    async fn test_search() {
+        // Arrange
+        let store = MemoryStore::default();
+        let model = CollectionModel::new("@cf/model");
+        store
+            .upsert(
+                &model,
+                vec![
+                    point("a", "1", DocumentTypeEntity::Page, vec![1.0, 0.0]),
+                    point("b", "1", DocumentTypeEntity::Block, vec![1.0, 1.0]),
+                    point("c", "2", DocumentTypeEntity::Block, vec![1.0, 0.1]),
+                    point("d", "2", DocumentTypeEntity::Block, vec![0.0, 1.0]),
+                ],
+            )
+            .await
+            .unwrap();
+        let query = Query::new(vec![2.0, 0.0], model.clone(), 10);
+
+        // Act
+        let all = store.search(&query).await.unwrap();
+        let blocks = store
+            .search(
+                &query
+                    .clone()
+                    .document_type(DocumentTypeEntity::Block)
+                    .score_threshold(0.5),
+            )
+            .await
+            .unwrap();
+        let page = store.search(&query.clone().page_id("1")).await.unwrap();
+
+        // Assert
+        let ids = |hits: Vec<crate::Hit>| {
+            hits.into_iter().map(|hit| hit.id).collect::<Vec<_>>()
+        };
+        assert_eq!(ids(all.clone()), vec!["a", "c", "b", "d"]);
+        assert!((all[0].score - 1.0).abs() < f32::EPSILON);
+        assert_eq!(ids(blocks), vec!["c", "b"]);
+        assert_eq!(ids(page), vec!["a", "b"]);
+    }
+
+    #[tokio::test]
+    async fn test_upsert_and_delete() {
+        // Arrange
+        let store = MemoryStore::default();
+        let model = CollectionModel::new("@cf/model");
+        let other = CollectionModel::new("@cf/other");
+        let block = |id, page_id| {
+            point(id, page_id, DocumentTypeEntity::Block, vec![1.0, 0.0])
+        };
+
+        // Act
+        store
+            .upsert(&model, vec![block("a", "1"), block("b", "1")])
+            .await
+            .unwrap();
+        store.upsert(&model, vec![block("c", "2")]).await.unwrap();
+        let mixed_model = store.upsert(&other, vec![block("d", "2")]).await;
+        let mixed_dimensions = store
+            .upsert(
+                &model,
+                vec![point("e", "2", DocumentTypeEntity::Block, vec![1.0])],
+            )
+            .await;
+        store.delete(vec!["a".to_string()]).await.unwrap();
+        let first_page = store
+            .page_points(&model, "1", DocumentTypeEntity::Block)
+            .await
+            .unwrap();
+        store.delete_page("2").await.unwrap();
+        let second_page = store
+            .page_points(&model, "2", DocumentTypeEntity::Block)
+            .await
+            .unwrap();
+
+        // Assert
+        assert_eq!(store.model().await.unwrap(), model);
+        assert!(mixed_model.is_err());
+        assert!(mixed_dimensions.is_err());
+        assert_eq!(first_page, vec![block("b", "1")]);
+        assert!(second_page.is_empty());
+    }
+}
@@ -0,0 +1,430 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use anyhow::Context as _;
+use entity::prelude::DocumentTypeEntity;
+use qdrant_client::{
+    client::{Payload, QdrantClient},
+    qdrant::{
+        point_id::PointIdOptions, value::Kind, vectors::VectorsOptions,
+        Condition, Filter, PointId, PointStruct, ScrollPoints, SearchPoints,
+        Value, Vectors,
+    },
+};
+
+use crate::{
+    collection, ChunkMetadata, CollectionModel, Document, Hit, Point, Query,
+    VectorStore,
+};
+
+const DEFAULT_UPSERT_BATCH_SIZE: usize = 100;
+const SCROLL_LIMIT: u32 = 256;
+// How long the collection behind the alias is trusted for before it's looked
+// up again, to spare every query the lookup
+const MODEL_TTL: Duration = Duration::from_secs(60);
+
+/// A qdrant collection, or an alias of one.
+#[derive(Clone)]
+pub struct QdrantStore {
+    client: Arc<QdrantClient>,
+    collection: String,
+    // Searched until the alias `collection` is made
+    legacy_collection: Option<String>,
+    upsert_batch_size: usize,
+    model: Arc<Mutex<Option<(Instant, CollectionModel)>>>,
+}
+
+impl QdrantStore {
+    pub fn new(client: QdrantClient, collection: &str) -> Self {
+        Self {
+            client: Arc::new(client),
+            collection: collection.to_string(),
+            legacy_collection: None,
+            upsert_batch_size: DEFAULT_UPSERT_BATCH_SIZE,
+            model: Arc::default(),
+        }
+    }
+
+    pub fn client(&self) -> &QdrantClient {
+        &self.client
+    }
+
+    pub fn collection(&self) -> &str {
+        &self.collection
+    }
+
+    /// The same client on another collection.
+    pub fn with_collection(&self, collection: &str) -> Self {
+        Self {
+            collection: collection.to_string(),
+            legacy_collection: None,
+            model: Arc::default(),
+            ..self.clone()
+        }
+    }
+
+    /// Searches `legacy_collection`, made before there were versions, while
+    /// the alias doesn't exist yet.
+    pub fn with_legacy_collection(&self, legacy_collection: &str) -> Self {
+        Self {
+            legacy_collection: Some(legacy_collection.to_string()),
+            model: Arc::default(),
+            ..self.clone()
+        }
+    }
+
+    // Points per upsert request
+    pub fn with_upsert_batch_size(&self, upsert_batch_size: usize) -> Self {
+        Self {
+            upsert_batch_size: upsert_batch_size.max(1),
+            ..self.clone()
+        }
+    }
+}
+
+impl VectorStore for QdrantStore {
+    async fn model(&self) -> anyhow::Result<CollectionModel> {
+        let cached = self.model.lock().ok().and_then(|cached| {
+            cached
+                .as_ref()
+                .filter(|(at, _)| at.elapsed() < MODEL_TTL)
+                .map(|(_, model)| model.clone())
+        });
+        if let Some(model) = cached {
+            return Ok(model);
+        }
+
+        let model = collection::model_of(
+            &self.client,
+            &self.collection,
+            self.legacy_collection.as_deref(),
+        )
+        .await?;
+        if let Ok(mut cached) = self.model.lock() {
+            *cached = Some((Instant::now(), model.clone()));
+        }
+
+        Ok(model)
+    }
+
+    async fn upsert(
+        &self,
+        model: &CollectionModel,
+        points: Vec<Point>,
+    ) -> anyhow::Result<()> {
+        if points.is_empty() {
+            return Ok(());
+        }
+
+        let points = points
+            .into_iter()
+            .map(|point| {
+                PointStruct::new(
+                    PointId::from(point.id),
+                    vectors(model, point.vector),
+                    Payload::new_from_hashmap(payload(&point.document)),
+                )
+            })
+            .collect::<Vec<_>>();
+
+        self.client
+            .upsert_points_batch_blocking(
+                self.collection.clone(),
+                None,
+                points,
+                None,
+                self.upsert_batch_size,
+            )
+            .await
+            .context("failed to upsert")?;
+
+        Ok(())
+    }
+
+    async fn page_points(
+        &self,
+        model: &CollectionModel,
+        page_id: &str,
+        document_type: DocumentTypeEntity,
+    ) -> anyhow::Result<Vec<Point>> {
+        let filter = Filter::must([
+            Condition::matches("page_id", page_id.to_string()),
+            Condition::matches("type", serde_json::to_string(&document_type)?),
+        ]);
+
+        let mut points = vec![];
+        let mut offset = None;
+        loop {
+            let response = self
+                .client
+                .scroll(&ScrollPoints {
+                    collection_name: self.collection.clone(),
+                    filter: Some(filter.clone()),
+                    offset,
+                    limit: Some(SCROLL_LIMIT),
+                    with_payload: Some(true.into()),
+                    with_vectors: Some(true.into()),
+                    ..Default::default()
+                })
+                .await
+                .context("failed to scroll")?;
+
+            for point in response.result {
+                let (Some(id), Some(vector), Some(document)) = (
+                    point_id(point.id),
+                    vector(model, point.vectors),
+                    document(&point.payload),
+                ) else {
+                    continue;
+                };
+                points.push(Point {
+                    id,
+                    vector,
+                    document,
+                });
+            }
+
+            offset = response.next_page_offset;
+            if offset.is_none() {
+                return Ok(points);
+            }
+        }
+    }
+
+    async fn delete(&self, ids: Vec<String>) -> anyhow::Result<()> {
+        if ids.is_empty() {
+            return Ok(());
+        }
+
+        let ids = ids.into_iter().map(PointId::from).collect::<Vec<_>>();
+        self.client
+            .delete_points(self.collection.clone(), None, &ids.into(), None)
+            .await
+            .context("failed to delete")?;
+
+        Ok(())
+    }
+
+    async fn delete_page(&self, page_id: &str) -> anyhow::Result<()> {
+        // Both the page and its block points carry the page id.
+        let filter =
+            Filter::must([Condition::matches("page_id", page_id.to_string())]);
+        self.client
+            .delete_points(self.collection.clone(), None, &filter.into(), None)
+            .await
+            .with_context(|| format!("failed to delete {}", page_id))?;
+
+        Ok(())
+    }
+
+    async fn search(&self, query: &Query) -> anyhow::Result<Vec<Hit>> {
+        let mut conditions = vec![];
+        if let Some(document_type) = query.document_type {
+            conditions.push(Condition::matches(
+                "type",
+                serde_json::to_string(&document_type)?,
+            ));
+        }
+        if let Some(page_id) = &query.page_id {
+            conditions.push(Condition::matches("page_id", page_id.clone()));
+        }
+
+        let response = self
+            .client
+            .search_points(&SearchPoints {
+                collection_name: query
+                    .model
+                    .collection
+                    .clone()
+                    .unwrap_or_else(|| self.collection.clone()),
+                vector: query.vector.clone(),
+                vector_name: query.model.vector_name.clone(),
+                limit: query.limit as u64,
+                with_payload: Some(true.into()),
+                filter: (!conditions.is_empty())
+                    .then(|| Filter::must(conditions)),
+                score_threshold: query.score_threshold,
+                ..Default::default()
+            })
+            .await
+            .context("failed to search points")?;
+
+        Ok(response
+            .result
+            .into_iter()
+            .filter_map(|point| {
+                Some(Hit {
+                    document: document(&point.payload)?,
+                    id: point_id(point.id)?,
+                    score: point.score,
+                })
+            })
+            .collect())
+    }
+}
+
+// Collections made before models were configurable have an unnamed vector.
+fn vectors(model: &CollectionModel, vector: Vec<f32>) -> Vectors {
+    match &model.vector_name {
+        Some(name) => HashMap::from([(name.clone(), vector)]).into(),
+        None => vector.into(),
+    }
+}
+
+fn vector(
+    model: &CollectionModel,
+    vectors: Option<Vectors>,
+) -> Option<Vec<f32>> {
+    match (vectors?.vectors_options?, &model.vector_name) {
+        (VectorsOptions::Vector(vector), None) => Some(vector.data),
+        (VectorsOptions::Vectors(mut vectors), Some(name)) => {
+            vectors.vectors.remove(name).map(|vector| vector.data)
+        }
+        _ => None,
+    }
+}
+
+fn point_id(id: Option<PointId>) -> Option<String> {
+    match id?.point_id_options? {
+        PointIdOptions::Uuid(id) => Some(id),
+        PointIdOptions::Num(id) => Some(id.to_string()),
+    }
+}
+
+fn payload(document: &Document) -> HashMap<String, Value> {
+    let mut payload = HashMap::from([
+        ("page_id".to_string(), Value::from(document.page_id.clone())),
+        ("document".to_string(), Value::from(document.text.clone())),
+        (
+            "type".to_string(),
+            Value::from(
+                serde_json::to_string(&document.document_type)
+                    .unwrap_or_default(),
+            ),
+        ),
+    ]);
+
+    if let Some(chunk) = &document.chunk {
+        payload.extend([
+            (
+                "heading_path".to_string(),
+                Value::from(chunk.heading_path.clone()),
+            ),
+            (
+                "block_ids".to_string(),
+                Value::from(chunk.block_ids.clone()),
+            ),
+            (
+                "block_types".to_string(),
+                Value::from(chunk.block_types.clone()),
+            ),
+            ("chunk_index".to_string(), Value::from(chunk.index as i64)),
+        ]);
+    }
+
+    payload
+}
+
+// None for a payload that isn't a page or block document.
+fn document(payload: &HashMap<String, Value>) -> Option<Document> {
+    let string = |key: &str| match payload.get(key)?.kind.as_ref()? {
+        Kind::StringValue(value) => Some(value.clone()),
+        _ => None,
+    };
+    let strings =
+        |key: &str| match payload.get(key).and_then(|v| v.kind.as_ref()) {
+            Some(Kind::ListValue(list)) => list
+                .values
+                .iter()
+                .filter_map(|value| match value.kind.as_ref()? {
+                    Kind::StringValue(value) => Some(value.clone()),
+                    _ => None,
+                })
+                .collect(),
+            _ => vec![],
+        };
+
+    let chunk = match payload.get("chunk_index").and_then(|v| v.kind.as_ref()) {
+        Some(Kind::IntegerValue(index)) => Some(ChunkMetadata {
+            heading_path: strings("heading_path"),
+            block_ids: strings("block_ids"),
+            block_types: strings("block_types"),
+            index: usize::try_from(*index).ok()?,
+        }),
+        _ => None,
+    };
+
+    Some(Document {
+        page_id: string("page_id")?,
+        document_type: serde_json::from_str(&string("type")?).ok()?,
+        text: string("document")?,
+        chunk,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+
+    use entity::prelude::DocumentTypeEntity;
+    use qdrant_client::qdrant::Value;
+
+    use super::{document, payload, vector, vectors};
+    use crate::{ChunkMetadata, CollectionModel, Document};
+
+    #[test]
+    fn test_payload() {
+        // Arrange
+        let page = Document {
+            page_id: "page".to_string(),
+            document_type: DocumentTypeEntity::Page,
+            text: "Title\nSummary".to_string(),
+            chunk: None,
+        };
+        let block = Document {
+            document_type: DocumentTypeEntity::Block,
+            chunk: Some(ChunkMetadata {
+                heading_path: vec!["Setup".to_string()],
+                block_ids: vec!["a".to_string(), "b".to_string()],
+                block_types: vec!["paragraph".to_string(); 2],
+                index: 3,
+            }),
+            ..page.clone()
+        };
+
+        // Act
+        let page_payload = payload(&page);
+        let block_payload = payload(&block);
+
+        // Assert
+        assert_eq!(page_payload["type"], Value::from("\"Page\""));
+        assert!(!page_payload.contains_key("chunk_index"));
+        assert_eq!(document(&page_payload), Some(page));
+        assert_eq!(document(&block_payload), Some(block));
+        assert_eq!(document(&HashMap::new()), None);
+    }
+
+    #[test]
+    fn test_vector() {
+        // Arrange
+        let named = CollectionModel::new("@cf/model");
+        let legacy = CollectionModel {
+            model: "@cf/legacy".to_string(),
+            vector_name: None,
+            collection: None,
+        };
+
+        // Act
+        let from_named = vector(&named, Some(vectors(&named, vec![1.0])));
+        let from_legacy = vector(&legacy, Some(vectors(&legacy, vec![2.0])));
+        let mismatched = vector(&named, Some(vectors(&legacy, vec![3.0])));
+
+        // Assert
+        assert_eq!(from_named, Some(vec![1.0]));
+        assert_eq!(from_legacy, Some(vec![2.0]));
+        assert_eq!(mismatched, None);
+    }
+}
@@ -6,7 +6,10 @@ use langfuse::apis::configuration;
 use repository::Repository;
 use shuttle_persist::PersistInstance;
 use shuttle_runtime::{SecretStore, Secrets};
-use tokio::join;
+use tokio::{
+    join,
+    signal::unix::{signal, SignalKind},
+};
 
 use tracing::level_filters::LevelFilter;
 use tracing_subscriber::{
@@ -34,6 +37,7 @@ async fn main(
     let bucket = secret_store.get("BUCKET").unwrap();
 
     let admin_user = secret_store.get("ADMIN_USER").unwrap();
+    let preview_secret = secret_store.get("PREVIEW_SECRET").unwrap();
 
     let config_name = &format!("Config{}", secret_store.get("CONFIG").unwrap());
     let config = util::load_config(config_name)?;
@@ -77,7 +81,12 @@ async fn main(
     let cloudflare = cloudflare::models::Models::new(
         &cloudflare_account_id,
         &cloudflare_token,
-    );
+    )
+    .with_config(cloudflare::config::ClientConfig::from_config(
+        config["cloudflare"]
+            .as_table()
+            .context("failed to find cloudflare config")?,
+    )?);
 
     let credentials =
         Credentials::new(access_key_id, secret_access_key, None, None, "");
@@ -91,23 +100,69 @@ async fn main(
 
     init_log(secret_store.clone(), config_name)?;
 
+    let langfuse = configuration::Configuration {
+        base_path: config
+            .get("langfuse")
+            .unwrap()
+            .get("base_url")
+            .unwrap()
+            .as_str()
+            .unwrap()
+            .to_string(),
+        basic_auth: Some((
+            secret_store.get("LANGFUSE_PUBLIC_KEY").unwrap(),
+            Some(secret_store.get("LANGFUSE_SECRET_KEY").unwrap()),
+        )),
+        ..Default::default()
+    };
+    let ingestion = langfuse::ingestion::Ingestion::from_config(
+        langfuse.clone(),
+        config
+            .get("langfuse")
+            .and_then(|langfuse| langfuse.as_table())
+            .context("failed to find langfuse config")?,
+        &util::workspace_dir(),
+    )?;
+
+    // Shuttle stops the service with SIGTERM and has no shutdown hook, so
+    // buffered traces are flushed on the signal. Handling it replaces the
+    // default of exiting, so the process exits once they are.
+    let terminating_ingestion = ingestion.clone();
+    tokio::spawn(async move {
+        let Ok(mut terminate) = signal(SignalKind::terminate()) else {
+            return;
+        };
+        terminate.recv().await;
+        if let Err(e) = terminating_ingestion.shutdown().await {
+            tracing::error!(task = "shutdown ingestion", error = e.to_string());
+        }
+        std::process::exit(0);
+    });
+
+    // One store for all services, so that a memory store is shared
+    let store = vector_store::Store::from_config(
+        &config,
+        qdrant_client::client::QdrantClient::from_url(
+            config
+                .get("qdrant")
+                .unwrap()
+                .get("base_url")
+                .unwrap()
+                .as_str()
+                .unwrap(),
+        )
+        .with_api_key(secret_store.get("QDRANT_API_KEY").unwrap())
+        .build()
+        .unwrap(),
+    )?;
+
     let (notion, github, router) = join!(
         sync_notion::serve(
             repository.clone(),
             notion_client.clone(),
             cloudflare.clone(),
-            qdrant_client::client::QdrantClient::from_url(
-                config
-                    .get("qdrant")
-                    .unwrap()
-                    .get("base_url")
-                    .unwrap()
-                    .as_str()
-                    .unwrap()
-            )
-            .with_api_key(secret_store.get("QDRANT_API_KEY").unwrap())
-            .build()
-            .unwrap(),
+            store.clone(),
+            ingestion.clone(),
             config_name
         ),
         sync_github::serve(repository.clone(), config_name, &github_token),
@@ -118,53 +173,19 @@ async fn main(
             rpc::serve(
                 config_name,
                 repository,
-                qdrant_client::client::QdrantClient::from_url(
-                    config
-                        .get("qdrant")
-                        .unwrap()
-                        .get("base_url")
-                        .unwrap()
-                        .as_str()
-                        .unwrap()
-                )
-                .with_api_key(secret_store.get("QDRANT_API_KEY").unwrap())
-                .build()
-                .unwrap(),
+                store.clone(),
                 cloudflare.clone()
             )
             .unwrap(),
             cloudflare,
             s3,
-            qdrant_client::client::QdrantClient::from_url(
-                config
-                    .get("qdrant")
-                    .unwrap()
-                    .get("base_url")
-                    .unwrap()
-                    .as_str()
-                    .unwrap()
-            )
-            .with_api_key(secret_store.get("QDRANT_API_KEY").unwrap())
-            .build()
-            .unwrap(),
-            configuration::Configuration {
-                base_path: config
-                    .get("langfuse")
-                    .unwrap()
-                    .get("base_url")
-                    .unwrap()
-                    .as_str()
-                    .unwrap()
-                    .to_string(),
-                basic_auth: Some((
-                    secret_store.get("LANGFUSE_PUBLIC_KEY").unwrap(),
-                    Some(secret_store.get("LANGFUSE_SECRET_KEY").unwrap(),),
-                )),
-                ..Default::default()
-            },
+            store,
+            langfuse,
+            ingestion.clone(),
             bucket,
             config_name,
             admin_user,
+            preview_secret,
         )
     );
 